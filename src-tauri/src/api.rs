@@ -0,0 +1,478 @@
+// API REST local para integraciones (tienda online, scripts de etiquetas).
+//
+// Se activa con `api=<puerto o direccion>` en `terminal.conf` (o `VENTAS_API`) y escucha
+// por defecto solo en 127.0.0.1. Cada peticion lleva `Authorization: Bearer <token>`; los
+// tokens se crean desde la app con un rol y la API aplica los mismos permisos que a un
+// usuario con ese rol. Las rutas llaman a los mismos comandos que la interfaz (ver
+// `terminales::despachar`) y su descripcion OpenAPI se sirve en `/api/v1/openapi.json`.
+
+use rand::Rng;
+use rusqlite::OptionalExtension;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::net::SocketAddr;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::thread;
+use tiny_http::{Method, Request, Server};
+
+use crate::comun::ahora;
+use crate::permisos::{self, require_permiso, Permiso, Rol};
+use crate::promociones::CalculoVentaRequest;
+use crate::ventas::{self, VentaCompletada};
+use crate::{abrir_conexion, auditoria, catalogo, ensure_db_initialized, terminales, ubicaciones, VentaItem};
+
+const PREFIJO: &str = "/api/v1";
+const HILOS: usize = 4;
+const OPENAPI: &str = include_str!("../openapi.json");
+
+thread_local! {
+    static SESION: RefCell<Option<SesionApi>> = const { RefCell::new(None) };
+}
+
+/// Identidad de la peticion en curso: el token hace las veces de usuario.
+#[derive(Clone)]
+pub(crate) struct SesionApi {
+    nombre: String,
+    rol: Rol,
+}
+
+impl SesionApi {
+    /// Nombre con el que la peticion queda en auditoria y movimientos.
+    pub(crate) fn usuario(&self) -> String {
+        format!("api:{}", self.nombre)
+    }
+
+    pub(crate) fn rol(&self) -> Rol {
+        self.rol
+    }
+}
+
+/// Sesion de la peticion de la API que atiende este hilo, si la hay.
+pub(crate) fn sesion_actual() -> Option<SesionApi> {
+    SESION.with(|s| s.borrow().clone())
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TokenApi {
+    id: i64,
+    nombre: String,
+    rol: String,
+    creado: String,
+    ultimo_uso: Option<String>,
+    revocado: bool,
+}
+
+/// El token solo se muestra al crearlo; en la base queda su hash.
+#[derive(Serialize, Deserialize)]
+pub struct TokenApiCreado {
+    id: i64,
+    nombre: String,
+    rol: String,
+    token: String,
+}
+
+#[derive(Deserialize)]
+struct LineaPedido {
+    producto_id: i64,
+    cantidad: f64,
+    ubicacion_id: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct Pedido {
+    lineas: Vec<LineaPedido>,
+    cliente_id: Option<i64>,
+    lista_id: Option<i64>,
+    #[serde(default)]
+    a_credito: bool,
+    /// Numero de pedido del sistema externo, para la auditoria
+    referencia: Option<String>,
+}
+
+/// Donde van los parametros de la consulta (`?a=1`) y el cuerpo de la peticion.
+enum Destino {
+    Ninguno,
+    Argumentos,
+    Argumento(&'static str),
+}
+
+struct Ruta {
+    metodo: Method,
+    /// Segmentos `{nombre}` (texto) o `{nombre:n}` (numero) pasan como argumentos
+    patron: &'static str,
+    comando: &'static str,
+    consulta: Destino,
+    cuerpo: Destino,
+}
+
+fn rutas() -> Vec<Ruta> {
+    use Destino::*;
+    let ruta = |metodo, patron, comando, consulta, cuerpo| Ruta {
+        metodo,
+        patron,
+        comando,
+        consulta,
+        cuerpo,
+    };
+    vec![
+        ruta(Method::Get, "/productos", "listar_inventarios", Argumento("filtro"), Ninguno),
+        ruta(Method::Get, "/productos/codigo/{codigo}", "obtener_inventario_por_codigo", Ninguno, Ninguno),
+        ruta(Method::Get, "/productos/{id:n}", "obtener_inventario_por_id", Ninguno, Ninguno),
+        ruta(Method::Get, "/productos/{producto_id:n}/precios", "precios_producto", Ninguno, Ninguno),
+        ruta(Method::Get, "/productos/{producto_id:n}/movimientos", "listar_movimientos", Ninguno, Ninguno),
+        ruta(Method::Post, "/productos/{id:n}/compras", "registrar_compra", Ninguno, Argumentos),
+        ruta(Method::Get, "/stock", "listar_stock_ubicaciones", Argumento("filtro"), Ninguno),
+        ruta(Method::Get, "/lotes/por-vencer", "lotes_por_vencer", Argumentos, Ninguno),
+        ruta(Method::Post, "/pedidos", "crear_pedido", Ninguno, Argumento("pedido")),
+        ruta(Method::Get, "/ventas/{numero_recibo}", "obtener_ticket", Ninguno, Ninguno),
+        ruta(Method::Get, "/reportes/ventas", "consolidado_sucursales", Argumentos, Ninguno),
+        ruta(Method::Get, "/reportes/valorizacion", "valorizar_inventario", Argumentos, Ninguno),
+        ruta(Method::Get, "/reportes/cuentas-por-cobrar", "cuentas_por_cobrar", Ninguno, Ninguno),
+    ]
+}
+
+fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+#[tauri::command]
+pub fn crear_token_api(nombre: String, rol: String) -> Result<TokenApiCreado, String> {
+    require_permiso(Permiso::AdministrarSistema)?;
+    let nombre = nombre.trim().to_string();
+    if nombre.is_empty() {
+        return Err("El nombre del token es obligatorio".to_string());
+    }
+    let rol = Rol::parse(&rol).ok_or_else(|| format!("Rol desconocido: {}", rol))?;
+    let aleatorio: String = rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(40)
+        .map(char::from)
+        .collect();
+    let token = format!("vta_{}", aleatorio);
+
+    let conn = abrir_conexion()?;
+    conn.execute(
+        "INSERT INTO api_tokens (nombre, rol, hash, creado) VALUES (?1, ?2, ?3, ?4)",
+        rusqlite::params![nombre, rol.as_str(), hash_token(&token), ahora()],
+    )
+    .map_err(|e| format!("Error al crear el token (el nombre no puede repetirse): {}", e))?;
+    let id = conn.last_insert_rowid();
+    auditoria::registrar_auditoria(
+        &conn,
+        "crear_token_api",
+        &format!("api_token:{}", id),
+        None,
+        Some(serde_json::json!({ "nombre": nombre, "rol": rol.as_str() })),
+        true,
+    )?;
+
+    Ok(TokenApiCreado {
+        id,
+        nombre,
+        rol: rol.as_str().to_string(),
+        token,
+    })
+}
+
+#[tauri::command]
+pub fn listar_tokens_api() -> Result<Vec<TokenApi>, String> {
+    require_permiso(Permiso::AdministrarSistema)?;
+    let conn = abrir_conexion()?;
+    let mut stmt = conn
+        .prepare("SELECT id, nombre, rol, creado, ultimo_uso, revocado FROM api_tokens ORDER BY id")
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let tokens = stmt
+        .query_map([], |row| {
+            Ok(TokenApi {
+                id: row.get(0)?,
+                nombre: row.get(1)?,
+                rol: row.get(2)?,
+                creado: row.get(3)?,
+                ultimo_uso: row.get(4)?,
+                revocado: row.get(5)?,
+            })
+        })
+        .map_err(|e| format!("Error al leer los tokens: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    Ok(tokens)
+}
+
+#[tauri::command]
+pub fn revocar_token_api(id: i64) -> Result<(), String> {
+    require_permiso(Permiso::AdministrarSistema)?;
+    let conn = abrir_conexion()?;
+    let cambiados = conn
+        .execute("UPDATE api_tokens SET revocado = 1 WHERE id = ?1", rusqlite::params![id])
+        .map_err(|e| format!("Error al revocar el token: {}", e))?;
+    if cambiados == 0 {
+        return Err(format!("Token {} no encontrado", id));
+    }
+    auditoria::registrar_auditoria(&conn, "revocar_token_api", &format!("api_token:{}", id), None, None, true)
+}
+
+/// Un pedido externo se vende como en la caja. Nada pasa por un carrito, asi que
+/// `completar_venta` descuenta todas las lineas en la misma transaccion que el ticket: si
+/// una no tiene stock no se guarda nada.
+fn crear_pedido(pedido: Pedido) -> Result<VentaCompletada, String> {
+    require_permiso(Permiso::Vender)?;
+    if pedido.lineas.is_empty() {
+        return Err("El pedido no tiene lineas".to_string());
+    }
+    let mut conn = abrir_conexion()?;
+    let lineas = pedido
+        .lineas
+        .iter()
+        .map(|l| {
+            let cantidad = catalogo::validar_cantidad(&conn, l.producto_id, l.cantidad)
+                .map_err(|e| format!("Producto {}: {}", l.producto_id, e))?;
+            Ok(VentaItem {
+                id: l.producto_id,
+                cantidad,
+                ubicacion_id: Some(ubicaciones::validar_ubicacion(&conn, l.ubicacion_id)?),
+                ..Default::default()
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    // Nombres, precios y promociones los calcula el backend como en la caja
+    let calculo = CalculoVentaRequest {
+        ventas: lineas,
+        descuento_ticket: None,
+        supervisor_usuario: None,
+        supervisor_password: None,
+        cliente_id: pedido.cliente_id,
+        lista_id: pedido.lista_id,
+        a_credito: pedido.a_credito,
+    };
+    let venta = ventas::completar_venta(&mut conn, &calculo, false)?;
+    // La venta ya esta confirmada: si la auditoria falla, responder error haria que el
+    // cliente reintente y duplique el pedido
+    if let Err(e) = auditoria::registrar_auditoria(
+        &conn,
+        "pedido_api",
+        &format!("ticket:{}", venta.numero),
+        None,
+        Some(serde_json::json!({ "referencia": pedido.referencia, "total": venta.total })),
+        true,
+    ) {
+        println!("[warn] pedido {} sin auditoria: {}", venta.numero, e);
+    }
+    Ok(venta)
+}
+
+fn decodificar(texto: &str) -> String {
+    let bytes = texto.as_bytes();
+    let mut salida = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => salida.push(b' '),
+            b'%' => match std::str::from_utf8(bytes.get(i + 1..i + 3).unwrap_or_default())
+                .ok()
+                .and_then(|h| u8::from_str_radix(h, 16).ok())
+            {
+                Some(b) => {
+                    salida.push(b);
+                    i += 2;
+                }
+                None => salida.push(b'%'),
+            },
+            b => salida.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&salida).into_owned()
+}
+
+/// Los valores de la consulta no tienen tipo: numeros y booleanos se pasan como tales.
+fn valor_consulta(texto: &str) -> Value {
+    if let Ok(n) = texto.parse::<i64>() {
+        return Value::from(n);
+    }
+    if let Ok(n) = texto.parse::<f64>() {
+        return Value::from(n);
+    }
+    match texto {
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        _ => Value::String(texto.to_string()),
+    }
+}
+
+/// Argumentos tomados de la ruta si `camino` coincide con `patron`.
+fn coincidir(patron: &str, camino: &str) -> Option<Map<String, Value>> {
+    let patron: Vec<&str> = patron.trim_matches('/').split('/').collect();
+    let camino: Vec<&str> = camino.trim_matches('/').split('/').collect();
+    if patron.len() != camino.len() {
+        return None;
+    }
+    let mut args = Map::new();
+    for (p, c) in patron.iter().zip(&camino) {
+        match p.strip_prefix('{').and_then(|p| p.strip_suffix('}')) {
+            Some(nombre) => {
+                let texto = decodificar(c);
+                let valor = match nombre.strip_suffix(":n") {
+                    Some(nombre) => (nombre, Value::from(texto.parse::<i64>().ok()?)),
+                    None => (nombre, Value::String(texto)),
+                };
+                args.insert(valor.0.to_string(), valor.1);
+            }
+            None if p == c => {}
+            None => return None,
+        }
+    }
+    Some(args)
+}
+
+fn colocar(args: &mut Map<String, Value>, destino: &Destino, valor: Map<String, Value>) {
+    match destino {
+        Destino::Ninguno => {}
+        Destino::Argumentos => args.extend(valor),
+        Destino::Argumento(nombre) => {
+            args.insert(nombre.to_string(), Value::Object(valor));
+        }
+    }
+}
+
+fn autenticar(peticion: &Request) -> Result<SesionApi, (u16, String)> {
+    let token = terminales::cabecera(peticion, "Authorization")
+        .and_then(|valor| valor.strip_prefix("Bearer ").map(|t| t.trim().to_string()))
+        .ok_or_else(|| (401, "Falta la cabecera Authorization: Bearer <token>".to_string()))?;
+    let conn = abrir_conexion().map_err(|e| (500, e))?;
+    let (id, nombre, rol): (i64, String, String) = conn
+        .query_row(
+            "SELECT id, nombre, rol FROM api_tokens WHERE hash = ?1 AND revocado = 0",
+            rusqlite::params![hash_token(&token)],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()
+        .map_err(|e| (500, format!("Error en la consulta: {}", e)))?
+        .ok_or_else(|| (401, "Token invalido o revocado".to_string()))?;
+    conn.execute(
+        "UPDATE api_tokens SET ultimo_uso = ?1 WHERE id = ?2",
+        rusqlite::params![ahora(), id],
+    )
+    .map_err(|e| (500, format!("Error al registrar el uso del token: {}", e)))?;
+    let rol = Rol::parse(&rol).ok_or_else(|| (500, format!("Rol desconocido en el token: {}", rol)))?;
+    Ok(SesionApi { nombre, rol })
+}
+
+fn ejecutar(comando: &str, args: &Value) -> Result<Value, String> {
+    if comando == "crear_pedido" {
+        let pedido: Pedido = serde_json::from_value(args.get("pedido").cloned().unwrap_or(Value::Null))
+            .map_err(|e| format!("Pedido invalido: {}", e))?;
+        return crear_pedido(pedido).and_then(|venta| serde_json::to_value(venta).map_err(|e| e.to_string()));
+    }
+    terminales::despachar(comando, args)
+}
+
+fn procesar(peticion: &mut Request) -> Result<(u16, Value), (u16, String)> {
+    let url = peticion.url().to_string();
+    let (camino, consulta) = url.split_once('?').unwrap_or((&url, ""));
+    let camino = camino
+        .strip_prefix(PREFIJO)
+        .ok_or_else(|| (404, "Ruta no encontrada".to_string()))?;
+    if *peticion.method() == Method::Get && camino == "/openapi.json" {
+        let descripcion = serde_json::from_str(OPENAPI).map_err(|e| (500, e.to_string()))?;
+        return Ok((200, descripcion));
+    }
+
+    let rutas = rutas();
+    let (ruta, mut args) = rutas
+        .iter()
+        .find_map(|r| coincidir(r.patron, camino).map(|args| (r, args)))
+        .ok_or_else(|| (404, "Ruta no encontrada".to_string()))?;
+    if ruta.metodo != *peticion.method() {
+        return Err((405, format!("Metodo no admitido en {}", camino)));
+    }
+    let sesion = autenticar(peticion)?;
+
+    let parametros: Map<String, Value> = consulta
+        .split('&')
+        .filter_map(|par| par.split_once('='))
+        .map(|(k, v)| (decodificar(k), valor_consulta(&decodificar(v))))
+        .collect();
+    colocar(&mut args, &ruta.consulta, parametros);
+    if !matches!(ruta.cuerpo, Destino::Ninguno) {
+        let mut cuerpo = String::new();
+        peticion
+            .as_reader()
+            .read_to_string(&mut cuerpo)
+            .map_err(|e| (400, format!("No se pudo leer la peticion: {}", e)))?;
+        let cuerpo = match serde_json::from_str::<Value>(&cuerpo) {
+            Ok(Value::Object(cuerpo)) => cuerpo,
+            Ok(_) => return Err((400, "El cuerpo debe ser un objeto JSON".to_string())),
+            Err(e) => return Err((400, format!("JSON invalido: {}", e))),
+        };
+        colocar(&mut args, &ruta.cuerpo, cuerpo);
+    }
+
+    SESION.with(|s| *s.borrow_mut() = Some(sesion));
+    permisos::tomar_denegacion();
+    let resultado = panic::catch_unwind(AssertUnwindSafe(|| ejecutar(ruta.comando, &Value::Object(args))));
+    SESION.with(|s| *s.borrow_mut() = None);
+    let estado = if ruta.metodo == Method::Post { 201 } else { 200 };
+    match resultado {
+        Ok(Ok(valor)) => Ok((estado, valor)),
+        // Solo si el error que sale es la denegacion, no una que el comando descarto
+        Ok(Err(e)) if permisos::tomar_denegacion().is_some_and(|d| d.to_string() == e) => Err((403, e)),
+        Ok(Err(e)) => Err((400, e)),
+        Err(_) => Err((500, format!("Error interno en {}", camino))),
+    }
+}
+
+fn atender(mut peticion: Request) {
+    let (estado, cuerpo) = match procesar(&mut peticion) {
+        Ok(respuesta) => respuesta,
+        Err((estado, e)) => (estado, serde_json::json!({ "error": e })),
+    };
+    terminales::responder(peticion, estado, &cuerpo);
+}
+
+/// Direccion de `api=`: un puerto solo escucha en 127.0.0.1.
+fn direccion_api() -> Option<String> {
+    let valor = terminales::leer_configuracion("api")?;
+    Some(if valor.chars().all(|c| c.is_ascii_digit()) {
+        format!("127.0.0.1:{}", valor)
+    } else {
+        valor
+    })
+}
+
+/// Arranca la API en segundo plano si esta configurada.
+pub(crate) fn iniciar() {
+    let Some(direccion) = direccion_api() else {
+        return;
+    };
+    if terminales::es_cliente() {
+        println!("[warn] la API se atiende en el servidor de cajas, no en una caja cliente");
+        return;
+    }
+    if let Err(e) = ensure_db_initialized() {
+        println!("[error] API: {}", e);
+        return;
+    }
+    let servidor = match Server::http(&direccion) {
+        Ok(servidor) => Arc::new(servidor),
+        Err(e) => {
+            println!("[error] la API no pudo escuchar en {}: {}", direccion, e);
+            return;
+        }
+    };
+    if !direccion.parse::<SocketAddr>().map(|d| d.ip().is_loopback()).unwrap_or(false) {
+        println!("[warn] la API escucha en {}, fuera de este equipo", direccion);
+    }
+    println!("[info] API escuchando en http://{}{}", direccion, PREFIJO);
+    for _ in 0..HILOS {
+        let servidor = Arc::clone(&servidor);
+        thread::spawn(move || {
+            for peticion in servidor.incoming_requests() {
+                atender(peticion);
+            }
+        });
+    }
+}
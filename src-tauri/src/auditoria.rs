@@ -0,0 +1,300 @@
+// Registro de auditoria de acciones sensibles.
+//
+// Cada fila guarda el hash SHA-256 de la fila anterior junto con su propio contenido,
+// de modo que cualquier alteracion o borrado rompe la cadena y `verificar_auditoria`
+// lo detecta. Los triggers de la tabla impiden ademas UPDATE y DELETE.
+
+use chrono::NaiveDate;
+use rusqlite::{Connection, OptionalExtension, Transaction, TransactionBehavior};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::permisos::{require_permiso, Permiso};
+use crate::{abrir_conexion, comun};
+
+const HASH_INICIAL: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Serialize, Deserialize)]
+pub struct RegistroAuditoria {
+    id: i64,
+    fecha: String,
+    usuario: String,
+    accion: String,
+    entidad: String,
+    antes: Option<String>,
+    despues: Option<String>,
+    exito: bool,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct FiltroAuditoria {
+    usuario: Option<String>,
+    accion: Option<String>,
+    desde: Option<String>,
+    hasta: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct VerificacionAuditoria {
+    valido: bool,
+    registros: i64,
+    primer_registro_invalido: Option<i64>,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn calcular_hash(
+    hash_anterior: &str,
+    fecha: &str,
+    usuario: &str,
+    accion: &str,
+    entidad: &str,
+    antes: Option<&str>,
+    despues: Option<&str>,
+    exito: bool,
+) -> String {
+    let mut hasher = Sha256::new();
+    for campo in [
+        hash_anterior,
+        fecha,
+        usuario,
+        accion,
+        entidad,
+        antes.unwrap_or(""),
+        despues.unwrap_or(""),
+        if exito { "1" } else { "0" },
+    ] {
+        hasher.update(campo.as_bytes());
+        // Separador para que "ab"+"c" no colisione con "a"+"bc"
+        hasher.update([0u8]);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Agrega una fila encadenada a la auditoria. El usuario se toma de la sesion activa.
+pub(crate) fn registrar_auditoria(
+    conn: &Connection,
+    accion: &str,
+    entidad: &str,
+    antes: Option<serde_json::Value>,
+    despues: Option<serde_json::Value>,
+    exito: bool,
+) -> Result<(), String> {
+    let usuario = comun::usuario();
+    let fecha = comun::ahora();
+    let antes = antes.map(|v| v.to_string());
+    let despues = despues.map(|v| v.to_string());
+
+    // Si el llamador no abrio una transaccion, usamos una inmediata para que leer el
+    // ultimo hash y anexar la fila sea atomico frente a otros procesos
+    let tx = if conn.is_autocommit() {
+        Some(
+            Transaction::new_unchecked(conn, TransactionBehavior::Immediate)
+                .map_err(|e| format!("Error al iniciar la transaccion: {}", e))?,
+        )
+    } else {
+        None
+    };
+
+    let hash_anterior: String = conn
+        .query_row("SELECT hash FROM auditoria ORDER BY id DESC LIMIT 1", [], |row| row.get(0))
+        .optional()
+        .map_err(|e| format!("Error al leer la auditoria: {}", e))?
+        .unwrap_or_else(|| HASH_INICIAL.to_string());
+
+    let hash = calcular_hash(
+        &hash_anterior,
+        &fecha,
+        &usuario,
+        accion,
+        entidad,
+        antes.as_deref(),
+        despues.as_deref(),
+        exito,
+    );
+
+    conn.execute(
+        "INSERT INTO auditoria (fecha, usuario, accion, entidad, antes, despues, exito, hash_anterior, hash) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        rusqlite::params![fecha, usuario, accion, entidad, antes, despues, exito as i32, hash_anterior, hash],
+    )
+    .map_err(|e| format!("Error al registrar auditoria: {}", e))?;
+
+    if let Some(tx) = tx {
+        tx.commit()
+            .map_err(|e| format!("Error al registrar auditoria: {}", e))?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub fn consultar_auditoria(filtro: Option<FiltroAuditoria>) -> Result<Vec<RegistroAuditoria>, String> {
+    require_permiso(Permiso::AdministrarSistema)?;
+    let filtro = filtro.unwrap_or_default();
+    for fecha in [&filtro.desde, &filtro.hasta].into_iter().flatten() {
+        NaiveDate::parse_from_str(fecha.trim(), "%Y-%m-%d")
+            .map_err(|_| format!("Fecha invalida (use AAAA-MM-DD): {}", fecha))?;
+    }
+
+    let conn = abrir_conexion()?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, fecha, usuario, accion, entidad, antes, despues, exito FROM auditoria \
+             WHERE (?1 IS NULL OR usuario = ?1) \
+               AND (?2 IS NULL OR accion = ?2) \
+               AND (?3 IS NULL OR date(fecha) >= ?3) \
+               AND (?4 IS NULL OR date(fecha) <= ?4) \
+             ORDER BY id DESC",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+
+    let rows = stmt
+        .query_map(
+            rusqlite::params![
+                filtro.usuario.as_deref().map(str::trim),
+                filtro.accion.as_deref().map(str::trim),
+                filtro.desde.as_deref().map(str::trim),
+                filtro.hasta.as_deref().map(str::trim)
+            ],
+            |row| {
+                Ok(RegistroAuditoria {
+                    id: row.get(0)?,
+                    fecha: row.get(1)?,
+                    usuario: row.get(2)?,
+                    accion: row.get(3)?,
+                    entidad: row.get(4)?,
+                    antes: row.get(5)?,
+                    despues: row.get(6)?,
+                    exito: row.get::<_, i32>(7)? == 1,
+                })
+            },
+        )
+        .map_err(|e| format!("Error al leer la auditoria: {}", e))?;
+
+    let mut registros = Vec::new();
+    for row in rows {
+        registros.push(row.map_err(|e| format!("Error en fila: {}", e))?);
+    }
+
+    Ok(registros)
+}
+
+#[tauri::command]
+pub fn verificar_auditoria() -> Result<VerificacionAuditoria, String> {
+    require_permiso(Permiso::AdministrarSistema)?;
+    let conn = abrir_conexion()?;
+    verificar_cadena(&conn)
+}
+
+/// Recorre la auditoria recalculando cada hash; se corta en la primera fila alterada o en
+/// la que sigue a una borrada.
+fn verificar_cadena(conn: &Connection) -> Result<VerificacionAuditoria, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, fecha, usuario, accion, entidad, antes, despues, exito, hash_anterior, hash \
+             FROM auditoria ORDER BY id",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+
+    let mut rows = stmt
+        .query([])
+        .map_err(|e| format!("Error al leer la auditoria: {}", e))?;
+
+    let mut esperado = HASH_INICIAL.to_string();
+    let mut registros = 0;
+    while let Some(row) = rows.next().map_err(|e| format!("Error en fila: {}", e))? {
+        let leer = || -> rusqlite::Result<(i64, String, String, String)> {
+            let hash_anterior: String = row.get(8)?;
+            let recalculado = calcular_hash(
+                &hash_anterior,
+                &row.get::<_, String>(1)?,
+                &row.get::<_, String>(2)?,
+                &row.get::<_, String>(3)?,
+                &row.get::<_, String>(4)?,
+                row.get::<_, Option<String>>(5)?.as_deref(),
+                row.get::<_, Option<String>>(6)?.as_deref(),
+                row.get::<_, i32>(7)? == 1,
+            );
+            Ok((row.get(0)?, hash_anterior, row.get(9)?, recalculado))
+        };
+        let (id, hash_anterior, hash, recalculado) = leer().map_err(|e| format!("Error en fila: {}", e))?;
+        registros += 1;
+
+        if hash_anterior != esperado || hash != recalculado {
+            return Ok(VerificacionAuditoria {
+                valido: false,
+                registros,
+                primer_registro_invalido: Some(id),
+            });
+        }
+        esperado = hash;
+    }
+
+    Ok(VerificacionAuditoria {
+        valido: true,
+        registros,
+        primer_registro_invalido: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comun::pruebas;
+
+    /// Auditoria con tres filas y sin los disparadores que la protegen, como quedaria si
+    /// alguien edita el archivo de la base a mano.
+    fn base() -> Connection {
+        let conn = pruebas::base(&[]);
+        for (accion, entidad) in [("crear", "inventario:1"), ("editar", "inventario:1"), ("borrar", "inventario:2")] {
+            registrar_auditoria(&conn, accion, entidad, None, Some(serde_json::json!({ "precio": 10 })), true).unwrap();
+        }
+        conn.execute_batch("DROP TRIGGER auditoria_sin_update; DROP TRIGGER auditoria_sin_delete;")
+            .unwrap();
+        conn
+    }
+
+    fn invalido(conn: &Connection) -> (bool, Option<i64>) {
+        let verificacion = verificar_cadena(conn).unwrap();
+        (verificacion.valido, verificacion.primer_registro_invalido)
+    }
+
+    #[test]
+    fn la_cadena_intacta_es_valida() {
+        let conn = base();
+        let verificacion = verificar_cadena(&conn).unwrap();
+        assert!(verificacion.valido);
+        assert_eq!(verificacion.registros, 3);
+    }
+
+    #[test]
+    fn detecta_una_fila_modificada() {
+        let conn = base();
+        conn.execute("UPDATE auditoria SET despues = '{\"precio\":1}' WHERE id = 2", [])
+            .unwrap();
+        assert_eq!(invalido(&conn), (false, Some(2)));
+    }
+
+    #[test]
+    fn detecta_una_fila_borrada() {
+        let conn = base();
+        conn.execute("DELETE FROM auditoria WHERE id = 2", []).unwrap();
+        assert_eq!(invalido(&conn), (false, Some(3)));
+    }
+
+    #[test]
+    fn detecta_una_fila_rehecha_con_su_propio_hash() {
+        let conn = base();
+        let (anterior, fecha): (String, String) = conn
+            .query_row("SELECT hash_anterior, fecha FROM auditoria WHERE id = 2", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        let hash = calcular_hash(&anterior, &fecha, "intruso", "editar", "inventario:1", None, None, true);
+        conn.execute(
+            "UPDATE auditoria SET usuario = 'intruso', despues = NULL, hash = ?1 WHERE id = 2",
+            rusqlite::params![hash],
+        )
+        .unwrap();
+        assert_eq!(invalido(&conn), (false, Some(3)));
+    }
+}
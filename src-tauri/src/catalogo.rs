@@ -0,0 +1,434 @@
+// Categorias jerarquicas, marcas y unidades de medida.
+//
+// Las categorias forman un arbol por `padre_id`; filtrar por una categoria incluye todas
+// sus subcategorias. La unidad decide si el producto admite cantidades fraccionarias: lo
+// que se vende por peso o volumen (kg, litro) acepta decimales, lo que se vende por pieza
+// (unidad, caja) solo enteros.
+
+use rusqlite::types::Value;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+
+use crate::permisos::{require_permiso, Permiso};
+use crate::{abrir_conexion, auditoria};
+
+const UNIDADES: [&str; 4] = ["unidad", "kg", "litro", "caja"];
+
+/// Subconsulta con el id `?` y todos sus descendientes en `categorias`.
+const SUBCATEGORIAS: &str = "WITH RECURSIVE sub(id) AS (SELECT ? UNION ALL \
+     SELECT c.id FROM categorias c JOIN sub ON c.padre_id = sub.id) SELECT id FROM sub";
+
+#[derive(Serialize, Deserialize)]
+pub struct Categoria {
+    id: Option<i64>,
+    nombre: String,
+    padre_id: Option<i64>,
+    /// Ruta completa, por ejemplo "Lacteos > Quesos". Solo lectura.
+    #[serde(default)]
+    ruta: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Marca {
+    id: Option<i64>,
+    nombre: String,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct FiltroCatalogo {
+    categoria_id: Option<i64>,
+    marca_id: Option<i64>,
+    unidad: Option<String>,
+}
+
+impl FiltroCatalogo {
+    /// Condiciones `AND ...` sobre las columnas de `inventario` y sus parametros, en orden.
+    pub(crate) fn clausula(&self) -> (String, Vec<Value>) {
+        let mut sql = String::new();
+        let mut params = Vec::new();
+        if let Some(categoria_id) = self.categoria_id {
+            sql.push_str(&format!(" AND categoria_id IN ({})", SUBCATEGORIAS));
+            params.push(Value::Integer(categoria_id));
+        }
+        if let Some(marca_id) = self.marca_id {
+            sql.push_str(" AND marca_id = ?");
+            params.push(Value::Integer(marca_id));
+        }
+        if let Some(unidad) = self.unidad.as_deref().map(str::trim).filter(|u| !u.is_empty()) {
+            sql.push_str(" AND unidad = ?");
+            params.push(Value::Text(unidad.to_lowercase()));
+        }
+        (sql, params)
+    }
+}
+
+pub(crate) fn admite_fraccion(unidad: &str) -> bool {
+    matches!(unidad, "kg" | "litro")
+}
+
+/// Las cantidades se guardan con tres decimales (gramos, mililitros).
+pub(crate) fn redondear_cantidad(cantidad: f64) -> f64 {
+    (cantidad * 1000.0).round() / 1000.0
+}
+
+pub(crate) fn validar_unidad(unidad: &str) -> Result<String, String> {
+    let unidad = unidad.trim().to_lowercase();
+    if UNIDADES.contains(&unidad.as_str()) {
+        Ok(unidad)
+    } else {
+        Err(format!("Unidad no soportada: {} (use {})", unidad, UNIDADES.join(", ")))
+    }
+}
+
+/// Cantidad mayor a 0 y acorde a la unidad del producto; devuelve la cantidad redondeada.
+pub(crate) fn validar_cantidad(conn: &Connection, producto_id: i64, cantidad: f64) -> Result<f64, String> {
+    if !cantidad.is_finite() || cantidad <= 0.0 {
+        return Err("La cantidad debe ser mayor a 0".to_string());
+    }
+    let (nombre, unidad): (String, String) = conn
+        .query_row(
+            "SELECT nombre_producto, unidad FROM inventario WHERE id = ?1",
+            rusqlite::params![producto_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .map_err(|e| format!("No se encontro el producto: {}", e))?;
+    let cantidad = redondear_cantidad(cantidad);
+    if !admite_fraccion(&unidad) && cantidad.fract() != 0.0 {
+        return Err(format!(
+            "{} se vende por {}; la cantidad debe ser entera",
+            nombre, unidad
+        ));
+    }
+    Ok(cantidad)
+}
+
+/// La categoria indicada y todas sus ancestras, para aplicar promociones por categoria.
+pub(crate) fn categoria_y_ancestros(conn: &Connection, categoria_id: i64) -> Result<Vec<i64>, String> {
+    let mut stmt = conn
+        .prepare(
+            "WITH RECURSIVE arriba(id, padre_id) AS ( \
+                 SELECT id, padre_id FROM categorias WHERE id = ?1 \
+                 UNION ALL \
+                 SELECT c.id, c.padre_id FROM categorias c JOIN arriba ON c.id = arriba.padre_id) \
+             SELECT id FROM arriba",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let ids = stmt
+        .query_map(rusqlite::params![categoria_id], |row| row.get(0))
+        .map_err(|e| format!("Error al leer categorias: {}", e))?
+        .collect::<Result<Vec<i64>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    Ok(ids)
+}
+
+/// Pasa las categorias de texto libre (columna `categoria`) a la tabla `categorias`.
+pub(crate) fn migrar_categorias_texto(conn: &Connection) -> Result<(), String> {
+    conn.execute_batch(
+        r#"
+        INSERT INTO categorias (nombre)
+            SELECT DISTINCT TRIM(categoria) FROM (
+                SELECT categoria FROM inventario UNION SELECT categoria FROM promociones
+            )
+            WHERE categoria IS NOT NULL AND TRIM(categoria) <> ''
+              AND NOT EXISTS (
+                  SELECT 1 FROM categorias c WHERE c.padre_id IS NULL AND c.nombre = TRIM(categoria)
+              );
+
+        UPDATE inventario SET
+            categoria_id = (SELECT id FROM categorias c WHERE c.padre_id IS NULL AND c.nombre = TRIM(inventario.categoria)),
+            categoria = NULL
+        WHERE categoria IS NOT NULL;
+
+        UPDATE promociones SET
+            categoria_id = (SELECT id FROM categorias c WHERE c.padre_id IS NULL AND c.nombre = TRIM(promociones.categoria)),
+            categoria = NULL
+        WHERE categoria IS NOT NULL;
+        "#,
+    )
+    .map_err(|e| format!("Error al migrar categorias: {}", e))
+}
+
+#[tauri::command]
+pub fn listar_categorias() -> Result<Vec<Categoria>, String> {
+    require_permiso(Permiso::ConsultarInventario)?;
+    let conn = abrir_conexion()?;
+    let mut stmt = conn
+        .prepare(
+            "WITH RECURSIVE arbol(id, nombre, padre_id, ruta) AS ( \
+                 SELECT id, nombre, padre_id, nombre FROM categorias WHERE padre_id IS NULL \
+                 UNION ALL \
+                 SELECT c.id, c.nombre, c.padre_id, arbol.ruta || ' > ' || c.nombre \
+                 FROM categorias c JOIN arbol ON c.padre_id = arbol.id) \
+             SELECT id, nombre, padre_id, ruta FROM arbol ORDER BY ruta",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let categorias = stmt
+        .query_map([], |row| {
+            Ok(Categoria {
+                id: row.get(0)?,
+                nombre: row.get(1)?,
+                padre_id: row.get(2)?,
+                ruta: row.get(3)?,
+            })
+        })
+        .map_err(|e| format!("Error al leer categorias: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    Ok(categorias)
+}
+
+#[tauri::command]
+pub fn guardar_categoria(categoria: Categoria) -> Result<i64, String> {
+    require_permiso(Permiso::EditarPrecios)?;
+    let nombre = categoria.nombre.trim();
+    if nombre.is_empty() {
+        return Err("El nombre de la categoria es obligatorio".to_string());
+    }
+    let conn = abrir_conexion()?;
+
+    if let Some(padre_id) = categoria.padre_id {
+        let existe: bool = conn
+            .query_row(
+                "SELECT EXISTS (SELECT 1 FROM categorias WHERE id = ?1)",
+                rusqlite::params![padre_id],
+                |row| row.get(0),
+            )
+            .map_err(|e| format!("Error en la consulta: {}", e))?;
+        if !existe {
+            return Err("No se encontro la categoria padre".to_string());
+        }
+    }
+
+    if let (Some(id), Some(padre_id)) = (categoria.id, categoria.padre_id) {
+        // El padre no puede ser la propia categoria ni una de sus subcategorias
+        let descendiente: bool = conn
+            .query_row(
+                &format!("SELECT ?1 IN ({})", SUBCATEGORIAS),
+                rusqlite::params![padre_id, id],
+                |row| row.get(0),
+            )
+            .map_err(|e| format!("Error en la consulta: {}", e))?;
+        if descendiente {
+            return Err("Una categoria no puede estar dentro de si misma".to_string());
+        }
+    }
+
+    let duplicada: bool = conn
+        .query_row(
+            "SELECT EXISTS (SELECT 1 FROM categorias WHERE nombre = ?1 AND padre_id IS ?2 AND id IS NOT ?3)",
+            rusqlite::params![nombre, categoria.padre_id, categoria.id],
+            |row| row.get(0),
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    if duplicada {
+        return Err(format!("Ya existe la categoria {} en ese nivel", nombre));
+    }
+
+    let id = match categoria.id {
+        Some(id) => {
+            let affected = conn
+                .execute(
+                    "UPDATE categorias SET nombre = ?1, padre_id = ?2 WHERE id = ?3",
+                    rusqlite::params![nombre, categoria.padre_id, id],
+                )
+                .map_err(|e| format!("Error al actualizar la categoria: {}", e))?;
+            if affected == 0 {
+                return Err("No se encontro la categoria".to_string());
+            }
+            id
+        }
+        None => {
+            conn.execute(
+                "INSERT INTO categorias (nombre, padre_id) VALUES (?1, ?2)",
+                rusqlite::params![nombre, categoria.padre_id],
+            )
+            .map_err(|e| format!("Error al guardar la categoria: {}", e))?;
+            conn.last_insert_rowid()
+        }
+    };
+
+    auditoria::registrar_auditoria(
+        &conn,
+        "guardar_categoria",
+        &format!("categoria:{}", id),
+        None,
+        serde_json::to_value(&categoria).ok(),
+        true,
+    )?;
+    Ok(id)
+}
+
+#[tauri::command]
+pub fn eliminar_categoria(id: i64) -> Result<(), String> {
+    require_permiso(Permiso::EditarPrecios)?;
+    let conn = abrir_conexion()?;
+    let en_uso: bool = conn
+        .query_row(
+            "SELECT EXISTS (SELECT 1 FROM categorias WHERE padre_id = ?1) \
+             OR EXISTS (SELECT 1 FROM inventario WHERE categoria_id = ?1) \
+             OR EXISTS (SELECT 1 FROM promociones WHERE categoria_id = ?1)",
+            rusqlite::params![id],
+            |row| row.get(0),
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    if en_uso {
+        return Err("La categoria tiene subcategorias, productos o promociones asociadas".to_string());
+    }
+    let affected = conn
+        .execute("DELETE FROM categorias WHERE id = ?1", rusqlite::params![id])
+        .map_err(|e| format!("Error al eliminar la categoria: {}", e))?;
+    if affected == 0 {
+        return Err("No se encontro la categoria".to_string());
+    }
+    auditoria::registrar_auditoria(&conn, "eliminar_categoria", &format!("categoria:{}", id), None, None, true)
+}
+
+#[tauri::command]
+pub fn listar_marcas() -> Result<Vec<Marca>, String> {
+    require_permiso(Permiso::ConsultarInventario)?;
+    let conn = abrir_conexion()?;
+    let mut stmt = conn
+        .prepare("SELECT id, nombre FROM marcas ORDER BY nombre")
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let marcas = stmt
+        .query_map([], |row| {
+            Ok(Marca {
+                id: row.get(0)?,
+                nombre: row.get(1)?,
+            })
+        })
+        .map_err(|e| format!("Error al leer marcas: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    Ok(marcas)
+}
+
+#[tauri::command]
+pub fn guardar_marca(marca: Marca) -> Result<i64, String> {
+    require_permiso(Permiso::EditarPrecios)?;
+    let nombre = marca.nombre.trim();
+    if nombre.is_empty() {
+        return Err("El nombre de la marca es obligatorio".to_string());
+    }
+    let conn = abrir_conexion()?;
+    let resultado = match marca.id {
+        Some(id) => conn
+            .execute(
+                "UPDATE marcas SET nombre = ?1 WHERE id = ?2",
+                rusqlite::params![nombre, id],
+            )
+            .map(|affected| (affected, id)),
+        None => conn
+            .execute("INSERT INTO marcas (nombre) VALUES (?1)", rusqlite::params![nombre])
+            .map(|affected| (affected, conn.last_insert_rowid())),
+    };
+    let (affected, id) = resultado.map_err(|e| match e {
+        rusqlite::Error::SqliteFailure(err, _) if err.code == rusqlite::ErrorCode::ConstraintViolation => {
+            format!("Ya existe la marca {}", nombre)
+        }
+        e => format!("Error al guardar la marca: {}", e),
+    })?;
+    if affected == 0 {
+        return Err("No se encontro la marca".to_string());
+    }
+    Ok(id)
+}
+
+#[tauri::command]
+pub fn listar_unidades() -> Vec<String> {
+    UNIDADES.iter().map(|u| u.to_string()).collect()
+}
+
+/// Categoria, marca y unidad de un producto. `None` deja el producto sin categoria o marca.
+#[tauri::command]
+pub fn asignar_clasificacion(
+    producto_id: i64,
+    categoria_id: Option<i64>,
+    marca_id: Option<i64>,
+    unidad: String,
+) -> Result<(), String> {
+    require_permiso(Permiso::EditarPrecios)?;
+    let unidad = validar_unidad(&unidad)?;
+    let conn = abrir_conexion()?;
+
+    let cantidad: f64 = conn
+        .query_row(
+            "SELECT COALESCE(CAST(cantidad_producto AS REAL), 0) FROM inventario WHERE id = ?1",
+            rusqlite::params![producto_id],
+            |row| row.get(0),
+        )
+        .map_err(|_| "No se encontro el registro para actualizar".to_string())?;
+    if !admite_fraccion(&unidad) && cantidad.fract() != 0.0 {
+        return Err(format!(
+            "El stock actual ({}) es fraccionario; ajustelo antes de cambiar la unidad a {}",
+            cantidad, unidad
+        ));
+    }
+
+    let existe = |tabla: &str, id: Option<i64>| -> Result<bool, String> {
+        match id {
+            None => Ok(true),
+            Some(id) => conn
+                .query_row(
+                    &format!("SELECT EXISTS (SELECT 1 FROM {} WHERE id = ?1)", tabla),
+                    rusqlite::params![id],
+                    |row| row.get(0),
+                )
+                .map_err(|e| format!("Error en la consulta: {}", e)),
+        }
+    };
+    if !existe("categorias", categoria_id)? {
+        return Err("No se encontro la categoria".to_string());
+    }
+    if !existe("marcas", marca_id)? {
+        return Err("No se encontro la marca".to_string());
+    }
+
+    conn.execute(
+        "UPDATE inventario SET categoria_id = ?1, marca_id = ?2, unidad = ?3 WHERE id = ?4",
+        rusqlite::params![categoria_id, marca_id, unidad, producto_id],
+    )
+    .map_err(|e| format!("Error al actualizar: {}", e))?;
+
+    auditoria::registrar_auditoria(
+        &conn,
+        "asignar_clasificacion",
+        &format!("inventario:{}", producto_id),
+        None,
+        Some(serde_json::json!({
+            "categoria_id": categoria_id,
+            "marca_id": marca_id,
+            "unidad": unidad
+        })),
+        true,
+    )
+}
+
+/// Stock a partir del cual el producto se avisa como bajo (evento `stock_bajo`). `None`
+/// deja el producto sin aviso.
+#[tauri::command]
+pub fn fijar_stock_minimo(producto_id: i64, minimo: Option<f64>) -> Result<(), String> {
+    require_permiso(Permiso::AjustarStock)?;
+    if minimo.is_some_and(|m| !m.is_finite() || m < 0.0) {
+        return Err("El stock minimo no puede ser negativo".to_string());
+    }
+    let conn = abrir_conexion()?;
+    let cambiados = conn
+        .execute(
+            "UPDATE inventario SET stock_minimo = ?1 WHERE id = ?2",
+            rusqlite::params![minimo.map(redondear_cantidad), producto_id],
+        )
+        .map_err(|e| format!("Error al actualizar: {}", e))?;
+    if cambiados == 0 {
+        return Err("No se encontro el registro para actualizar".to_string());
+    }
+
+    auditoria::registrar_auditoria(
+        &conn,
+        "fijar_stock_minimo",
+        &format!("inventario:{}", producto_id),
+        None,
+        Some(serde_json::json!({ "stock_minimo": minimo })),
+        true,
+    )
+}
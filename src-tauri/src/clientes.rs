@@ -0,0 +1,219 @@
+// Registro de clientes.
+//
+// Un cliente se identifica por su cedula o RIF, normalizado a la forma `V-12345678` o
+// `J-12345678-9`. Las empresas deben tener RIF, porque toda factura a su nombre lo exige.
+// Una venta puede llevar un cliente opcional, que se imprime en el recibo.
+
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::permisos::{require_permiso, Permiso};
+use crate::ventas::{self, Ticket};
+use crate::{abrir_conexion, auditoria, comun};
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Cliente {
+    pub(crate) id: Option<i64>,
+    /// Cedula (`V-12345678`) o RIF (`J-12345678-9`)
+    pub(crate) documento: String,
+    /// "persona" o "empresa"
+    pub(crate) tipo: String,
+    pub(crate) nombre: String,
+    pub(crate) telefono: Option<String>,
+    pub(crate) direccion: Option<String>,
+    /// Solo se modifica con `asignar_limite_credito`
+    #[serde(default)]
+    pub(crate) limite_credito: f64,
+    /// Solo se modifica con `precios::asignar_lista_cliente`; `None` es la lista minorista
+    #[serde(default)]
+    pub(crate) lista_precios_id: Option<i64>,
+}
+
+fn es_rif(documento: &str) -> bool {
+    let partes: Vec<&str> = documento.split('-').collect();
+    partes.len() == 3
+        && matches!(partes[0], "V" | "E" | "J" | "P" | "G" | "C")
+        && partes[1].len() == 8
+        && partes[1].chars().all(|c| c.is_ascii_digit())
+        && partes[2].len() == 1
+        && partes[2].chars().all(|c| c.is_ascii_digit())
+}
+
+fn es_cedula(documento: &str) -> bool {
+    match documento.split_once('-') {
+        Some((letra, numero)) => {
+            matches!(letra, "V" | "E")
+                && (6..=8).contains(&numero.len())
+                && numero.chars().all(|c| c.is_ascii_digit())
+        }
+        None => false,
+    }
+}
+
+/// Mayusculas, sin espacios ni puntos y con guiones; nueve digitos se leen como RIF
+/// ("v 12.345.678" -> "V-12345678", "J123456789" -> "J-12345678-9").
+pub(crate) fn normalizar_documento(texto: &str) -> String {
+    let limpio = texto.trim().to_uppercase();
+    let mut chars = limpio.chars();
+    let Some(letra) = chars.next().filter(|c| c.is_ascii_alphabetic()) else {
+        return limpio;
+    };
+    let digitos: String = chars.filter(|c| c.is_ascii_digit()).collect();
+    if digitos.len() == 9 {
+        format!("{}-{}-{}", letra, &digitos[..8], &digitos[8..])
+    } else {
+        format!("{}-{}", letra, digitos)
+    }
+}
+
+impl Cliente {
+    fn validar(&mut self) -> Result<(), String> {
+        self.documento = normalizar_documento(&self.documento);
+        self.nombre = self.nombre.trim().to_string();
+        self.tipo = self.tipo.trim().to_lowercase();
+        if self.nombre.is_empty() {
+            return Err("El nombre del cliente es obligatorio".to_string());
+        }
+        match self.tipo.as_str() {
+            "empresa" if !es_rif(&self.documento) => Err(format!(
+                "Las empresas requieren un RIF valido (ej. J-12345678-9): {}",
+                self.documento
+            )),
+            "persona" if !es_cedula(&self.documento) && !es_rif(&self.documento) => Err(format!(
+                "Cedula o RIF invalido (ej. V-12345678): {}",
+                self.documento
+            )),
+            "empresa" | "persona" => Ok(()),
+            otro => Err(format!("Tipo de cliente no soportado: {}", otro)),
+        }
+    }
+}
+
+fn cliente_desde_fila(row: &rusqlite::Row) -> rusqlite::Result<Cliente> {
+    Ok(Cliente {
+        id: row.get(0)?,
+        documento: row.get(1)?,
+        tipo: row.get(2)?,
+        nombre: row.get(3)?,
+        telefono: row.get(4)?,
+        direccion: row.get(5)?,
+        limite_credito: row.get(6)?,
+        lista_precios_id: row.get(7)?,
+    })
+}
+
+pub(crate) fn cargar_cliente(conn: &Connection, id: i64) -> Result<Cliente, String> {
+    conn.query_row(
+        "SELECT id, documento, tipo, nombre, telefono, direccion, limite_credito, lista_precios_id FROM clientes \
+         WHERE id = ?1",
+        rusqlite::params![id],
+        cliente_desde_fila,
+    )
+    .optional()
+    .map_err(|e| format!("Error en la consulta: {}", e))?
+    .ok_or_else(|| format!("No se encontro el cliente {}", id))
+}
+
+#[tauri::command]
+pub fn buscar_clientes(texto: String) -> Result<Vec<Cliente>, String> {
+    require_permiso(Permiso::Vender)?;
+    let conn = abrir_conexion()?;
+    let patron = format!("%{}%", texto.trim());
+    // El documento se compara tambien normalizado para encontrar "v12345678" como V-12345678
+    let documento = format!("%{}%", normalizar_documento(&texto));
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, documento, tipo, nombre, telefono, direccion, limite_credito, lista_precios_id FROM clientes \
+             WHERE nombre LIKE ?1 OR documento LIKE ?1 OR documento LIKE ?2 OR telefono LIKE ?1 \
+             ORDER BY nombre LIMIT 50",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let clientes = stmt
+        .query_map(rusqlite::params![patron, documento], cliente_desde_fila)
+        .map_err(|e| format!("Error al leer clientes: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    Ok(clientes)
+}
+
+#[tauri::command]
+pub fn obtener_cliente(id: i64) -> Result<Cliente, String> {
+    require_permiso(Permiso::Vender)?;
+    let conn = abrir_conexion()?;
+    cargar_cliente(&conn, id)
+}
+
+#[tauri::command]
+pub fn guardar_cliente(mut cliente: Cliente) -> Result<Cliente, String> {
+    require_permiso(Permiso::Vender)?;
+    cliente.validar()?;
+    let conn = abrir_conexion()?;
+    let antes = match cliente.id {
+        Some(id) => Some(cargar_cliente(&conn, id)?),
+        None => None,
+    };
+
+    let resultado = match cliente.id {
+        Some(id) => conn.execute(
+            "UPDATE clientes SET documento = ?1, tipo = ?2, nombre = ?3, telefono = ?4, direccion = ?5 WHERE id = ?6",
+            rusqlite::params![
+                cliente.documento,
+                cliente.tipo,
+                cliente.nombre,
+                cliente.telefono,
+                cliente.direccion,
+                id
+            ],
+        ),
+        None => conn.execute(
+            "INSERT INTO clientes (documento, tipo, nombre, telefono, direccion, creado) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            rusqlite::params![
+                cliente.documento,
+                cliente.tipo,
+                cliente.nombre,
+                cliente.telefono,
+                cliente.direccion,
+                comun::ahora()
+            ],
+        ),
+    };
+    resultado.map_err(|e| match e {
+        rusqlite::Error::SqliteFailure(err, _) if err.code == rusqlite::ErrorCode::ConstraintViolation => {
+            format!("Ya existe un cliente con el documento {}", cliente.documento)
+        }
+        e => format!("Error al guardar el cliente: {}", e),
+    })?;
+    let id = cliente.id.unwrap_or_else(|| conn.last_insert_rowid());
+    let cliente = cargar_cliente(&conn, id)?;
+
+    auditoria::registrar_auditoria(
+        &conn,
+        "guardar_cliente",
+        &format!("cliente:{}", id),
+        antes.and_then(|c| serde_json::to_value(c).ok()),
+        serde_json::to_value(&cliente).ok(),
+        true,
+    )?;
+    Ok(cliente)
+}
+
+/// Tickets del cliente, del mas reciente al mas antiguo, con sus lineas.
+#[tauri::command]
+pub fn historial_cliente(id: i64) -> Result<Vec<Ticket>, String> {
+    require_permiso(Permiso::Vender)?;
+    let conn = abrir_conexion()?;
+    cargar_cliente(&conn, id)?;
+    let mut stmt = conn
+        .prepare("SELECT numero_recibo FROM tickets WHERE cliente_id = ?1 ORDER BY id DESC")
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let numeros = stmt
+        .query_map(rusqlite::params![id], |row| row.get::<_, String>(0))
+        .map_err(|e| format!("Error al leer tickets: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+
+    numeros
+        .iter()
+        .map(|numero| ventas::cargar_ticket(&conn, numero))
+        .collect()
+}
@@ -0,0 +1,177 @@
+// Combos y cajas.
+//
+// Un combo es un producto armado con otros: el "combo desayuno" o la caja de 12 botellas
+// que tambien se venden sueltas. El combo no tiene stock propio; su disponibilidad es la
+// cantidad de combos completos que alcanzan a armarse con el stock de sus componentes.
+// `movimientos::ajustar_stock` expande los combos, asi vender una caja descuenta 12
+// botellas y comprarla (o devolverla) suma 12 botellas.
+
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+
+use crate::catalogo::{admite_fraccion, redondear_cantidad};
+use crate::permisos::{require_permiso, Permiso};
+use crate::{abrir_conexion, auditoria, series};
+
+#[derive(Serialize, Deserialize)]
+pub struct Componente {
+    componente_id: i64,
+    /// Unidades del componente por cada combo
+    cantidad: f64,
+    /// Solo lectura
+    #[serde(default)]
+    nombre: String,
+    #[serde(default)]
+    stock: f64,
+}
+
+/// Expresion SQL con los combos completos que se pueden armar del producto `tabla.id`;
+/// NULL si el producto no es un combo.
+pub(crate) fn disponible_sql(tabla: &str) -> String {
+    format!(
+        "(SELECT MIN(CAST(COALESCE(CAST(comp.cantidad_producto AS REAL), 0) / k.cantidad AS INTEGER)) \
+         FROM componentes k JOIN inventario comp ON comp.id = k.componente_id WHERE k.producto_id = {}.id)",
+        tabla
+    )
+}
+
+/// Componentes del producto y sus cantidades; vacio si no es un combo.
+pub(crate) fn componentes_de(conn: &Connection, producto_id: i64) -> Result<Vec<(i64, f64)>, String> {
+    let mut stmt = conn
+        .prepare("SELECT componente_id, cantidad FROM componentes WHERE producto_id = ?1 ORDER BY componente_id")
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let componentes = stmt
+        .query_map(rusqlite::params![producto_id], |row| Ok((row.get(0)?, row.get(1)?)))
+        .map_err(|e| format!("Error al leer componentes: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    Ok(componentes)
+}
+
+pub(crate) fn es_combo(conn: &Connection, producto_id: i64) -> Result<bool, String> {
+    conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM componentes WHERE producto_id = ?1)",
+        rusqlite::params![producto_id],
+        |row| row.get(0),
+    )
+    .map_err(|e| format!("Error en la consulta: {}", e))
+}
+
+pub(crate) fn es_componente(conn: &Connection, producto_id: i64) -> Result<bool, String> {
+    conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM componentes WHERE componente_id = ?1)",
+        rusqlite::params![producto_id],
+        |row| row.get(0),
+    )
+    .map_err(|e| format!("Error en la consulta: {}", e))
+}
+
+#[tauri::command]
+pub fn listar_componentes(producto_id: i64) -> Result<Vec<Componente>, String> {
+    require_permiso(Permiso::ConsultarInventario)?;
+    let conn = abrir_conexion()?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT k.componente_id, k.cantidad, i.nombre_producto, COALESCE(CAST(i.cantidad_producto AS REAL), 0) \
+             FROM componentes k JOIN inventario i ON i.id = k.componente_id \
+             WHERE k.producto_id = ?1 ORDER BY i.nombre_producto",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let componentes = stmt
+        .query_map(rusqlite::params![producto_id], |row| {
+            Ok(Componente {
+                componente_id: row.get(0)?,
+                cantidad: row.get(1)?,
+                nombre: row.get(2)?,
+                stock: row.get(3)?,
+            })
+        })
+        .map_err(|e| format!("Error al leer componentes: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    Ok(componentes)
+}
+
+/// Define los componentes del combo; una lista vacia lo vuelve un producto comun.
+/// Los componentes no pueden ser combos a su vez.
+#[tauri::command]
+pub fn guardar_componentes(producto_id: i64, componentes: Vec<Componente>) -> Result<(), String> {
+    require_permiso(Permiso::EditarPrecios)?;
+    let mut conn = abrir_conexion()?;
+
+    let (stock, usado_como_componente): (f64, bool) = conn
+        .query_row(
+            "SELECT COALESCE(CAST(cantidad_producto AS REAL), 0), \
+             EXISTS (SELECT 1 FROM componentes WHERE componente_id = ?1) FROM inventario WHERE id = ?1",
+            rusqlite::params![producto_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .map_err(|_| format!("No se encontro el producto {}", producto_id))?;
+    if !componentes.is_empty() {
+        if usado_como_componente {
+            return Err("El producto es componente de otro combo".to_string());
+        }
+        if stock != 0.0 {
+            return Err(format!(
+                "El producto tiene stock propio ({}); ajustelo a 0 antes de convertirlo en combo",
+                stock
+            ));
+        }
+    }
+
+    let mut vistos = Vec::with_capacity(componentes.len());
+    for componente in &componentes {
+        if componente.componente_id == producto_id {
+            return Err("Un combo no puede contenerse a si mismo".to_string());
+        }
+        if vistos.contains(&componente.componente_id) {
+            return Err(format!("El componente {} esta repetido", componente.componente_id));
+        }
+        vistos.push(componente.componente_id);
+
+        let (nombre, unidad): (String, String) = conn
+            .query_row(
+                "SELECT nombre_producto, unidad FROM inventario WHERE id = ?1",
+                rusqlite::params![componente.componente_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .map_err(|_| format!("No se encontro el componente {}", componente.componente_id))?;
+        if es_combo(&conn, componente.componente_id)? {
+            return Err(format!("{} es un combo y no puede ser componente", nombre));
+        }
+        // La venta de un combo no pide series, asi que sus componentes no pueden llevarlas
+        if series::es_serializado(&conn, componente.componente_id)? {
+            return Err(format!("{} se controla por numero de serie y no puede ser componente", nombre));
+        }
+        let cantidad = redondear_cantidad(componente.cantidad);
+        if !cantidad.is_finite() || cantidad <= 0.0 {
+            return Err(format!("Cantidad invalida para {}", nombre));
+        }
+        if !admite_fraccion(&unidad) && cantidad.fract() != 0.0 {
+            return Err(format!("{} se vende por {}; la cantidad debe ser entera", nombre, unidad));
+        }
+    }
+
+    let tx = conn
+        .transaction()
+        .map_err(|e| format!("Error al iniciar la transaccion: {}", e))?;
+    tx.execute("DELETE FROM componentes WHERE producto_id = ?1", rusqlite::params![producto_id])
+        .map_err(|e| format!("Error al guardar componentes: {}", e))?;
+    for componente in &componentes {
+        tx.execute(
+            "INSERT INTO componentes (producto_id, componente_id, cantidad) VALUES (?1, ?2, ?3)",
+            rusqlite::params![producto_id, componente.componente_id, redondear_cantidad(componente.cantidad)],
+        )
+        .map_err(|e| format!("Error al guardar componentes: {}", e))?;
+    }
+    auditoria::registrar_auditoria(
+        &tx,
+        "guardar_componentes",
+        &format!("inventario:{}", producto_id),
+        None,
+        serde_json::to_value(&componentes).ok(),
+        true,
+    )?;
+    tx.commit()
+        .map_err(|e| format!("Error al confirmar: {}", e))
+}
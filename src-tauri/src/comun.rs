@@ -0,0 +1,48 @@
+// Funciones chicas que usan varios modulos: fecha actual, redondeo de importes y
+// usuario de la sesion para registrar quien hizo cada cambio.
+
+use chrono::Local;
+
+use crate::leer_usuario_sesion;
+
+/// Fecha y hora local con el formato que se guarda en la base.
+pub(crate) fn ahora() -> String {
+    Local::now().format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// Segundos Unix, para plazos que se comparan como numeros.
+pub(crate) fn ahora_unix() -> i64 {
+    Local::now().timestamp()
+}
+
+/// Redondea un importe a centavos.
+pub(crate) fn redondear(valor: f64) -> f64 {
+    (valor * 100.0).round() / 100.0
+}
+
+/// Usuario de la sesion, o "desconocido" si no hay una.
+pub(crate) fn usuario() -> String {
+    leer_usuario_sesion().unwrap_or_else(|| "desconocido".to_string())
+}
+
+/// Datos para las pruebas de los modulos.
+#[cfg(test)]
+pub(crate) mod pruebas {
+    use rusqlite::Connection;
+
+    /// Base en memoria con todas las tablas y los productos `(id, nombre, sku, cantidad)`,
+    /// a $100 cada uno.
+    pub(crate) fn base(productos: &[(i64, &str, &str, f64)]) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::crear_tablas(&conn).unwrap();
+        for (id, nombre, sku, cantidad) in productos {
+            conn.execute(
+                "INSERT INTO inventario (id, nombre_producto, precio_producto, cantidad_producto, sku) \
+                 VALUES (?1, ?2, '100', ?3, ?4)",
+                rusqlite::params![id, nombre, cantidad, sku],
+            )
+            .unwrap();
+        }
+        conn
+    }
+}
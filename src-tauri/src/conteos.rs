@@ -0,0 +1,363 @@
+// Toma de inventario fisico.
+//
+// Al abrir un conteo se congela la cantidad esperada de cada producto en la ubicacion
+// contada (todo el catalogo o lo que pase el filtro, por ejemplo una categoria). Varios
+// empleados cargan lo que cuentan, escaneando o a mano; cada registro suma al contado del
+// producto, asi dos personas pueden contar estantes distintos del mismo articulo y una
+// carga negativa corrige un error. Las
+// diferencias se valorizan al costo promedio del momento en que se abrio el conteo. Al
+// aplicarlo, los ajustes aprobados entran como movimientos "conteo" en una sola transaccion.
+//
+// Los combos no tienen stock propio y los productos serializados se concilian por numero
+// de serie, por eso ninguno de los dos entra en un conteo.
+
+use rusqlite::types::Value;
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::catalogo::{admite_fraccion, redondear_cantidad, FiltroCatalogo};
+use crate::comun::{ahora, redondear, usuario};
+use crate::movimientos::{ajustar_stock, transaccion_stock};
+use crate::permisos::{require_permiso, Permiso};
+use crate::ubicaciones;
+use crate::variantes::id_por_codigo;
+use crate::{abrir_conexion, auditoria};
+
+#[derive(Serialize, Deserialize)]
+pub struct Conteo {
+    id: i64,
+    descripcion: Option<String>,
+    /// Filtro con que se armo el conteo, en JSON; `None` si abarca todo el catalogo
+    filtro: Option<String>,
+    ubicacion_id: i64,
+    iniciado: String,
+    usuario: String,
+    /// "abierto", "aplicado" o "cancelado"
+    estado: String,
+    cerrado: Option<String>,
+    cerrado_por: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LineaConteo {
+    producto_id: i64,
+    nombre: String,
+    unidad: String,
+    esperado: f64,
+    /// `None` mientras nadie lo haya contado
+    contado: Option<f64>,
+    diferencia: Option<f64>,
+    costo_unitario: f64,
+    valor_diferencia: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ResumenConteo {
+    conteo: Conteo,
+    lineas: Vec<LineaConteo>,
+    contados: usize,
+    sin_contar: usize,
+    valor_faltante: f64,
+    valor_sobrante: f64,
+    valor_neto: f64,
+}
+
+fn cargar_conteo(conn: &Connection, id: i64) -> Result<Conteo, String> {
+    conn.query_row(
+        "SELECT id, descripcion, filtro, iniciado, usuario, estado, cerrado, cerrado_por, ubicacion_id FROM conteos \
+         WHERE id = ?1",
+        rusqlite::params![id],
+        conteo_desde_fila,
+    )
+    .optional()
+    .map_err(|e| format!("Error en la consulta: {}", e))?
+    .ok_or_else(|| format!("No se encontro el conteo {}", id))
+}
+
+fn conteo_desde_fila(row: &rusqlite::Row) -> rusqlite::Result<Conteo> {
+    Ok(Conteo {
+        id: row.get(0)?,
+        descripcion: row.get(1)?,
+        filtro: row.get(2)?,
+        iniciado: row.get(3)?,
+        usuario: row.get(4)?,
+        estado: row.get(5)?,
+        cerrado: row.get(6)?,
+        cerrado_por: row.get(7)?,
+        ubicacion_id: row.get(8)?,
+    })
+}
+
+fn conteo_abierto(conn: &Connection, id: i64) -> Result<Conteo, String> {
+    let conteo = cargar_conteo(conn, id)?;
+    if conteo.estado != "abierto" {
+        return Err(format!("El conteo {} ya esta {}", id, conteo.estado));
+    }
+    Ok(conteo)
+}
+
+fn leer_lineas(conn: &Connection, conteo_id: i64) -> Result<Vec<LineaConteo>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT d.producto_id, i.nombre_producto, i.unidad, d.esperado, d.costo_unitario, \
+             (SELECT SUM(r.cantidad) FROM conteos_registros r \
+              WHERE r.conteo_id = d.conteo_id AND r.producto_id = d.producto_id) \
+             FROM conteos_detalle d JOIN inventario i ON i.id = d.producto_id \
+             WHERE d.conteo_id = ?1 ORDER BY i.nombre_producto",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let lineas = stmt
+        .query_map(rusqlite::params![conteo_id], |row| {
+            let esperado: f64 = row.get(3)?;
+            let costo_unitario: f64 = row.get(4)?;
+            let contado = row.get::<_, Option<f64>>(5)?.map(redondear_cantidad);
+            let diferencia = contado.map(|c| redondear_cantidad(c - esperado));
+            Ok(LineaConteo {
+                producto_id: row.get(0)?,
+                nombre: row.get(1)?,
+                unidad: row.get(2)?,
+                esperado,
+                contado,
+                diferencia,
+                costo_unitario,
+                valor_diferencia: diferencia.map(|d| redondear(d * costo_unitario)),
+            })
+        })
+        .map_err(|e| format!("Error al leer el conteo: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    Ok(lineas)
+}
+
+/// Abre un conteo de la ubicacion (la principal si se omite) con los productos que pasan
+/// el filtro (todos si se omite) y congela su stock actual como cantidad esperada. Un
+/// producto no puede estar en dos conteos abiertos de la misma ubicacion.
+#[tauri::command]
+pub fn iniciar_conteo(
+    filtro: Option<FiltroCatalogo>,
+    ubicacion_id: Option<i64>,
+    descripcion: Option<String>,
+) -> Result<Conteo, String> {
+    require_permiso(Permiso::AjustarStock)?;
+    let filtro_json = filtro.as_ref().and_then(|f| serde_json::to_string(f).ok());
+    let (condiciones, mut params) = filtro.unwrap_or_default().clausula();
+    let mut conn = abrir_conexion()?;
+    let ubicacion_id = ubicaciones::validar_ubicacion(&conn, ubicacion_id)?;
+
+    params.insert(0, Value::Integer(ubicacion_id));
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT id, COALESCE(costo_promedio, 0), \
+             EXISTS (SELECT 1 FROM conteos_detalle d JOIN conteos c ON c.id = d.conteo_id \
+                     WHERE d.producto_id = inventario.id AND c.estado = 'abierto' AND c.ubicacion_id = ?) \
+             FROM inventario WHERE serializado = 0 \
+             AND NOT EXISTS (SELECT 1 FROM componentes k WHERE k.producto_id = inventario.id){} ORDER BY id",
+            condiciones
+        ))
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let filas = stmt
+        .query_map(rusqlite::params_from_iter(params), |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, f64>(1)?, row.get::<_, bool>(2)?))
+        })
+        .map_err(|e| format!("Error al leer inventarios: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    drop(stmt);
+    let productos = filas
+        .into_iter()
+        .map(|(id, costo, ocupado)| Ok((id, ubicaciones::stock_en(&conn, id, ubicacion_id)?, costo, ocupado)))
+        .collect::<Result<Vec<_>, String>>()?;
+
+    if productos.is_empty() {
+        return Err("Ningun producto coincide con el filtro".to_string());
+    }
+    let ocupados = productos.iter().filter(|p| p.3).count();
+    if ocupados > 0 {
+        return Err(format!("{} producto(s) ya estan en otro conteo abierto", ocupados));
+    }
+
+    let descripcion = descripcion.map(|d| d.trim().to_string()).filter(|d| !d.is_empty());
+    let tx = conn
+        .transaction()
+        .map_err(|e| format!("Error al iniciar la transaccion: {}", e))?;
+    tx.execute(
+        "INSERT INTO conteos (descripcion, filtro, ubicacion_id, iniciado, usuario, estado) \
+         VALUES (?1, ?2, ?3, ?4, ?5, 'abierto')",
+        rusqlite::params![descripcion, filtro_json, ubicacion_id, ahora(), usuario()],
+    )
+    .map_err(|e| format!("Error al crear el conteo: {}", e))?;
+    let id = tx.last_insert_rowid();
+    for (producto_id, esperado, costo, _) in &productos {
+        tx.execute(
+            "INSERT INTO conteos_detalle (conteo_id, producto_id, esperado, costo_unitario) VALUES (?1, ?2, ?3, ?4)",
+            rusqlite::params![id, producto_id, esperado, costo],
+        )
+        .map_err(|e| format!("Error al crear el conteo: {}", e))?;
+    }
+    auditoria::registrar_auditoria(
+        &tx,
+        "iniciar_conteo",
+        &format!("conteo:{}", id),
+        None,
+        Some(serde_json::json!({ "filtro": filtro_json, "productos": productos.len() })),
+        true,
+    )?;
+    let conteo = cargar_conteo(&tx, id)?;
+    tx.commit()
+        .map_err(|e| format!("Error al confirmar: {}", e))?;
+    Ok(conteo)
+}
+
+#[tauri::command]
+pub fn listar_conteos() -> Result<Vec<Conteo>, String> {
+    require_permiso(Permiso::ConsultarInventario)?;
+    let conn = abrir_conexion()?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, descripcion, filtro, iniciado, usuario, estado, cerrado, cerrado_por, ubicacion_id \
+             FROM conteos ORDER BY id DESC",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let conteos = stmt
+        .query_map([], conteo_desde_fila)
+        .map_err(|e| format!("Error al leer conteos: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    Ok(conteos)
+}
+
+/// Suma lo contado de un producto, identificado por id o por SKU/codigo de barras.
+/// Devuelve la linea con el total contado hasta ahora.
+#[tauri::command]
+pub fn registrar_conteo(
+    conteo_id: i64,
+    producto_id: Option<i64>,
+    codigo: Option<String>,
+    cantidad: f64,
+) -> Result<LineaConteo, String> {
+    require_permiso(Permiso::ConsultarInventario)?;
+    let conn = abrir_conexion()?;
+    conteo_abierto(&conn, conteo_id)?;
+    let producto_id = match (producto_id, codigo.as_deref().map(str::trim).filter(|c| !c.is_empty())) {
+        (Some(id), _) => id,
+        (None, Some(codigo)) => id_por_codigo(&conn, codigo)?,
+        (None, None) => return Err("Indique el producto o su codigo".to_string()),
+    };
+
+    let cantidad = redondear_cantidad(cantidad);
+    if !cantidad.is_finite() || cantidad == 0.0 {
+        return Err("La cantidad contada no puede ser cero".to_string());
+    }
+    let lineas = leer_lineas(&conn, conteo_id)?;
+    let linea = lineas
+        .iter()
+        .find(|l| l.producto_id == producto_id)
+        .ok_or_else(|| format!("El producto {} no forma parte del conteo {}", producto_id, conteo_id))?;
+    if !admite_fraccion(&linea.unidad) && cantidad.fract() != 0.0 {
+        return Err(format!("{} se cuenta por {}; la cantidad debe ser entera", linea.nombre, linea.unidad));
+    }
+    if redondear_cantidad(linea.contado.unwrap_or(0.0) + cantidad) < 0.0 {
+        return Err(format!("El total contado de {} no puede quedar negativo", linea.nombre));
+    }
+
+    conn.execute(
+        "INSERT INTO conteos_registros (conteo_id, producto_id, cantidad, usuario, fecha) VALUES (?1, ?2, ?3, ?4, ?5)",
+        rusqlite::params![conteo_id, producto_id, cantidad, usuario(), ahora()],
+    )
+    .map_err(|e| format!("Error al registrar el conteo: {}", e))?;
+
+    leer_lineas(&conn, conteo_id)?
+        .into_iter()
+        .find(|l| l.producto_id == producto_id)
+        .ok_or_else(|| format!("El producto {} no forma parte del conteo {}", producto_id, conteo_id))
+}
+
+/// Lineas del conteo con sus diferencias y el valor de faltantes y sobrantes.
+#[tauri::command]
+pub fn diferencias_conteo(conteo_id: i64) -> Result<ResumenConteo, String> {
+    require_permiso(Permiso::AjustarStock)?;
+    let conn = abrir_conexion()?;
+    let conteo = cargar_conteo(&conn, conteo_id)?;
+    let lineas = leer_lineas(&conn, conteo_id)?;
+
+    let valores: Vec<f64> = lineas.iter().filter_map(|l| l.valor_diferencia).collect();
+    let valor_faltante = redondear(-valores.iter().filter(|v| **v < 0.0).sum::<f64>());
+    let valor_sobrante = redondear(valores.iter().filter(|v| **v > 0.0).sum());
+    let contados = lineas.iter().filter(|l| l.contado.is_some()).count();
+    Ok(ResumenConteo {
+        conteo,
+        contados,
+        sin_contar: lineas.len() - contados,
+        lineas,
+        valor_faltante,
+        valor_sobrante,
+        valor_neto: redondear(valor_sobrante - valor_faltante),
+    })
+}
+
+/// Aplica las diferencias aprobadas como movimientos "conteo" y cierra el conteo.
+/// `productos` limita los ajustes a esos productos (todos los contados si se omite);
+/// con `sin_contar_en_cero` lo que nadie conto se toma como faltante. Devuelve cuantos
+/// productos se ajustaron.
+#[tauri::command]
+pub fn aplicar_conteo(conteo_id: i64, productos: Option<Vec<i64>>, sin_contar_en_cero: bool) -> Result<usize, String> {
+    require_permiso(Permiso::AjustarStock)?;
+    let mut conn = abrir_conexion()?;
+    // Con la base bloqueada, asi el conteo no se aplica dos veces desde cajas distintas
+    let tx = transaccion_stock(&mut conn)?;
+    let conteo = conteo_abierto(&tx, conteo_id)?;
+    let lineas = leer_lineas(&tx, conteo_id)?;
+    if let Some(ids) = &productos {
+        if let Some(id) = ids.iter().find(|id| !lineas.iter().any(|l| l.producto_id == **id)) {
+            return Err(format!("El producto {} no forma parte del conteo {}", id, conteo_id));
+        }
+    }
+
+    let referencia = format!("conteo {}", conteo_id);
+    let mut ajustados = Vec::new();
+    for linea in &lineas {
+        if productos.as_ref().is_some_and(|ids| !ids.contains(&linea.producto_id)) {
+            continue;
+        }
+        let diferencia = match (linea.diferencia, sin_contar_en_cero) {
+            (Some(d), _) => d,
+            (None, true) => -linea.esperado,
+            (None, false) => continue,
+        };
+        if diferencia == 0.0 {
+            continue;
+        }
+        ajustar_stock(&tx, linea.producto_id, diferencia, conteo.ubicacion_id, "conteo", Some(&referencia))
+            .map_err(|e| format!("{}: {}", linea.nombre, e))?;
+        ajustados.push(serde_json::json!({ "producto_id": linea.producto_id, "diferencia": diferencia }));
+    }
+    tx.execute(
+        "UPDATE conteos SET estado = 'aplicado', cerrado = ?1, cerrado_por = ?2 WHERE id = ?3",
+        rusqlite::params![ahora(), usuario(), conteo_id],
+    )
+    .map_err(|e| format!("Error al cerrar el conteo: {}", e))?;
+    auditoria::registrar_auditoria(
+        &tx,
+        "aplicar_conteo",
+        &format!("conteo:{}", conteo_id),
+        None,
+        Some(serde_json::json!({ "ajustes": ajustados })),
+        true,
+    )?;
+    tx.commit()
+        .map_err(|e| format!("Error al confirmar: {}", e))?;
+    Ok(ajustados.len())
+}
+
+#[tauri::command]
+pub fn cancelar_conteo(conteo_id: i64) -> Result<(), String> {
+    require_permiso(Permiso::AjustarStock)?;
+    let conn = abrir_conexion()?;
+    conteo_abierto(&conn, conteo_id)?;
+    conn.execute(
+        "UPDATE conteos SET estado = 'cancelado', cerrado = ?1, cerrado_por = ?2 WHERE id = ?3",
+        rusqlite::params![ahora(), usuario(), conteo_id],
+    )
+    .map_err(|e| format!("Error al cancelar el conteo: {}", e))?;
+    auditoria::registrar_auditoria(&conn, "cancelar_conteo", &format!("conteo:{}", conteo_id), None, None, true)
+}
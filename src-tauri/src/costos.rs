@@ -0,0 +1,308 @@
+// Costos y valorizacion de inventario.
+//
+// Cada compra registra su costo unitario y actualiza el costo promedio ponderado del
+// producto (`inventario.costo_promedio`), que es el costo de venta que se guarda en cada
+// linea vendida. Los movimientos de stock guardan su costo unitario, asi la valorizacion
+// a una fecha pasada se reconstruye recorriendo el historial, por promedio ponderado o
+// por capas FIFO.
+
+use chrono::{Local, NaiveDate};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+use crate::combos;
+use crate::comun::redondear;
+use crate::exportacion::parse_fecha;
+use crate::permisos::{require_permiso, Permiso};
+use crate::{abrir_conexion, auditoria};
+
+#[derive(Serialize, Deserialize)]
+pub struct LineaValorizacion {
+    producto_id: i64,
+    nombre: String,
+    cantidad: f64,
+    costo_unitario: f64,
+    valor: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Valorizacion {
+    fecha: String,
+    /// "promedio" o "fifo"
+    metodo: String,
+    lineas: Vec<LineaValorizacion>,
+    total: f64,
+}
+
+/// Costo promedio vigente; el de un combo es la suma del de sus componentes.
+pub(crate) fn costo_promedio(conn: &Connection, producto_id: i64) -> Result<f64, String> {
+    conn.query_row(
+        "SELECT COALESCE((SELECT SUM(k.cantidad * COALESCE(c.costo_promedio, 0)) FROM componentes k \
+         JOIN inventario c ON c.id = k.componente_id WHERE k.producto_id = i.id), COALESCE(i.costo_promedio, 0)) \
+         FROM inventario i WHERE i.id = ?1",
+        rusqlite::params![producto_id],
+        |row| row.get(0),
+    )
+    .map_err(|e| format!("No se encontro el producto: {}", e))
+}
+
+/// Nuevo promedio ponderado tras una entrada de `cantidad` a `costo`, con `anterior`
+/// unidades en stock. Si no habia stock el promedio pasa a ser el costo de la entrada.
+pub(crate) fn actualizar_promedio(
+    conn: &Connection,
+    producto_id: i64,
+    anterior: f64,
+    cantidad: f64,
+    costo: f64,
+) -> Result<(), String> {
+    if !costo.is_finite() || costo < 0.0 {
+        return Err("El costo unitario no puede ser negativo".to_string());
+    }
+    let promedio = costo_promedio(conn, producto_id)?;
+    let nuevo = if anterior > 0.0 {
+        (anterior * promedio + cantidad * costo) / (anterior + cantidad)
+    } else {
+        costo
+    };
+    conn.execute(
+        "UPDATE inventario SET costo_promedio = ?1 WHERE id = ?2",
+        rusqlite::params![(nuevo * 10000.0).round() / 10000.0, producto_id],
+    )
+    .map_err(|e| format!("Error al actualizar el costo: {}", e))?;
+    Ok(())
+}
+
+/// Reparte el costo de un combo comprado entre sus componentes, en proporcion a su costo
+/// promedio (o a su precio, si todavia no tienen costo). Devuelve el costo por unidad de
+/// cada componente.
+pub(crate) fn repartir_costo(
+    conn: &Connection,
+    componentes: &[(i64, f64)],
+    costo: f64,
+) -> Result<Vec<Option<f64>>, String> {
+    let mut pesos = Vec::with_capacity(componentes.len());
+    for (componente_id, por_combo) in componentes {
+        let (promedio, precio): (f64, f64) = conn
+            .query_row(
+                "SELECT COALESCE(costo_promedio, 0), CAST(precio_producto AS REAL) FROM inventario WHERE id = ?1",
+                rusqlite::params![componente_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .map_err(|e| format!("No se encontro el componente: {}", e))?;
+        pesos.push((promedio * por_combo, precio * por_combo, *por_combo));
+    }
+    let por_costo: f64 = pesos.iter().map(|p| p.0).sum();
+    let por_precio: f64 = pesos.iter().map(|p| p.1).sum();
+    let por_cantidad: f64 = pesos.iter().map(|p| p.2).sum();
+    Ok(pesos
+        .iter()
+        .map(|(c, p, q)| {
+            let parte = if por_costo > 0.0 {
+                c / por_costo
+            } else if por_precio > 0.0 {
+                p / por_precio
+            } else {
+                q / por_cantidad
+            };
+            Some(costo * parte / q)
+        })
+        .collect())
+}
+
+/// Fija el costo promedio de un producto, por ejemplo para el stock que habia antes de
+/// registrar costos.
+#[tauri::command]
+pub fn asignar_costo(producto_id: i64, costo: f64) -> Result<(), String> {
+    require_permiso(Permiso::EditarPrecios)?;
+    if !costo.is_finite() || costo < 0.0 {
+        return Err("El costo unitario no puede ser negativo".to_string());
+    }
+    let conn = abrir_conexion()?;
+    if combos::es_combo(&conn, producto_id)? {
+        return Err("El costo de un combo se calcula de sus componentes".to_string());
+    }
+    let anterior = costo_promedio(&conn, producto_id)?;
+    conn.execute(
+        "UPDATE inventario SET costo_promedio = ?1 WHERE id = ?2",
+        rusqlite::params![costo, producto_id],
+    )
+    .map_err(|e| format!("Error al actualizar: {}", e))?;
+    auditoria::registrar_auditoria(
+        &conn,
+        "asignar_costo",
+        &format!("inventario:{}", producto_id),
+        Some(serde_json::json!({ "costo_promedio": anterior })),
+        Some(serde_json::json!({ "costo_promedio": costo })),
+        true,
+    )
+}
+
+/// Cantidad y costo unitario de un producto al cierre de `hasta`, recorriendo sus
+/// movimientos. El stock anterior al primer movimiento entra como saldo inicial.
+fn valorizar_producto(
+    conn: &Connection,
+    producto_id: i64,
+    cantidad_actual: f64,
+    costo_actual: f64,
+    hasta: &str,
+    fifo: bool,
+) -> Result<(f64, f64), String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT fecha <= ?2, CAST(cantidad AS REAL), costo_unitario FROM movimientos_stock \
+             WHERE producto_id = ?1 ORDER BY id",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let movimientos = stmt
+        .query_map(rusqlite::params![producto_id, hasta], |row| {
+            Ok((row.get::<_, bool>(0)?, row.get::<_, f64>(1)?, row.get::<_, Option<f64>>(2)?))
+        })
+        .map_err(|e| format!("Error al leer movimientos: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+
+    let posteriores: f64 = movimientos.iter().filter(|m| !m.0).map(|m| m.1).sum();
+    let anteriores: Vec<(f64, Option<f64>)> = movimientos.iter().filter(|m| m.0).map(|m| (m.1, m.2)).collect();
+    let cantidad = cantidad_actual - posteriores;
+    let inicial = cantidad - anteriores.iter().map(|m| m.0).sum::<f64>();
+    let costo_inicial = movimientos
+        .iter()
+        .find_map(|m| m.2.filter(|_| m.1 > 0.0))
+        .unwrap_or(costo_actual);
+
+    let mut promedio = costo_inicial;
+    let mut en_stock = inicial;
+    let mut capas: VecDeque<(f64, f64)> = VecDeque::new();
+    if inicial > 0.0 {
+        capas.push_back((inicial, costo_inicial));
+    }
+    for (movida, costo) in anteriores {
+        if movida > 0.0 {
+            let costo = costo.unwrap_or(promedio);
+            if en_stock + movida > 0.0 && en_stock > 0.0 {
+                promedio = (en_stock * promedio + movida * costo) / (en_stock + movida);
+            } else {
+                promedio = costo;
+            }
+            capas.push_back((movida, costo));
+        } else {
+            let mut salida = -movida;
+            while salida > 0.0 {
+                let Some(capa) = capas.front_mut() else { break };
+                let tomada = capa.0.min(salida);
+                capa.0 -= tomada;
+                salida -= tomada;
+                if capa.0 <= 0.0005 {
+                    capas.pop_front();
+                }
+            }
+        }
+        en_stock += movida;
+    }
+
+    let costo_unitario = if fifo && cantidad > 0.0 {
+        capas.iter().map(|(q, c)| q * c).sum::<f64>() / cantidad
+    } else {
+        promedio
+    };
+    Ok((cantidad, costo_unitario))
+}
+
+/// Valor del inventario al cierre de `fecha` (hoy si se omite). `metodo` es "promedio"
+/// (por defecto) o "fifo". Los combos no tienen stock propio y no se incluyen.
+#[tauri::command]
+pub fn valorizar_inventario(fecha: Option<String>, metodo: Option<String>) -> Result<Valorizacion, String> {
+    require_permiso(Permiso::VerReportes)?;
+    let fecha: NaiveDate = match fecha.as_deref().map(str::trim).filter(|f| !f.is_empty()) {
+        Some(f) => parse_fecha(f)?,
+        None => Local::now().date_naive(),
+    };
+    let metodo = metodo.unwrap_or_else(|| "promedio".to_string()).trim().to_lowercase();
+    if metodo != "promedio" && metodo != "fifo" {
+        return Err(format!("Metodo de valorizacion no soportado: {}", metodo));
+    }
+    let hasta = format!("{} 23:59:59", fecha);
+
+    let conn = abrir_conexion()?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, nombre_producto, COALESCE(CAST(cantidad_producto AS REAL), 0), COALESCE(costo_promedio, 0) \
+             FROM inventario i WHERE NOT EXISTS (SELECT 1 FROM componentes k WHERE k.producto_id = i.id) ORDER BY id",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let productos = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, f64>(2)?, row.get::<_, f64>(3)?))
+        })
+        .map_err(|e| format!("Error al leer inventarios: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+
+    let mut lineas = Vec::new();
+    for (producto_id, nombre, cantidad_actual, costo_actual) in productos {
+        let (cantidad, costo_unitario) =
+            valorizar_producto(&conn, producto_id, cantidad_actual, costo_actual, &hasta, metodo == "fifo")?;
+        let cantidad = (cantidad * 1000.0).round() / 1000.0;
+        if cantidad <= 0.0 {
+            continue;
+        }
+        lineas.push(LineaValorizacion {
+            producto_id,
+            nombre,
+            cantidad,
+            costo_unitario: (costo_unitario * 10000.0).round() / 10000.0,
+            valor: redondear(cantidad * costo_unitario),
+        });
+    }
+    let total = redondear(lineas.iter().map(|l| l.valor).sum());
+
+    Ok(Valorizacion {
+        fecha: fecha.to_string(),
+        metodo,
+        lineas,
+        total,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comun::pruebas;
+
+    fn base(movimientos: &[(&str, f64, Option<f64>)]) -> Connection {
+        let conn = pruebas::base(&[(1, "Harina", "HAR", 0.0)]);
+        for (fecha, cantidad, costo) in movimientos {
+            conn.execute(
+                "INSERT INTO movimientos_stock (fecha, producto_id, cantidad, motivo, usuario, costo_unitario) \
+                 VALUES (?1, 1, ?2, 'prueba', 'user', ?3)",
+                rusqlite::params![fecha, cantidad, costo],
+            )
+            .unwrap();
+        }
+        conn
+    }
+
+    #[test]
+    fn promedio_y_fifo_ignoran_lo_posterior_a_la_fecha() {
+        let conn = base(&[
+            ("2025-01-01 10:00:00", 10.0, Some(10.0)),
+            ("2025-01-02 10:00:00", 10.0, Some(20.0)),
+            ("2025-01-03 10:00:00", -15.0, None),
+            ("2025-01-05 10:00:00", 5.0, Some(30.0)),
+        ]);
+        let hasta = "2025-01-04 23:59:59";
+
+        assert_eq!(valorizar_producto(&conn, 1, 10.0, 25.0, hasta, false).unwrap(), (5.0, 15.0));
+        assert_eq!(valorizar_producto(&conn, 1, 10.0, 25.0, hasta, true).unwrap(), (5.0, 20.0));
+    }
+
+    #[test]
+    fn el_stock_previo_a_los_movimientos_es_el_saldo_inicial() {
+        let conn = base(&[("2025-01-02 10:00:00", -2.0, None)]);
+
+        let (cantidad, costo) = valorizar_producto(&conn, 1, 8.0, 5.0, "2025-01-01 23:59:59", true).unwrap();
+
+        assert_eq!((cantidad, costo), (10.0, 5.0));
+    }
+}
@@ -0,0 +1,388 @@
+// Cuentas de credito de clientes (fiado).
+//
+// Cada cliente tiene una cuenta corriente en `cuentas_movimientos`: las ventas a credito
+// suman un cargo y los abonos y notas de credito lo reducen (montos con signo). El saldo
+// es la suma de los movimientos. Para la antiguedad de saldos los pagos se aplican a los
+// cargos mas antiguos primero.
+
+use chrono::{Local, NaiveDate};
+use printpdf::*;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+
+use crate::clientes::{self, Cliente};
+use crate::comun::{ahora, redondear, usuario};
+use crate::permisos::{require_permiso, Permiso};
+use crate::{abrir_conexion, auditoria, format_money, get_documentos_recibos_dir, PaginasPdf};
+
+#[derive(Serialize, Deserialize)]
+pub struct MovimientoCuenta {
+    id: i64,
+    fecha: String,
+    /// "cargo", "abono" o "nota_credito"
+    tipo: String,
+    monto: f64,
+    referencia: Option<String>,
+    usuario: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EstadoCuenta {
+    cliente: Cliente,
+    saldo: f64,
+    limite_credito: f64,
+    disponible: f64,
+    movimientos: Vec<MovimientoCuenta>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct AntiguedadSaldo {
+    cliente_id: i64,
+    documento: String,
+    nombre: String,
+    saldo: f64,
+    hasta_30: f64,
+    de_31_a_60: f64,
+    mas_de_60: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EstadoCuentaPdf {
+    ruta: String,
+    saldo: f64,
+}
+
+pub(crate) fn saldo_cliente(conn: &Connection, cliente_id: i64) -> Result<f64, String> {
+    conn.query_row(
+        "SELECT COALESCE(SUM(monto), 0) FROM cuentas_movimientos WHERE cliente_id = ?1",
+        rusqlite::params![cliente_id],
+        |row| row.get::<_, f64>(0),
+    )
+    .map(redondear)
+    .map_err(|e| format!("Error al calcular el saldo: {}", e))
+}
+
+/// Registra un movimiento en la cuenta del cliente. `monto` positivo es deuda del cliente.
+pub(crate) fn registrar_movimiento_cuenta(
+    conn: &Connection,
+    cliente_id: i64,
+    tipo: &str,
+    monto: f64,
+    ticket_id: Option<i64>,
+    referencia: Option<&str>,
+) -> Result<(), String> {
+    conn.execute(
+        "INSERT INTO cuentas_movimientos (cliente_id, fecha, tipo, monto, ticket_id, referencia, usuario) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        rusqlite::params![
+            cliente_id,
+            ahora(),
+            tipo,
+            redondear(monto),
+            ticket_id,
+            referencia,
+            usuario()
+        ],
+    )
+    .map_err(|e| format!("Error al registrar el movimiento de cuenta: {}", e))?;
+    Ok(())
+}
+
+/// Carga una venta a credito; falla si el saldo resultante supera el limite del cliente.
+pub(crate) fn cargar_venta_a_credito(
+    conn: &Connection,
+    cliente: &Cliente,
+    ticket_id: i64,
+    numero_recibo: &str,
+    total: f64,
+) -> Result<(), String> {
+    let cliente_id = cliente.id.ok_or_else(|| "Cliente invalido".to_string())?;
+    let saldo = saldo_cliente(conn, cliente_id)?;
+    if saldo + total > cliente.limite_credito + 0.005 {
+        return Err(format!(
+            "La venta supera el limite de credito de {} (limite {}, saldo {}, disponible {})",
+            cliente.nombre,
+            format_money(cliente.limite_credito),
+            format_money(saldo),
+            format_money((cliente.limite_credito - saldo).max(0.0))
+        ));
+    }
+    registrar_movimiento_cuenta(conn, cliente_id, "cargo", total, Some(ticket_id), Some(numero_recibo))
+}
+
+fn leer_movimientos(conn: &Connection, cliente_id: i64) -> Result<Vec<MovimientoCuenta>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, fecha, tipo, monto, referencia, usuario FROM cuentas_movimientos \
+             WHERE cliente_id = ?1 ORDER BY fecha, id",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let movimientos = stmt
+        .query_map(rusqlite::params![cliente_id], |row| {
+            Ok(MovimientoCuenta {
+                id: row.get(0)?,
+                fecha: row.get(1)?,
+                tipo: row.get(2)?,
+                monto: row.get(3)?,
+                referencia: row.get(4)?,
+                usuario: row.get(5)?,
+            })
+        })
+        .map_err(|e| format!("Error al leer movimientos de cuenta: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    Ok(movimientos)
+}
+
+/// Reparte el saldo pendiente por antiguedad aplicando los pagos a los cargos mas viejos.
+fn antiguedad(movimientos: &[MovimientoCuenta], hoy: NaiveDate) -> (f64, f64, f64) {
+    let mut creditos: f64 = movimientos.iter().filter(|m| m.monto < 0.0).map(|m| -m.monto).sum();
+    let mut tramos = (0.0, 0.0, 0.0);
+    for cargo in movimientos.iter().filter(|m| m.monto > 0.0) {
+        let aplicado = creditos.min(cargo.monto);
+        creditos -= aplicado;
+        let pendiente = cargo.monto - aplicado;
+        if pendiente <= 0.0 {
+            continue;
+        }
+        let fecha = cargo
+            .fecha
+            .get(..10)
+            .and_then(|f| NaiveDate::parse_from_str(f, "%Y-%m-%d").ok())
+            .unwrap_or(hoy);
+        match (hoy - fecha).num_days() {
+            d if d <= 30 => tramos.0 += pendiente,
+            d if d <= 60 => tramos.1 += pendiente,
+            _ => tramos.2 += pendiente,
+        }
+    }
+    (redondear(tramos.0), redondear(tramos.1), redondear(tramos.2))
+}
+
+fn estado_de(conn: &Connection, cliente_id: i64) -> Result<EstadoCuenta, String> {
+    let cliente = clientes::cargar_cliente(conn, cliente_id)?;
+    let movimientos = leer_movimientos(conn, cliente_id)?;
+    let saldo = redondear(movimientos.iter().map(|m| m.monto).sum());
+    Ok(EstadoCuenta {
+        limite_credito: cliente.limite_credito,
+        disponible: redondear((cliente.limite_credito - saldo).max(0.0)),
+        cliente,
+        saldo,
+        movimientos,
+    })
+}
+
+#[tauri::command]
+pub fn estado_cuenta(cliente_id: i64) -> Result<EstadoCuenta, String> {
+    require_permiso(Permiso::Vender)?;
+    let conn = abrir_conexion()?;
+    estado_de(&conn, cliente_id)
+}
+
+/// Abono parcial o total a la cuenta del cliente.
+#[tauri::command]
+pub fn registrar_abono(cliente_id: i64, monto: f64, referencia: Option<String>) -> Result<f64, String> {
+    require_permiso(Permiso::Vender)?;
+    if !monto.is_finite() || monto <= 0.0 {
+        return Err("El monto del abono debe ser mayor a 0".to_string());
+    }
+    let conn = abrir_conexion()?;
+    clientes::cargar_cliente(&conn, cliente_id)?;
+    let saldo = saldo_cliente(&conn, cliente_id)?;
+    if monto > saldo + 0.005 {
+        return Err(format!("El abono supera el saldo pendiente ({})", format_money(saldo)));
+    }
+
+    let referencia = referencia.as_deref().map(str::trim).filter(|r| !r.is_empty());
+    registrar_movimiento_cuenta(&conn, cliente_id, "abono", -monto, None, referencia)?;
+    auditoria::registrar_auditoria(
+        &conn,
+        "registrar_abono",
+        &format!("cliente:{}", cliente_id),
+        Some(serde_json::json!({ "saldo": saldo })),
+        Some(serde_json::json!({ "abono": monto, "saldo": redondear(saldo - monto) })),
+        true,
+    )?;
+    Ok(redondear(saldo - monto))
+}
+
+#[tauri::command]
+pub fn asignar_limite_credito(cliente_id: i64, limite: f64) -> Result<(), String> {
+    require_permiso(Permiso::OtorgarCredito)?;
+    if !limite.is_finite() || limite < 0.0 {
+        return Err("El limite de credito no puede ser negativo".to_string());
+    }
+    let conn = abrir_conexion()?;
+    let cliente = clientes::cargar_cliente(&conn, cliente_id)?;
+    conn.execute(
+        "UPDATE clientes SET limite_credito = ?1 WHERE id = ?2",
+        rusqlite::params![limite, cliente_id],
+    )
+    .map_err(|e| format!("Error al actualizar: {}", e))?;
+    auditoria::registrar_auditoria(
+        &conn,
+        "asignar_limite_credito",
+        &format!("cliente:{}", cliente_id),
+        Some(serde_json::json!({ "limite_credito": cliente.limite_credito })),
+        Some(serde_json::json!({ "limite_credito": limite })),
+        true,
+    )
+}
+
+/// Saldos pendientes por cliente en tramos de 0-30, 31-60 y mas de 60 dias.
+#[tauri::command]
+pub fn cuentas_por_cobrar() -> Result<Vec<AntiguedadSaldo>, String> {
+    require_permiso(Permiso::VerReportes)?;
+    let conn = abrir_conexion()?;
+    let hoy = Local::now().date_naive();
+    let mut stmt = conn
+        .prepare(
+            "SELECT c.id, c.documento, c.nombre FROM clientes c \
+             WHERE EXISTS (SELECT 1 FROM cuentas_movimientos m WHERE m.cliente_id = c.id) ORDER BY c.nombre",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let clientes = stmt
+        .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get(1)?, row.get(2)?)))
+        .map_err(|e| format!("Error al leer clientes: {}", e))?
+        .collect::<Result<Vec<(i64, String, String)>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+
+    let mut reporte = Vec::new();
+    for (cliente_id, documento, nombre) in clientes {
+        let movimientos = leer_movimientos(&conn, cliente_id)?;
+        let saldo = redondear(movimientos.iter().map(|m| m.monto).sum());
+        if saldo <= 0.0 {
+            continue;
+        }
+        let (hasta_30, de_31_a_60, mas_de_60) = antiguedad(&movimientos, hoy);
+        reporte.push(AntiguedadSaldo {
+            cliente_id,
+            documento,
+            nombre,
+            saldo,
+            hasta_30,
+            de_31_a_60,
+            mas_de_60,
+        });
+    }
+    Ok(reporte)
+}
+
+fn crear_pdf_estado_cuenta(estado: &EstadoCuenta, ruta_salida: &std::path::Path) -> Result<(), String> {
+    let (doc, page1, layer1) = PdfDocument::new("Estado de cuenta", Mm(210.0), Mm(180.0), "Layer 1");
+
+    let font = doc.add_builtin_font(BuiltinFont::Helvetica)
+        .map_err(|e| format!("No se pudo cargar fuente: {:?}", e))?;
+    let font_bold = doc.add_builtin_font(BuiltinFont::HelveticaBold)
+        .map_err(|e| format!("No se pudo cargar fuente: {:?}", e))?;
+
+    let start_x: f32 = 12.0;
+    let fecha_texto = Local::now().format("%Y-%m-%d %H:%M").to_string();
+
+    let draw_header = |layer: &PdfLayerReference, y: f32| -> f32 {
+        let mut y_cursor: f32 = y;
+        layer.use_text("Estado de cuenta", 14.0, Mm(start_x), Mm(y_cursor), &font_bold);
+        y_cursor -= 8.0;
+        layer.use_text(
+            format!(
+                "Cliente: {}   C.I./RIF: {}   Fecha: {}",
+                estado.cliente.nombre, estado.cliente.documento, fecha_texto
+            ),
+            10.0,
+            Mm(start_x),
+            Mm(y_cursor),
+            &font,
+        );
+        y_cursor -= 10.0;
+        layer.use_text(
+            "Fecha                Concepto               Cargo       Abono       Saldo",
+            9.0,
+            Mm(start_x),
+            Mm(y_cursor),
+            &font_bold,
+        );
+        y_cursor - 6.0
+    };
+
+    let mut hoja = PaginasPdf::nueva(&doc, doc.get_page(page1).get_layer(layer1), &draw_header);
+    let mut saldo = 0.0;
+    for movimiento in &estado.movimientos {
+        saldo += movimiento.monto;
+        let concepto = match &movimiento.referencia {
+            Some(r) => format!("{} {}", movimiento.tipo, r),
+            None => movimiento.tipo.clone(),
+        };
+        let concepto: String = concepto.chars().take(22).collect();
+        let (cargo, abono) = if movimiento.monto >= 0.0 {
+            (format_money(movimiento.monto), String::new())
+        } else {
+            (String::new(), format_money(-movimiento.monto))
+        };
+        let line = format!(
+            "{:<20} {:<22} {:>10} {:>10} {:>10}",
+            movimiento.fecha,
+            concepto,
+            cargo,
+            abono,
+            format_money(saldo)
+        );
+
+        let y = hoja.bajar(5.0);
+        hoja.capa.use_text(line, 9.0, Mm(start_x), Mm(y), &font);
+    }
+
+    let (hasta_30, de_31_a_60, mas_de_60) = antiguedad(&estado.movimientos, Local::now().date_naive());
+    let resumen = [
+        format!(
+            "Limite: {}   Disponible: {}",
+            format_money(estado.limite_credito),
+            format_money(estado.disponible)
+        ),
+        format!(
+            "0-30 dias: {}   31-60 dias: {}   Mas de 60 dias: {}",
+            format_money(hasta_30),
+            format_money(de_31_a_60),
+            format_money(mas_de_60)
+        ),
+    ];
+    hoja.reservar(25.0);
+    for linea in resumen {
+        let y = hoja.bajar(6.0);
+        hoja.capa.use_text(linea, 10.0, Mm(start_x), Mm(y), &font);
+    }
+    let y = hoja.bajar(8.0);
+    hoja.capa.use_text(
+        format!("Saldo pendiente: {}", format_money(estado.saldo)),
+        12.0,
+        Mm(start_x),
+        Mm(y),
+        &font_bold,
+    );
+
+    let pdf_bytes = doc.save_to_bytes()
+        .map_err(|e| format!("Error al generar el PDF: {:?}", e))?;
+    let mut file = fs::File::create(ruta_salida)
+        .map_err(|e| format!("No se pudo crear el archivo PDF: {}", e))?;
+    file.write_all(&pdf_bytes)
+        .map_err(|e| format!("No se pudo escribir el PDF: {}", e))
+}
+
+#[tauri::command]
+pub fn generar_estado_cuenta(cliente_id: i64) -> Result<EstadoCuentaPdf, String> {
+    require_permiso(Permiso::Vender)?;
+    let conn = abrir_conexion()?;
+    let estado = estado_de(&conn, cliente_id)?;
+    let nombre = format!(
+        "EC-{}-{}.pdf",
+        estado.cliente.documento,
+        Local::now().format("%Y%m%d-%H%M%S")
+    );
+    let ruta = get_documentos_recibos_dir()?.join(nombre);
+    crear_pdf_estado_cuenta(&estado, &ruta)?;
+    Ok(EstadoCuentaPdf {
+        ruta: ruta.display().to_string(),
+        saldo: estado.saldo,
+    })
+}
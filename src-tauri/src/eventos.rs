@@ -0,0 +1,665 @@
+// Eventos del negocio para integraciones y para las ventanas abiertas.
+//
+// Cada evento se guarda en la tabla `eventos` dentro de la misma transaccion que el
+// cambio, asi un cambio deshecho no avisa nada. Las ventas, los precios y el cierre del dia
+// los anota su modulo con `emitir`; el stock se escribe desde muchos lugares y lo anota
+// un disparador sobre `inventario` (`stock_cambiado` y, al cruzar `stock_minimo`,
+// `stock_bajo`). Otro disparador encola una entrega en `entregas_webhook` por cada webhook
+// activo suscrito al tipo.
+//
+// Un hilo entrega la cola con un POST JSON `{id, tipo, fecha, datos}`; con secreto se
+// agrega `X-Firma: sha256=<HMAC del cuerpo>`. Cada webhook se entrega en su propio hilo y
+// en orden: cuando uno falla, su cola entera espera el reintento (con espera creciente),
+// asi un destino caido no demora a los demas. Tras `MAX_INTENTOS` la entrega queda
+// "fallida" hasta reintentarla a mano.
+//
+// Otro hilo reenvia cada evento nuevo a las ventanas con el nombre del tipo. Una caja
+// cliente no tiene base propia: pide los eventos al servidor con `eventos_desde`.
+
+use chrono::{Duration as Plazo, Local};
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::thread;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter};
+
+use crate::comun::ahora;
+use crate::permisos::{require_permiso, Permiso};
+use crate::{abrir_conexion, auditoria, ensure_db_initialized, terminales};
+
+pub(crate) const TIPOS: &[&str] = &[
+    "venta_completada",
+    "stock_cambiado",
+    "stock_bajo",
+    "precio_cambiado",
+    "dia_cerrado",
+];
+
+const MAX_INTENTOS: i64 = 8;
+/// Espera antes del primer reintento; se duplica en cada fallo hasta `ESPERA_MAXIMA`.
+const ESPERA_INICIAL: i64 = 15;
+const ESPERA_MAXIMA: i64 = 3600;
+const INTERVALO: Duration = Duration::from_secs(1);
+const LOTE: i64 = 100;
+/// Un destino que no responde en este tiempo cuenta como fallo.
+const TIEMPO_RESPUESTA: Duration = Duration::from_secs(10);
+
+const FECHA_SQL: &str = "datetime('now', 'localtime')";
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Evento {
+    id: i64,
+    tipo: String,
+    fecha: String,
+    datos: Value,
+}
+
+/// Eventos posteriores a un id y el ultimo id existente.
+#[derive(Serialize, Deserialize)]
+pub struct LoteEventos {
+    ultimo: i64,
+    eventos: Vec<Evento>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Webhook {
+    id: Option<i64>,
+    url: String,
+    /// Tipos suscritos; vacio recibe todos
+    #[serde(default)]
+    eventos: Vec<String>,
+    /// Al guardar, `None` conserva el secreto actual y "" lo quita. Nunca se devuelve.
+    #[serde(default)]
+    secreto: Option<String>,
+    #[serde(default = "activo_por_defecto")]
+    activo: bool,
+    #[serde(default)]
+    con_secreto: bool,
+    #[serde(default)]
+    pendientes: i64,
+    #[serde(default)]
+    fallidas: i64,
+}
+
+fn activo_por_defecto() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EntregaWebhook {
+    evento_id: i64,
+    webhook_id: i64,
+    tipo: String,
+    fecha: String,
+    estado: String,
+    intentos: i64,
+    proximo_intento: String,
+    entregado: Option<String>,
+    ultimo_error: Option<String>,
+}
+
+/// Anota un evento; llamarlo con la transaccion del cambio que lo origina.
+pub(crate) fn emitir(conn: &Connection, tipo: &str, datos: Value) -> Result<(), String> {
+    conn.execute(
+        "INSERT INTO eventos (tipo, fecha, datos) VALUES (?1, ?2, ?3)",
+        rusqlite::params![tipo, ahora(), datos.to_string()],
+    )
+    .map_err(|e| format!("Error al registrar el evento {}: {}", tipo, e))?;
+    Ok(())
+}
+
+/// Disparadores del stock y del reparto de eventos a los webhooks.
+pub(crate) fn crear_disparadores(conn: &Connection) -> Result<(), String> {
+    let anterior = "COALESCE(CAST(OLD.cantidad_producto AS REAL), 0)";
+    let actual = "COALESCE(CAST(NEW.cantidad_producto AS REAL), 0)";
+    let stock_cambiado = |anterior: &str| {
+        format!(
+            "INSERT INTO eventos (tipo, fecha, datos) VALUES ('stock_cambiado', {}, json_object('producto_id', NEW.id, \
+             'nombre', NEW.nombre_producto, 'anterior', {}, 'cantidad', {}));",
+            FECHA_SQL, anterior, actual
+        )
+    };
+    let sql = format!(
+        "CREATE TRIGGER IF NOT EXISTS eventos_stock_alta AFTER INSERT ON inventario WHEN {actual} <> 0 \
+         BEGIN {alta} END;\n\
+         CREATE TRIGGER IF NOT EXISTS eventos_stock AFTER UPDATE OF cantidad_producto ON inventario \
+         WHEN {anterior} <> {actual} BEGIN {cambio} \
+         INSERT INTO eventos (tipo, fecha, datos) SELECT 'stock_bajo', {fecha}, json_object('producto_id', NEW.id, \
+         'nombre', NEW.nombre_producto, 'cantidad', {actual}, 'minimo', NEW.stock_minimo) \
+         WHERE NEW.stock_minimo IS NOT NULL AND {actual} <= NEW.stock_minimo AND {anterior} > NEW.stock_minimo; END;\n\
+         CREATE TRIGGER IF NOT EXISTS eventos_entregas AFTER INSERT ON eventos BEGIN \
+         INSERT INTO entregas_webhook (evento_id, webhook_id, proximo_intento) SELECT NEW.id, id, NEW.fecha \
+         FROM webhooks WHERE activo = 1 AND (eventos = '*' OR instr(',' || eventos || ',', ',' || NEW.tipo || ',') > 0); END;\n",
+        actual = actual,
+        anterior = anterior,
+        fecha = FECHA_SQL,
+        alta = stock_cambiado("0"),
+        cambio = stock_cambiado(anterior),
+    );
+    conn.execute_batch(&sql)
+        .map_err(|e| format!("Error al crear los disparadores de eventos: {}", e))
+}
+
+/// HMAC-SHA256 del cuerpo con el secreto del webhook, en hexadecimal.
+fn firmar(secreto: &str, cuerpo: &str) -> String {
+    let mut clave = [0u8; 64];
+    if secreto.len() > clave.len() {
+        clave[..32].copy_from_slice(&Sha256::digest(secreto.as_bytes()));
+    } else {
+        clave[..secreto.len()].copy_from_slice(secreto.as_bytes());
+    }
+    let relleno = |byte: u8| clave.iter().map(|k| k ^ byte).collect::<Vec<u8>>();
+    let interno = Sha256::new().chain_update(relleno(0x36)).chain_update(cuerpo).finalize();
+    let externo = Sha256::new().chain_update(relleno(0x5c)).chain_update(interno).finalize();
+    format!("{:x}", externo)
+}
+
+fn agente() -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout_connect(Duration::from_secs(3))
+        .timeout(TIEMPO_RESPUESTA)
+        .build()
+}
+
+fn enviar(agente: &ureq::Agent, url: &str, secreto: Option<&str>, evento: &Evento) -> Result<u16, String> {
+    let cuerpo = serde_json::to_string(evento).map_err(|e| format!("Error al generar el evento: {}", e))?;
+    let mut peticion = agente
+        .post(url)
+        .set("Content-Type", "application/json")
+        .set("X-Evento", &evento.tipo)
+        .set("X-Evento-Id", &evento.id.to_string());
+    if let Some(secreto) = secreto.filter(|s| !s.is_empty()) {
+        peticion = peticion.set("X-Firma", &format!("sha256={}", firmar(secreto, &cuerpo)));
+    }
+    match peticion.send_string(&cuerpo) {
+        Ok(respuesta) => Ok(respuesta.status()),
+        Err(ureq::Error::Status(estado, _)) => Err(format!("Respuesta {}", estado)),
+        Err(e) => Err(format!("No se pudo conectar: {}", e)),
+    }
+}
+
+fn espera(intentos: i64) -> i64 {
+    (ESPERA_INICIAL << (intentos - 1).clamp(0, 16)).min(ESPERA_MAXIMA)
+}
+
+fn leer_evento(row: &rusqlite::Row, desde: usize) -> rusqlite::Result<Evento> {
+    Ok(Evento {
+        id: row.get(desde)?,
+        tipo: row.get(desde + 1)?,
+        fecha: row.get(desde + 2)?,
+        datos: serde_json::from_str(&row.get::<_, String>(desde + 3)?).unwrap_or(Value::Null),
+    })
+}
+
+/// Webhooks activos con alguna entrega que ya toca.
+fn webhooks_pendientes(conn: &Connection) -> Result<Vec<i64>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT DISTINCT e.webhook_id FROM entregas_webhook e JOIN webhooks w ON w.id = e.webhook_id \
+             WHERE e.estado = 'pendiente' AND w.activo = 1 AND e.proximo_intento <= ?1",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let ids = stmt
+        .query_map(rusqlite::params![ahora()], |row| row.get(0))
+        .map_err(|e| format!("Error al leer la cola de webhooks: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    Ok(ids)
+}
+
+/// Entrega en orden lo que toca a un webhook. Al primer fallo deja de intentar y pospone
+/// el resto de su cola hasta el reintento de esa entrega.
+fn entregar_webhook(conn: &Connection, agente: &ureq::Agent, webhook_id: i64) -> Result<(), String> {
+    let (url, secreto): (String, Option<String>) = conn
+        .query_row(
+            "SELECT url, secreto FROM webhooks WHERE id = ?1",
+            rusqlite::params![webhook_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT e.intentos, v.id, v.tipo, v.fecha, v.datos FROM entregas_webhook e \
+             JOIN eventos v ON v.id = e.evento_id \
+             WHERE e.webhook_id = ?1 AND e.estado = 'pendiente' AND e.proximo_intento <= ?2 \
+             ORDER BY e.evento_id LIMIT ?3",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let pendientes = stmt
+        .query_map(rusqlite::params![webhook_id, ahora(), LOTE], |row| {
+            Ok((row.get::<_, i64>(0)?, leer_evento(row, 1)?))
+        })
+        .map_err(|e| format!("Error al leer la cola de webhooks: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+
+    for (intentos, evento) in pendientes {
+        let intentos = intentos + 1;
+        let error = match enviar(agente, &url, secreto.as_deref(), &evento) {
+            Ok(_) => {
+                conn.execute(
+                    "UPDATE entregas_webhook SET estado = 'entregado', intentos = ?1, entregado = ?2, ultimo_error = NULL \
+                     WHERE evento_id = ?3 AND webhook_id = ?4",
+                    rusqlite::params![intentos, ahora(), evento.id, webhook_id],
+                )
+                .map_err(|e| format!("Error al actualizar la entrega: {}", e))?;
+                continue;
+            }
+            Err(error) => error,
+        };
+        let estado = if intentos >= MAX_INTENTOS { "fallido" } else { "pendiente" };
+        let proximo = (Local::now() + Plazo::seconds(espera(intentos))).format("%Y-%m-%d %H:%M:%S").to_string();
+        conn.execute(
+            "UPDATE entregas_webhook SET estado = ?1, intentos = ?2, proximo_intento = ?3, ultimo_error = ?4 \
+             WHERE evento_id = ?5 AND webhook_id = ?6",
+            rusqlite::params![estado, intentos, proximo, error, evento.id, webhook_id],
+        )
+        .map_err(|e| format!("Error al actualizar la entrega: {}", e))?;
+        conn.execute(
+            "UPDATE entregas_webhook SET proximo_intento = MAX(proximo_intento, ?1) \
+             WHERE webhook_id = ?2 AND estado = 'pendiente'",
+            rusqlite::params![proximo, webhook_id],
+        )
+        .map_err(|e| format!("Error al posponer el webhook: {}", e))?;
+        break;
+    }
+    Ok(())
+}
+
+/// Entrega lo que toca en la cola, cada webhook en su hilo.
+fn entregar_pendientes(agente: &ureq::Agent) -> Result<(), String> {
+    let webhooks = webhooks_pendientes(&abrir_conexion()?)?;
+    thread::scope(|hilos| {
+        for webhook_id in webhooks {
+            hilos.spawn(move || {
+                if let Err(e) = abrir_conexion().and_then(|conn| entregar_webhook(&conn, agente, webhook_id)) {
+                    println!("[warn] webhook {}: {}", webhook_id, e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn leer_eventos(conn: &Connection, desde: i64) -> Result<Vec<Evento>, String> {
+    let mut stmt = conn
+        .prepare("SELECT id, tipo, fecha, datos FROM eventos WHERE id > ?1 ORDER BY id LIMIT ?2")
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let eventos = stmt
+        .query_map(rusqlite::params![desde, LOTE], |row| leer_evento(row, 0))
+        .map_err(|e| format!("Error al leer eventos: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    Ok(eventos)
+}
+
+fn ultimo_evento(conn: &Connection) -> Result<i64, String> {
+    conn.query_row("SELECT COALESCE(MAX(id), 0) FROM eventos", [], |row| row.get(0))
+        .map_err(|e| format!("Error en la consulta: {}", e))
+}
+
+/// Eventos posteriores a `desde`; sin `desde` solo el ultimo id, para empezar a seguirlos.
+/// Lo piden las cajas cliente sin sesion iniciada: basta la clave de terminales.
+#[tauri::command]
+pub fn eventos_desde(desde: Option<i64>) -> Result<LoteEventos, String> {
+    let conn = abrir_conexion()?;
+    let eventos = match desde {
+        Some(desde) => leer_eventos(&conn, desde)?,
+        None => Vec::new(),
+    };
+    let ultimo = match eventos.last() {
+        Some(evento) => evento.id,
+        None => ultimo_evento(&conn)?,
+    };
+    Ok(LoteEventos { ultimo, eventos })
+}
+
+/// Eventos nuevos desde `desde`: de la base propia o, en una caja cliente, del servidor.
+fn siguientes(desde: Option<i64>) -> Result<LoteEventos, String> {
+    if !terminales::es_cliente() {
+        return eventos_desde(desde);
+    }
+    let lote = terminales::consultar_servidor("eventos_desde", &serde_json::json!({ "desde": desde }))?;
+    serde_json::from_value(lote).map_err(|e| format!("Respuesta invalida del servidor: {}", e))
+}
+
+/// Reenvia a las ventanas los eventos nuevos. Las ventanas solo reciben lo que pase desde
+/// que se abren.
+fn avisar_ventanas(app: AppHandle) {
+    let mut ultimo = None;
+    loop {
+        match siguientes(ultimo) {
+            Ok(lote) => {
+                for evento in &lote.eventos {
+                    if let Err(e) = app.emit(&evento.tipo, evento.clone()) {
+                        println!("[warn] no se pudo avisar a las ventanas: {}", e);
+                    }
+                }
+                ultimo = Some(lote.ultimo);
+            }
+            Err(e) => println!("[warn] eventos: {}", e),
+        }
+        thread::sleep(INTERVALO);
+    }
+}
+
+/// Arranca la entrega de webhooks y, con `app`, el aviso a las ventanas.
+pub(crate) fn iniciar(app: Option<AppHandle>) {
+    if let Some(app) = app {
+        thread::spawn(move || avisar_ventanas(app));
+    }
+    if terminales::es_cliente() {
+        return;
+    }
+    if let Err(e) = ensure_db_initialized() {
+        println!("[error] eventos: {}", e);
+        return;
+    }
+    thread::spawn(|| {
+        let agente = agente();
+        loop {
+            if let Err(e) = entregar_pendientes(&agente) {
+                println!("[warn] webhooks: {}", e);
+            }
+            thread::sleep(INTERVALO);
+        }
+    });
+}
+
+fn validar_webhook(webhook: &Webhook) -> Result<String, String> {
+    let url = webhook.url.trim();
+    if !(url.starts_with("http://") || url.starts_with("https://")) {
+        return Err("La URL del webhook debe empezar con http:// o https://".to_string());
+    }
+    if let Some(tipo) = webhook.eventos.iter().find(|t| !TIPOS.contains(&t.as_str())) {
+        return Err(format!("Evento desconocido: {} (validos: {})", tipo, TIPOS.join(", ")));
+    }
+    Ok(if webhook.eventos.is_empty() {
+        "*".to_string()
+    } else {
+        webhook.eventos.join(",")
+    })
+}
+
+#[tauri::command]
+pub fn listar_webhooks() -> Result<Vec<Webhook>, String> {
+    require_permiso(Permiso::AdministrarSistema)?;
+    let conn = abrir_conexion()?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT w.id, w.url, w.eventos, COALESCE(w.secreto, '') <> '', w.activo, \
+             (SELECT COUNT(*) FROM entregas_webhook e WHERE e.webhook_id = w.id AND e.estado = 'pendiente'), \
+             (SELECT COUNT(*) FROM entregas_webhook e WHERE e.webhook_id = w.id AND e.estado = 'fallido') \
+             FROM webhooks w ORDER BY w.id",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let webhooks = stmt
+        .query_map([], |row| {
+            let eventos: String = row.get(2)?;
+            Ok(Webhook {
+                id: row.get(0)?,
+                url: row.get(1)?,
+                eventos: if eventos == "*" {
+                    Vec::new()
+                } else {
+                    eventos.split(',').map(str::to_string).collect()
+                },
+                secreto: None,
+                con_secreto: row.get(3)?,
+                activo: row.get(4)?,
+                pendientes: row.get(5)?,
+                fallidas: row.get(6)?,
+            })
+        })
+        .map_err(|e| format!("Error al leer los webhooks: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    Ok(webhooks)
+}
+
+/// Crea o modifica un webhook. Los eventos ya encolados se siguen entregando.
+#[tauri::command]
+pub fn guardar_webhook(webhook: Webhook) -> Result<i64, String> {
+    require_permiso(Permiso::AdministrarSistema)?;
+    let eventos = validar_webhook(&webhook)?;
+    let url = webhook.url.trim();
+    let secreto = webhook.secreto.as_deref().map(str::trim);
+    let conn = abrir_conexion()?;
+
+    let id = match webhook.id {
+        Some(id) => {
+            let cambiados = conn
+                .execute(
+                    "UPDATE webhooks SET url = ?1, eventos = ?2, activo = ?3, secreto = COALESCE(?4, secreto) WHERE id = ?5",
+                    rusqlite::params![url, eventos, webhook.activo, secreto, id],
+                )
+                .map_err(|e| format!("Error al actualizar el webhook: {}", e))?;
+            if cambiados == 0 {
+                return Err(format!("Webhook {} no encontrado", id));
+            }
+            id
+        }
+        None => {
+            conn.execute(
+                "INSERT INTO webhooks (url, eventos, secreto, activo, creado) VALUES (?1, ?2, ?3, ?4, ?5)",
+                rusqlite::params![url, eventos, secreto, webhook.activo, ahora()],
+            )
+            .map_err(|e| format!("Error al crear el webhook: {}", e))?;
+            conn.last_insert_rowid()
+        }
+    };
+
+    auditoria::registrar_auditoria(
+        &conn,
+        "guardar_webhook",
+        &format!("webhook:{}", id),
+        None,
+        Some(serde_json::json!({ "url": url, "eventos": eventos, "activo": webhook.activo })),
+        true,
+    )?;
+    Ok(id)
+}
+
+#[tauri::command]
+pub fn eliminar_webhook(id: i64) -> Result<(), String> {
+    require_permiso(Permiso::AdministrarSistema)?;
+    let mut conn = abrir_conexion()?;
+    let tx = conn
+        .transaction()
+        .map_err(|e| format!("Error al iniciar la transaccion: {}", e))?;
+    tx.execute("DELETE FROM entregas_webhook WHERE webhook_id = ?1", rusqlite::params![id])
+        .map_err(|e| format!("Error al eliminar las entregas: {}", e))?;
+    let borrados = tx
+        .execute("DELETE FROM webhooks WHERE id = ?1", rusqlite::params![id])
+        .map_err(|e| format!("Error al eliminar el webhook: {}", e))?;
+    if borrados == 0 {
+        return Err(format!("Webhook {} no encontrado", id));
+    }
+    auditoria::registrar_auditoria(&tx, "eliminar_webhook", &format!("webhook:{}", id), None, None, true)?;
+    tx.commit()
+        .map_err(|e| format!("Error al confirmar: {}", e))
+}
+
+/// Ultimas entregas, opcionalmente de un webhook y en un estado
+/// ("pendiente", "entregado" o "fallido").
+#[tauri::command]
+pub fn listar_entregas(webhook_id: Option<i64>, estado: Option<String>) -> Result<Vec<EntregaWebhook>, String> {
+    require_permiso(Permiso::AdministrarSistema)?;
+    let conn = abrir_conexion()?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT e.evento_id, e.webhook_id, v.tipo, v.fecha, e.estado, e.intentos, e.proximo_intento, \
+             e.entregado, e.ultimo_error FROM entregas_webhook e JOIN eventos v ON v.id = e.evento_id \
+             WHERE (?1 IS NULL OR e.webhook_id = ?1) AND (?2 IS NULL OR e.estado = ?2) \
+             ORDER BY e.evento_id DESC LIMIT 200",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let entregas = stmt
+        .query_map(rusqlite::params![webhook_id, estado], |row| {
+            Ok(EntregaWebhook {
+                evento_id: row.get(0)?,
+                webhook_id: row.get(1)?,
+                tipo: row.get(2)?,
+                fecha: row.get(3)?,
+                estado: row.get(4)?,
+                intentos: row.get(5)?,
+                proximo_intento: row.get(6)?,
+                entregado: row.get(7)?,
+                ultimo_error: row.get(8)?,
+            })
+        })
+        .map_err(|e| format!("Error al leer las entregas: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    Ok(entregas)
+}
+
+/// Vuelve a poner en cola las entregas fallidas de un webhook. Devuelve cuantas.
+#[tauri::command]
+pub fn reintentar_entregas(webhook_id: i64) -> Result<usize, String> {
+    require_permiso(Permiso::AdministrarSistema)?;
+    let mut conn = abrir_conexion()?;
+    let tx = conn
+        .transaction()
+        .map_err(|e| format!("Error al iniciar la transaccion: {}", e))?;
+    let reintentadas = tx
+        .execute(
+            "UPDATE entregas_webhook SET estado = 'pendiente', intentos = 0, proximo_intento = ?1 \
+             WHERE webhook_id = ?2 AND estado = 'fallido'",
+            rusqlite::params![ahora(), webhook_id],
+        )
+        .map_err(|e| format!("Error al reintentar las entregas: {}", e))?;
+    auditoria::registrar_auditoria(
+        &tx,
+        "reintentar_entregas",
+        &format!("webhook:{}", webhook_id),
+        None,
+        Some(serde_json::json!({ "reintentadas": reintentadas })),
+        true,
+    )?;
+    tx.commit()
+        .map_err(|e| format!("Error al confirmar: {}", e))?;
+    Ok(reintentadas)
+}
+
+/// Envia al webhook un evento "prueba" fuera de la cola y devuelve la respuesta.
+#[tauri::command]
+pub fn probar_webhook(id: i64) -> Result<String, String> {
+    require_permiso(Permiso::AdministrarSistema)?;
+    let conn = abrir_conexion()?;
+    let (url, secreto): (String, Option<String>) = conn
+        .query_row(
+            "SELECT url, secreto FROM webhooks WHERE id = ?1",
+            rusqlite::params![id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+        .map_err(|e| format!("Error en la consulta: {}", e))?
+        .ok_or_else(|| format!("Webhook {} no encontrado", id))?;
+    let evento = Evento {
+        id: 0,
+        tipo: "prueba".to_string(),
+        fecha: ahora(),
+        datos: serde_json::json!({ "webhook_id": id }),
+    };
+    let estado = enviar(&agente(), &url, secreto.as_deref(), &evento)?;
+    Ok(format!("Respuesta {}", estado))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comun::pruebas::base;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    /// Servidor HTTP local que responde 200 y pasa cada peticion recibida por el canal.
+    fn receptor() -> (String, mpsc::Receiver<(Option<String>, String)>) {
+        let servidor = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", servidor.server_addr().to_ip().unwrap());
+        let (enviar, recibir) = mpsc::channel();
+        thread::spawn(move || {
+            for mut peticion in servidor.incoming_requests() {
+                let firma = peticion
+                    .headers()
+                    .iter()
+                    .find(|h| h.field.equiv("X-Firma"))
+                    .map(|h| h.value.to_string());
+                let mut cuerpo = String::new();
+                peticion.as_reader().read_to_string(&mut cuerpo).unwrap();
+                if enviar.send((firma, cuerpo)).is_err() {
+                    break;
+                }
+                let _ = peticion.respond(tiny_http::Response::empty(200));
+            }
+        });
+        (url, recibir)
+    }
+
+    fn crear_webhook(conn: &Connection, url: &str, secreto: Option<&str>) -> i64 {
+        conn.execute(
+            "INSERT INTO webhooks (url, eventos, secreto, activo, creado) VALUES (?1, '*', ?2, 1, ?3)",
+            rusqlite::params![url, secreto, ahora()],
+        )
+        .unwrap();
+        conn.last_insert_rowid()
+    }
+
+    fn estados(conn: &Connection, webhook_id: i64) -> Vec<(String, i64)> {
+        let mut stmt = conn
+            .prepare("SELECT estado, intentos FROM entregas_webhook WHERE webhook_id = ?1 ORDER BY evento_id")
+            .unwrap();
+        let filas = stmt
+            .query_map(rusqlite::params![webhook_id], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        filas.collect::<Result<_, _>>().unwrap()
+    }
+
+    #[test]
+    fn entrega_en_orden_y_firmada() {
+        let conn = base(&[]);
+        let (url, recibidas) = receptor();
+        let webhook = crear_webhook(&conn, &url, Some("secreto"));
+        emitir(&conn, "dia_cerrado", serde_json::json!({ "n": 1 })).unwrap();
+        emitir(&conn, "dia_cerrado", serde_json::json!({ "n": 2 })).unwrap();
+
+        entregar_webhook(&conn, &agente(), webhook).unwrap();
+
+        for n in 1..=2 {
+            let (firma, cuerpo) = recibidas.recv_timeout(Duration::from_secs(5)).unwrap();
+            let evento: Evento = serde_json::from_str(&cuerpo).unwrap();
+            assert_eq!(evento.datos["n"], n);
+            assert_eq!(firma, Some(format!("sha256={}", firmar("secreto", &cuerpo))));
+        }
+        assert_eq!(estados(&conn, webhook), vec![("entregado".to_string(), 1), ("entregado".to_string(), 1)]);
+    }
+
+    #[test]
+    fn un_destino_caido_se_intenta_una_vez_y_espera() {
+        let conn = base(&[]);
+        // Un puerto sin nadie escuchando rechaza la conexion enseguida
+        let caido = {
+            let libre = TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}/hook", libre.local_addr().unwrap())
+        };
+        let (url, recibidas) = receptor();
+        let roto = crear_webhook(&conn, &caido, None);
+        let sano = crear_webhook(&conn, &url, None);
+        for n in 0..3 {
+            emitir(&conn, "dia_cerrado", serde_json::json!({ "n": n })).unwrap();
+        }
+
+        let agente = agente();
+        entregar_webhook(&conn, &agente, roto).unwrap();
+        entregar_webhook(&conn, &agente, sano).unwrap();
+
+        let pendiente = |intentos| ("pendiente".to_string(), intentos);
+        assert_eq!(estados(&conn, roto), vec![pendiente(1), pendiente(0), pendiente(0)]);
+        assert_eq!(estados(&conn, sano).iter().filter(|(e, _)| e == "entregado").count(), 3);
+        assert_eq!(recibidas.try_iter().count(), 3);
+        // Hasta el reintento el destino caido no vuelve a la cola
+        assert!(webhooks_pendientes(&conn).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,299 @@
+// Exportacion de inventario, historial de ventas y usuarios a CSV, XLSX o JSON.
+//
+// Los archivos se escriben en `Documentos/exportaciones`, junto a la carpeta de recibos.
+
+use chrono::{Local, NaiveDate};
+use rust_xlsxwriter::{Format, Workbook};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::catalogo::FiltroCatalogo;
+use crate::combos;
+use crate::permisos::{require_permiso, Permiso};
+use crate::{abrir_conexion, get_documentos_exportaciones_dir};
+
+#[derive(Serialize, Deserialize)]
+pub struct OpcionesExportacion {
+    formato: String,
+    delimitador: Option<String>,
+    coma_decimal: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ExportacionResponse {
+    ruta: String,
+    filas: usize,
+}
+
+enum Valor {
+    Texto(String),
+    Entero(i64),
+    Decimal(f64),
+    /// Cantidad de producto: entera o con hasta tres decimales (kg, litro)
+    Cantidad(f64),
+}
+
+struct Tabla {
+    encabezados: Vec<&'static str>,
+    filas: Vec<Vec<Valor>>,
+}
+
+fn escribir_csv(tabla: &Tabla, ruta: &Path, delimitador: u8, coma_decimal: bool) -> Result<(), String> {
+    let mut escritor = csv::WriterBuilder::new()
+        .delimiter(delimitador)
+        .from_path(ruta)
+        .map_err(|e| format!("No se pudo crear el archivo CSV: {}", e))?;
+
+    escritor
+        .write_record(&tabla.encabezados)
+        .map_err(|e| format!("No se pudo escribir el CSV: {}", e))?;
+
+    for fila in &tabla.filas {
+        let campos: Vec<String> = fila
+            .iter()
+            .map(|valor| match valor {
+                Valor::Texto(t) => t.clone(),
+                Valor::Entero(n) => n.to_string(),
+                Valor::Decimal(d) if coma_decimal => format!("{:.2}", d).replace('.', ","),
+                Valor::Decimal(d) => format!("{:.2}", d),
+                Valor::Cantidad(c) if coma_decimal => c.to_string().replace('.', ","),
+                Valor::Cantidad(c) => c.to_string(),
+            })
+            .collect();
+        escritor
+            .write_record(&campos)
+            .map_err(|e| format!("No se pudo escribir el CSV: {}", e))?;
+    }
+
+    escritor
+        .flush()
+        .map_err(|e| format!("No se pudo escribir el CSV: {}", e))
+}
+
+fn escribir_xlsx(tabla: &Tabla, ruta: &Path, nombre_hoja: &str) -> Result<(), String> {
+    let mut libro = Workbook::new();
+    let hoja = libro.add_worksheet();
+    hoja.set_name(nombre_hoja)
+        .map_err(|e| format!("No se pudo crear la hoja: {}", e))?;
+
+    let negrita = Format::new().set_bold();
+    let moneda = Format::new().set_num_format("0.00");
+
+    for (col, encabezado) in tabla.encabezados.iter().enumerate() {
+        hoja.write_string_with_format(0, col as u16, *encabezado, &negrita)
+            .map_err(|e| format!("No se pudo escribir el XLSX: {}", e))?;
+    }
+
+    for (fila_idx, fila) in tabla.filas.iter().enumerate() {
+        let fila_xlsx = (fila_idx + 1) as u32;
+        for (col, valor) in fila.iter().enumerate() {
+            let col = col as u16;
+            let resultado = match valor {
+                Valor::Texto(t) => hoja.write_string(fila_xlsx, col, t),
+                Valor::Entero(n) => hoja.write_number(fila_xlsx, col, *n as f64),
+                Valor::Decimal(d) => hoja.write_number_with_format(fila_xlsx, col, *d, &moneda),
+                Valor::Cantidad(c) => hoja.write_number(fila_xlsx, col, *c),
+            };
+            resultado.map_err(|e| format!("No se pudo escribir el XLSX: {}", e))?;
+        }
+    }
+
+    libro
+        .save(ruta)
+        .map_err(|e| format!("No se pudo guardar el XLSX: {}", e))
+}
+
+fn escribir_json(tabla: &Tabla, ruta: &Path) -> Result<(), String> {
+    let registros: Vec<serde_json::Value> = tabla
+        .filas
+        .iter()
+        .map(|fila| {
+            let mut objeto = serde_json::Map::new();
+            for (encabezado, valor) in tabla.encabezados.iter().zip(fila) {
+                let valor_json = match valor {
+                    Valor::Texto(t) => serde_json::Value::from(t.as_str()),
+                    Valor::Entero(n) => serde_json::Value::from(*n),
+                    Valor::Decimal(d) | Valor::Cantidad(d) => serde_json::Value::from(*d),
+                };
+                objeto.insert(encabezado.to_string(), valor_json);
+            }
+            serde_json::Value::Object(objeto)
+        })
+        .collect();
+
+    let contenido = serde_json::to_string_pretty(&registros)
+        .map_err(|e| format!("No se pudo generar el JSON: {}", e))?;
+    fs::write(ruta, contenido).map_err(|e| format!("No se pudo escribir el JSON: {}", e))
+}
+
+fn escribir_tabla(tabla: Tabla, nombre_base: &str, opciones: &OpcionesExportacion) -> Result<ExportacionResponse, String> {
+    let formato = opciones.formato.trim().to_lowercase();
+    let exportaciones_dir = get_documentos_exportaciones_dir()?;
+    let marca = Local::now().format("%Y%m%d-%H%M%S");
+    let ruta = exportaciones_dir.join(format!("{}-{}.{}", nombre_base, marca, formato));
+
+    match formato.as_str() {
+        "csv" => {
+            let coma_decimal = opciones.coma_decimal.unwrap_or(false);
+            let delimitador = match opciones.delimitador.as_deref() {
+                Some(d) if d.len() == 1 => d.as_bytes()[0],
+                Some(_) => return Err("El delimitador debe ser un solo caracter".to_string()),
+                // Con coma decimal la coma no puede separar columnas
+                None if coma_decimal => b';',
+                None => b',',
+            };
+            if coma_decimal && delimitador == b',' {
+                return Err("El delimitador no puede ser coma si se usa coma decimal".to_string());
+            }
+            escribir_csv(&tabla, &ruta, delimitador, coma_decimal)?;
+        }
+        "xlsx" => escribir_xlsx(&tabla, &ruta, nombre_base)?,
+        "json" => escribir_json(&tabla, &ruta)?,
+        otro => return Err(format!("Formato de exportacion no soportado: {}", otro)),
+    }
+
+    Ok(ExportacionResponse {
+        ruta: ruta.display().to_string(),
+        filas: tabla.filas.len(),
+    })
+}
+
+pub(crate) fn parse_fecha(texto: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(texto.trim(), "%Y-%m-%d")
+        .map_err(|_| format!("Fecha invalida (use AAAA-MM-DD): {}", texto))
+}
+
+#[tauri::command]
+pub fn exportar_inventario(
+    opciones: OpcionesExportacion,
+    filtro: Option<FiltroCatalogo>,
+) -> Result<ExportacionResponse, String> {
+    require_permiso(Permiso::VerReportes)?;
+    let conn = abrir_conexion()?;
+    let (condiciones, params) = filtro.unwrap_or_default().clausula();
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT i.id, i.nombre_producto, CAST(i.precio_producto AS REAL), \
+             COALESCE({}, CAST(i.cantidad_producto AS REAL), 0), i.unidad, \
+             COALESCE(c.nombre, ''), COALESCE(m.nombre, '') FROM inventario i \
+             LEFT JOIN categorias c ON c.id = i.categoria_id \
+             LEFT JOIN marcas m ON m.id = i.marca_id \
+             WHERE 1 = 1{} ORDER BY i.id",
+            combos::disponible_sql("i"),
+            condiciones
+        ))
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+
+    let filas = stmt
+        .query_map(rusqlite::params_from_iter(params), |row| {
+            Ok(vec![
+                Valor::Entero(row.get(0)?),
+                Valor::Texto(row.get(1)?),
+                Valor::Decimal(row.get(2)?),
+                Valor::Cantidad(row.get(3)?),
+                Valor::Texto(row.get(4)?),
+                Valor::Texto(row.get(5)?),
+                Valor::Texto(row.get(6)?),
+            ])
+        })
+        .map_err(|e| format!("Error al leer inventarios: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+
+    let tabla = Tabla {
+        encabezados: vec!["id", "nombre", "precio", "cantidad", "unidad", "categoria", "marca"],
+        filas,
+    };
+    escribir_tabla(tabla, "inventario", &opciones)
+}
+
+#[tauri::command]
+pub fn exportar_ventas(desde: String, hasta: String, opciones: OpcionesExportacion) -> Result<ExportacionResponse, String> {
+    require_permiso(Permiso::VerReportes)?;
+    let desde = parse_fecha(&desde)?;
+    let hasta = parse_fecha(&hasta)?;
+    if desde > hasta {
+        return Err("La fecha inicial no puede ser posterior a la final".to_string());
+    }
+
+    let conn = abrir_conexion()?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, fecha, producto_id, nombre_producto, precio, cantidad, descuento, subtotal, \
+             tasa_impuesto, COALESCE(base_imponible, subtotal), impuesto, COALESCE(costo_unitario, 0) FROM ventas \
+             WHERE date(fecha) BETWEEN ?1 AND ?2 ORDER BY fecha, id",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+
+    let filas = stmt
+        .query_map(
+            rusqlite::params![desde.to_string(), hasta.to_string()],
+            |row| {
+                Ok(vec![
+                    Valor::Entero(row.get(0)?),
+                    Valor::Texto(row.get(1)?),
+                    Valor::Entero(row.get(2)?),
+                    Valor::Texto(row.get(3)?),
+                    Valor::Decimal(row.get(4)?),
+                    Valor::Cantidad(row.get(5)?),
+                    Valor::Decimal(row.get(6)?),
+                    Valor::Decimal(row.get(7)?),
+                    Valor::Decimal(row.get(8)?),
+                    Valor::Decimal(row.get(9)?),
+                    Valor::Decimal(row.get(10)?),
+                    Valor::Decimal(row.get(11)?),
+                ])
+            },
+        )
+        .map_err(|e| format!("Error al leer ventas: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+
+    let tabla = Tabla {
+        encabezados: vec![
+            "id",
+            "fecha",
+            "producto_id",
+            "producto",
+            "precio",
+            "cantidad",
+            "descuento",
+            "subtotal",
+            "tasa_impuesto",
+            "base_imponible",
+            "impuesto",
+            "costo_unitario",
+        ],
+        filas,
+    };
+    escribir_tabla(tabla, "ventas", &opciones)
+}
+
+#[tauri::command]
+pub fn exportar_usuarios(opciones: OpcionesExportacion) -> Result<ExportacionResponse, String> {
+    require_permiso(Permiso::GestionarUsuarios)?;
+    let conn = abrir_conexion()?;
+    // Nunca se exportan las contrasenas
+    let mut stmt = conn
+        .prepare("SELECT name, \"correo electronico\", Admin FROM users ORDER BY name")
+        .map_err(|e| format!("Error al preparar consulta de usuarios: {}", e))?;
+
+    let filas = stmt
+        .query_map([], |row| {
+            Ok(vec![
+                Valor::Texto(row.get(0)?),
+                Valor::Texto(row.get(1)?),
+                Valor::Texto(if row.get::<_, i32>(2)? == 1 { "si" } else { "no" }.to_string()),
+            ])
+        })
+        .map_err(|e| format!("Error al leer usuarios: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+
+    let tabla = Tabla {
+        encabezados: vec!["nombre", "correo", "admin"],
+        filas,
+    };
+    escribir_tabla(tabla, "usuarios", &opciones)
+}
@@ -0,0 +1,472 @@
+// Importacion masiva del catalogo de productos desde archivos CSV o XLSX.
+//
+// El flujo tiene dos pasos: `previsualizar_importacion` valida todas las filas y
+// devuelve un reporte sin tocar la base de datos; `importar_inventario` repite la
+// validacion y, si no hay errores, aplica todos los cambios en una sola transaccion.
+
+use calamine::{open_workbook_auto, Data, Reader};
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::catalogo::{admite_fraccion, redondear_cantidad};
+use crate::{combos, lotes, series};
+use crate::permisos::{require_permiso, Permiso};
+use crate::precios::{registrar_historial, LISTA_MINORISTA};
+use crate::{abrir_conexion, movimientos, ubicaciones};
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MapeoColumnas {
+    id: String,
+    nombre: String,
+    precio: String,
+    cantidad: Option<String>,
+}
+
+impl Default for MapeoColumnas {
+    fn default() -> Self {
+        MapeoColumnas {
+            id: "id".to_string(),
+            nombre: "nombre".to_string(),
+            precio: "precio".to_string(),
+            cantidad: Some("cantidad".to_string()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ImportacionRequest {
+    ruta: String,
+    columnas: Option<MapeoColumnas>,
+    delimitador: Option<String>,
+    actualizar_existentes: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ErrorImportacion {
+    fila: usize,
+    mensaje: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReporteImportacion {
+    total_filas: usize,
+    nuevos: usize,
+    actualizados: usize,
+    errores: Vec<ErrorImportacion>,
+    aplicado: bool,
+}
+
+struct FilaProducto {
+    id: i64,
+    nombre: String,
+    precio: f64,
+    cantidad: Option<f64>,
+    existente: bool,
+}
+
+struct TablaImportada {
+    encabezados: Vec<String>,
+    filas: Vec<Vec<String>>,
+}
+
+fn leer_csv(ruta: &Path, delimitador: u8) -> Result<TablaImportada, String> {
+    let mut lector = csv::ReaderBuilder::new()
+        .delimiter(delimitador)
+        .flexible(true)
+        .from_path(ruta)
+        .map_err(|e| format!("No se pudo abrir el CSV: {}", e))?;
+
+    let encabezados = lector
+        .headers()
+        .map_err(|e| format!("No se pudieron leer los encabezados: {}", e))?
+        .iter()
+        .map(|h| h.trim().to_string())
+        .collect();
+
+    let mut filas = Vec::new();
+    for registro in lector.records() {
+        let registro = registro.map_err(|e| format!("Error al leer el CSV: {}", e))?;
+        filas.push(registro.iter().map(|c| c.trim().to_string()).collect());
+    }
+
+    Ok(TablaImportada { encabezados, filas })
+}
+
+fn leer_xlsx(ruta: &Path) -> Result<TablaImportada, String> {
+    let mut libro = open_workbook_auto(ruta)
+        .map_err(|e| format!("No se pudo abrir el libro: {}", e))?;
+    let hoja = libro
+        .worksheet_range_at(0)
+        .ok_or_else(|| "El libro no tiene hojas".to_string())?
+        .map_err(|e| format!("No se pudo leer la hoja: {}", e))?;
+
+    let mut filas_hoja = hoja.rows();
+    let encabezados = filas_hoja
+        .next()
+        .ok_or_else(|| "La hoja esta vacia".to_string())?
+        .iter()
+        .map(|c| c.to_string().trim().to_string())
+        .collect();
+
+    let filas = filas_hoja
+        .map(|fila| {
+            fila.iter()
+                .map(|c| match c {
+                    Data::Empty => String::new(),
+                    otro => otro.to_string().trim().to_string(),
+                })
+                .collect()
+        })
+        .collect();
+
+    Ok(TablaImportada { encabezados, filas })
+}
+
+fn leer_archivo(ruta: &Path, delimitador: Option<&str>) -> Result<TablaImportada, String> {
+    let extension = ruta
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "csv" | "txt" => {
+            let delimitador = match delimitador {
+                Some(d) if d.len() == 1 => d.as_bytes()[0],
+                Some(_) => return Err("El delimitador debe ser un solo caracter".to_string()),
+                None => b',',
+            };
+            leer_csv(ruta, delimitador)
+        }
+        "xlsx" | "xlsm" | "xls" | "ods" => leer_xlsx(ruta),
+        _ => Err(format!("Formato de archivo no soportado: .{}", extension)),
+    }
+}
+
+fn indice_columna(encabezados: &[String], nombre: &str) -> Option<usize> {
+    encabezados
+        .iter()
+        .position(|h| h.eq_ignore_ascii_case(nombre.trim()))
+}
+
+/// Convierte precios escritos como "12.50", "12,50", "1.234,56" o "$ 1,234.56". Un separador
+/// que se repite ("1.234.567") es de miles.
+pub(crate) fn parse_precio(texto: &str) -> Option<f64> {
+    let limpio: String = texto
+        .trim()
+        .trim_start_matches("Bs")
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '$')
+        .collect();
+    if limpio.is_empty() {
+        return None;
+    }
+
+    let repetido = |separador: char| limpio.matches(separador).count() > 1;
+    let normalizado = match (limpio.rfind('.'), limpio.rfind(',')) {
+        (Some(punto), Some(coma)) if coma > punto => limpio.replace('.', "").replace(',', "."),
+        (Some(_), Some(_)) => limpio.replace(',', ""),
+        (None, Some(_)) if repetido(',') => limpio.replace(',', ""),
+        (None, Some(_)) => limpio.replace(',', "."),
+        (Some(_), None) if repetido('.') => limpio.replace('.', ""),
+        _ => limpio,
+    };
+
+    normalizado.parse::<f64>().ok().filter(|v| v.is_finite())
+}
+
+fn parse_entero(texto: &str) -> Option<i64> {
+    let limpio = texto.trim();
+    if let Ok(valor) = limpio.parse::<i64>() {
+        return Some(valor);
+    }
+    // Las hojas de calculo suelen entregar enteros como "12.0"
+    limpio
+        .parse::<f64>()
+        .ok()
+        .filter(|v| v.fract() == 0.0)
+        .map(|v| v as i64)
+}
+
+fn validar_filas(
+    conn: &Connection,
+    tabla: &TablaImportada,
+    mapeo: &MapeoColumnas,
+    actualizar_existentes: bool,
+) -> Result<(Vec<FilaProducto>, Vec<ErrorImportacion>), String> {
+    let col_id = indice_columna(&tabla.encabezados, &mapeo.id)
+        .ok_or_else(|| format!("No se encontro la columna '{}'", mapeo.id))?;
+    let col_nombre = indice_columna(&tabla.encabezados, &mapeo.nombre)
+        .ok_or_else(|| format!("No se encontro la columna '{}'", mapeo.nombre))?;
+    let col_precio = indice_columna(&tabla.encabezados, &mapeo.precio)
+        .ok_or_else(|| format!("No se encontro la columna '{}'", mapeo.precio))?;
+    let col_cantidad = match &mapeo.cantidad {
+        Some(nombre) => Some(
+            indice_columna(&tabla.encabezados, nombre)
+                .ok_or_else(|| format!("No se encontro la columna '{}'", nombre))?,
+        ),
+        None => None,
+    };
+
+    let mut productos = Vec::new();
+    let mut errores = Vec::new();
+    let mut ids_vistos: HashMap<i64, usize> = HashMap::new();
+    let mut nombres_vistos: HashMap<String, usize> = HashMap::new();
+
+    for (indice, fila) in tabla.filas.iter().enumerate() {
+        // Fila 1 es el encabezado
+        let numero_fila = indice + 2;
+        let celda = |col: usize| fila.get(col).map(|s| s.as_str()).unwrap_or("");
+
+        if fila.iter().all(|c| c.is_empty()) {
+            continue;
+        }
+
+        let mut error = |mensaje: String| {
+            errores.push(ErrorImportacion {
+                fila: numero_fila,
+                mensaje,
+            })
+        };
+
+        let id = match parse_entero(celda(col_id)) {
+            Some(id) if id > 0 => id,
+            _ => {
+                error(format!("ID invalido: '{}'", celda(col_id)));
+                continue;
+            }
+        };
+
+        let nombre = celda(col_nombre).to_string();
+        if nombre.is_empty() {
+            error("El nombre del producto es obligatorio".to_string());
+            continue;
+        }
+
+        let precio = match parse_precio(celda(col_precio)) {
+            Some(p) if p >= 0.0 => p,
+            Some(_) => {
+                error(format!("El precio no puede ser negativo: '{}'", celda(col_precio)));
+                continue;
+            }
+            None => {
+                error(format!("Precio invalido: '{}'", celda(col_precio)));
+                continue;
+            }
+        };
+
+        let cantidad = match col_cantidad.map(celda) {
+            None | Some("") => None,
+            Some(texto) => match parse_precio(texto).map(redondear_cantidad) {
+                Some(c) if c >= 0.0 => Some(c),
+                Some(_) => {
+                    error(format!("La cantidad no puede ser negativa: '{}'", texto));
+                    continue;
+                }
+                None => {
+                    error(format!("Cantidad invalida: '{}'", texto));
+                    continue;
+                }
+            },
+        };
+
+        if let Some(previa) = ids_vistos.insert(id, numero_fila) {
+            error(format!("ID {} duplicado (tambien en la fila {})", id, previa));
+            continue;
+        }
+        let clave_nombre = nombre.to_lowercase();
+        if let Some(previa) = nombres_vistos.insert(clave_nombre, numero_fila) {
+            error(format!("Nombre '{}' duplicado (tambien en la fila {})", nombre, previa));
+            continue;
+        }
+
+        // Los productos nuevos se crean por unidad
+        let unidad_existente: Option<String> = conn
+            .query_row(
+                "SELECT unidad FROM inventario WHERE id = ?1",
+                rusqlite::params![id],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| format!("Error en la consulta: {}", e))?;
+        let existente = unidad_existente.is_some();
+        if existente && cantidad.is_some() && combos::es_combo(conn, id)? {
+            error(format!("El producto {} es un combo; su stock se calcula de los componentes", id));
+            continue;
+        }
+        if existente && cantidad.is_some() && lotes::controla_lotes(conn, id)? {
+            error(format!("El producto {} se controla por lotes; ajuste la cantidad de cada lote", id));
+            continue;
+        }
+        if existente && cantidad.is_some() && series::es_serializado(conn, id)? {
+            error(format!("El producto {} se controla por numero de serie", id));
+            continue;
+        }
+        let unidad = unidad_existente.unwrap_or_else(|| "unidad".to_string());
+        if let Some(c) = cantidad.filter(|c| c.fract() != 0.0) {
+            if !admite_fraccion(&unidad) {
+                error(format!("El producto se vende por {}; la cantidad debe ser entera: '{}'", unidad, c));
+                continue;
+            }
+        }
+
+        if existente && !actualizar_existentes {
+            error(format!("El producto con ID {} ya existe", id));
+            continue;
+        }
+
+        let id_con_mismo_nombre: Option<i64> = conn
+            .query_row(
+                "SELECT id FROM inventario WHERE LOWER(nombre_producto) = LOWER(?1) LIMIT 1",
+                rusqlite::params![nombre],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| format!("Error en la consulta: {}", e))?;
+
+        if let Some(otro_id) = id_con_mismo_nombre {
+            if otro_id != id {
+                error(format!(
+                    "El nombre '{}' ya pertenece al producto con ID {}",
+                    nombre, otro_id
+                ));
+                continue;
+            }
+        }
+
+        productos.push(FilaProducto {
+            id,
+            nombre,
+            precio,
+            cantidad,
+            existente,
+        });
+    }
+
+    Ok((productos, errores))
+}
+
+fn preparar_reporte(
+    conn: &Connection,
+    payload: &ImportacionRequest,
+) -> Result<(Vec<FilaProducto>, ReporteImportacion), String> {
+    let tabla = leer_archivo(Path::new(payload.ruta.trim()), payload.delimitador.as_deref())?;
+    let mapeo = payload.columnas.clone().unwrap_or_default();
+    let (productos, errores) = validar_filas(conn, &tabla, &mapeo, payload.actualizar_existentes)?;
+
+    let actualizados = productos.iter().filter(|p| p.existente).count();
+    let reporte = ReporteImportacion {
+        total_filas: tabla.filas.len(),
+        nuevos: productos.len() - actualizados,
+        actualizados,
+        errores,
+        aplicado: false,
+    };
+
+    Ok((productos, reporte))
+}
+
+#[tauri::command]
+pub fn previsualizar_importacion(payload: ImportacionRequest) -> Result<ReporteImportacion, String> {
+    require_permiso(Permiso::EditarPrecios)?;
+    require_permiso(Permiso::AjustarStock)?;
+    let conn = abrir_conexion()?;
+    let (_, reporte) = preparar_reporte(&conn, &payload)?;
+    Ok(reporte)
+}
+
+#[tauri::command]
+pub fn importar_inventario(payload: ImportacionRequest) -> Result<ReporteImportacion, String> {
+    require_permiso(Permiso::EditarPrecios)?;
+    require_permiso(Permiso::AjustarStock)?;
+    let mut conn = abrir_conexion()?;
+    let (productos, mut reporte) = preparar_reporte(&conn, &payload)?;
+
+    if !reporte.errores.is_empty() {
+        return Ok(reporte);
+    }
+
+    let tx = movimientos::transaccion_stock(&mut conn)?;
+
+    for producto in &productos {
+        if producto.existente {
+            let (anterior, precio_anterior): (f64, f64) = tx
+                .query_row(
+                    "SELECT COALESCE(CAST(cantidad_producto AS REAL), 0), CAST(precio_producto AS REAL) \
+                     FROM inventario WHERE id = ?1",
+                    rusqlite::params![producto.id],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .map_err(|e| format!("Error al leer el producto {}: {}", producto.id, e))?;
+            if let Some(cantidad) = producto.cantidad.filter(|c| *c != anterior) {
+                let diferencia = redondear_cantidad(cantidad - anterior);
+                ubicaciones::validar_principal(&tx, producto.id, diferencia)
+                    .map_err(|e| format!("Producto {}: {}", producto.id, e))?;
+                movimientos::registrar_movimiento(&tx, producto.id, diferencia, "importacion", None)?;
+            }
+            tx.execute(
+                "UPDATE inventario SET nombre_producto = ?1, precio_producto = ?2, \
+                 cantidad_producto = COALESCE(?3, cantidad_producto) WHERE id = ?4",
+                rusqlite::params![producto.nombre, producto.precio, producto.cantidad, producto.id],
+            )
+            .map_err(|e| format!("Error al actualizar el producto {}: {}", producto.id, e))?;
+            registrar_historial(&tx, producto.id, LISTA_MINORISTA, Some(precio_anterior), producto.precio, "importacion")?;
+        } else {
+            tx.execute(
+                "INSERT INTO inventario (id, nombre_producto, precio_producto, cantidad_producto) VALUES (?1, ?2, ?3, ?4)",
+                rusqlite::params![producto.id, producto.nombre, producto.precio, producto.cantidad.unwrap_or(0.0)],
+            )
+            .map_err(|e| format!("Error al insertar el producto {}: {}", producto.id, e))?;
+            registrar_historial(&tx, producto.id, LISTA_MINORISTA, None, producto.precio, "importacion")?;
+            if let Some(cantidad) = producto.cantidad.filter(|c| *c != 0.0) {
+                movimientos::registrar_movimiento(&tx, producto.id, cantidad, "importacion", None)?;
+            }
+        }
+    }
+
+    tx.commit()
+        .map_err(|e| format!("Error al confirmar la importacion: {}", e))?;
+
+    reporte.aplicado = true;
+    Ok(reporte)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn acepta_los_formatos_de_precio_habituales() {
+        assert_eq!(parse_precio("12.50"), Some(12.5));
+        assert_eq!(parse_precio("12,50"), Some(12.5));
+        assert_eq!(parse_precio(" $ 3 "), Some(3.0));
+        assert_eq!(parse_precio("Bs 7,25"), Some(7.25));
+    }
+
+    #[test]
+    fn distingue_el_separador_de_miles_del_decimal() {
+        assert_eq!(parse_precio("1.234,56"), Some(1234.56));
+        assert_eq!(parse_precio("$ 1,234.56"), Some(1234.56));
+        assert_eq!(parse_precio("1.234.567"), Some(1234567.0));
+        assert_eq!(parse_precio("1,234,567"), Some(1234567.0));
+        assert_eq!(parse_precio("1.234.567,8"), Some(1234567.8));
+    }
+
+    #[test]
+    fn sirve_para_cantidades_fraccionadas() {
+        assert_eq!(parse_precio("0,375"), Some(0.375));
+        assert_eq!(parse_precio("2.5"), Some(2.5));
+        assert_eq!(parse_precio("-1"), Some(-1.0));
+    }
+
+    #[test]
+    fn rechaza_lo_que_no_es_un_numero() {
+        assert_eq!(parse_precio(""), None);
+        assert_eq!(parse_precio("  $ "), None);
+        assert_eq!(parse_precio("doce"), None);
+        assert_eq!(parse_precio("1,2.3,4"), None);
+        assert_eq!(parse_precio("inf"), None);
+    }
+}
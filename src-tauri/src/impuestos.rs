@@ -0,0 +1,269 @@
+// Impuestos (IVA) por producto.
+//
+// Cada producto puede tener una tasa de `impuestos` y un indicador de si su precio ya
+// incluye el impuesto. Un producto sin tasa asignada se vende como exento. Cada linea de
+// venta guarda su base imponible y el impuesto; el recibo y el cierre del dia muestran el
+// desglose por tasa.
+
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+
+use crate::comun::redondear;
+use crate::permisos::{require_permiso, Permiso};
+use crate::{abrir_conexion, auditoria, VentaItem};
+
+#[derive(Serialize, Deserialize)]
+pub struct Impuesto {
+    id: Option<i64>,
+    nombre: String,
+    tasa: f64,
+    activo: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DesgloseImpuesto {
+    pub(crate) etiqueta: String,
+    pub(crate) tasa: f64,
+    pub(crate) base_imponible: f64,
+    pub(crate) impuesto: f64,
+}
+
+pub(crate) fn etiqueta_tasa(tasa: f64) -> String {
+    if tasa == 0.0 {
+        "Exento".to_string()
+    } else {
+        format!("IVA {}%", tasa)
+    }
+}
+
+/// Tasa y modo de precio del producto; sin tasa asignada se considera exento.
+pub(crate) fn tasa_producto(conn: &Connection, producto_id: i64) -> Result<(f64, bool), String> {
+    conn.query_row(
+        "SELECT COALESCE(i.tasa, 0), COALESCE(inv.precio_incluye_impuesto, 1) FROM inventario inv \
+         LEFT JOIN impuestos i ON i.id = inv.impuesto_id WHERE inv.id = ?1",
+        rusqlite::params![producto_id],
+        |row| Ok((row.get::<_, f64>(0)?, row.get::<_, i64>(1)? == 1)),
+    )
+    .map_err(|_| format!("No se encontro el producto {}", producto_id))
+}
+
+/// Separa base e impuesto de una linea cuyo `subtotal` ya tiene aplicados los descuentos.
+/// Con precio sin impuesto, el impuesto se suma al subtotal cobrado.
+pub(crate) fn aplicar_impuesto(linea: &mut VentaItem, tasa: f64, precio_incluye: bool) {
+    let factor = tasa / 100.0;
+    linea.tasa_impuesto = tasa;
+    if precio_incluye {
+        linea.base_imponible = redondear(linea.subtotal / (1.0 + factor));
+        linea.impuesto = redondear(linea.subtotal - linea.base_imponible);
+    } else {
+        linea.base_imponible = linea.subtotal;
+        linea.impuesto = redondear(linea.subtotal * factor);
+        linea.subtotal = redondear(linea.subtotal + linea.impuesto);
+    }
+}
+
+/// Totales de base e impuesto agrupados por tasa, de menor a mayor.
+pub(crate) fn desglosar(ventas: &[VentaItem]) -> Vec<DesgloseImpuesto> {
+    let mut desglose: Vec<DesgloseImpuesto> = Vec::new();
+    for venta in ventas {
+        match desglose.iter_mut().find(|d| d.tasa == venta.tasa_impuesto) {
+            Some(d) => {
+                d.base_imponible = redondear(d.base_imponible + venta.base_imponible);
+                d.impuesto = redondear(d.impuesto + venta.impuesto);
+            }
+            None => desglose.push(DesgloseImpuesto {
+                etiqueta: etiqueta_tasa(venta.tasa_impuesto),
+                tasa: venta.tasa_impuesto,
+                base_imponible: venta.base_imponible,
+                impuesto: venta.impuesto,
+            }),
+        }
+    }
+    desglose.sort_by(|a, b| a.tasa.total_cmp(&b.tasa));
+    desglose
+}
+
+#[tauri::command]
+pub fn listar_impuestos() -> Result<Vec<Impuesto>, String> {
+    require_permiso(Permiso::ConsultarInventario)?;
+    let conn = abrir_conexion()?;
+    let mut stmt = conn
+        .prepare("SELECT id, nombre, tasa, activo FROM impuestos ORDER BY tasa DESC, nombre")
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let impuestos = stmt
+        .query_map([], |row| {
+            Ok(Impuesto {
+                id: row.get(0)?,
+                nombre: row.get(1)?,
+                tasa: row.get(2)?,
+                activo: row.get::<_, i64>(3)? == 1,
+            })
+        })
+        .map_err(|e| format!("Error al leer impuestos: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    Ok(impuestos)
+}
+
+#[tauri::command]
+pub fn guardar_impuesto(impuesto: Impuesto) -> Result<i64, String> {
+    require_permiso(Permiso::AdministrarSistema)?;
+    let nombre = impuesto.nombre.trim();
+    if nombre.is_empty() {
+        return Err("El nombre del impuesto es obligatorio".to_string());
+    }
+    if !impuesto.tasa.is_finite() || impuesto.tasa < 0.0 || impuesto.tasa > 100.0 {
+        return Err("La tasa debe estar entre 0 y 100".to_string());
+    }
+
+    let conn = abrir_conexion()?;
+    let id = match impuesto.id {
+        Some(id) => {
+            let affected = conn
+                .execute(
+                    "UPDATE impuestos SET nombre = ?1, tasa = ?2, activo = ?3 WHERE id = ?4",
+                    rusqlite::params![nombre, impuesto.tasa, impuesto.activo, id],
+                )
+                .map_err(|e| format!("Error al actualizar el impuesto: {}", e))?;
+            if affected == 0 {
+                return Err("No se encontro el impuesto".to_string());
+            }
+            id
+        }
+        None => {
+            conn.execute(
+                "INSERT INTO impuestos (nombre, tasa, activo) VALUES (?1, ?2, ?3)",
+                rusqlite::params![nombre, impuesto.tasa, impuesto.activo],
+            )
+            .map_err(|e| format!("Error al guardar el impuesto: {}", e))?;
+            conn.last_insert_rowid()
+        }
+    };
+
+    auditoria::registrar_auditoria(
+        &conn,
+        "guardar_impuesto",
+        &format!("impuesto:{}", id),
+        None,
+        serde_json::to_value(&impuesto).ok(),
+        true,
+    )?;
+    Ok(id)
+}
+
+/// Asigna la tasa de un producto. `impuesto_id = None` lo deja exento.
+#[tauri::command]
+pub fn asignar_impuesto(producto_id: i64, impuesto_id: Option<i64>, precio_incluye_impuesto: bool) -> Result<(), String> {
+    require_permiso(Permiso::EditarPrecios)?;
+    let conn = abrir_conexion()?;
+    if let Some(impuesto_id) = impuesto_id {
+        let activo: i64 = conn
+            .query_row(
+                "SELECT activo FROM impuestos WHERE id = ?1",
+                rusqlite::params![impuesto_id],
+                |row| row.get(0),
+            )
+            .map_err(|_| format!("No se encontro el impuesto {}", impuesto_id))?;
+        if activo != 1 {
+            return Err("El impuesto esta inactivo".to_string());
+        }
+    }
+
+    let affected = conn
+        .execute(
+            "UPDATE inventario SET impuesto_id = ?1, precio_incluye_impuesto = ?2 WHERE id = ?3",
+            rusqlite::params![impuesto_id, precio_incluye_impuesto, producto_id],
+        )
+        .map_err(|e| format!("Error al actualizar: {}", e))?;
+    if affected == 0 {
+        return Err("No se encontro el registro para actualizar".to_string());
+    }
+
+    auditoria::registrar_auditoria(
+        &conn,
+        "asignar_impuesto",
+        &format!("inventario:{}", producto_id),
+        None,
+        Some(serde_json::json!({
+            "impuesto_id": impuesto_id,
+            "precio_incluye_impuesto": precio_incluye_impuesto
+        })),
+        true,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comun::pruebas;
+
+    fn linea(subtotal: f64, tasa: f64, precio_incluye: bool) -> VentaItem {
+        let mut linea = VentaItem {
+            subtotal,
+            ..Default::default()
+        };
+        aplicar_impuesto(&mut linea, tasa, precio_incluye);
+        linea
+    }
+
+    fn partes(linea: &VentaItem) -> (f64, f64, f64) {
+        (linea.base_imponible, linea.impuesto, linea.subtotal)
+    }
+
+    #[test]
+    fn el_precio_con_iva_se_separa_sin_cambiar_lo_cobrado() {
+        assert_eq!(partes(&linea(116.0, 16.0, true)), (100.0, 16.0, 116.0));
+        // 10 / 1.16 = 8.6206...; el impuesto es lo que falta para no perder el centavo
+        assert_eq!(partes(&linea(10.0, 16.0, true)), (8.62, 1.38, 10.0));
+    }
+
+    #[test]
+    fn el_precio_sin_iva_suma_el_impuesto_al_cobro() {
+        assert_eq!(partes(&linea(100.0, 16.0, false)), (100.0, 16.0, 116.0));
+        // 3.33 * 0.16 = 0.5328
+        assert_eq!(partes(&linea(3.33, 16.0, false)), (3.33, 0.53, 3.86));
+    }
+
+    #[test]
+    fn un_producto_exento_no_lleva_impuesto() {
+        assert_eq!(partes(&linea(45.5, 0.0, true)), (45.5, 0.0, 45.5));
+        assert_eq!(partes(&linea(45.5, 0.0, false)), (45.5, 0.0, 45.5));
+    }
+
+    #[test]
+    fn el_desglose_agrupa_por_tasa_de_menor_a_mayor() {
+        let lineas = [
+            linea(10.0, 16.0, true),
+            linea(5.0, 0.0, true),
+            linea(10.0, 16.0, true),
+            linea(20.0, 8.0, false),
+        ];
+        let desglose: Vec<(String, f64, f64)> = desglosar(&lineas)
+            .into_iter()
+            .map(|d| (d.etiqueta, d.base_imponible, d.impuesto))
+            .collect();
+        assert_eq!(
+            desglose,
+            vec![
+                ("Exento".to_string(), 5.0, 0.0),
+                ("IVA 8%".to_string(), 20.0, 1.6),
+                ("IVA 16%".to_string(), 17.24, 2.76),
+            ]
+        );
+    }
+
+    #[test]
+    fn la_tasa_sale_del_impuesto_del_producto() {
+        let conn = pruebas::base(&[(1, "Pan", "PAN", 1.0), (2, "Queso", "QUE", 1.0)]);
+        conn.execute(
+            "UPDATE inventario SET impuesto_id = (SELECT id FROM impuestos WHERE nombre = 'IVA general'), \
+             precio_incluye_impuesto = 0 WHERE id = 2",
+            [],
+        )
+        .unwrap();
+
+        assert_eq!(tasa_producto(&conn, 1).unwrap(), (0.0, true));
+        assert_eq!(tasa_producto(&conn, 2).unwrap(), (16.0, false));
+        assert!(tasa_producto(&conn, 9).is_err());
+    }
+}
@@ -0,0 +1,394 @@
+// Lotes y fechas de vencimiento.
+//
+// Un producto pasa a controlarse por lotes con su primera compra con lote; desde entonces
+// `cantidad_producto` es siempre la suma de sus lotes. El stock que tenia antes queda en
+// un lote "SIN-LOTE" sin vencimiento. Las salidas consumen primero lo que vence antes
+// (FEFO) y nunca toman lotes vencidos; el vencimiento es el ultimo dia en que se puede
+// vender. Las entradas sin lote (devoluciones, cancelaciones) vuelven al lote vigente que
+// vence primero, que es de donde FEFO las habria sacado.
+
+use chrono::{Duration, Local};
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::catalogo::redondear_cantidad;
+use crate::costos;
+use crate::exportacion::parse_fecha;
+use crate::movimientos::{registrar_movimiento, registrar_movimiento_costeado, transaccion_stock};
+use crate::permisos::{require_permiso, Permiso};
+use crate::{abrir_conexion, auditoria, comun, ubicaciones};
+
+const SIN_LOTE: &str = "SIN-LOTE";
+
+#[derive(Serialize, Deserialize)]
+pub struct LoteCompra {
+    numero: String,
+    /// AAAA-MM-DD; `None` para productos que no vencen
+    vencimiento: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Lote {
+    id: i64,
+    producto_id: i64,
+    nombre: String,
+    numero: String,
+    vencimiento: Option<String>,
+    cantidad: f64,
+    recibido: String,
+    vencido: bool,
+}
+
+fn hoy() -> String {
+    Local::now().format("%Y-%m-%d").to_string()
+}
+
+fn lote_desde_fila(row: &rusqlite::Row) -> rusqlite::Result<Lote> {
+    let vencimiento: Option<String> = row.get(4)?;
+    Ok(Lote {
+        id: row.get(0)?,
+        producto_id: row.get(1)?,
+        nombre: row.get(2)?,
+        numero: row.get(3)?,
+        vencido: vencimiento.as_deref().is_some_and(|v| v < hoy().as_str()),
+        vencimiento,
+        cantidad: row.get(5)?,
+        recibido: row.get(6)?,
+    })
+}
+
+const COLUMNAS_LOTE: &str = "l.id, l.producto_id, i.nombre_producto, l.numero, l.vencimiento, l.cantidad, l.recibido \
+     FROM lotes l JOIN inventario i ON i.id = l.producto_id";
+
+pub(crate) fn controla_lotes(conn: &Connection, producto_id: i64) -> Result<bool, String> {
+    conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM lotes WHERE producto_id = ?1)",
+        rusqlite::params![producto_id],
+        |row| row.get(0),
+    )
+    .map_err(|e| format!("Error en la consulta: {}", e))
+}
+
+fn sumar_a_lote(conn: &Connection, lote_id: i64, cantidad: f64) -> Result<(), String> {
+    conn.execute(
+        "UPDATE lotes SET cantidad = ROUND(cantidad + ?1, 3) WHERE id = ?2",
+        rusqlite::params![cantidad, lote_id],
+    )
+    .map_err(|e| format!("Error al actualizar el lote: {}", e))?;
+    Ok(())
+}
+
+/// Crea el lote o suma al existente con el mismo numero y vencimiento.
+fn guardar_lote(
+    conn: &Connection,
+    producto_id: i64,
+    numero: &str,
+    vencimiento: Option<&str>,
+    cantidad: f64,
+) -> Result<(), String> {
+    let existente: Option<i64> = conn
+        .query_row(
+            "SELECT id FROM lotes WHERE producto_id = ?1 AND numero = ?2 AND vencimiento IS ?3",
+            rusqlite::params![producto_id, numero, vencimiento],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    match existente {
+        Some(lote_id) => sumar_a_lote(conn, lote_id, cantidad),
+        None => conn
+            .execute(
+                "INSERT INTO lotes (producto_id, numero, vencimiento, cantidad, recibido) VALUES (?1, ?2, ?3, ?4, ?5)",
+                rusqlite::params![
+                    producto_id,
+                    numero,
+                    vencimiento,
+                    cantidad,
+                    comun::ahora()
+                ],
+            )
+            .map(|_| ())
+            .map_err(|e| format!("Error al guardar el lote: {}", e)),
+    }
+}
+
+/// Reparte en los lotes un ajuste de stock sin lote indicado. Se llama desde
+/// `movimientos::ajustar_stock` antes de tocar `cantidad_producto`.
+pub(crate) fn repartir_en_lotes(conn: &Connection, producto_id: i64, cantidad: f64) -> Result<(), String> {
+    let hoy = hoy();
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, cantidad FROM lotes WHERE producto_id = ?1 AND (vencimiento IS NULL OR vencimiento >= ?2) \
+             ORDER BY vencimiento IS NULL, vencimiento, id",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let vigentes = stmt
+        .query_map(rusqlite::params![producto_id, hoy], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, f64>(1)?)))
+        .map_err(|e| format!("Error al leer lotes: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+
+    if cantidad > 0.0 {
+        return match vigentes.first() {
+            Some((lote_id, _)) => sumar_a_lote(conn, *lote_id, cantidad),
+            None => guardar_lote(conn, producto_id, SIN_LOTE, None, cantidad),
+        };
+    }
+
+    let disponible: f64 = vigentes.iter().map(|(_, c)| c).sum();
+    let pedida = -cantidad;
+    if redondear_cantidad(disponible) < pedida {
+        let vencido: f64 = conn
+            .query_row(
+                "SELECT COALESCE(SUM(cantidad), 0) FROM lotes WHERE producto_id = ?1 AND vencimiento < ?2",
+                rusqlite::params![producto_id, hoy],
+                |row| row.get(0),
+            )
+            .map_err(|e| format!("Error en la consulta: {}", e))?;
+        return Err(format!(
+            "Stock insuficiente. Disponible: {} (vencido: {})",
+            redondear_cantidad(disponible),
+            redondear_cantidad(vencido)
+        ));
+    }
+
+    let mut restante = pedida;
+    for (lote_id, en_lote) in vigentes {
+        if restante <= 0.0 {
+            break;
+        }
+        let tomada = en_lote.min(restante);
+        if tomada > 0.0 {
+            sumar_a_lote(conn, lote_id, -tomada)?;
+            restante = redondear_cantidad(restante - tomada);
+        }
+    }
+    Ok(())
+}
+
+/// Entrada de compra en un lote. El stock previo del producto, si no tenia lotes,
+/// queda en el lote "SIN-LOTE".
+pub(crate) fn recibir_lote(
+    conn: &Connection,
+    producto_id: i64,
+    lote: &LoteCompra,
+    cantidad: f64,
+    costo: Option<f64>,
+    ubicacion_id: i64,
+) -> Result<f64, String> {
+    let numero = lote.numero.trim().to_uppercase();
+    if numero.is_empty() {
+        return Err("El numero de lote es obligatorio".to_string());
+    }
+    let vencimiento = match lote.vencimiento.as_deref().map(str::trim).filter(|v| !v.is_empty()) {
+        Some(v) => Some(parse_fecha(v)?.to_string()),
+        None => None,
+    };
+    if vencimiento.as_deref().is_some_and(|v| v < hoy().as_str()) {
+        return Err(format!("El lote {} ya esta vencido", numero));
+    }
+
+    let actual: f64 = conn
+        .query_row(
+            "SELECT COALESCE(CAST(cantidad_producto AS REAL), 0) FROM inventario WHERE id = ?1",
+            rusqlite::params![producto_id],
+            |row| row.get(0),
+        )
+        .map_err(|e| format!("No se encontro el producto: {}", e))?;
+    if !controla_lotes(conn, producto_id)? && actual > 0.0 {
+        guardar_lote(conn, producto_id, SIN_LOTE, None, actual)?;
+    }
+    guardar_lote(conn, producto_id, &numero, vencimiento.as_deref(), cantidad)?;
+    if let Some(costo) = costo {
+        costos::actualizar_promedio(conn, producto_id, actual, cantidad, costo)?;
+    }
+
+    let nueva = redondear_cantidad(actual + cantidad);
+    conn.execute(
+        "UPDATE inventario SET cantidad_producto = ?1 WHERE id = ?2",
+        rusqlite::params![nueva, producto_id],
+    )
+    .map_err(|e| format!("Error al actualizar: {}", e))?;
+    ubicaciones::sumar_en(conn, producto_id, ubicacion_id, cantidad)?;
+    let referencia = format!("lote {}", numero);
+    registrar_movimiento_costeado(conn, producto_id, cantidad, costo, ubicacion_id, "compra", Some(&referencia))?;
+    Ok(nueva)
+}
+
+#[tauri::command]
+pub fn listar_lotes(producto_id: i64) -> Result<Vec<Lote>, String> {
+    require_permiso(Permiso::ConsultarInventario)?;
+    let conn = abrir_conexion()?;
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {} WHERE l.producto_id = ?1 AND l.cantidad > 0 \
+             ORDER BY l.vencimiento IS NULL, l.vencimiento, l.id",
+            COLUMNAS_LOTE
+        ))
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let lotes = stmt
+        .query_map(rusqlite::params![producto_id], lote_desde_fila)
+        .map_err(|e| format!("Error al leer lotes: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    Ok(lotes)
+}
+
+/// Lotes con stock que vencen dentro de `dias` dias, incluidos los ya vencidos.
+#[tauri::command]
+pub fn lotes_por_vencer(dias: i64) -> Result<Vec<Lote>, String> {
+    require_permiso(Permiso::ConsultarInventario)?;
+    if dias < 0 {
+        return Err("Los dias no pueden ser negativos".to_string());
+    }
+    let limite = (Local::now().date_naive() + Duration::days(dias)).to_string();
+    let conn = abrir_conexion()?;
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {} WHERE l.cantidad > 0 AND l.vencimiento IS NOT NULL AND l.vencimiento <= ?1 \
+             ORDER BY l.vencimiento, i.nombre_producto",
+            COLUMNAS_LOTE
+        ))
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let lotes = stmt
+        .query_map(rusqlite::params![limite], lote_desde_fila)
+        .map_err(|e| format!("Error al leer lotes: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    Ok(lotes)
+}
+
+/// Fija la cantidad de un lote, por ejemplo para dar de baja lo vencido o corregir un conteo.
+#[tauri::command]
+pub fn ajustar_lote(lote_id: i64, cantidad: f64, motivo: Option<String>) -> Result<(), String> {
+    require_permiso(Permiso::AjustarStock)?;
+    let cantidad = redondear_cantidad(cantidad);
+    if !cantidad.is_finite() || cantidad < 0.0 {
+        return Err("La cantidad no puede ser negativa".to_string());
+    }
+    let mut conn = abrir_conexion()?;
+    // La cantidad anterior se lee con la base bloqueada: una venta en otra caja la cambia
+    let tx = transaccion_stock(&mut conn)?;
+    let (producto_id, numero, anterior): (i64, String, f64) = tx
+        .query_row(
+            "SELECT producto_id, numero, cantidad FROM lotes WHERE id = ?1",
+            rusqlite::params![lote_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()
+        .map_err(|e| format!("Error en la consulta: {}", e))?
+        .ok_or_else(|| "No se encontro el lote".to_string())?;
+    let diferencia = redondear_cantidad(cantidad - anterior);
+    if diferencia == 0.0 {
+        return Ok(());
+    }
+    let motivo = motivo
+        .as_deref()
+        .map(str::trim)
+        .filter(|m| !m.is_empty())
+        .unwrap_or("ajuste")
+        .to_string();
+
+    ubicaciones::validar_principal(&tx, producto_id, diferencia)?;
+    tx.execute(
+        "UPDATE lotes SET cantidad = ?1 WHERE id = ?2",
+        rusqlite::params![cantidad, lote_id],
+    )
+    .map_err(|e| format!("Error al actualizar el lote: {}", e))?;
+    tx.execute(
+        "UPDATE inventario SET cantidad_producto = ROUND(COALESCE(CAST(cantidad_producto AS REAL), 0) + ?1, 3) \
+         WHERE id = ?2",
+        rusqlite::params![diferencia, producto_id],
+    )
+    .map_err(|e| format!("Error al actualizar: {}", e))?;
+    registrar_movimiento(&tx, producto_id, diferencia, &motivo, Some(&format!("lote {}", numero)))?;
+    auditoria::registrar_auditoria(
+        &tx,
+        "ajustar_lote",
+        &format!("lote:{}", lote_id),
+        Some(serde_json::json!({ "cantidad": anterior })),
+        Some(serde_json::json!({ "cantidad": cantidad, "motivo": motivo })),
+        true,
+    )?;
+    tx.commit()
+        .map_err(|e| format!("Error al confirmar: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comun::pruebas;
+
+    fn base() -> Connection {
+        pruebas::base(&[(1, "Yogur", "YOG", 0.0)])
+    }
+
+    fn dias(desde_hoy: i64) -> String {
+        (Local::now() + Duration::days(desde_hoy)).format("%Y-%m-%d").to_string()
+    }
+
+    fn cantidades(conn: &Connection) -> Vec<(String, f64)> {
+        let mut stmt = conn
+            .prepare("SELECT numero, cantidad FROM lotes WHERE producto_id = 1 ORDER BY numero")
+            .unwrap();
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn las_salidas_consumen_primero_lo_que_vence_antes() {
+        let conn = base();
+        guardar_lote(&conn, 1, "B", Some(&dias(20)), 5.0).unwrap();
+        guardar_lote(&conn, 1, "A", Some(&dias(3)), 4.0).unwrap();
+        guardar_lote(&conn, 1, "C", None, 10.0).unwrap();
+
+        repartir_en_lotes(&conn, 1, -6.0).unwrap();
+
+        assert_eq!(
+            cantidades(&conn),
+            vec![("A".to_string(), 0.0), ("B".to_string(), 3.0), ("C".to_string(), 10.0)]
+        );
+    }
+
+    #[test]
+    fn los_lotes_vencidos_no_cuentan_como_disponibles() {
+        let conn = base();
+        guardar_lote(&conn, 1, "VIEJO", Some(&dias(-1)), 8.0).unwrap();
+        guardar_lote(&conn, 1, "NUEVO", Some(&dias(10)), 2.0).unwrap();
+
+        let error = repartir_en_lotes(&conn, 1, -3.0).unwrap_err();
+
+        assert_eq!(error, "Stock insuficiente. Disponible: 2 (vencido: 8)");
+        assert_eq!(cantidades(&conn), vec![("NUEVO".to_string(), 2.0), ("VIEJO".to_string(), 8.0)]);
+    }
+
+    #[test]
+    fn las_entradas_vuelven_al_lote_vigente_que_vence_primero() {
+        let conn = base();
+        guardar_lote(&conn, 1, "VIEJO", Some(&dias(-1)), 1.0).unwrap();
+        guardar_lote(&conn, 1, "B", Some(&dias(20)), 1.0).unwrap();
+        guardar_lote(&conn, 1, "A", Some(&dias(5)), 1.0).unwrap();
+
+        repartir_en_lotes(&conn, 1, 2.5).unwrap();
+
+        assert_eq!(
+            cantidades(&conn),
+            vec![("A".to_string(), 3.5), ("B".to_string(), 1.0), ("VIEJO".to_string(), 1.0)]
+        );
+    }
+
+    #[test]
+    fn sin_lotes_vigentes_la_entrada_va_a_sin_lote() {
+        let conn = base();
+        guardar_lote(&conn, 1, "VIEJO", Some(&dias(-1)), 1.0).unwrap();
+
+        repartir_en_lotes(&conn, 1, 4.0).unwrap();
+
+        assert_eq!(
+            cantidades(&conn),
+            vec![(SIN_LOTE.to_string(), 4.0), ("VIEJO".to_string(), 1.0)]
+        );
+    }
+}
@@ -9,9 +9,38 @@ use std::path::PathBuf;
 use std::env;
 use std::sync::OnceLock;
 use chrono::Local;
+use rand::Rng;
 use printpdf::*;
 use dirs;
 use tauri_plugin_opener;
+use permisos::{require_permiso, Permiso};
+
+mod api;
+mod auditoria;
+mod catalogo;
+mod clientes;
+mod combos;
+mod comun;
+mod conteos;
+mod costos;
+mod cuentas;
+mod eventos;
+mod exportacion;
+mod importacion;
+mod impuestos;
+mod lotes;
+mod movimientos;
+mod permisos;
+mod precios;
+mod promociones;
+mod respaldo;
+mod seguridad;
+mod series;
+mod sincronizacion;
+mod terminales;
+mod ubicaciones;
+mod variantes;
+mod ventas;
 
 static INIT_DB: OnceLock<Result<(), String>> = OnceLock::new();
 
@@ -59,52 +88,604 @@ fn ensure_db_initialized() -> Result<(), String> {
             }
         }
 
-        let conn = Connection::open(&db_path)
+        let mut conn = Connection::open(&db_path)
             .map_err(|e| format!("Error al abrir base de datos: {} (ruta={})", e, db_path.display()))?;
 
-        // Crear tablas si no existen
-        conn.execute_batch(
-            r#"
-            CREATE TABLE IF NOT EXISTS "users" (
-                "name" TEXT NOT NULL UNIQUE,
-                "password" TEXT NOT NULL UNIQUE,
-                "correo electronico" TEXT NOT NULL UNIQUE,
-                "Admin" INTEGER NOT NULL,
-                PRIMARY KEY("name","password","correo electronico","Admin")
-            );
+        // Con WAL las lecturas de una caja no esperan a la escritura de otra; el esquema se
+        // crea con la base bloqueada por si dos procesos arrancan a la vez
+        conn.pragma_update(None, "journal_mode", "WAL")
+            .map_err(|e| format!("Error al configurar la base de datos: {}", e))?;
+        let tx = movimientos::transaccion_stock(&mut conn)?;
+        crear_tablas(&tx)?;
+        tx.commit()
+            .map_err(|e| format!("Error al crear las tablas: {}", e))
+    });
 
-            CREATE TABLE IF NOT EXISTS "inventario" (
-                "id" INTEGER NOT NULL UNIQUE,
-                "nombre_producto" TEXT NOT NULL,
-                "precio_producto" TEXT NOT NULL,
-                "cantidad_producto" TEXT,
-                PRIMARY KEY("id","nombre_producto")
-            );
-            "#,
+    result.clone()
+}
+
+/// Crea las tablas que falten y el usuario por defecto. Tambien se usa tras restaurar un respaldo.
+fn crear_tablas(conn: &Connection) -> Result<(), String> {
+    // Crear tablas si no existen
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS "users" (
+            "name" TEXT NOT NULL PRIMARY KEY,
+            "password" TEXT NOT NULL,
+            "correo electronico" TEXT NOT NULL UNIQUE,
+            "Admin" INTEGER NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS "inventario" (
+            "id" INTEGER NOT NULL UNIQUE,
+            "nombre_producto" TEXT NOT NULL,
+            "precio_producto" TEXT NOT NULL,
+            "cantidad_producto" TEXT,
+            PRIMARY KEY("id","nombre_producto")
+        );
+
+        CREATE TABLE IF NOT EXISTS "ventas" (
+            "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+            "fecha" TEXT NOT NULL,
+            "producto_id" INTEGER NOT NULL,
+            "nombre_producto" TEXT NOT NULL,
+            "precio" REAL NOT NULL,
+            "cantidad" INTEGER NOT NULL,
+            "subtotal" REAL NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS "tickets" (
+            "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+            "numero_recibo" TEXT NOT NULL UNIQUE,
+            "fecha" TEXT NOT NULL,
+            "total" REAL NOT NULL,
+            "usuario" TEXT NOT NULL,
+            "estado" TEXT NOT NULL DEFAULT 'completada'
+        );
+
+        CREATE TABLE IF NOT EXISTS "sesiones_terminal" (
+            "token" TEXT PRIMARY KEY,
+            "terminal" TEXT NOT NULL,
+            "usuario" TEXT NOT NULL,
+            "creada" TEXT NOT NULL,
+            "ultimo_uso" INTEGER NOT NULL
+        );
+
+        -- Sesion de la app de escritorio; el archivo de sesion solo guarda el token
+        CREATE TABLE IF NOT EXISTS "sesiones_locales" (
+            "token" TEXT PRIMARY KEY,
+            "usuario" TEXT NOT NULL,
+            "creada" TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS "carrito" (
+            "caja" TEXT NOT NULL,
+            "producto_id" INTEGER NOT NULL REFERENCES "inventario"("id"),
+            "ubicacion_id" INTEGER NOT NULL,
+            "cantidad" REAL NOT NULL,
+            PRIMARY KEY("caja", "producto_id", "ubicacion_id")
+        );
+
+        CREATE TABLE IF NOT EXISTS "categorias" (
+            "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+            "nombre" TEXT NOT NULL,
+            "padre_id" INTEGER REFERENCES "categorias"("id")
+        );
+
+        CREATE TABLE IF NOT EXISTS "marcas" (
+            "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+            "nombre" TEXT NOT NULL UNIQUE
+        );
+
+        CREATE TABLE IF NOT EXISTS "productos_padre" (
+            "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+            "nombre" TEXT NOT NULL UNIQUE,
+            "precio" REAL NOT NULL,
+            "categoria_id" INTEGER REFERENCES "categorias"("id"),
+            "marca_id" INTEGER REFERENCES "marcas"("id"),
+            "unidad" TEXT NOT NULL DEFAULT 'unidad'
+        );
+
+        CREATE TABLE IF NOT EXISTS "variantes_atributos" (
+            "producto_id" INTEGER NOT NULL,
+            "atributo" TEXT NOT NULL,
+            "valor" TEXT NOT NULL,
+            PRIMARY KEY("producto_id", "atributo")
+        );
+
+        CREATE TABLE IF NOT EXISTS "componentes" (
+            "producto_id" INTEGER NOT NULL,
+            "componente_id" INTEGER NOT NULL,
+            "cantidad" REAL NOT NULL,
+            PRIMARY KEY("producto_id", "componente_id")
+        );
+
+        CREATE TABLE IF NOT EXISTS "lotes" (
+            "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+            "producto_id" INTEGER NOT NULL REFERENCES "inventario"("id"),
+            "numero" TEXT NOT NULL,
+            "vencimiento" TEXT,
+            "cantidad" REAL NOT NULL,
+            "recibido" TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS "series" (
+            "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+            "producto_id" INTEGER NOT NULL REFERENCES "inventario"("id"),
+            "serie" TEXT NOT NULL,
+            "estado" TEXT NOT NULL DEFAULT 'en_stock',
+            "venta_id" INTEGER REFERENCES "ventas"("id"),
+            UNIQUE("producto_id", "serie")
+        );
+
+        CREATE TABLE IF NOT EXISTS "series_historial" (
+            "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+            "serie_id" INTEGER NOT NULL REFERENCES "series"("id"),
+            "fecha" TEXT NOT NULL,
+            "evento" TEXT NOT NULL,
+            "venta_id" INTEGER,
+            "referencia" TEXT,
+            "usuario" TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS "promociones" (
+            "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+            "nombre" TEXT NOT NULL,
+            "tipo" TEXT NOT NULL,
+            "producto_id" INTEGER,
+            "categoria" TEXT,
+            "cantidad_minima" INTEGER,
+            "porcentaje" REAL,
+            "desde" TEXT,
+            "hasta" TEXT,
+            "activa" INTEGER NOT NULL DEFAULT 1
+        );
+
+        CREATE TABLE IF NOT EXISTS "listas_precios" (
+            "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+            "nombre" TEXT NOT NULL UNIQUE,
+            "ajuste" REAL NOT NULL DEFAULT 0
+        );
+
+        INSERT OR IGNORE INTO "listas_precios" ("id", "nombre") VALUES
+            (1, 'Minorista'),
+            (2, 'Mayorista'),
+            (3, 'Empleado');
+
+        CREATE TABLE IF NOT EXISTS "precios_lista" (
+            "lista_id" INTEGER NOT NULL REFERENCES "listas_precios"("id"),
+            "producto_id" INTEGER NOT NULL REFERENCES "inventario"("id"),
+            "precio" REAL NOT NULL,
+            PRIMARY KEY ("lista_id", "producto_id")
+        );
+
+        CREATE TABLE IF NOT EXISTS "precios_programados" (
+            "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+            "lista_id" INTEGER NOT NULL REFERENCES "listas_precios"("id"),
+            "producto_id" INTEGER NOT NULL REFERENCES "inventario"("id"),
+            "precio" REAL NOT NULL,
+            "desde" TEXT NOT NULL,
+            "creado" TEXT NOT NULL,
+            "usuario" TEXT NOT NULL,
+            "aplicado" TEXT
+        );
+
+        CREATE TABLE IF NOT EXISTS "historial_precios" (
+            "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+            "producto_id" INTEGER NOT NULL REFERENCES "inventario"("id"),
+            "lista_id" INTEGER NOT NULL REFERENCES "listas_precios"("id"),
+            "fecha" TEXT NOT NULL,
+            "anterior" REAL,
+            "nuevo" REAL NOT NULL,
+            "origen" TEXT NOT NULL,
+            "usuario" TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS "impuestos" (
+            "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+            "nombre" TEXT NOT NULL UNIQUE,
+            "tasa" REAL NOT NULL,
+            "activo" INTEGER NOT NULL DEFAULT 1
+        );
+
+        CREATE TABLE IF NOT EXISTS "tickets_impuestos" (
+            "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+            "ticket_id" INTEGER NOT NULL REFERENCES "tickets"("id"),
+            "tasa" REAL NOT NULL,
+            "base_imponible" REAL NOT NULL,
+            "impuesto" REAL NOT NULL
+        );
+
+        INSERT OR IGNORE INTO "impuestos" ("nombre", "tasa") VALUES
+            ('IVA general', 16.0),
+            ('IVA reducido', 8.0),
+            ('Exento', 0.0);
+
+        CREATE TABLE IF NOT EXISTS "clientes" (
+            "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+            "documento" TEXT NOT NULL UNIQUE,
+            "tipo" TEXT NOT NULL DEFAULT 'persona',
+            "nombre" TEXT NOT NULL,
+            "telefono" TEXT,
+            "direccion" TEXT,
+            "creado" TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS "cuentas_movimientos" (
+            "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+            "cliente_id" INTEGER NOT NULL REFERENCES "clientes"("id"),
+            "fecha" TEXT NOT NULL,
+            "tipo" TEXT NOT NULL,
+            "monto" REAL NOT NULL,
+            "ticket_id" INTEGER REFERENCES "tickets"("id"),
+            "referencia" TEXT,
+            "usuario" TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS "notas_credito" (
+            "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+            "numero" TEXT NOT NULL UNIQUE,
+            "ticket_id" INTEGER NOT NULL REFERENCES "tickets"("id"),
+            "fecha" TEXT NOT NULL,
+            "tipo" TEXT NOT NULL,
+            "motivo" TEXT NOT NULL,
+            "total" REAL NOT NULL,
+            "usuario" TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS "notas_credito_detalle" (
+            "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+            "nota_id" INTEGER NOT NULL REFERENCES "notas_credito"("id"),
+            "venta_id" INTEGER NOT NULL,
+            "producto_id" INTEGER NOT NULL,
+            "cantidad" INTEGER NOT NULL,
+            "subtotal" REAL NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS "movimientos_stock" (
+            "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+            "fecha" TEXT NOT NULL,
+            "producto_id" INTEGER NOT NULL,
+            "cantidad" INTEGER NOT NULL,
+            "motivo" TEXT NOT NULL,
+            "referencia" TEXT,
+            "usuario" TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS "ubicaciones" (
+            "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+            "nombre" TEXT NOT NULL UNIQUE,
+            "activa" INTEGER NOT NULL DEFAULT 1
+        );
+
+        INSERT OR IGNORE INTO "ubicaciones" ("id", "nombre") VALUES (1, 'Principal');
+
+        CREATE TABLE IF NOT EXISTS "stock_ubicacion" (
+            "producto_id" INTEGER NOT NULL REFERENCES "inventario"("id"),
+            "ubicacion_id" INTEGER NOT NULL REFERENCES "ubicaciones"("id"),
+            "cantidad" REAL NOT NULL DEFAULT 0,
+            PRIMARY KEY ("producto_id", "ubicacion_id")
+        );
+
+        CREATE TABLE IF NOT EXISTS "traslados" (
+            "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+            "origen_id" INTEGER NOT NULL REFERENCES "ubicaciones"("id"),
+            "destino_id" INTEGER NOT NULL REFERENCES "ubicaciones"("id"),
+            "estado" TEXT NOT NULL DEFAULT 'en_transito',
+            "nota" TEXT,
+            "enviado" TEXT NOT NULL,
+            "enviado_por" TEXT NOT NULL,
+            "cerrado" TEXT,
+            "cerrado_por" TEXT
+        );
+
+        CREATE TABLE IF NOT EXISTS "traslados_detalle" (
+            "traslado_id" INTEGER NOT NULL REFERENCES "traslados"("id"),
+            "producto_id" INTEGER NOT NULL REFERENCES "inventario"("id"),
+            "cantidad" REAL NOT NULL,
+            "costo_unitario" REAL NOT NULL DEFAULT 0,
+            PRIMARY KEY ("traslado_id", "producto_id")
+        );
+
+        CREATE TABLE IF NOT EXISTS "conteos" (
+            "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+            "descripcion" TEXT,
+            "filtro" TEXT,
+            "iniciado" TEXT NOT NULL,
+            "usuario" TEXT NOT NULL,
+            "estado" TEXT NOT NULL DEFAULT 'abierto',
+            "cerrado" TEXT,
+            "cerrado_por" TEXT
+        );
+
+        CREATE TABLE IF NOT EXISTS "conteos_detalle" (
+            "conteo_id" INTEGER NOT NULL REFERENCES "conteos"("id"),
+            "producto_id" INTEGER NOT NULL REFERENCES "inventario"("id"),
+            "esperado" REAL NOT NULL,
+            "costo_unitario" REAL NOT NULL DEFAULT 0,
+            PRIMARY KEY ("conteo_id", "producto_id")
+        );
+
+        CREATE TABLE IF NOT EXISTS "conteos_registros" (
+            "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+            "conteo_id" INTEGER NOT NULL REFERENCES "conteos"("id"),
+            "producto_id" INTEGER NOT NULL REFERENCES "inventario"("id"),
+            "cantidad" REAL NOT NULL,
+            "usuario" TEXT NOT NULL,
+            "fecha" TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS "eventos" (
+            "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+            "tipo" TEXT NOT NULL,
+            "fecha" TEXT NOT NULL,
+            "datos" TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS "webhooks" (
+            "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+            "url" TEXT NOT NULL,
+            "eventos" TEXT NOT NULL DEFAULT '*',
+            "secreto" TEXT,
+            "activo" INTEGER NOT NULL DEFAULT 1,
+            "creado" TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS "entregas_webhook" (
+            "evento_id" INTEGER NOT NULL REFERENCES "eventos"("id"),
+            "webhook_id" INTEGER NOT NULL REFERENCES "webhooks"("id"),
+            "estado" TEXT NOT NULL DEFAULT 'pendiente',
+            "intentos" INTEGER NOT NULL DEFAULT 0,
+            "proximo_intento" TEXT NOT NULL,
+            "entregado" TEXT,
+            "ultimo_error" TEXT,
+            PRIMARY KEY ("evento_id", "webhook_id")
+        );
+
+        CREATE TABLE IF NOT EXISTS "api_tokens" (
+            "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+            "nombre" TEXT NOT NULL UNIQUE,
+            "rol" TEXT NOT NULL,
+            "hash" TEXT NOT NULL UNIQUE,
+            "creado" TEXT NOT NULL,
+            "ultimo_uso" TEXT,
+            "revocado" INTEGER NOT NULL DEFAULT 0
+        );
+
+        CREATE TABLE IF NOT EXISTS "sync_sucursal" (
+            "id" INTEGER PRIMARY KEY CHECK ("id" = 1),
+            "codigo" TEXT NOT NULL,
+            "nombre" TEXT NOT NULL,
+            "central" INTEGER NOT NULL DEFAULT 0,
+            "aplicando" INTEGER NOT NULL DEFAULT 0
+        );
+
+        CREATE TABLE IF NOT EXISTS "sync_salida" (
+            "seq" INTEGER PRIMARY KEY AUTOINCREMENT,
+            "uid" TEXT UNIQUE,
+            "origen" TEXT NOT NULL,
+            "tipo" TEXT NOT NULL,
+            "fecha" TEXT NOT NULL,
+            "datos" TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS "sync_recibidos" (
+            "uid" TEXT PRIMARY KEY,
+            "origen" TEXT NOT NULL,
+            "recibido" TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS "sync_versiones" (
+            "producto_id" INTEGER NOT NULL,
+            "campo" TEXT NOT NULL,
+            "fecha" TEXT NOT NULL,
+            "origen" TEXT NOT NULL,
+            PRIMARY KEY ("producto_id", "campo")
+        );
+
+        CREATE TABLE IF NOT EXISTS "sync_productos" (
+            "uid" TEXT PRIMARY KEY,
+            "producto_id" INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS idx_sync_productos_producto ON sync_productos(producto_id);
+
+        CREATE TABLE IF NOT EXISTS "sync_pares" (
+            "codigo" TEXT PRIMARY KEY,
+            "nombre" TEXT,
+            "enviado_hasta" INTEGER NOT NULL DEFAULT 0,
+            "recibido_hasta" INTEGER NOT NULL DEFAULT 0,
+            "ultima" TEXT
+        );
+
+        CREATE TABLE IF NOT EXISTS "movimientos_sucursales" (
+            "uid" TEXT PRIMARY KEY,
+            "sucursal" TEXT NOT NULL,
+            "fecha" TEXT NOT NULL,
+            "producto_id" INTEGER NOT NULL,
+            "cantidad" REAL NOT NULL,
+            "motivo" TEXT NOT NULL,
+            "costo_unitario" REAL
+        );
+
+        CREATE TABLE IF NOT EXISTS "ventas_sucursales" (
+            "sucursal" TEXT NOT NULL,
+            "venta_id" INTEGER NOT NULL,
+            "numero_recibo" TEXT,
+            "fecha" TEXT NOT NULL,
+            "producto_id" INTEGER NOT NULL,
+            "nombre_producto" TEXT NOT NULL,
+            "cantidad" REAL NOT NULL,
+            "cantidad_devuelta" REAL NOT NULL DEFAULT 0,
+            "subtotal" REAL NOT NULL,
+            "costo_unitario" REAL,
+            PRIMARY KEY ("sucursal", "venta_id")
+        );
+
+        CREATE TABLE IF NOT EXISTS "auditoria" (
+            "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+            "fecha" TEXT NOT NULL,
+            "usuario" TEXT NOT NULL,
+            "accion" TEXT NOT NULL,
+            "entidad" TEXT NOT NULL,
+            "antes" TEXT,
+            "despues" TEXT,
+            "exito" INTEGER NOT NULL,
+            "hash_anterior" TEXT NOT NULL,
+            "hash" TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS "intentos_acceso" (
+            "clave" TEXT PRIMARY KEY,
+            "fallos" INTEGER NOT NULL DEFAULT 0,
+            "ultimo_fallo" INTEGER NOT NULL DEFAULT 0,
+            "espera_hasta" INTEGER NOT NULL DEFAULT 0,
+            "bloqueado_hasta" INTEGER NOT NULL DEFAULT 0
+        );
+
+        -- La auditoria es de solo anexado
+        CREATE TRIGGER IF NOT EXISTS "auditoria_sin_update" BEFORE UPDATE ON "auditoria"
+        BEGIN
+            SELECT RAISE(ABORT, 'La auditoria no se puede modificar');
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS "auditoria_sin_delete" BEFORE DELETE ON "auditoria"
+        BEGIN
+            SELECT RAISE(ABORT, 'La auditoria no se puede modificar');
+        END;
+        "#,
+    )
+    .map_err(|e| format!("Error al crear tablas: {}", e))?;
+
+    // Roles: los usuarios previos heredan el rol segun su columna Admin
+    agregar_columna_si_falta(conn, "users", "rol", "TEXT")?;
+    conn.execute(
+        "UPDATE users SET rol = CASE WHEN Admin = 1 THEN 'admin' ELSE 'cajero' END WHERE rol IS NULL",
+        [],
+    )
+    .map_err(|e| format!("Error al asignar roles: {}", e))?;
+    agregar_columna_si_falta(conn, "users", "debe_cambiar_password", "INTEGER NOT NULL DEFAULT 0")?;
+    quitar_password_unica(conn)?;
+    agregar_columna_si_falta(conn, "ventas", "ticket_id", "INTEGER REFERENCES tickets(id)")?;
+    agregar_columna_si_falta(conn, "ventas", "cantidad_devuelta", "INTEGER NOT NULL DEFAULT 0")?;
+    agregar_columna_si_falta(conn, "ventas", "descuento", "REAL NOT NULL DEFAULT 0")?;
+    agregar_columna_si_falta(conn, "ventas", "promocion", "TEXT")?;
+    agregar_columna_si_falta(conn, "tickets", "descuento", "REAL NOT NULL DEFAULT 0")?;
+    agregar_columna_si_falta(conn, "tickets", "cliente_id", "INTEGER REFERENCES clientes(id)")?;
+    agregar_columna_si_falta(conn, "tickets", "a_credito", "INTEGER NOT NULL DEFAULT 0")?;
+    agregar_columna_si_falta(conn, "clientes", "limite_credito", "REAL NOT NULL DEFAULT 0")?;
+    agregar_columna_si_falta(conn, "clientes", "lista_precios_id", "INTEGER REFERENCES listas_precios(id)")?;
+    // `categoria` (texto libre) quedo reemplazada por `categoria_id`; ver catalogo::migrar_categorias_texto
+    agregar_columna_si_falta(conn, "inventario", "categoria", "TEXT")?;
+    agregar_columna_si_falta(conn, "inventario", "categoria_id", "INTEGER REFERENCES categorias(id)")?;
+    agregar_columna_si_falta(conn, "inventario", "marca_id", "INTEGER REFERENCES marcas(id)")?;
+    agregar_columna_si_falta(conn, "inventario", "unidad", "TEXT NOT NULL DEFAULT 'unidad'")?;
+    agregar_columna_si_falta(conn, "promociones", "categoria_id", "INTEGER REFERENCES categorias(id)")?;
+    catalogo::migrar_categorias_texto(conn)?;
+    agregar_columna_si_falta(conn, "inventario", "padre_id", "INTEGER REFERENCES productos_padre(id)")?;
+    agregar_columna_si_falta(conn, "inventario", "sku", "TEXT")?;
+    agregar_columna_si_falta(conn, "inventario", "codigo_barras", "TEXT")?;
+    agregar_columna_si_falta(conn, "inventario", "hereda_precio", "INTEGER NOT NULL DEFAULT 0")?;
+    agregar_columna_si_falta(conn, "inventario", "serializado", "INTEGER NOT NULL DEFAULT 0")?;
+    agregar_columna_si_falta(conn, "inventario", "costo_promedio", "REAL NOT NULL DEFAULT 0")?;
+    agregar_columna_si_falta(conn, "movimientos_stock", "costo_unitario", "REAL")?;
+    agregar_columna_si_falta(conn, "ventas", "costo_unitario", "REAL")?;
+    agregar_columna_si_falta(conn, "movimientos_stock", "ubicacion_id", "INTEGER NOT NULL DEFAULT 1")?;
+    agregar_columna_si_falta(conn, "ventas", "ubicacion_id", "INTEGER NOT NULL DEFAULT 1")?;
+    agregar_columna_si_falta(conn, "conteos", "ubicacion_id", "INTEGER NOT NULL DEFAULT 1")?;
+    conn.execute_batch(
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_inventario_sku ON inventario(sku); \
+         CREATE UNIQUE INDEX IF NOT EXISTS idx_inventario_codigo_barras ON inventario(codigo_barras);",
+    )
+    .map_err(|e| format!("Error al crear indices: {}", e))?;
+    agregar_columna_si_falta(conn, "inventario", "impuesto_id", "INTEGER REFERENCES impuestos(id)")?;
+    agregar_columna_si_falta(conn, "inventario", "precio_incluye_impuesto", "INTEGER NOT NULL DEFAULT 1")?;
+    agregar_columna_si_falta(conn, "ventas", "tasa_impuesto", "REAL NOT NULL DEFAULT 0")?;
+    agregar_columna_si_falta(conn, "ventas", "base_imponible", "REAL")?;
+    agregar_columna_si_falta(conn, "ventas", "impuesto", "REAL NOT NULL DEFAULT 0")?;
+    agregar_columna_si_falta(conn, "notas_credito_detalle", "base_imponible", "REAL")?;
+    agregar_columna_si_falta(conn, "notas_credito_detalle", "impuesto", "REAL NOT NULL DEFAULT 0")?;
+    agregar_columna_si_falta(conn, "inventario", "stock_minimo", "REAL")?;
+    agregar_columna_si_falta(conn, "sync_pares", "clave", "TEXT")?;
+    // Los disparadores de la bandeja de salida y de eventos leen columnas agregadas arriba
+    sincronizacion::crear_disparadores(conn)?;
+    eventos::crear_disparadores(conn)?;
+
+    // Semilla: usuario admin por defecto si no existe
+    let mut stmt = conn
+        .prepare("SELECT COUNT(1) FROM users WHERE name = 'user'")
+        .map_err(|e| format!("Error al preparar verificacion de usuario: {}", e))?;
+    let count: i64 = stmt
+        .query_row([], |row| row.get(0))
+        .map_err(|e| format!("Error al consultar usuario: {}", e))?;
+
+    if count == 0 {
+        conn.execute(
+            "INSERT INTO users (name, password, \"correo electronico\", Admin, rol) VALUES ('user', 'user', 'user@example.com', 1, 'admin')",
+            [],
         )
-        .map_err(|e| format!("Error al crear tablas: {}", e))?;
-
-        // Semilla: usuario admin por defecto si no existe
-        let mut stmt = conn
-            .prepare("SELECT COUNT(1) FROM users WHERE name = 'user'")
-            .map_err(|e| format!("Error al preparar verificacion de usuario: {}", e))?;
-        let count: i64 = stmt
-            .query_row([], |row| row.get(0))
-            .map_err(|e| format!("Error al consultar usuario: {}", e))?;
-
-        if count == 0 {
-            conn.execute(
-                "INSERT INTO users (name, password, \"correo electronico\", Admin) VALUES ('user', 'user', 'user@example.com', 1)",
-                [],
-            )
-            .map_err(|e| format!("Error al insertar usuario por defecto: {}", e))?;
-            println!("[info] Usuario por defecto 'user' creado.");
-        }
+        .map_err(|e| format!("Error al insertar usuario por defecto: {}", e))?;
+        println!("[info] Usuario por defecto 'user' creado.");
+    }
 
-        Ok(())
-    });
+    Ok(())
+}
 
-    result.clone()
+/// Quita la restriccion UNIQUE de `users.password`.
+/// Las bases antiguas tenian `password` UNIQUE (y dentro de la clave primaria): al crear un
+/// usuario el error revelaba que otra cuenta usaba esa clave. SQLite no permite quitar una
+/// restriccion, asi que la tabla se reconstruye.
+fn quitar_password_unica(conn: &Connection) -> Result<(), String> {
+    let sql: String = conn
+        .query_row("SELECT sql FROM sqlite_master WHERE type = 'table' AND name = 'users'", [], |row| row.get(0))
+        .map_err(|e| format!("Error al leer la tabla users: {}", e))?;
+    if !sql.contains("\"password\" TEXT NOT NULL UNIQUE") {
+        return Ok(());
+    }
+    conn.execute_batch(
+        r#"
+        CREATE TABLE "users_nueva" (
+            "name" TEXT NOT NULL PRIMARY KEY,
+            "password" TEXT NOT NULL,
+            "correo electronico" TEXT NOT NULL UNIQUE,
+            "Admin" INTEGER NOT NULL,
+            "rol" TEXT,
+            "debe_cambiar_password" INTEGER NOT NULL DEFAULT 0
+        );
+        INSERT INTO "users_nueva" (name, password, "correo electronico", Admin, rol, debe_cambiar_password)
+            SELECT name, password, "correo electronico", Admin, rol, debe_cambiar_password FROM "users";
+        DROP TABLE "users";
+        ALTER TABLE "users_nueva" RENAME TO "users";
+        "#,
+    )
+    .map_err(|e| format!("Error al migrar la tabla users: {}", e))
+}
+
+/// Agrega una columna a una tabla existente si todavia no la tiene.
+fn agregar_columna_si_falta(conn: &Connection, tabla: &str, columna: &str, definicion: &str) -> Result<(), String> {
+    let mut stmt = conn
+        .prepare(&format!("PRAGMA table_info(\"{}\")", tabla))
+        .map_err(|e| format!("Error al leer la tabla {}: {}", tabla, e))?;
+    let existe = stmt
+        .query_map([], |row| row.get::<_, String>(1))
+        .map_err(|e| format!("Error al leer la tabla {}: {}", tabla, e))?
+        .flatten()
+        .any(|nombre| nombre == columna);
+
+    if !existe {
+        conn.execute(
+            &format!("ALTER TABLE \"{}\" ADD COLUMN \"{}\" {}", tabla, columna, definicion),
+            [],
+        )
+        .map_err(|e| format!("Error al agregar la columna {}.{}: {}", tabla, columna, e))?;
+    }
+    Ok(())
+}
+
+fn abrir_conexion() -> Result<Connection, String> {
+    ensure_db_initialized()?;
+    let db_path = find_db_path();
+    Connection::open(&db_path)
+        .map_err(|e| format!("Error al conectar: {} (ruta={})", e, db_path.display()))
 }
 
 #[derive(Serialize, Deserialize)]
@@ -112,6 +693,20 @@ struct LoginResponse {
     success: bool,
     message: String,
     is_admin: bool,
+    rol: Option<permisos::Rol>,
+    permisos: Vec<permisos::Permiso>,
+    debe_cambiar_password: bool,
+}
+
+fn login_fallido(message: String) -> LoginResponse {
+    LoginResponse {
+        success: false,
+        message,
+        is_admin: false,
+        rol: None,
+        permisos: Vec::new(),
+        debe_cambiar_password: false,
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -119,29 +714,84 @@ struct InventarioItem {
     id: i64,
     nombre: String,
     precio: f64,
-    cantidad: i64,
+    /// Fraccionaria solo en productos por peso o volumen (ver `catalogo`)
+    cantidad: f64,
+    #[serde(default)]
+    unidad: String,
+    #[serde(default)]
+    categoria_id: Option<i64>,
+    #[serde(default)]
+    marca_id: Option<i64>,
+}
+
+/// Columnas de `item_desde_fila`; en los combos la cantidad es la que alcanza a armarse.
+fn columnas_item() -> String {
+    format!(
+        "id, nombre_producto AS nombre, CAST(precio_producto AS REAL) AS precio, \
+         COALESCE({}, CAST(cantidad_producto AS REAL), 0) AS cantidad, unidad, categoria_id, marca_id",
+        combos::disponible_sql("inventario")
+    )
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+fn item_desde_fila(row: &rusqlite::Row) -> rusqlite::Result<InventarioItem> {
+    Ok(InventarioItem {
+        id: row.get(0)?,
+        nombre: row.get(1)?,
+        precio: row.get(2)?,
+        cantidad: row.get(3)?,
+        unidad: row.get(4)?,
+        categoria_id: row.get(5)?,
+        marca_id: row.get(6)?,
+    })
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
 struct VentaItem {
     id: i64,
     nombre: String,
     precio: f64,
-    cantidad: i64,
+    cantidad: f64,
+    /// Importe cobrado: `precio * cantidad - descuento`, mas el impuesto si el precio no lo incluye
     subtotal: f64,
+    #[serde(default)]
+    descuento: f64,
+    /// Promociones y descuentos aplicados, tal como se imprimen en el recibo
+    #[serde(default)]
+    promocion: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    descuento_linea: Option<promociones::Descuento>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    precio_manual: Option<f64>,
+    #[serde(default)]
+    tasa_impuesto: f64,
+    #[serde(default)]
+    base_imponible: f64,
+    #[serde(default)]
+    impuesto: f64,
+    /// Numeros de serie vendidos, uno por unidad, en productos serializados
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    series: Vec<String>,
+    /// Ubicacion de donde sale la mercaderia; la principal si se omite
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ubicacion_id: Option<i64>,
 }
 
 #[derive(Serialize, Deserialize)]
 struct ReciboRequest {
-    ventas: Vec<VentaItem>,
+    /// Carrito de la venta; en el cierre del dia se ignora y las lineas salen de los tickets
+    #[serde(flatten)]
+    venta: promociones::CalculoVentaRequest,
     total: f64,
     es_cierre_dia: bool,
+    admin_usuario: Option<String>,
     admin_password: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
 struct ReciboResponse {
     ruta: String,
+    numero: String,
+    total: f64,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -149,39 +799,41 @@ struct Usuario {
     name: String,
     correo: String,
     admin: bool,
+    rol: permisos::Rol,
+}
+
+fn usuario_desde_fila(row: &rusqlite::Row) -> rusqlite::Result<Usuario> {
+    let admin = row.get::<_, i32>(2)? == 1;
+    let rol = row
+        .get::<_, Option<String>>(3)?
+        .as_deref()
+        .and_then(permisos::Rol::parse)
+        .unwrap_or_else(|| permisos::Rol::desde_admin(admin));
+    Ok(Usuario {
+        name: row.get(0)?,
+        correo: row.get(1)?,
+        admin,
+        rol,
+    })
 }
 
 fn obtener_item_por_id(conn: &Connection, id: i64) -> Result<InventarioItem, String> {
     conn.query_row(
-        "SELECT id, nombre_producto AS nombre, CAST(precio_producto AS REAL) AS precio, \
-         COALESCE(CAST(cantidad_producto AS INTEGER), 0) AS cantidad FROM inventario WHERE id = ?1",
+        &format!("SELECT {} FROM inventario WHERE id = ?1", columnas_item()),
         rusqlite::params![id],
-        |row| {
-            Ok(InventarioItem {
-                id: row.get(0)?,
-                nombre: row.get(1)?,
-                precio: row.get(2)?,
-                cantidad: row.get(3)?,
-            })
-        },
+        item_desde_fila,
     )
     .map_err(|e| format!("No se encontro el producto: {}", e))
 }
 
 fn obtener_item_por_nombre(conn: &Connection, nombre: &str) -> Result<InventarioItem, String> {
     conn.query_row(
-        "SELECT id, nombre_producto AS nombre, CAST(precio_producto AS REAL) AS precio, \
-         COALESCE(CAST(cantidad_producto AS INTEGER), 0) AS cantidad \
-         FROM inventario WHERE LOWER(nombre_producto) = LOWER(?1) LIMIT 1",
+        &format!(
+            "SELECT {} FROM inventario WHERE LOWER(nombre_producto) = LOWER(?1) LIMIT 1",
+            columnas_item()
+        ),
         rusqlite::params![nombre],
-        |row| {
-            Ok(InventarioItem {
-                id: row.get(0)?,
-                nombre: row.get(1)?,
-                precio: row.get(2)?,
-                cantidad: row.get(3)?,
-            })
-        },
+        item_desde_fila,
     )
     .map_err(|e| format!("No se encontro el producto: {}", e))
 }
@@ -195,39 +847,29 @@ fn get_documentos_recibos_dir() -> Result<PathBuf, String> {
     Ok(recibos_dir)
 }
 
-fn validar_admin_password(password: &str) -> Result<(), String> {
-    ensure_db_initialized()?;
-    let db_path = find_db_path();
-    let conn = Connection::open(&db_path)
-        .map_err(|e| format!("Error al conectar: {} (ruta={})", e, db_path.display()))?;
-
-    let mut stmt = conn
-        .prepare("SELECT COUNT(1) FROM users WHERE Admin = 1 AND password = ?1")
-        .map_err(|e| format!("Error en la consulta: {}", e))?;
-
-    let count: i64 = stmt
-        .query_row(rusqlite::params![password], |row| row.get(0))
-        .map_err(|e| format!("Error en la consulta: {}", e))?;
-
-    if count > 0 {
-        Ok(())
-    } else {
-        Err("Clave de administrador incorrecta".to_string())
+fn get_documentos_exportaciones_dir() -> Result<PathBuf, String> {
+    let documentos = dirs::document_dir().ok_or_else(|| "No se pudo obtener la carpeta Documentos".to_string())?;
+    let exportaciones_dir = documentos.join("exportaciones");
+    if let Err(e) = fs::create_dir_all(&exportaciones_dir) {
+        return Err(format!("No se pudo crear la carpeta de exportaciones: {}", e));
     }
+    Ok(exportaciones_dir)
 }
 
-#[tauri::command]
-fn validar_password_admin(password: String) -> Result<(), String> {
-    validar_admin_password(password.trim())
+/// Usuario y clave de un administrador; los fallos cuentan en `seguridad` como cualquier aprobacion.
+fn validar_admin_password(usuario: &str, password: &str) -> Result<(), String> {
+    let conn = abrir_conexion()?;
+    permisos::validar_aprobacion(&conn, Permiso::AdministrarSistema, usuario, password).map(|_| ())
 }
 
-fn require_admin_session() -> Result<(), String> {
-    let es_admin = leer_estado_admin().unwrap_or(false);
-    if es_admin {
-        Ok(())
-    } else {
-        Err("Se requiere una sesion de administrador.".to_string())
-    }
+/// Sin usuario se comprueba la clave de quien tiene la sesion abierta.
+#[tauri::command]
+fn validar_password_admin(usuario: Option<String>, password: String) -> Result<(), String> {
+    let usuario = usuario
+        .filter(|u| !u.trim().is_empty())
+        .or_else(leer_usuario_sesion)
+        .ok_or_else(|| "Indique el usuario administrador".to_string())?;
+    validar_admin_password(&usuario, password.trim())
 }
 
 fn format_money(value: f64) -> String {
@@ -261,9 +903,55 @@ fn obtener_siguiente_numero(recibos_dir: &PathBuf, date_stamp: &str) -> Result<u
     Ok(max_num + 1)
 }
 
-fn crear_pdf_recibo(ventas: &[VentaItem], total: f64, titulo: &str, ruta_salida: &PathBuf) -> Result<(), String> {
+/// Hoja de los listados en PDF: sigue en una pagina nueva, con su encabezado, cuando la
+/// siguiente linea no entra sobre el margen inferior.
+struct PaginasPdf<'a> {
+    doc: &'a PdfDocumentReference,
+    capa: PdfLayerReference,
+    y: f32,
+    paginas: usize,
+    encabezado: &'a dyn Fn(&PdfLayerReference, f32) -> f32,
+}
+
+impl<'a> PaginasPdf<'a> {
+    const INICIO_Y: f32 = 170.0;
+    const MARGEN_Y: f32 = 18.0;
+
+    fn nueva(
+        doc: &'a PdfDocumentReference,
+        capa: PdfLayerReference,
+        encabezado: &'a dyn Fn(&PdfLayerReference, f32) -> f32,
+    ) -> Self {
+        let y = encabezado(&capa, Self::INICIO_Y);
+        PaginasPdf { doc, capa, y, paginas: 1, encabezado }
+    }
+
+    /// Asegura `alto` mm libres, pasando a otra pagina si hace falta.
+    fn reservar(&mut self, alto: f32) {
+        if self.y - alto < Self::MARGEN_Y {
+            self.paginas += 1;
+            let (page, layer) = self.doc.add_page(Mm(210.0), Mm(180.0), format!("Layer {}", self.paginas));
+            self.capa = self.doc.get_page(page).get_layer(layer);
+            self.y = (self.encabezado)(&self.capa, Self::INICIO_Y);
+        }
+    }
+
+    /// Baja `alto` mm y devuelve la altura de la nueva linea.
+    fn bajar(&mut self, alto: f32) -> f32 {
+        self.reservar(alto);
+        self.y -= alto;
+        self.y
+    }
+}
+
+fn crear_pdf_recibo(
+    ventas: &[VentaItem],
+    total: f64,
+    titulo: &str,
+    cliente: Option<&clientes::Cliente>,
+    ruta_salida: &PathBuf,
+) -> Result<(), String> {
     let (doc, page1, layer1) = PdfDocument::new("Recibo de ventas", Mm(210.0), Mm(180.0), "Layer 1");
-    let mut current_layer = doc.get_page(page1).get_layer(layer1);
 
     let font = doc.add_builtin_font(BuiltinFont::Helvetica)
         .map_err(|e| format!("No se pudo cargar fuente: {:?}", e))?;
@@ -271,11 +959,7 @@ fn crear_pdf_recibo(ventas: &[VentaItem], total: f64, titulo: &str, ruta_salida:
         .map_err(|e| format!("No se pudo cargar fuente: {:?}", e))?;
 
     let start_x: f32 = 12.0;
-    let start_y: f32 = 170.0;
-    let min_y: f32 = 18.0;
     let fecha_texto = Local::now().format("%Y-%m-%d %H:%M").to_string();
-    let mut current_y: f32 = start_y;
-    let mut page_count = 1;
 
     let draw_header = |layer: &PdfLayerReference, y: f32| -> f32 {
         let mut y_cursor: f32 = y;
@@ -288,6 +972,29 @@ fn crear_pdf_recibo(ventas: &[VentaItem], total: f64, titulo: &str, ruta_salida:
             Mm(y_cursor),
             &font,
         );
+        if let Some(cliente) = cliente {
+            let etiqueta = if cliente.tipo == "empresa" { "RIF" } else { "C.I./RIF" };
+            y_cursor -= 5.0;
+            layer.use_text(
+                format!("Cliente: {}   {}: {}", cliente.nombre, etiqueta, cliente.documento),
+                10.0,
+                Mm(start_x),
+                Mm(y_cursor),
+                &font,
+            );
+            let direccion = cliente.direccion.as_deref().map(str::trim).filter(|t| !t.is_empty());
+            let telefono = cliente.telefono.as_deref().map(str::trim).filter(|t| !t.is_empty());
+            let contacto = match (direccion, telefono) {
+                (Some(d), Some(t)) => Some(format!("{}  Tel: {}", d, t)),
+                (Some(d), None) => Some(d.to_string()),
+                (None, Some(t)) => Some(format!("Tel: {}", t)),
+                (None, None) => None,
+            };
+            if let Some(contacto) = contacto {
+                y_cursor -= 5.0;
+                layer.use_text(contacto, 9.0, Mm(start_x), Mm(y_cursor), &font);
+            }
+        }
         y_cursor -= 10.0;
         layer.use_text(
             "ID  Producto                           Cant  Precio   Subtotal",
@@ -299,9 +1006,10 @@ fn crear_pdf_recibo(ventas: &[VentaItem], total: f64, titulo: &str, ruta_salida:
         y_cursor - 6.0
     };
 
-    current_y = draw_header(&current_layer, current_y);
+    let mut hoja = PaginasPdf::nueva(&doc, doc.get_page(page1).get_layer(layer1), &draw_header);
 
     for venta in ventas {
+        let mut lineas = Vec::with_capacity(2);
         let nombre = if venta.nombre.len() > 28 {
             let mut nombre_truncado = venta.nombre.chars().take(25).collect::<String>();
             nombre_truncado.push_str("...");
@@ -315,33 +1023,71 @@ fn crear_pdf_recibo(ventas: &[VentaItem], total: f64, titulo: &str, ruta_salida:
             nombre,
             venta.cantidad,
             format_money(venta.precio),
-            format_money(venta.subtotal)
+            format_money(venta.precio * venta.cantidad)
         );
+        lineas.push(line);
+
+        if venta.descuento > 0.0 {
+            let detalle: String = venta
+                .promocion
+                .as_deref()
+                .unwrap_or("Descuento")
+                .chars()
+                .take(44)
+                .collect();
+            lineas.push(format!(
+                "     {:<44} {:>8}",
+                detalle,
+                format!("-{}", format_money(venta.descuento))
+            ));
+        }
+
+        if !venta.series.is_empty() {
+            let series: String = format!("S/N: {}", venta.series.join(", ")).chars().take(80).collect();
+            lineas.push(format!("     {}", series));
+        }
 
-        if current_y - 5.0 < min_y {
-            page_count += 1;
-            let (page, layer) = doc.add_page(Mm(210.0), Mm(180.0), format!("Layer {}", page_count));
-            current_layer = doc.get_page(page).get_layer(layer);
-            current_y = draw_header(&current_layer, start_y);
+        for line in lineas {
+            let y = hoja.bajar(5.0);
+            hoja.capa.use_text(line, 9.0, Mm(start_x), Mm(y), &font);
         }
+    }
 
-        current_y -= 5.0;
-        current_layer.use_text(line, 9.0, Mm(start_x), Mm(current_y), &font);
+    let descuentos: f64 = ventas.iter().map(|v| v.descuento).sum();
+    if descuentos > 0.0 {
+        let y = hoja.bajar(6.0);
+        hoja.capa.use_text(
+            format!("Descuentos: -{}", format_money(descuentos)),
+            10.0,
+            Mm(start_x),
+            Mm(y),
+            &font,
+        );
     }
 
-    if current_y - 10.0 < min_y {
-        page_count += 1;
-        let (page, layer) = doc.add_page(Mm(210.0), Mm(180.0), format!("Layer {}", page_count));
-        current_layer = doc.get_page(page).get_layer(layer);
-        current_y = draw_header(&current_layer, start_y);
+    // Desglose fiscal: base imponible e impuesto por tasa
+    for desglose in impuestos::desglosar(ventas) {
+        let y = hoja.bajar(5.0);
+        hoja.capa.use_text(
+            format!(
+                "{:<14} Base: {:>10}   Impuesto: {:>9}",
+                desglose.etiqueta,
+                format_money(desglose.base_imponible),
+                format_money(desglose.impuesto)
+            ),
+            9.0,
+            Mm(start_x),
+            Mm(y),
+            &font,
+        );
     }
 
-    current_y -= 10.0;
-    current_layer.use_text(
+    let y = hoja.bajar(10.0);
+    hoja.capa.use_text(
         format!("Total: {}", format_money(total)),
         12.0,
         Mm(start_x),
-        Mm(current_y),
+        Mm(y),
         &font_bold,
     );
 
@@ -359,42 +1105,29 @@ fn crear_pdf_recibo(ventas: &[VentaItem], total: f64, titulo: &str, ruta_salida:
 #[tauri::command]
 fn validar_login(usuario: String, contrasena: String) -> LoginResponse {
     if let Err(err) = ensure_db_initialized() {
-        return LoginResponse {
-            success: false,
-            message: err,
-            is_admin: false,
-        };
+        return login_fallido(err);
     }
 
     // Determinar la ruta a la base de datos usando búsqueda de candidatos
     let db_path = find_db_path();
 
-    println!(
-        "[debug] validar_login called for user='{}' using DB path={}",
-        usuario,
-        db_path.display()
-    );
-
     // Conectar a la base de datos
     let conn = match Connection::open(&db_path) {
         Ok(c) => c,
-        Err(e) => return LoginResponse {
-            success: false,
-            message: format!("Error al conectar: {} (ruta={})", e, db_path.display()),
-            is_admin: false,
-        },
+        Err(e) => return login_fallido(format!("Error al conectar: {} (ruta={})", e, db_path.display())),
     };
 
+    let clave_intentos = seguridad::clave_login(&usuario);
+    if let Err(e) = seguridad::comprobar_intento(&conn, &clave_intentos) {
+        return login_fallido(e);
+    }
+
     // Consultar la base de datos: la tabla en el proyecto se llama `users`
     let mut stmt = match conn.prepare(
-        "SELECT password, Admin FROM users WHERE name = ?1"
+        "SELECT password, Admin, rol, debe_cambiar_password FROM users WHERE name = ?1"
     ) {
         Ok(s) => s,
-        Err(e) => return LoginResponse {
-            success: false,
-            message: format!("Error en la consulta: {}", e),
-            is_admin: false,
-        },
+        Err(e) => return login_fallido(format!("Error en la consulta: {}", e)),
     };
 
     let result = stmt.query_row(
@@ -403,67 +1136,69 @@ fn validar_login(usuario: String, contrasena: String) -> LoginResponse {
             Ok((
                 row.get::<_, String>(0)?,
                 row.get::<_, i32>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, i32>(3)? == 1,
             ))
         },
     );
 
+    // Usuario inexistente y contraseña incorrecta devuelven el mismo mensaje para no
+    // revelar que cuentas existen
     match result {
-        Ok((pass_db, admin_value)) => {
-            println!("[debug] usuario encontrado. admin_value={}", admin_value);
-            if contrasena == pass_db {
-                let es_admin = admin_value == 1;
-
-                // Crear archivo con estado admin
-                crear_archivo_admin(es_admin);
-
-                LoginResponse {
-                    success: true,
-                    message: "Login exitoso".to_string(),
-                    is_admin: es_admin,
-                }
-            } else {
-                println!("[debug] contrasena incorrecta para user={}", usuario);
-                LoginResponse {
-                    success: false,
-                    message: "Contraseña incorrecta".to_string(),
-                    is_admin: false,
-                }
+        Ok((pass_db, admin_value, rol_db, debe_cambiar_password)) if contrasena == pass_db => {
+            let rol = rol_db
+                .as_deref()
+                .and_then(permisos::Rol::parse)
+                .unwrap_or_else(|| permisos::Rol::desde_admin(admin_value == 1));
+            let es_admin = rol == permisos::Rol::Admin;
+
+            if let Err(e) = seguridad::registrar_exito(&conn, &clave_intentos) {
+                println!("[warn] {}", e);
             }
-        }
-        Err(e) => {
-            println!("[debug] usuario no encontrado: {}", e);
+
+            // Guardar quien inicio sesion; el rol se consulta en cada comando
+            guardar_sesion(&usuario);
+
             LoginResponse {
-                success: false,
-                message: "Usuario no encontrado".to_string(),
-                is_admin: false,
+                success: true,
+                message: if debe_cambiar_password {
+                    "Debe cambiar su contraseña temporal".to_string()
+                } else {
+                    "Login exitoso".to_string()
+                },
+                is_admin: es_admin,
+                rol: Some(rol),
+                permisos: rol.permisos().to_vec(),
+                debe_cambiar_password,
+            }
+        }
+        _ => {
+            if let Err(e) = seguridad::registrar_fallo(&conn, &clave_intentos) {
+                println!("[warn] {}", e);
             }
+            login_fallido(seguridad::MENSAJE_CREDENCIALES_INVALIDAS.to_string())
         }
     }
 }
 
 #[tauri::command]
-fn listar_inventarios() -> Result<Vec<InventarioItem>, String> {
+fn listar_inventarios(filtro: Option<catalogo::FiltroCatalogo>) -> Result<Vec<InventarioItem>, String> {
+    require_permiso(Permiso::ConsultarInventario)?;
     ensure_db_initialized()?;
     let db_path = find_db_path();
     let conn = Connection::open(&db_path)
         .map_err(|e| format!("Error al conectar: {} (ruta={})", e, db_path.display()))?;
 
+    let (condiciones, params) = filtro.unwrap_or_default().clausula();
     let mut stmt = conn
-        .prepare(
-            "SELECT id, nombre_producto AS nombre, CAST(precio_producto AS REAL) AS precio, CAST(cantidad_producto AS INTEGER) AS cantidad \
-             FROM inventario ORDER BY id, nombre_producto, precio_producto, cantidad_producto",
-        )
+        .prepare(&format!(
+            "SELECT {} FROM inventario WHERE 1 = 1{} ORDER BY id, nombre_producto, precio_producto, cantidad_producto",
+            columnas_item(), condiciones
+        ))
         .map_err(|e| format!("Error en la consulta: {}", e))?;
 
     let rows = stmt
-        .query_map([], |row| {
-            Ok(InventarioItem {
-                id: row.get(0)?,
-                nombre: row.get(1)?,
-                precio: row.get(2)?,
-                cantidad: row.get(3)?,
-            })
-        })
+        .query_map(rusqlite::params_from_iter(params), item_desde_fila)
         .map_err(|e| format!("Error al leer inventarios: {}", e))?;
 
     let mut items = Vec::new();
@@ -476,6 +1211,7 @@ fn listar_inventarios() -> Result<Vec<InventarioItem>, String> {
 
 #[tauri::command]
 fn obtener_inventario_por_id(id: i64) -> Result<InventarioItem, String> {
+    require_permiso(Permiso::ConsultarInventario)?;
     ensure_db_initialized()?;
     let db_path = find_db_path();
     let conn = Connection::open(&db_path)
@@ -486,6 +1222,7 @@ fn obtener_inventario_por_id(id: i64) -> Result<InventarioItem, String> {
 
 #[tauri::command]
 fn obtener_inventario_por_nombre(nombre: String) -> Result<InventarioItem, String> {
+    require_permiso(Permiso::ConsultarInventario)?;
     ensure_db_initialized()?;
     let db_path = find_db_path();
     let conn = Connection::open(&db_path)
@@ -495,30 +1232,35 @@ fn obtener_inventario_por_nombre(nombre: String) -> Result<InventarioItem, Strin
 }
 
 #[tauri::command]
-fn registrar_venta(id: i64, cantidad: i64) -> Result<InventarioItem, String> {
-    if cantidad <= 0 {
-        return Err("La cantidad debe ser mayor a 0".to_string());
-    }
+fn registrar_venta(id: i64, cantidad: f64, ubicacion_id: Option<i64>) -> Result<InventarioItem, String> {
+    require_permiso(Permiso::Vender)?;
 
     ensure_db_initialized()?;
     let db_path = find_db_path();
-    let conn = Connection::open(&db_path)
+    let mut conn = Connection::open(&db_path)
         .map_err(|e| format!("Error al conectar: {} (ruta={})", e, db_path.display()))?;
+    let cantidad = catalogo::validar_cantidad(&conn, id, cantidad)?;
+    let ubicacion_id = ubicaciones::validar_ubicacion(&conn, ubicacion_id)?;
 
-    let item = obtener_item_por_id(&conn, id)?;
+    let tx = movimientos::transaccion_stock(&mut conn)?;
+    let item = obtener_item_por_id(&tx, id)?;
     if item.cantidad < cantidad {
         return Err(format!(
             "Stock insuficiente. Disponible: {}",
             item.cantidad
         ));
     }
+    // Las unidades con serie se descuentan al completar el ticket, cuando se sabe cuales salen
+    if series::es_serializado(&tx, id)? {
+        return Ok(item);
+    }
 
-    let nueva_cantidad = item.cantidad - cantidad;
-    conn.execute(
-        "UPDATE inventario SET cantidad_producto = ?1 WHERE id = ?2",
-        rusqlite::params![nueva_cantidad, id],
-    )
-    .map_err(|e| format!("Error al actualizar: {}", e))?;
+    // El historial por ticket se guarda al emitir el recibo; aqui se descuenta el stock y se
+    // anota la linea en el carrito de la caja, que es lo unico que se puede cancelar despues
+    let nueva_cantidad = movimientos::ajustar_stock(&tx, id, -cantidad, ubicacion_id, "venta", None)?;
+    ventas::agregar_al_carrito(&tx, id, ubicacion_id, cantidad)?;
+    tx.commit()
+        .map_err(|e| format!("Error al confirmar la venta: {}", e))?;
 
     Ok(InventarioItem {
         cantidad: nueva_cantidad,
@@ -527,23 +1269,39 @@ fn registrar_venta(id: i64, cantidad: i64) -> Result<InventarioItem, String> {
 }
 
 #[tauri::command]
-fn registrar_compra(id: i64, cantidad: i64) -> Result<InventarioItem, String> {
-    if cantidad <= 0 {
-        return Err("La cantidad debe ser mayor a 0".to_string());
-    }
+fn registrar_compra(
+    id: i64,
+    cantidad: f64,
+    lote: Option<lotes::LoteCompra>,
+    series: Option<Vec<String>>,
+    costo_unitario: Option<f64>,
+    ubicacion_id: Option<i64>,
+) -> Result<InventarioItem, String> {
+    require_permiso(Permiso::AjustarStock)?;
 
     ensure_db_initialized()?;
     let db_path = find_db_path();
-    let conn = Connection::open(&db_path)
+    let mut conn = Connection::open(&db_path)
         .map_err(|e| format!("Error al conectar: {} (ruta={})", e, db_path.display()))?;
-
-    let item = obtener_item_por_id(&conn, id)?;
-    let nueva_cantidad = item.cantidad + cantidad;
-    conn.execute(
-        "UPDATE inventario SET cantidad_producto = ?1 WHERE id = ?2",
-        rusqlite::params![nueva_cantidad, id],
-    )
-    .map_err(|e| format!("Error al actualizar: {}", e))?;
+    let cantidad = catalogo::validar_cantidad(&conn, id, cantidad)?;
+    let ubicacion_id = ubicaciones::validar_ubicacion(&conn, ubicacion_id)?;
+
+    let tx = movimientos::transaccion_stock(&mut conn)?;
+    let item = obtener_item_por_id(&tx, id)?;
+    if series::es_serializado(&tx, id)? {
+        series::recibir_series(&tx, id, &series.unwrap_or_default(), cantidad)?;
+    } else if series.is_some_and(|s| !s.is_empty()) {
+        return Err(format!("{} no se controla por numero de serie", item.nombre));
+    }
+    let nueva_cantidad = match lote {
+        Some(_) if combos::es_combo(&tx, id)? => {
+            return Err("Los combos no llevan lote; registre el lote en cada componente".to_string())
+        }
+        Some(lote) => lotes::recibir_lote(&tx, id, &lote, cantidad, costo_unitario, ubicacion_id)?,
+        None => movimientos::ajustar_stock_costeado(&tx, id, cantidad, costo_unitario, ubicacion_id, "compra", None)?,
+    };
+    tx.commit()
+        .map_err(|e| format!("Error al confirmar la compra: {}", e))?;
 
     Ok(InventarioItem {
         cantidad: nueva_cantidad,
@@ -554,49 +1312,113 @@ fn registrar_compra(id: i64, cantidad: i64) -> Result<InventarioItem, String> {
 #[tauri::command]
 fn generar_recibo_ventas(payload: ReciboRequest) -> Result<ReciboResponse, String> {
     ensure_db_initialized()?;
-    if payload.ventas.is_empty() {
+    if payload.venta.ventas.is_empty() && !payload.es_cierre_dia {
         return Err("No hay ventas para generar el recibo".to_string());
     }
 
-    let es_admin = leer_estado_admin().unwrap_or(false);
-    if payload.es_cierre_dia && !es_admin {
-        let pass = payload
-            .admin_password
-            .as_deref()
-            .ok_or_else(|| "Se requiere clave de administrador".to_string())?;
-        validar_admin_password(pass)?;
+    require_permiso(Permiso::Vender)?;
+    // Sin permiso de cierre se puede cerrar el dia con la clave de un administrador
+    if payload.es_cierre_dia && !permisos::tiene_permiso(Permiso::CerrarDia) {
+        let (usuario, pass) = permisos::credenciales_aprobacion(
+            payload.admin_usuario.as_deref(),
+            payload.admin_password.as_deref(),
+            "Se requiere usuario y clave de administrador",
+        )?;
+        validar_admin_password(usuario, pass)?;
+    }
+
+    let mut conn = abrir_conexion()?;
+    if !payload.es_cierre_dia {
+        let venta = ventas::completar_venta(&mut conn, &payload.venta, true)?;
+        return Ok(ReciboResponse {
+            ruta: venta.ruta.unwrap_or_default(),
+            numero: venta.numero,
+            total: venta.total,
+        });
+    }
+
+    // El cierre se arma con los tickets del dia para que el desglose fiscal cuadre
+    let lineas = ventas::lineas_del_dia(&conn, &Local::now().format("%Y-%m-%d").to_string())?;
+    if lineas.is_empty() {
+        return Err("No hay ventas del dia para el cierre".to_string());
     }
+    let total: f64 = lineas.iter().map(|l| l.subtotal).sum();
 
+    // El numero se toma con la base bloqueada, como en las ventas, y el PDF que lo reserva
+    // se escribe antes de soltarla
+    let tx = movimientos::transaccion_stock(&mut conn)?;
     let recibos_dir = get_documentos_recibos_dir()?;
     let date_stamp = format_date_stamp();
-    let numero = obtener_siguiente_numero(&recibos_dir, &date_stamp)?;
-    let file_name = format!("{}-{}.pdf", date_stamp, numero);
-    let ruta = recibos_dir.join(file_name);
-
-    let titulo = if payload.es_cierre_dia {
-        "Recibo cierre del dia"
-    } else {
-        "Recibo cliente"
+    let numero = ventas::siguiente_numero(&tx, &recibos_dir, &date_stamp)?;
+    let ruta = recibos_dir.join(format!("{}-{}.pdf", date_stamp, numero));
+    crear_pdf_recibo(&lineas, total, "Recibo cierre del dia", None, &ruta)?;
+    let confirmar = || -> Result<(), String> {
+        eventos::emitir(
+            &tx,
+            "dia_cerrado",
+            serde_json::json!({
+                "fecha": Local::now().format("%Y-%m-%d").to_string(),
+                "numero": format!("{}-{}", date_stamp, numero),
+                "total": total,
+            }),
+        )?;
+        tx.commit()
+            .map_err(|e| format!("Error al confirmar el cierre: {}", e))
     };
+    if let Err(e) = confirmar() {
+        let _ = fs::remove_file(&ruta);
+        return Err(e);
+    }
 
-    crear_pdf_recibo(&payload.ventas, payload.total, titulo, &ruta)?;
+    if let Err(e) = respaldo::respaldo_automatico_diario(true) {
+        println!("[warn] no se pudo crear el respaldo automatico: {}", e);
+    }
 
     Ok(ReciboResponse {
         ruta: ruta.display().to_string(),
+        numero: format!("{}-{}", date_stamp, numero),
+        total,
     })
 }
 
 #[tauri::command]
-fn actualizar_inventario(id: i64, nombre: String, precio: f64, cantidad: i64) -> Result<(), String> {
-    ensure_db_initialized()?;
-    let db_path = find_db_path();
-    let conn = Connection::open(&db_path)
-        .map_err(|e| format!("Error al conectar: {} (ruta={})", e, db_path.display()))?;
+fn actualizar_inventario(id: i64, nombre: String, precio: f64, cantidad: f64) -> Result<(), String> {
+    let mut conn = abrir_conexion()?;
+    // El stock se lee y se ajusta con la base bloqueada: una venta de otra caja entre la
+    // lectura y la escritura no se pierde
+    let tx = movimientos::transaccion_stock(&mut conn)?;
+
+    let antes = obtener_item_por_id(&tx, id)
+        .map_err(|_| "No se encontro el registro para actualizar".to_string())?;
+
+    // Nombre y precio son datos de catalogo; la cantidad es un ajuste de stock
+    if antes.nombre != nombre || antes.precio != precio {
+        require_permiso(Permiso::EditarPrecios)?;
+    }
+    let cantidad = catalogo::redondear_cantidad(cantidad);
+    let diferencia = catalogo::redondear_cantidad(cantidad - antes.cantidad);
+    if diferencia != 0.0 {
+        require_permiso(Permiso::AjustarStock)?;
+        if combos::es_combo(&tx, id)? {
+            return Err("El stock de un combo se calcula de sus componentes; ajuste los componentes".to_string());
+        }
+        if lotes::controla_lotes(&tx, id)? {
+            return Err("El producto se controla por lotes; ajuste la cantidad de cada lote".to_string());
+        }
+        if series::es_serializado(&tx, id)? {
+            return Err("El producto se controla por numero de serie; registre las unidades con una compra".to_string());
+        }
+        if cantidad < 0.0 || (!catalogo::admite_fraccion(&antes.unidad) && cantidad.fract() != 0.0) {
+            return Err(format!("Cantidad invalida para un producto por {}: {}", antes.unidad, cantidad));
+        }
+        ubicaciones::validar_principal(&tx, id, diferencia)?;
+        movimientos::ajustar_stock(&tx, id, diferencia, ubicaciones::PRINCIPAL, "ajuste", None)?;
+    }
 
-    let affected = conn
+    let affected = tx
         .execute(
-            "UPDATE inventario SET nombre_producto = ?1, precio_producto = ?2, cantidad_producto = ?3 WHERE id = ?4",
-            rusqlite::params![nombre, precio, cantidad, id],
+            "UPDATE inventario SET nombre_producto = ?1, precio_producto = ?2 WHERE id = ?3",
+            rusqlite::params![nombre, precio, id],
         )
         .map_err(|e| format!("Error al actualizar: {}", e))?;
 
@@ -604,7 +1426,28 @@ fn actualizar_inventario(id: i64, nombre: String, precio: f64, cantidad: i64) ->
         return Err("No se encontro el registro para actualizar".to_string());
     }
 
-    Ok(())
+    precios::registrar_historial(&tx, id, precios::LISTA_MINORISTA, Some(antes.precio), precio, "inventario")?;
+
+    let despues = InventarioItem {
+        id,
+        nombre,
+        precio,
+        cantidad,
+        unidad: antes.unidad.clone(),
+        categoria_id: antes.categoria_id,
+        marca_id: antes.marca_id,
+    };
+    auditoria::registrar_auditoria(
+        &tx,
+        "actualizar_inventario",
+        &format!("inventario:{}", id),
+        serde_json::to_value(&antes).ok(),
+        serde_json::to_value(&despues).ok(),
+        true,
+    )?;
+
+    tx.commit()
+        .map_err(|e| format!("Error al confirmar: {}", e))
 }
 
 #[tauri::command]
@@ -613,41 +1456,61 @@ fn cerrar_ventana(window: tauri::Window) -> Result<(), String> {
 }
 
 #[tauri::command]
-fn insertar_inventario(id: i64, nombre: String, precio: f64, cantidad: Option<i64>) -> Result<(), String> {
-    ensure_db_initialized()?;
-    let db_path = find_db_path();
-    let conn = Connection::open(&db_path)
-        .map_err(|e| format!("Error al conectar: {} (ruta={})", e, db_path.display()))?;
-
-    conn.execute(
-        "INSERT INTO inventario (id, nombre_producto, precio_producto, cantidad_producto) VALUES (?1, ?2, ?3, ?4)",
-        rusqlite::params![id, nombre, precio, cantidad],
+fn insertar_inventario(
+    id: i64,
+    nombre: String,
+    precio: f64,
+    cantidad: Option<f64>,
+    unidad: Option<String>,
+    categoria_id: Option<i64>,
+    marca_id: Option<i64>,
+) -> Result<(), String> {
+    require_permiso(Permiso::EditarPrecios)?;
+    let cantidad = cantidad.map(catalogo::redondear_cantidad);
+    if cantidad.unwrap_or(0.0) != 0.0 {
+        require_permiso(Permiso::AjustarStock)?;
+    }
+    let unidad = catalogo::validar_unidad(unidad.as_deref().unwrap_or("unidad"))?;
+    if let Some(c) = cantidad {
+        if c < 0.0 || (!catalogo::admite_fraccion(&unidad) && c.fract() != 0.0) {
+            return Err(format!("Cantidad invalida para un producto por {}: {}", unidad, c));
+        }
+    }
+    let mut conn = abrir_conexion()?;
+    // El alta, su precio y su movimiento de stock quedan juntos o no quedan
+    let tx = movimientos::transaccion_stock(&mut conn)?;
+
+    tx.execute(
+        "INSERT INTO inventario (id, nombre_producto, precio_producto, cantidad_producto, unidad, categoria_id, marca_id) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        rusqlite::params![id, nombre, precio, cantidad, unidad, categoria_id, marca_id],
     )
     .map_err(|e| format!("Error al insertar: {}", e))?;
+    precios::registrar_historial(&tx, id, precios::LISTA_MINORISTA, None, precio, "inventario")?;
 
-    Ok(())
+    if let Some(cantidad) = cantidad.filter(|c| *c != 0.0) {
+        movimientos::registrar_movimiento(&tx, id, cantidad, "alta", None)?;
+    }
+
+    tx.commit()
+        .map_err(|e| format!("Error al confirmar: {}", e))
 }
 
+
 #[tauri::command]
 fn listar_usuarios() -> Result<Vec<Usuario>, String> {
-    require_admin_session()?;
+    require_permiso(Permiso::GestionarUsuarios)?;
     ensure_db_initialized()?;
     let db_path = find_db_path();
     let conn = Connection::open(&db_path)
         .map_err(|e| format!("Error al conectar: {} (ruta={})", e, db_path.display()))?;
 
     let mut stmt = conn
-        .prepare("SELECT name, \"correo electronico\" as correo, Admin FROM users ORDER BY name")
+        .prepare("SELECT name, \"correo electronico\" as correo, Admin, rol FROM users ORDER BY name")
         .map_err(|e| format!("Error al preparar consulta de usuarios: {}", e))?;
 
     let rows = stmt
-        .query_map([], |row| {
-            Ok(Usuario {
-                name: row.get(0)?,
-                correo: row.get(1)?,
-                admin: row.get::<_, i32>(2)? == 1,
-            })
-        })
+        .query_map([], usuario_desde_fila)
         .map_err(|e| format!("Error al leer usuarios: {}", e))?;
 
     let mut usuarios = Vec::new();
@@ -659,8 +1522,8 @@ fn listar_usuarios() -> Result<Vec<Usuario>, String> {
 }
 
 #[tauri::command]
-fn insertar_usuario(name: String, password: String, correo: String, admin: bool) -> Result<(), String> {
-    require_admin_session()?;
+fn insertar_usuario(name: String, password: String, correo: String, admin: bool, rol: Option<String>) -> Result<(), String> {
+    require_permiso(Permiso::GestionarUsuarios)?;
     let trimmed_name = name.trim();
     let trimmed_pass = password.trim();
     let trimmed_correo = correo.trim();
@@ -669,23 +1532,45 @@ fn insertar_usuario(name: String, password: String, correo: String, admin: bool)
         return Err("Todos los campos son obligatorios".to_string());
     }
 
+    // Si no se indica rol se mantiene el comportamiento anterior basado en `admin`
+    let rol = match rol.as_deref().map(str::trim).filter(|r| !r.is_empty()) {
+        Some(texto) => permisos::Rol::parse(texto).ok_or_else(|| format!("Rol desconocido: {}", texto))?,
+        None => permisos::Rol::desde_admin(admin),
+    };
+    let admin = rol == permisos::Rol::Admin;
+
     ensure_db_initialized()?;
     let db_path = find_db_path();
     let conn = Connection::open(&db_path)
         .map_err(|e| format!("Error al conectar: {} (ruta={})", e, db_path.display()))?;
 
     conn.execute(
-        "INSERT INTO users (name, password, \"correo electronico\", Admin) VALUES (?1, ?2, ?3, ?4)",
-        rusqlite::params![trimmed_name, trimmed_pass, trimmed_correo, if admin { 1 } else { 0 }],
+        "INSERT INTO users (name, password, \"correo electronico\", Admin, rol) VALUES (?1, ?2, ?3, ?4, ?5)",
+        rusqlite::params![trimmed_name, trimmed_pass, trimmed_correo, if admin { 1 } else { 0 }, rol.as_str()],
     )
     .map_err(|e| format!("Error al insertar usuario: {}", e))?;
 
+    let despues = Usuario {
+        name: trimmed_name.to_string(),
+        correo: trimmed_correo.to_string(),
+        admin,
+        rol,
+    };
+    auditoria::registrar_auditoria(
+        &conn,
+        "insertar_usuario",
+        &format!("usuario:{}", trimmed_name),
+        None,
+        serde_json::to_value(&despues).ok(),
+        true,
+    )?;
+
     Ok(())
 }
 
 #[tauri::command]
 fn eliminar_usuario(name: String) -> Result<(), String> {
-    require_admin_session()?;
+    require_permiso(Permiso::GestionarUsuarios)?;
     let trimmed = name.trim();
     if trimmed.is_empty() {
         return Err("El nombre de usuario es obligatorio".to_string());
@@ -696,6 +1581,18 @@ fn eliminar_usuario(name: String) -> Result<(), String> {
     let conn = Connection::open(&db_path)
         .map_err(|e| format!("Error al conectar: {} (ruta={})", e, db_path.display()))?;
 
+    let antes = conn
+        .query_row(
+            "SELECT name, \"correo electronico\", Admin, rol FROM users WHERE name = ?1",
+            rusqlite::params![trimmed],
+            usuario_desde_fila,
+        )
+        .map_err(|_| "No se encontro el usuario".to_string())?;
+
+    if antes.rol == permisos::Rol::Admin && contar_admins(&conn)? <= 1 {
+        return Err("No se puede eliminar al ultimo administrador".to_string());
+    }
+
     let affected = conn
         .execute("DELETE FROM users WHERE name = ?1", rusqlite::params![trimmed])
         .map_err(|e| format!("Error al eliminar usuario: {}", e))?;
@@ -704,59 +1601,297 @@ fn eliminar_usuario(name: String) -> Result<(), String> {
         return Err("No se encontro el usuario".to_string());
     }
 
+    auditoria::registrar_auditoria(
+        &conn,
+        "eliminar_usuario",
+        &format!("usuario:{}", trimmed),
+        serde_json::to_value(&antes).ok(),
+        None,
+        true,
+    )?;
+
+    Ok(())
+}
+
+fn contar_admins(conn: &Connection) -> Result<i64, String> {
+    conn.query_row("SELECT COUNT(1) FROM users WHERE rol = 'admin'", [], |row| row.get(0))
+        .map_err(|e| format!("Error al contar administradores: {}", e))
+}
+
+fn obtener_usuario(conn: &Connection, name: &str) -> Result<Usuario, String> {
+    conn.query_row(
+        "SELECT name, \"correo electronico\", Admin, rol FROM users WHERE name = ?1",
+        rusqlite::params![name],
+        usuario_desde_fila,
+    )
+    .map_err(|_| "No se encontro el usuario".to_string())
+}
+
+/// Traduce los errores de UNIQUE de `users` sin revelar datos de otras cuentas.
+fn error_usuario_duplicado(e: rusqlite::Error, accion: &str) -> String {
+    let texto = e.to_string();
+    if texto.contains("users.name") {
+        "Ya existe un usuario con ese nombre".to_string()
+    } else if texto.contains("correo electronico") {
+        "Ya existe un usuario con ese correo".to_string()
+    } else {
+        format!("Error al {}: {}", accion, texto)
+    }
+}
+
+#[tauri::command]
+fn actualizar_usuario(name: String, nuevo_nombre: Option<String>, correo: Option<String>, rol: Option<String>) -> Result<(), String> {
+    require_permiso(Permiso::GestionarUsuarios)?;
+    let trimmed = name.trim();
+    let conn = abrir_conexion()?;
+    let antes = obtener_usuario(&conn, trimmed)?;
+
+    let nuevo_nombre = nuevo_nombre
+        .as_deref()
+        .map(str::trim)
+        .filter(|n| !n.is_empty())
+        .unwrap_or(&antes.name)
+        .to_string();
+    let correo = correo
+        .as_deref()
+        .map(str::trim)
+        .filter(|c| !c.is_empty())
+        .unwrap_or(&antes.correo)
+        .to_string();
+    let rol = match rol.as_deref().map(str::trim).filter(|r| !r.is_empty()) {
+        Some(texto) => permisos::Rol::parse(texto).ok_or_else(|| format!("Rol desconocido: {}", texto))?,
+        None => antes.rol,
+    };
+
+    if antes.rol == permisos::Rol::Admin && rol != permisos::Rol::Admin && contar_admins(&conn)? <= 1 {
+        return Err("No se puede quitar el rol de administrador al ultimo administrador".to_string());
+    }
+
+    conn.execute(
+        "UPDATE users SET name = ?1, \"correo electronico\" = ?2, rol = ?3, Admin = ?4 WHERE name = ?5",
+        rusqlite::params![
+            nuevo_nombre,
+            correo,
+            rol.as_str(),
+            if rol == permisos::Rol::Admin { 1 } else { 0 },
+            antes.name
+        ],
+    )
+    .map_err(|e| error_usuario_duplicado(e, "actualizar usuario"))?;
+    conn.execute(
+        "UPDATE sesiones_terminal SET usuario = ?1 WHERE usuario = ?2",
+        rusqlite::params![nuevo_nombre, antes.name],
+    )
+    .map_err(|e| format!("Error al actualizar las sesiones: {}", e))?;
+
+    let despues = obtener_usuario(&conn, &nuevo_nombre)?;
+    auditoria::registrar_auditoria(
+        &conn,
+        "actualizar_usuario",
+        &format!("usuario:{}", antes.name),
+        serde_json::to_value(&antes).ok(),
+        serde_json::to_value(&despues).ok(),
+        true,
+    )?;
+
+    // Si el usuario editado es el de la sesion, la sesion debe reflejar el cambio
+    if leer_usuario_sesion().as_deref() == Some(antes.name.as_str()) {
+        guardar_sesion(&despues.name);
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+fn cambiar_password(actual: String, nueva: String) -> Result<(), String> {
+    let usuario = leer_usuario_sesion().ok_or_else(|| "Debe iniciar sesion.".to_string())?;
+    let nueva = nueva.trim();
+    if nueva.len() < 4 {
+        return Err("La nueva contraseña debe tener al menos 4 caracteres".to_string());
+    }
+    if nueva == actual.trim() {
+        return Err("La nueva contraseña debe ser distinta de la actual".to_string());
+    }
+
+    let conn = abrir_conexion()?;
+    let clave_intentos = seguridad::clave_login(&usuario);
+    seguridad::comprobar_intento(&conn, &clave_intentos)?;
+
+    let pass_db: String = conn
+        .query_row("SELECT password FROM users WHERE name = ?1", rusqlite::params![usuario], |row| row.get(0))
+        .map_err(|_| "No se encontro el usuario".to_string())?;
+    if pass_db != actual.trim() {
+        seguridad::registrar_fallo(&conn, &clave_intentos)?;
+        auditoria::registrar_auditoria(&conn, "cambiar_password", &format!("usuario:{}", usuario), None, None, false)?;
+        return Err("La contraseña actual es incorrecta".to_string());
+    }
+
+    conn.execute(
+        "UPDATE users SET password = ?1, debe_cambiar_password = 0 WHERE name = ?2",
+        rusqlite::params![nueva, usuario],
+    )
+    .map_err(|e| error_usuario_duplicado(e, "cambiar la contraseña"))?;
+
+    seguridad::registrar_exito(&conn, &clave_intentos)?;
+    auditoria::registrar_auditoria(&conn, "cambiar_password", &format!("usuario:{}", usuario), None, None, true)?;
+
     Ok(())
 }
 
-fn crear_archivo_admin(es_admin: bool) {
-    let admin_value = if es_admin { 1 } else { 0 };
-    let contenido = format!("admin={}", admin_value);
+#[tauri::command]
+fn restablecer_password(name: String) -> Result<String, String> {
+    require_permiso(Permiso::GestionarUsuarios)?;
+    let conn = abrir_conexion()?;
+    let usuario = obtener_usuario(&conn, name.trim())?;
+
+    let temporal: String = rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(10)
+        .map(char::from)
+        .collect();
+
+    conn.execute(
+        "UPDATE users SET password = ?1, debe_cambiar_password = 1 WHERE name = ?2",
+        rusqlite::params![temporal, usuario.name],
+    )
+    .map_err(|e| format!("Error al restablecer la contraseña: {}", e))?;
+
+    // Un restablecimiento tambien levanta cualquier bloqueo por intentos
+    conn.execute(
+        "DELETE FROM intentos_acceso WHERE clave IN (?1, ?2)",
+        rusqlite::params![seguridad::clave_login(&usuario.name), seguridad::clave_admin(&usuario.name)],
+    )
+    .map_err(|e| format!("Error al restablecer la contraseña: {}", e))?;
+
+    auditoria::registrar_auditoria(
+        &conn,
+        "restablecer_password",
+        &format!("usuario:{}", usuario.name),
+        None,
+        None,
+        true,
+    )?;
+
+    Ok(temporal)
+}
+
+/// La sesion de escritorio es un token al azar: el archivo solo lo guarda y el usuario se
+/// busca por su hash en `sesiones_locales`, asi escribir el archivo a mano no abre una
+/// sesion. Rol y permisos se leen de `users` en cada comando (ver
+/// `permisos::require_permiso`). En el servidor de cajas la sesion es un token que emite
+/// `terminales::abrir_sesion`.
+fn guardar_sesion(usuario: &str) {
+    if terminales::terminal_actual().is_some() {
+        if let Err(e) = terminales::abrir_sesion(usuario) {
+            println!("[warn] {}", e);
+        }
+        return;
+    }
+    let token = terminales::nuevo_token();
+    let registrada = abrir_conexion().and_then(|conn| {
+        conn.execute("DELETE FROM sesiones_locales", [])
+            .and_then(|_| {
+                conn.execute(
+                    "INSERT INTO sesiones_locales (token, usuario, creada) VALUES (?1, ?2, ?3)",
+                    rusqlite::params![terminales::hash_token(&token), usuario, comun::ahora()],
+                )
+            })
+            .map_err(|e| format!("Error al abrir la sesion: {}", e))
+    });
+    if let Err(e) = registrada {
+        println!("[warn] {}", e);
+        return;
+    }
 
     // Escribir en un directorio seguro que normalmente no está observado por herramientas
     // de desarrollo (por ejemplo, `cargo tauri dev`). Usamos el directorio temporal del
     // sistema para evitar que la creación/actualización del archivo dispare recargas.
-    let ruta = env::temp_dir().join("ventas_admin.conf");
-
-    if let Err(e) = fs::write(&ruta, contenido) {
+    let ruta = ruta_sesion();
+    if let Err(e) = escribir_privado(&ruta, &format!("sesion={}", token)) {
         println!("[warn] no se pudo escribir ventas_admin.conf en {}: {}", ruta.display(), e);
-    } else {
-        println!("[debug] ventas_admin.conf escrito en {}", ruta.display());
     }
 }
 
-fn leer_estado_admin() -> Option<bool> {
-    let ruta = env::temp_dir().join("ventas_admin.conf");
-    let contenido = fs::read_to_string(&ruta).ok()?;
-    let partes: Vec<&str> = contenido.trim().split('=').collect();
-    if partes.len() != 2 {
-        return None;
+/// El token no debe quedar legible para los demas usuarios del equipo.
+fn escribir_privado(ruta: &PathBuf, contenido: &str) -> std::io::Result<()> {
+    let mut opciones = fs::OpenOptions::new();
+    opciones.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        opciones.mode(0o600);
+        // Un archivo que ya existia conserva sus permisos al abrirlo
+        if ruta.exists() {
+            fs::set_permissions(ruta, fs::Permissions::from_mode(0o600))?;
+        }
     }
-    match partes[1].trim() {
-        "1" => Some(true),
-        "0" => Some(false),
-        _ => None,
+    opciones.open(ruta)?.write_all(contenido.as_bytes())
+}
+
+fn ruta_sesion() -> PathBuf {
+    env::temp_dir().join("ventas_admin.conf")
+}
+
+fn leer_usuario_sesion() -> Option<String> {
+    // Las peticiones de la API se identifican con su token, no con un archivo de sesion
+    if let Some(sesion) = api::sesion_actual() {
+        return Some(sesion.usuario());
+    }
+    if terminales::terminal_actual().is_some() {
+        return terminales::usuario_actual();
     }
+    let contenido = fs::read_to_string(ruta_sesion()).ok()?;
+    let token = contenido
+        .lines()
+        .find_map(|linea| linea.trim().strip_prefix("sesion=").map(str::trim))
+        .filter(|t| !t.is_empty())?;
+    let conn = abrir_conexion().ok()?;
+    conn.query_row(
+        "SELECT usuario FROM sesiones_locales WHERE token = ?1",
+        rusqlite::params![terminales::hash_token(token)],
+        |row| row.get(0),
+    )
+    .ok()
 }
 
 fn main() {
+    let direccion = terminales::direccion_servidor();
+    // Una caja cliente no tiene base propia: los respaldos los hace el servidor
+    if direccion.is_some() || !terminales::es_cliente() {
+        if let Err(e) = respaldo::respaldo_automatico_diario(false) {
+            println!("[warn] no se pudo crear el respaldo automatico: {}", e);
+        }
+    }
+    api::iniciar();
+    precios::iniciar_programados();
+    if let Some(direccion) = direccion {
+        eventos::iniciar(None);
+        if let Err(e) = terminales::servir(&direccion) {
+            println!("[error] {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Los comandos salen de la misma lista que atiende el servidor de cajas
+    macro_rules! manejador {
+        ($($modulo:ident :: $nombre:ident ( $($arg:ident),* );)*) => {
+            tauri::generate_handler![$($modulo::$nombre,)* cerrar_ventana, greet]
+        };
+    }
+    let locales: Box<dyn Fn(tauri::ipc::Invoke) -> bool + Send + Sync> =
+        Box::new(terminales::con_comandos!(manejador!()));
+
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
-        .invoke_handler(tauri::generate_handler![
-            validar_login,
-            listar_inventarios,
-            obtener_inventario_por_id,
-            obtener_inventario_por_nombre,
-            actualizar_inventario,
-            insertar_inventario,
-            registrar_venta,
-            registrar_compra,
-            generar_recibo_ventas,
-            validar_password_admin,
-            listar_usuarios,
-            insertar_usuario,
-            eliminar_usuario,
-            cerrar_ventana,
-            greet
-        ])
+        .setup(|app| {
+            eventos::iniciar(Some(app.handle().clone()));
+            Ok(())
+        })
+        .invoke_handler(move |invoke| match terminales::reenviar(invoke) {
+            Some(invoke) => locales(invoke),
+            None => true,
+        })
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
@@ -0,0 +1,219 @@
+// Historial de movimientos de stock.
+//
+// Cada cambio de `cantidad_producto` deja una fila en `movimientos_stock` con la cantidad
+// con signo (positiva entra, negativa sale), el motivo y una referencia opcional, por
+// ejemplo el numero de recibo de la venta o de la nota de credito. El costo unitario de
+// cada movimiento permite reconstruir la valorizacion a cualquier fecha (ver `costos`).
+//
+// Varias cajas pueden escribir en la misma base (ver `terminales`): todo lo que lee el
+// stock disponible y luego lo descuenta corre dentro de `transaccion_stock`.
+
+use rusqlite::{Connection, Transaction, TransactionBehavior};
+use serde::{Deserialize, Serialize};
+
+use crate::catalogo::redondear_cantidad;
+use crate::{combos, comun, costos};
+use crate::{lotes, ubicaciones};
+use crate::permisos::{require_permiso, Permiso};
+use crate::abrir_conexion;
+
+#[derive(Serialize, Deserialize)]
+pub struct MovimientoStock {
+    id: i64,
+    fecha: String,
+    producto_id: i64,
+    cantidad: f64,
+    motivo: String,
+    referencia: Option<String>,
+    usuario: String,
+    costo_unitario: Option<f64>,
+    ubicacion_id: i64,
+}
+
+/// Transaccion que toma el bloqueo de escritura de la base al iniciar, asi otra caja no
+/// puede descontar el mismo stock entre la comprobacion y el descuento.
+pub(crate) fn transaccion_stock(conn: &mut Connection) -> Result<Transaction<'_>, String> {
+    conn.transaction_with_behavior(TransactionBehavior::Immediate)
+        .map_err(|e| format!("Error al iniciar la transaccion: {}", e))
+}
+
+pub(crate) fn registrar_movimiento(
+    conn: &Connection,
+    producto_id: i64,
+    cantidad: f64,
+    motivo: &str,
+    referencia: Option<&str>,
+) -> Result<(), String> {
+    registrar_movimiento_costeado(conn, producto_id, cantidad, None, ubicaciones::PRINCIPAL, motivo, referencia)
+}
+
+/// Como `registrar_movimiento`, con el costo unitario de una compra y la ubicacion; sin
+/// costo se registra el costo promedio vigente.
+pub(crate) fn registrar_movimiento_costeado(
+    conn: &Connection,
+    producto_id: i64,
+    cantidad: f64,
+    costo: Option<f64>,
+    ubicacion_id: i64,
+    motivo: &str,
+    referencia: Option<&str>,
+) -> Result<(), String> {
+    let costo = match costo {
+        Some(costo) => costo,
+        None => costos::costo_promedio(conn, producto_id)?,
+    };
+    conn.execute(
+        "INSERT INTO movimientos_stock (fecha, producto_id, cantidad, motivo, referencia, usuario, costo_unitario, \
+         ubicacion_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        rusqlite::params![
+            comun::ahora(),
+            producto_id,
+            cantidad,
+            motivo,
+            referencia,
+            comun::usuario(),
+            costo,
+            ubicacion_id
+        ],
+    )
+    .map_err(|e| format!("Error al registrar movimiento de stock: {}", e))?;
+    Ok(())
+}
+
+/// Suma `cantidad` (con signo) al stock del producto en la ubicacion y deja el movimiento
+/// registrado. Devuelve el stock total; en un combo ajusta cada componente y devuelve los
+/// combos disponibles.
+pub(crate) fn ajustar_stock(
+    conn: &Connection,
+    producto_id: i64,
+    cantidad: f64,
+    ubicacion_id: i64,
+    motivo: &str,
+    referencia: Option<&str>,
+) -> Result<f64, String> {
+    ajustar_stock_costeado(conn, producto_id, cantidad, None, ubicacion_id, motivo, referencia)
+}
+
+/// Entrada con costo unitario conocido (compras, traslados): actualiza el costo promedio.
+pub(crate) fn ajustar_stock_costeado(
+    conn: &Connection,
+    producto_id: i64,
+    cantidad: f64,
+    costo: Option<f64>,
+    ubicacion_id: i64,
+    motivo: &str,
+    referencia: Option<&str>,
+) -> Result<f64, String> {
+    let componentes = combos::componentes_de(conn, producto_id)?;
+    if !componentes.is_empty() {
+        ajustar_combo(conn, &componentes, cantidad, costo, ubicacion_id, motivo, referencia)?;
+        return conn
+            .query_row(
+                &format!("SELECT COALESCE({}, 0) FROM inventario WHERE id = ?1", combos::disponible_sql("inventario")),
+                rusqlite::params![producto_id],
+                |row| row.get(0),
+            )
+            .map_err(|e| format!("Error en la consulta: {}", e));
+    }
+
+    let actual: f64 = conn
+        .query_row(
+            "SELECT COALESCE(CAST(cantidad_producto AS REAL), 0) FROM inventario WHERE id = ?1",
+            rusqlite::params![producto_id],
+            |row| row.get(0),
+        )
+        .map_err(|e| format!("No se encontro el producto: {}", e))?;
+
+    let nueva = redondear_cantidad(actual + cantidad);
+    if cantidad < 0.0 {
+        let disponible = ubicaciones::stock_en(conn, producto_id, ubicacion_id)?;
+        if redondear_cantidad(disponible + cantidad) < 0.0 {
+            return Err(format!("Stock insuficiente. Disponible: {}", disponible));
+        }
+    }
+    if lotes::controla_lotes(conn, producto_id)? {
+        lotes::repartir_en_lotes(conn, producto_id, cantidad)?;
+    }
+    if let (true, Some(costo)) = (cantidad > 0.0, costo) {
+        costos::actualizar_promedio(conn, producto_id, actual, cantidad, costo)?;
+    }
+
+    conn.execute(
+        "UPDATE inventario SET cantidad_producto = ?1 WHERE id = ?2",
+        rusqlite::params![nueva, producto_id],
+    )
+    .map_err(|e| format!("Error al actualizar: {}", e))?;
+    ubicaciones::sumar_en(conn, producto_id, ubicacion_id, cantidad)?;
+
+    registrar_movimiento_costeado(conn, producto_id, cantidad, costo, ubicacion_id, motivo, referencia)?;
+    Ok(nueva)
+}
+
+fn ajustar_combo(
+    conn: &Connection,
+    componentes: &[(i64, f64)],
+    cantidad: f64,
+    costo: Option<f64>,
+    ubicacion_id: i64,
+    motivo: &str,
+    referencia: Option<&str>,
+) -> Result<(), String> {
+    let costos_componentes = match costo {
+        Some(costo) => costos::repartir_costo(conn, componentes, costo)?,
+        None => vec![None; componentes.len()],
+    };
+    // Si falta stock de un componente no debe quedar descontado ninguno
+    conn.execute_batch("SAVEPOINT combo")
+        .map_err(|e| format!("Error al iniciar el ajuste del combo: {}", e))?;
+    let resultado = componentes
+        .iter()
+        .zip(costos_componentes)
+        .try_for_each(|((componente_id, por_combo), costo)| {
+            let cantidad = redondear_cantidad(cantidad * por_combo);
+            ajustar_stock_costeado(conn, *componente_id, cantidad, costo, ubicacion_id, motivo, referencia).map(|_| ())
+        });
+    match resultado {
+        Ok(()) => conn
+            .execute_batch("RELEASE combo")
+            .map_err(|e| format!("Error al confirmar el ajuste del combo: {}", e)),
+        Err(e) => {
+            let _ = conn.execute_batch("ROLLBACK TO combo; RELEASE combo");
+            Err(e)
+        }
+    }
+}
+
+#[tauri::command]
+pub fn listar_movimientos(producto_id: i64) -> Result<Vec<MovimientoStock>, String> {
+    require_permiso(Permiso::VerReportes)?;
+    let conn = abrir_conexion()?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, fecha, producto_id, CAST(cantidad AS REAL), motivo, referencia, usuario, costo_unitario, \
+             ubicacion_id FROM movimientos_stock \
+             WHERE producto_id = ?1 ORDER BY id DESC",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+
+    let rows = stmt
+        .query_map(rusqlite::params![producto_id], |row| {
+            Ok(MovimientoStock {
+                id: row.get(0)?,
+                fecha: row.get(1)?,
+                producto_id: row.get(2)?,
+                cantidad: row.get(3)?,
+                motivo: row.get(4)?,
+                referencia: row.get(5)?,
+                usuario: row.get(6)?,
+                costo_unitario: row.get(7)?,
+                ubicacion_id: row.get(8)?,
+            })
+        })
+        .map_err(|e| format!("Error al leer movimientos: {}", e))?;
+
+    let mut movimientos = Vec::new();
+    for row in rows {
+        movimientos.push(row.map_err(|e| format!("Error en fila: {}", e))?);
+    }
+    Ok(movimientos)
+}
@@ -0,0 +1,257 @@
+// Roles de usuario y permisos por comando.
+//
+// Cada usuario tiene un rol (`users.rol`) que se traduce a un conjunto de permisos.
+// La columna `Admin` se mantiene sincronizada (1 solo para el rol admin) para no romper
+// instalaciones existentes. Los comandos llaman a `require_permiso` con lo que necesitan.
+
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::fmt;
+
+use crate::{abrir_conexion, api, auditoria, leer_usuario_sesion, seguridad};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Rol {
+    Cajero,
+    Almacenista,
+    Supervisor,
+    Admin,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Permiso {
+    ConsultarInventario,
+    Vender,
+    Anular,
+    AjustarStock,
+    EditarPrecios,
+    GestionarUsuarios,
+    VerReportes,
+    CerrarDia,
+    OtorgarCredito,
+    AdministrarSistema,
+}
+
+thread_local! {
+    static DENEGADO: Cell<Option<PermisoDenegado>> = const { Cell::new(None) };
+}
+
+/// Permiso que le falto al rol de la sesion. Los comandos lo devuelven como texto; quien
+/// necesite distinguirlo de otros errores (la API, para responder 403) lo recupera con
+/// `tomar_denegacion`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) struct PermisoDenegado {
+    pub(crate) rol: Rol,
+    pub(crate) permiso: Permiso,
+}
+
+impl fmt::Display for PermisoDenegado {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "El rol {} no tiene permiso para esta accion ({:?}).", self.rol.as_str(), self.permiso)
+    }
+}
+
+/// Ultimo permiso denegado en este hilo; lo deja en blanco.
+pub(crate) fn tomar_denegacion() -> Option<PermisoDenegado> {
+    DENEGADO.with(Cell::take)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RolInfo {
+    rol: Rol,
+    permisos: Vec<Permiso>,
+}
+
+impl Rol {
+    pub(crate) const TODOS: [Rol; 4] = [Rol::Cajero, Rol::Almacenista, Rol::Supervisor, Rol::Admin];
+
+    pub(crate) fn parse(texto: &str) -> Option<Rol> {
+        match texto.trim().to_lowercase().as_str() {
+            "cajero" => Some(Rol::Cajero),
+            "almacenista" => Some(Rol::Almacenista),
+            "supervisor" => Some(Rol::Supervisor),
+            "admin" => Some(Rol::Admin),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Rol::Cajero => "cajero",
+            Rol::Almacenista => "almacenista",
+            Rol::Supervisor => "supervisor",
+            Rol::Admin => "admin",
+        }
+    }
+
+    /// Rol para usuarios creados antes de existir la columna `rol`.
+    pub(crate) fn desde_admin(admin: bool) -> Rol {
+        if admin {
+            Rol::Admin
+        } else {
+            Rol::Cajero
+        }
+    }
+
+    pub(crate) fn permisos(&self) -> &'static [Permiso] {
+        use Permiso::*;
+        match self {
+            Rol::Cajero => &[ConsultarInventario, Vender],
+            Rol::Almacenista => &[ConsultarInventario, AjustarStock, VerReportes],
+            Rol::Supervisor => &[
+                ConsultarInventario,
+                Vender,
+                Anular,
+                AjustarStock,
+                EditarPrecios,
+                VerReportes,
+                CerrarDia,
+                OtorgarCredito,
+            ],
+            Rol::Admin => &[
+                ConsultarInventario,
+                Vender,
+                Anular,
+                AjustarStock,
+                EditarPrecios,
+                GestionarUsuarios,
+                VerReportes,
+                CerrarDia,
+                OtorgarCredito,
+                AdministrarSistema,
+            ],
+        }
+    }
+
+    pub(crate) fn tiene(&self, permiso: Permiso) -> bool {
+        self.permisos().contains(&permiso)
+    }
+}
+
+/// Rol del usuario en sesion y si debe cambiar su contraseña temporal. Se lee de `users`
+/// en cada llamada: el archivo de sesion solo dice quien es el usuario.
+fn estado_sesion() -> Option<(Rol, bool)> {
+    if let Some(sesion) = api::sesion_actual() {
+        return Some((sesion.rol(), false));
+    }
+    let usuario = leer_usuario_sesion()?;
+    let conn = abrir_conexion().ok()?;
+    conn.query_row(
+        "SELECT Admin, rol, debe_cambiar_password FROM users WHERE name = ?1",
+        rusqlite::params![usuario],
+        |row| {
+            let admin = row.get::<_, i32>(0)? == 1;
+            let rol = row
+                .get::<_, Option<String>>(1)?
+                .as_deref()
+                .and_then(Rol::parse)
+                .unwrap_or_else(|| Rol::desde_admin(admin));
+            Ok((rol, row.get::<_, i32>(2)? == 1))
+        },
+    )
+    .optional()
+    .ok()
+    .flatten()
+}
+
+/// Rol de la sesion activa, o `None` si nadie ha iniciado sesion.
+pub(crate) fn rol_sesion() -> Option<Rol> {
+    estado_sesion().map(|(rol, _)| rol)
+}
+
+pub(crate) fn tiene_permiso(permiso: Permiso) -> bool {
+    rol_sesion().map(|rol| rol.tiene(permiso)).unwrap_or(false)
+}
+
+pub(crate) fn require_permiso(permiso: Permiso) -> Result<(), String> {
+    match estado_sesion() {
+        None => Err("Debe iniciar sesion.".to_string()),
+        Some((_, true)) => Err("Debe cambiar su contraseña temporal antes de continuar.".to_string()),
+        Some((rol, _)) if rol.tiene(permiso) => Ok(()),
+        Some((rol, _)) => {
+            let denegado = PermisoDenegado { rol, permiso };
+            DENEGADO.with(|d| d.set(Some(denegado)));
+            Err(denegado.to_string())
+        }
+    }
+}
+
+/// Aprobacion puntual: usuario y clave de alguien cuyo rol tenga `permiso` (por ejemplo un
+/// supervisor autorizando una anulacion en la caja de un cajero). Los fallos cuentan contra
+/// la cuenta del aprobador, asi que adivinar su clave desde una caja termina bloqueandola.
+/// Devuelve el nombre de quien aprobo.
+pub(crate) fn validar_aprobacion(conn: &Connection, permiso: Permiso, usuario: &str, password: &str) -> Result<String, String> {
+    let usuario = usuario.trim();
+    let clave_intentos = seguridad::clave_admin(usuario);
+    seguridad::comprobar_intento(conn, &clave_intentos)?;
+
+    let fila = conn
+        .query_row(
+            "SELECT name, password, Admin, rol FROM users WHERE name = ?1",
+            rusqlite::params![usuario],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, i32>(2)? == 1,
+                    row.get::<_, Option<String>>(3)?,
+                ))
+            },
+        )
+        .optional()
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+
+    let aprobador = fila.and_then(|(name, pass_db, admin, rol)| {
+        let rol = rol.as_deref().and_then(Rol::parse).unwrap_or_else(|| Rol::desde_admin(admin));
+        if !usuario.is_empty() && pass_db == password && rol.tiene(permiso) {
+            Some(name)
+        } else {
+            None
+        }
+    });
+
+    auditoria::registrar_auditoria(
+        conn,
+        "aprobacion",
+        &format!("permiso:{:?}", permiso),
+        None,
+        Some(serde_json::json!({ "aprobador": usuario })),
+        aprobador.is_some(),
+    )?;
+
+    match aprobador {
+        Some(name) => {
+            seguridad::registrar_exito(conn, &clave_intentos)?;
+            Ok(name)
+        }
+        None => {
+            seguridad::registrar_fallo(conn, &clave_intentos)?;
+            Err("Usuario o clave de aprobacion incorrectos".to_string())
+        }
+    }
+}
+
+/// Usuario y clave de aprobacion que vienen de la pantalla, o `mensaje` si falta alguno.
+pub(crate) fn credenciales_aprobacion<'a>(
+    usuario: Option<&'a str>,
+    password: Option<&'a str>,
+    mensaje: &str,
+) -> Result<(&'a str, &'a str), String> {
+    let usuario = usuario.map(str::trim).filter(|u| !u.is_empty());
+    let password = password.map(str::trim).filter(|p| !p.is_empty());
+    usuario.zip(password).ok_or_else(|| mensaje.to_string())
+}
+
+#[tauri::command]
+pub fn listar_roles() -> Vec<RolInfo> {
+    Rol::TODOS
+        .iter()
+        .map(|rol| RolInfo {
+            rol: *rol,
+            permisos: rol.permisos().to_vec(),
+        })
+        .collect()
+}
@@ -0,0 +1,605 @@
+// Listas de precios, cambios programados y actualizaciones masivas.
+//
+// La lista minorista es el precio del inventario (`precio_producto`). Las demas listas
+// (mayorista, empleado, ...) guardan precios propios por producto; sin precio propio usan
+// el minorista con el ajuste porcentual de la lista. Cada cliente puede tener una lista
+// por defecto. Todo cambio de precio queda en `historial_precios`, y los cambios con fecha
+// futura esperan en `precios_programados`; `iniciar_programados` los aplica al arrancar y
+// los revisa cada minuto, asi entran al cambiar el dia sin que las consultas escriban.
+
+use chrono::Local;
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::thread;
+use std::time::Duration;
+
+use crate::catalogo::FiltroCatalogo;
+use crate::clientes;
+use crate::comun::{ahora, redondear, usuario};
+use crate::costos;
+use crate::eventos;
+use crate::exportacion::parse_fecha;
+use crate::permisos::{require_permiso, Permiso};
+use crate::{abrir_conexion, auditoria, movimientos, terminales};
+
+/// Cada cuanto se revisan los precios programados.
+const INTERVALO_PROGRAMADOS: Duration = Duration::from_secs(60);
+
+/// La lista cuyos precios son los del inventario.
+pub(crate) const LISTA_MINORISTA: i64 = 1;
+
+#[derive(Serialize, Deserialize)]
+pub struct ListaPrecios {
+    id: Option<i64>,
+    nombre: String,
+    /// Porcentaje sobre el precio minorista para los productos sin precio propio en la lista
+    ajuste: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PrecioLista {
+    lista_id: i64,
+    lista: String,
+    precio: f64,
+    /// `false` si el precio sale del minorista con el ajuste de la lista
+    propio: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PrecioProgramado {
+    id: i64,
+    lista_id: i64,
+    lista: String,
+    producto_id: i64,
+    nombre: String,
+    precio: f64,
+    desde: String,
+    usuario: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct HistorialPrecio {
+    lista_id: i64,
+    lista: String,
+    fecha: String,
+    anterior: Option<f64>,
+    nuevo: f64,
+    /// "manual", "masivo", "programado", "inventario", "importacion" o "variante"
+    origen: String,
+    usuario: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ActualizacionPrecios {
+    lista_id: i64,
+    #[serde(default)]
+    filtro: FiltroCatalogo,
+    /// "porcentaje" sobre el precio actual en la lista o "margen" sobre el costo promedio
+    modo: String,
+    valor: f64,
+    /// AAAA-MM-DD; si es posterior a hoy los cambios quedan programados
+    desde: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CambioPrecio {
+    producto_id: i64,
+    nombre: String,
+    anterior: f64,
+    nuevo: f64,
+}
+
+fn validar_precio(precio: f64) -> Result<f64, String> {
+    if !precio.is_finite() || precio < 0.0 {
+        return Err("El precio no puede ser negativo".to_string());
+    }
+    Ok(redondear(precio))
+}
+
+/// Fecha de entrada en vigencia si es posterior a hoy; `None` si el cambio es inmediato.
+fn fecha_programada(desde: Option<&str>) -> Result<Option<String>, String> {
+    match desde.map(str::trim).filter(|d| !d.is_empty()) {
+        Some(d) => {
+            let fecha = parse_fecha(d)?;
+            Ok(Some(fecha.to_string()).filter(|_| fecha > Local::now().date_naive()))
+        }
+        None => Ok(None),
+    }
+}
+
+fn nombre_lista(conn: &Connection, lista_id: i64) -> Result<String, String> {
+    conn.query_row(
+        "SELECT nombre FROM listas_precios WHERE id = ?1",
+        rusqlite::params![lista_id],
+        |row| row.get(0),
+    )
+    .optional()
+    .map_err(|e| format!("Error en la consulta: {}", e))?
+    .ok_or_else(|| format!("No se encontro la lista de precios {}", lista_id))
+}
+
+/// Anota un cambio de precio; no hace nada si el precio no cambio.
+pub(crate) fn registrar_historial(
+    conn: &Connection,
+    producto_id: i64,
+    lista_id: i64,
+    anterior: Option<f64>,
+    nuevo: f64,
+    origen: &str,
+) -> Result<(), String> {
+    if anterior.is_some_and(|a| (a - nuevo).abs() < 0.005) {
+        return Ok(());
+    }
+    conn.execute(
+        "INSERT INTO historial_precios (producto_id, lista_id, fecha, anterior, nuevo, origen, usuario) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        rusqlite::params![producto_id, lista_id, ahora(), anterior, nuevo, origen, usuario()],
+    )
+    .map_err(|e| format!("Error al registrar el historial de precios: {}", e))?;
+    eventos::emitir(
+        conn,
+        "precio_cambiado",
+        serde_json::json!({
+            "producto_id": producto_id,
+            "lista_id": lista_id,
+            "anterior": anterior,
+            "nuevo": nuevo,
+            "origen": origen,
+        }),
+    )
+}
+
+fn precio_minorista(conn: &Connection, producto_id: i64) -> Result<f64, String> {
+    conn.query_row(
+        "SELECT CAST(precio_producto AS REAL) FROM inventario WHERE id = ?1",
+        rusqlite::params![producto_id],
+        |row| row.get(0),
+    )
+    .map_err(|_| format!("No se encontro el producto {}", producto_id))
+}
+
+fn precio_propio(conn: &Connection, producto_id: i64, lista_id: i64) -> Result<Option<f64>, String> {
+    conn.query_row(
+        "SELECT precio FROM precios_lista WHERE lista_id = ?1 AND producto_id = ?2",
+        rusqlite::params![lista_id, producto_id],
+        |row| row.get(0),
+    )
+    .optional()
+    .map_err(|e| format!("Error en la consulta: {}", e))
+}
+
+/// Precio vigente del producto en la lista.
+pub(crate) fn precio_en_lista(conn: &Connection, producto_id: i64, lista_id: i64) -> Result<f64, String> {
+    let minorista = precio_minorista(conn, producto_id)?;
+    if lista_id == LISTA_MINORISTA {
+        return Ok(minorista);
+    }
+    if let Some(precio) = precio_propio(conn, producto_id, lista_id)? {
+        return Ok(precio);
+    }
+    let ajuste: f64 = conn
+        .query_row(
+            "SELECT ajuste FROM listas_precios WHERE id = ?1",
+            rusqlite::params![lista_id],
+            |row| row.get(0),
+        )
+        .map_err(|_| format!("No se encontro la lista de precios {}", lista_id))?;
+    Ok(redondear(minorista * (1.0 + ajuste / 100.0)))
+}
+
+/// Lista por defecto del cliente; la minorista si no tiene o si la venta no tiene cliente.
+pub(crate) fn lista_de_cliente(conn: &Connection, cliente_id: Option<i64>) -> Result<i64, String> {
+    let Some(cliente_id) = cliente_id else {
+        return Ok(LISTA_MINORISTA);
+    };
+    let lista: Option<i64> = conn
+        .query_row(
+            "SELECT lista_precios_id FROM clientes WHERE id = ?1",
+            rusqlite::params![cliente_id],
+            |row| row.get(0),
+        )
+        .map_err(|_| format!("No se encontro el cliente {}", cliente_id))?;
+    Ok(lista.unwrap_or(LISTA_MINORISTA))
+}
+
+/// Cambia el precio en la lista y lo anota en el historial. Fijar el precio minorista de
+/// una variante la desliga del precio de su padre.
+fn fijar(conn: &Connection, producto_id: i64, lista_id: i64, precio: f64, origen: &str) -> Result<(), String> {
+    let anterior = if lista_id == LISTA_MINORISTA {
+        let anterior = precio_minorista(conn, producto_id)?;
+        conn.execute(
+            "UPDATE inventario SET precio_producto = ?1, hereda_precio = 0 WHERE id = ?2",
+            rusqlite::params![precio, producto_id],
+        )
+        .map_err(|e| format!("Error al actualizar el precio: {}", e))?;
+        Some(anterior)
+    } else {
+        let anterior = precio_propio(conn, producto_id, lista_id)?;
+        conn.execute(
+            "INSERT OR REPLACE INTO precios_lista (lista_id, producto_id, precio) VALUES (?1, ?2, ?3)",
+            rusqlite::params![lista_id, producto_id, precio],
+        )
+        .map_err(|e| format!("Error al actualizar el precio: {}", e))?;
+        anterior
+    };
+    registrar_historial(conn, producto_id, lista_id, anterior, precio, origen)
+}
+
+fn programar(conn: &Connection, producto_id: i64, lista_id: i64, precio: f64, desde: &str) -> Result<(), String> {
+    conn.execute(
+        "INSERT INTO precios_programados (lista_id, producto_id, precio, desde, creado, usuario) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        rusqlite::params![lista_id, producto_id, precio, desde, ahora(), usuario()],
+    )
+    .map_err(|e| format!("Error al programar el precio: {}", e))?;
+    Ok(())
+}
+
+/// Aplica los precios programados pendientes y deja un hilo que los revisa cada minuto.
+pub(crate) fn iniciar_programados() {
+    if terminales::es_cliente() {
+        return;
+    }
+    let aplicar = || abrir_conexion().and_then(|mut conn| aplicar_programados(&mut conn));
+    if let Err(e) = aplicar() {
+        println!("[warn] precios programados: {}", e);
+    }
+    thread::spawn(move || loop {
+        thread::sleep(INTERVALO_PROGRAMADOS);
+        if let Err(e) = aplicar() {
+            println!("[warn] precios programados: {}", e);
+        }
+    });
+}
+
+/// Aplica los cambios programados cuya fecha ya llego, en el orden en que se cargaron.
+/// Todo en una transaccion: o entran todos los del dia o ninguno.
+fn aplicar_programados(conn: &mut Connection) -> Result<(), String> {
+    let hoy = Local::now().format("%Y-%m-%d").to_string();
+    let tx = movimientos::transaccion_stock(conn)?;
+    let mut stmt = tx
+        .prepare(
+            "SELECT p.id, p.producto_id, p.lista_id, p.precio FROM precios_programados p \
+             JOIN inventario i ON i.id = p.producto_id \
+             WHERE p.aplicado IS NULL AND p.desde <= ?1 ORDER BY p.desde, p.id",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let pendientes = stmt
+        .query_map(rusqlite::params![hoy], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?, row.get::<_, i64>(2)?, row.get::<_, f64>(3)?))
+        })
+        .map_err(|e| format!("Error al leer precios programados: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+
+    drop(stmt);
+
+    for (id, producto_id, lista_id, precio) in pendientes {
+        fijar(&tx, producto_id, lista_id, precio, "programado")?;
+        tx.execute(
+            "UPDATE precios_programados SET aplicado = ?1 WHERE id = ?2",
+            rusqlite::params![ahora(), id],
+        )
+        .map_err(|e| format!("Error al actualizar el precio programado: {}", e))?;
+    }
+    tx.commit()
+        .map_err(|e| format!("Error al aplicar los precios programados: {}", e))
+}
+
+#[tauri::command]
+pub fn listar_listas_precios() -> Result<Vec<ListaPrecios>, String> {
+    require_permiso(Permiso::ConsultarInventario)?;
+    let conn = abrir_conexion()?;
+    let mut stmt = conn
+        .prepare("SELECT id, nombre, ajuste FROM listas_precios ORDER BY id")
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let listas = stmt
+        .query_map([], |row| {
+            Ok(ListaPrecios {
+                id: row.get(0)?,
+                nombre: row.get(1)?,
+                ajuste: row.get(2)?,
+            })
+        })
+        .map_err(|e| format!("Error al leer listas de precios: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    Ok(listas)
+}
+
+#[tauri::command]
+pub fn guardar_lista_precios(lista: ListaPrecios) -> Result<i64, String> {
+    require_permiso(Permiso::EditarPrecios)?;
+    let nombre = lista.nombre.trim();
+    if nombre.is_empty() {
+        return Err("El nombre de la lista es obligatorio".to_string());
+    }
+    if !lista.ajuste.is_finite() || lista.ajuste <= -100.0 {
+        return Err("El ajuste debe ser mayor a -100%".to_string());
+    }
+    if lista.id == Some(LISTA_MINORISTA) && lista.ajuste != 0.0 {
+        return Err("La lista minorista usa los precios del inventario y no lleva ajuste".to_string());
+    }
+    let conn = abrir_conexion()?;
+    let duplicado = |e: rusqlite::Error| match e {
+        rusqlite::Error::SqliteFailure(err, _) if err.code == rusqlite::ErrorCode::ConstraintViolation => {
+            format!("Ya existe una lista llamada {}", nombre)
+        }
+        e => format!("Error al guardar la lista: {}", e),
+    };
+    let id = match lista.id {
+        Some(id) => {
+            nombre_lista(&conn, id)?;
+            conn.execute(
+                "UPDATE listas_precios SET nombre = ?1, ajuste = ?2 WHERE id = ?3",
+                rusqlite::params![nombre, lista.ajuste, id],
+            )
+            .map_err(duplicado)?;
+            id
+        }
+        None => {
+            conn.execute(
+                "INSERT INTO listas_precios (nombre, ajuste) VALUES (?1, ?2)",
+                rusqlite::params![nombre, lista.ajuste],
+            )
+            .map_err(duplicado)?;
+            conn.last_insert_rowid()
+        }
+    };
+    auditoria::registrar_auditoria(
+        &conn,
+        "guardar_lista_precios",
+        &format!("lista_precios:{}", id),
+        None,
+        serde_json::to_value(&lista).ok(),
+        true,
+    )?;
+    Ok(id)
+}
+
+/// Lista de precios por defecto del cliente; `None` vuelve a la minorista.
+#[tauri::command]
+pub fn asignar_lista_cliente(cliente_id: i64, lista_id: Option<i64>) -> Result<(), String> {
+    require_permiso(Permiso::EditarPrecios)?;
+    let conn = abrir_conexion()?;
+    let cliente = clientes::cargar_cliente(&conn, cliente_id)?;
+    if let Some(id) = lista_id {
+        nombre_lista(&conn, id)?;
+    }
+    conn.execute(
+        "UPDATE clientes SET lista_precios_id = ?1 WHERE id = ?2",
+        rusqlite::params![lista_id, cliente_id],
+    )
+    .map_err(|e| format!("Error al actualizar: {}", e))?;
+    auditoria::registrar_auditoria(
+        &conn,
+        "asignar_lista_cliente",
+        &format!("cliente:{}", cliente_id),
+        Some(serde_json::json!({ "lista_precios_id": cliente.lista_precios_id })),
+        Some(serde_json::json!({ "lista_precios_id": lista_id })),
+        true,
+    )
+}
+
+/// Precio del producto en cada lista.
+#[tauri::command]
+pub fn precios_producto(producto_id: i64) -> Result<Vec<PrecioLista>, String> {
+    require_permiso(Permiso::ConsultarInventario)?;
+    let conn = abrir_conexion()?;
+    precio_minorista(&conn, producto_id)?;
+    let mut stmt = conn
+        .prepare("SELECT id, nombre FROM listas_precios ORDER BY id")
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let listas = stmt
+        .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))
+        .map_err(|e| format!("Error al leer listas de precios: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+
+    listas
+        .into_iter()
+        .map(|(lista_id, lista)| {
+            Ok(PrecioLista {
+                lista_id,
+                lista,
+                precio: precio_en_lista(&conn, producto_id, lista_id)?,
+                propio: lista_id == LISTA_MINORISTA || precio_propio(&conn, producto_id, lista_id)?.is_some(),
+            })
+        })
+        .collect()
+}
+
+/// Fija el precio del producto en una lista, de inmediato o desde la fecha `desde`.
+#[tauri::command]
+pub fn fijar_precio(producto_id: i64, lista_id: i64, precio: f64, desde: Option<String>) -> Result<(), String> {
+    require_permiso(Permiso::EditarPrecios)?;
+    let precio = validar_precio(precio)?;
+    let programada = fecha_programada(desde.as_deref())?;
+    let mut conn = abrir_conexion()?;
+    nombre_lista(&conn, lista_id)?;
+    let anterior = precio_en_lista(&conn, producto_id, lista_id)?;
+
+    let tx = conn
+        .transaction()
+        .map_err(|e| format!("Error al iniciar la transaccion: {}", e))?;
+    match &programada {
+        Some(fecha) => programar(&tx, producto_id, lista_id, precio, fecha)?,
+        None => fijar(&tx, producto_id, lista_id, precio, "manual")?,
+    }
+    auditoria::registrar_auditoria(
+        &tx,
+        "fijar_precio",
+        &format!("inventario:{}", producto_id),
+        Some(serde_json::json!({ "lista_id": lista_id, "precio": anterior })),
+        Some(serde_json::json!({ "lista_id": lista_id, "precio": precio, "desde": programada })),
+        true,
+    )?;
+    tx.commit()
+        .map_err(|e| format!("Error al confirmar: {}", e))
+}
+
+#[tauri::command]
+pub fn listar_precios_programados() -> Result<Vec<PrecioProgramado>, String> {
+    require_permiso(Permiso::ConsultarInventario)?;
+    let conn = abrir_conexion()?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT p.id, p.lista_id, l.nombre, p.producto_id, i.nombre_producto, p.precio, p.desde, p.usuario \
+             FROM precios_programados p JOIN listas_precios l ON l.id = p.lista_id \
+             JOIN inventario i ON i.id = p.producto_id \
+             WHERE p.aplicado IS NULL ORDER BY p.desde, p.id",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let programados = stmt
+        .query_map([], |row| {
+            Ok(PrecioProgramado {
+                id: row.get(0)?,
+                lista_id: row.get(1)?,
+                lista: row.get(2)?,
+                producto_id: row.get(3)?,
+                nombre: row.get(4)?,
+                precio: row.get(5)?,
+                desde: row.get(6)?,
+                usuario: row.get(7)?,
+            })
+        })
+        .map_err(|e| format!("Error al leer precios programados: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    Ok(programados)
+}
+
+#[tauri::command]
+pub fn cancelar_precio_programado(id: i64) -> Result<(), String> {
+    require_permiso(Permiso::EditarPrecios)?;
+    let conn = abrir_conexion()?;
+    let affected = conn
+        .execute(
+            "DELETE FROM precios_programados WHERE id = ?1 AND aplicado IS NULL",
+            rusqlite::params![id],
+        )
+        .map_err(|e| format!("Error al cancelar el precio programado: {}", e))?;
+    if affected == 0 {
+        return Err("No se encontro el precio programado o ya fue aplicado".to_string());
+    }
+    auditoria::registrar_auditoria(&conn, "cancelar_precio_programado", &format!("precio_programado:{}", id), None, None, true)
+}
+
+fn calcular_cambios(conn: &Connection, actualizacion: &ActualizacionPrecios) -> Result<Vec<CambioPrecio>, String> {
+    nombre_lista(conn, actualizacion.lista_id)?;
+    let valor = actualizacion.valor;
+    if !valor.is_finite() || valor <= -100.0 {
+        return Err("El porcentaje debe ser mayor a -100%".to_string());
+    }
+    let por_margen = match actualizacion.modo.trim().to_lowercase().as_str() {
+        "porcentaje" => false,
+        "margen" => true,
+        otro => return Err(format!("Modo de actualizacion no soportado: {}", otro)),
+    };
+
+    let (condiciones, params) = actualizacion.filtro.clausula();
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT id, nombre_producto FROM inventario WHERE 1 = 1{} ORDER BY nombre_producto",
+            condiciones
+        ))
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let productos = stmt
+        .query_map(rusqlite::params_from_iter(params), |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))
+        .map_err(|e| format!("Error al leer inventarios: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+
+    let mut cambios = Vec::new();
+    for (producto_id, nombre) in productos {
+        let anterior = precio_en_lista(conn, producto_id, actualizacion.lista_id)?;
+        let nuevo = if por_margen {
+            // Sin costo registrado no hay de donde calcular el precio
+            let costo = costos::costo_promedio(conn, producto_id)?;
+            if costo <= 0.0 {
+                continue;
+            }
+            redondear(costo * (1.0 + valor / 100.0))
+        } else {
+            redondear(anterior * (1.0 + valor / 100.0))
+        };
+        if (nuevo - anterior).abs() >= 0.005 {
+            cambios.push(CambioPrecio {
+                producto_id,
+                nombre,
+                anterior,
+                nuevo,
+            });
+        }
+    }
+    Ok(cambios)
+}
+
+/// Vista previa de la actualizacion masiva: los productos que cambian y su nuevo precio.
+#[tauri::command]
+pub fn previsualizar_actualizacion_precios(actualizacion: ActualizacionPrecios) -> Result<Vec<CambioPrecio>, String> {
+    require_permiso(Permiso::EditarPrecios)?;
+    let conn = abrir_conexion()?;
+    calcular_cambios(&conn, &actualizacion)
+}
+
+/// Aplica (o programa, si `desde` es futura) los cambios de la vista previa. Devuelve
+/// cuantos productos cambiaron.
+#[tauri::command]
+pub fn aplicar_actualizacion_precios(actualizacion: ActualizacionPrecios) -> Result<usize, String> {
+    require_permiso(Permiso::EditarPrecios)?;
+    let programada = fecha_programada(actualizacion.desde.as_deref())?;
+    let mut conn = abrir_conexion()?;
+    let cambios = calcular_cambios(&conn, &actualizacion)?;
+
+    let tx = conn
+        .transaction()
+        .map_err(|e| format!("Error al iniciar la transaccion: {}", e))?;
+    for cambio in &cambios {
+        match &programada {
+            Some(fecha) => programar(&tx, cambio.producto_id, actualizacion.lista_id, cambio.nuevo, fecha)?,
+            None => fijar(&tx, cambio.producto_id, actualizacion.lista_id, cambio.nuevo, "masivo")?,
+        }
+    }
+    auditoria::registrar_auditoria(
+        &tx,
+        "actualizar_precios",
+        &format!("lista_precios:{}", actualizacion.lista_id),
+        None,
+        Some(serde_json::json!({ "actualizacion": &actualizacion, "productos": cambios.len() })),
+        true,
+    )?;
+    tx.commit()
+        .map_err(|e| format!("Error al confirmar: {}", e))?;
+    Ok(cambios.len())
+}
+
+/// Cambios de precio del producto en todas las listas, del mas reciente al mas antiguo.
+#[tauri::command]
+pub fn historial_precios(producto_id: i64) -> Result<Vec<HistorialPrecio>, String> {
+    require_permiso(Permiso::ConsultarInventario)?;
+    let conn = abrir_conexion()?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT h.lista_id, l.nombre, h.fecha, h.anterior, h.nuevo, h.origen, h.usuario \
+             FROM historial_precios h JOIN listas_precios l ON l.id = h.lista_id \
+             WHERE h.producto_id = ?1 ORDER BY h.id DESC",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let historial = stmt
+        .query_map(rusqlite::params![producto_id], |row| {
+            Ok(HistorialPrecio {
+                lista_id: row.get(0)?,
+                lista: row.get(1)?,
+                fecha: row.get(2)?,
+                anterior: row.get(3)?,
+                nuevo: row.get(4)?,
+                origen: row.get(5)?,
+                usuario: row.get(6)?,
+            })
+        })
+        .map_err(|e| format!("Error al leer el historial de precios: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    Ok(historial)
+}
@@ -0,0 +1,563 @@
+// Descuentos, promociones y cambios de precio en caja.
+//
+// El backend recalcula cada linea a partir del precio del inventario: aplica la mejor
+// promocion vigente del producto, luego el descuento manual de la linea y al final el
+// descuento del ticket, que se reparte entre las lineas para que una devolucion reintegre
+// exactamente lo cobrado. Un precio manual deja la linea fuera de las promociones; tanto el
+// precio manual como los descuentos manuales requieren permiso de edicion de precios o la
+// aprobacion de un supervisor.
+
+use chrono::Local;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+
+use crate::catalogo::{categoria_y_ancestros, validar_cantidad};
+use crate::comun::redondear;
+use crate::exportacion::parse_fecha;
+use crate::impuestos::{self, DesgloseImpuesto};
+use crate::permisos::{credenciales_aprobacion, require_permiso, tiene_permiso, validar_aprobacion, Permiso};
+use crate::{precios, series};
+use crate::{abrir_conexion, auditoria, format_money, VentaItem};
+
+const TIPOS_PROMOCION: [&str; 3] = ["2x1", "lleve_n", "porcentaje"];
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Descuento {
+    /// "porcentaje" o "monto"
+    tipo: String,
+    valor: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Promocion {
+    id: Option<i64>,
+    nombre: String,
+    /// "2x1", "lleve_n" (cada grupo de N unidades lleva M% de descuento) o "porcentaje"
+    tipo: String,
+    producto_id: Option<i64>,
+    /// Incluye las subcategorias
+    categoria_id: Option<i64>,
+    cantidad_minima: Option<i64>,
+    porcentaje: Option<f64>,
+    desde: Option<String>,
+    hasta: Option<String>,
+    activa: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CalculoVentaRequest {
+    pub(crate) ventas: Vec<VentaItem>,
+    pub(crate) descuento_ticket: Option<Descuento>,
+    /// Quien aprueba precios manuales, descuentos o cambio de lista sin permiso propio
+    pub(crate) supervisor_usuario: Option<String>,
+    pub(crate) supervisor_password: Option<String>,
+    pub(crate) cliente_id: Option<i64>,
+    /// Lista de precios distinta de la del cliente; requiere permiso de edicion de precios
+    /// o la clave de un supervisor
+    #[serde(default)]
+    pub(crate) lista_id: Option<i64>,
+    /// Venta al fiado: se carga a la cuenta del cliente
+    #[serde(default)]
+    pub(crate) a_credito: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TicketCalculado {
+    pub(crate) ventas: Vec<VentaItem>,
+    pub(crate) subtotal: f64,
+    pub(crate) descuento: f64,
+    pub(crate) impuestos: Vec<DesgloseImpuesto>,
+    pub(crate) total: f64,
+}
+
+impl Descuento {
+    fn monto(&self, base: f64) -> Result<f64, String> {
+        if !self.valor.is_finite() || self.valor < 0.0 {
+            return Err("El descuento no puede ser negativo".to_string());
+        }
+        let monto = match self.tipo.as_str() {
+            "porcentaje" if self.valor > 100.0 => {
+                return Err("El descuento no puede superar el 100%".to_string())
+            }
+            "porcentaje" => base * self.valor / 100.0,
+            "monto" => self.valor,
+            otro => return Err(format!("Tipo de descuento no soportado: {}", otro)),
+        };
+        if monto > base + 0.005 {
+            return Err(format!("El descuento supera el importe ({})", format_money(base)));
+        }
+        Ok(redondear(monto))
+    }
+
+    fn etiqueta(&self) -> String {
+        match self.tipo.as_str() {
+            "porcentaje" => format!("-{}%", self.valor),
+            _ => format!("-{}", format_money(self.valor)),
+        }
+    }
+}
+
+impl Promocion {
+    /// `categorias` es la categoria del producto y sus ancestras.
+    fn aplica(&self, producto_id: i64, categorias: &[i64]) -> bool {
+        match (self.producto_id, self.categoria_id) {
+            (Some(id), _) => id == producto_id,
+            (None, Some(categoria_id)) => categorias.contains(&categoria_id),
+            (None, None) => false,
+        }
+    }
+
+    /// Los grupos de 2x1 y lleve_n se cuentan en unidades enteras.
+    fn descuento(&self, precio: f64, cantidad: f64) -> f64 {
+        let porcentaje = self.porcentaje.unwrap_or(0.0) / 100.0;
+        match self.tipo.as_str() {
+            "2x1" => (cantidad / 2.0).floor() * precio,
+            "lleve_n" => {
+                let n = self.cantidad_minima.unwrap_or(0);
+                if n <= 0 {
+                    return 0.0;
+                }
+                let n = n as f64;
+                (cantidad / n).floor() * n * precio * porcentaje
+            }
+            "porcentaje" => cantidad * precio * porcentaje,
+            _ => 0.0,
+        }
+    }
+
+    fn validar(&self) -> Result<(), String> {
+        if self.nombre.trim().is_empty() {
+            return Err("El nombre de la promocion es obligatorio".to_string());
+        }
+        if !TIPOS_PROMOCION.contains(&self.tipo.as_str()) {
+            return Err(format!("Tipo de promocion no soportado: {}", self.tipo));
+        }
+        if self.producto_id.is_some() == self.categoria_id.is_some() {
+            return Err("Indique un producto o una categoria (solo uno)".to_string());
+        }
+        if self.tipo != "2x1" {
+            match self.porcentaje {
+                Some(p) if p > 0.0 && p <= 100.0 => {}
+                _ => return Err("El porcentaje debe estar entre 0 y 100".to_string()),
+            }
+        }
+        if self.tipo == "lleve_n" && self.cantidad_minima.unwrap_or(0) < 2 {
+            return Err("La cantidad minima debe ser al menos 2".to_string());
+        }
+        let desde = self.desde.as_deref().map(parse_fecha).transpose()?;
+        let hasta = self.hasta.as_deref().map(parse_fecha).transpose()?;
+        if let (Some(desde), Some(hasta)) = (desde, hasta) {
+            if desde > hasta {
+                return Err("La fecha inicial no puede ser posterior a la final".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+fn leer_promociones(conn: &Connection, solo_vigentes: bool) -> Result<Vec<Promocion>, String> {
+    let hoy = Local::now().format("%Y-%m-%d").to_string();
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, nombre, tipo, producto_id, categoria_id, cantidad_minima, porcentaje, desde, hasta, activa \
+             FROM promociones \
+             WHERE ?1 = 0 OR (activa = 1 AND (desde IS NULL OR desde <= ?2) AND (hasta IS NULL OR hasta >= ?2)) \
+             ORDER BY id",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+
+    let promociones = stmt
+        .query_map(rusqlite::params![solo_vigentes, hoy], |row| {
+            Ok(Promocion {
+                id: row.get(0)?,
+                nombre: row.get(1)?,
+                tipo: row.get(2)?,
+                producto_id: row.get(3)?,
+                categoria_id: row.get(4)?,
+                cantidad_minima: row.get(5)?,
+                porcentaje: row.get(6)?,
+                desde: row.get(7)?,
+                hasta: row.get(8)?,
+                activa: row.get::<_, i64>(9)? == 1,
+            })
+        })
+        .map_err(|e| format!("Error al leer promociones: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    Ok(promociones)
+}
+
+/// Recalcula precios y descuentos del carrito. Lo que envie la interfaz en `precio` y
+/// `subtotal` se ignora; solo se respetan `precio_manual` y `descuento_linea`. El precio
+/// de lista sale de la lista del cliente, o de `lista_id` si se pide otra. Con
+/// `puede_editar_precios` (el permiso de quien vende) no se pide aprobacion de supervisor.
+pub(crate) fn calcular_ticket(
+    conn: &Connection,
+    pedido: &CalculoVentaRequest,
+    puede_editar_precios: bool,
+) -> Result<TicketCalculado, String> {
+    let ventas = &pedido.ventas;
+    let supervisor_usuario = pedido.supervisor_usuario.as_deref();
+    let supervisor_password = pedido.supervisor_password.as_deref();
+    let promociones = leer_promociones(conn, true)?;
+    let mut lineas = Vec::with_capacity(ventas.len());
+
+    // Una sola aprobacion cubre todo el ticket
+    let mut precio_aprobado = puede_editar_precios;
+    let mut aprobar = |mensaje: &str| -> Result<(), String> {
+        if !precio_aprobado {
+            let (usuario, pass) = credenciales_aprobacion(supervisor_usuario, supervisor_password, mensaje)?;
+            validar_aprobacion(conn, Permiso::EditarPrecios, usuario, pass)?;
+            precio_aprobado = true;
+        }
+        Ok(())
+    };
+
+    let lista_cliente = precios::lista_de_cliente(conn, pedido.cliente_id)?;
+    let lista_id = match pedido.lista_id {
+        Some(id) if id != lista_cliente => {
+            aprobar("Cambiar la lista de precios requiere la aprobacion de un supervisor")?;
+            id
+        }
+        _ => lista_cliente,
+    };
+
+    for venta in ventas {
+        let cantidad = validar_cantidad(conn, venta.id, venta.cantidad)?;
+        let series = series::validar_series_venta(conn, venta.id, cantidad, &venta.series)?;
+        let (nombre, categoria_id): (String, Option<i64>) = conn
+            .query_row(
+                "SELECT nombre_producto, categoria_id FROM inventario WHERE id = ?1",
+                rusqlite::params![venta.id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .map_err(|_| format!("No se encontro el producto {}", venta.id))?;
+        let precio_lista = precios::precio_en_lista(conn, venta.id, lista_id)?;
+        let categorias = match categoria_id {
+            Some(id) => categoria_y_ancestros(conn, id)?,
+            None => Vec::new(),
+        };
+
+        let mut etiquetas = Vec::new();
+        let precio_manual = venta
+            .precio_manual
+            .filter(|p| (p - precio_lista).abs() >= 0.005);
+
+        let (precio, mut descuento) = match precio_manual {
+            Some(precio) => {
+                if !precio.is_finite() || precio < 0.0 {
+                    return Err(format!("Precio manual invalido para {}", nombre));
+                }
+                aprobar("El cambio de precio requiere la aprobacion de un supervisor")?;
+                etiquetas.push(format!("Precio manual (lista {})", format_money(precio_lista)));
+                (precio, 0.0)
+            }
+            None => {
+                let mejor = promociones
+                    .iter()
+                    .filter(|p| p.aplica(venta.id, &categorias))
+                    .map(|p| (p, redondear(p.descuento(precio_lista, cantidad))))
+                    .filter(|(_, d)| *d > 0.0)
+                    .max_by(|a, b| a.1.total_cmp(&b.1));
+                match mejor {
+                    Some((promocion, descuento)) => {
+                        etiquetas.push(promocion.nombre.clone());
+                        (precio_lista, descuento)
+                    }
+                    None => (precio_lista, 0.0),
+                }
+            }
+        };
+
+        let bruto = redondear(precio * cantidad);
+        if let Some(manual) = &venta.descuento_linea {
+            let monto = manual.monto(bruto - descuento)?;
+            if monto > 0.0 {
+                aprobar("Un descuento manual requiere la aprobacion de un supervisor")?;
+            }
+            descuento = redondear(descuento + monto);
+            etiquetas.push(manual.etiqueta());
+        }
+
+        lineas.push(VentaItem {
+            id: venta.id,
+            nombre,
+            precio,
+            cantidad,
+            subtotal: redondear(bruto - descuento),
+            descuento,
+            promocion: Some(etiquetas.join("; ")).filter(|e| !e.is_empty()),
+            descuento_linea: venta.descuento_linea.clone(),
+            precio_manual,
+            series,
+            ubicacion_id: venta.ubicacion_id,
+            ..Default::default()
+        });
+    }
+
+    if let Some(descuento) = pedido.descuento_ticket.as_ref() {
+        let base: f64 = lineas.iter().map(|l| l.subtotal).sum();
+        let monto = descuento.monto(base)?;
+        if monto > 0.0 {
+            aprobar("Un descuento sobre el ticket requiere la aprobacion de un supervisor")?;
+            // Reparto proporcional; la ultima linea absorbe el redondeo
+            let mut restante = monto;
+            let ultima = lineas.len() - 1;
+            for (idx, linea) in lineas.iter_mut().enumerate() {
+                let parte = if idx == ultima {
+                    restante
+                } else {
+                    redondear(monto * linea.subtotal / base).min(restante)
+                };
+                restante = redondear(restante - parte);
+                linea.descuento = redondear(linea.descuento + parte);
+                linea.subtotal = redondear(linea.subtotal - parte);
+                let etiqueta = format!("Desc. ticket {}", descuento.etiqueta());
+                linea.promocion = Some(match linea.promocion.take() {
+                    Some(previa) => format!("{}; {}", previa, etiqueta),
+                    None => etiqueta,
+                });
+            }
+        }
+    }
+
+    // El impuesto va al final, sobre el importe ya descontado
+    for linea in lineas.iter_mut() {
+        let (tasa, precio_incluye) = impuestos::tasa_producto(conn, linea.id)?;
+        impuestos::aplicar_impuesto(linea, tasa, precio_incluye);
+    }
+
+    let subtotal = redondear(lineas.iter().map(|l| l.precio * l.cantidad).sum());
+    let descuento = redondear(lineas.iter().map(|l| l.descuento).sum());
+    let total = redondear(lineas.iter().map(|l| l.subtotal).sum());
+    Ok(TicketCalculado {
+        impuestos: impuestos::desglosar(&lineas),
+        ventas: lineas,
+        subtotal,
+        descuento,
+        total,
+    })
+}
+
+/// Vista previa del ticket con promociones y descuentos, sin registrar nada.
+#[tauri::command]
+pub fn calcular_venta(payload: CalculoVentaRequest) -> Result<TicketCalculado, String> {
+    require_permiso(Permiso::Vender)?;
+    let conn = abrir_conexion()?;
+    calcular_ticket(&conn, &payload, tiene_permiso(Permiso::EditarPrecios))
+}
+
+#[tauri::command]
+pub fn listar_promociones() -> Result<Vec<Promocion>, String> {
+    require_permiso(Permiso::ConsultarInventario)?;
+    let conn = abrir_conexion()?;
+    leer_promociones(&conn, false)
+}
+
+#[tauri::command]
+pub fn guardar_promocion(promocion: Promocion) -> Result<i64, String> {
+    require_permiso(Permiso::EditarPrecios)?;
+    promocion.validar()?;
+    let conn = abrir_conexion()?;
+    if let Some(categoria_id) = promocion.categoria_id {
+        let existe: bool = conn
+            .query_row(
+                "SELECT EXISTS (SELECT 1 FROM categorias WHERE id = ?1)",
+                rusqlite::params![categoria_id],
+                |row| row.get(0),
+            )
+            .map_err(|e| format!("Error en la consulta: {}", e))?;
+        if !existe {
+            return Err("No se encontro la categoria".to_string());
+        }
+    }
+    let params = rusqlite::params![
+        promocion.nombre.trim(),
+        promocion.tipo,
+        promocion.producto_id,
+        promocion.categoria_id,
+        promocion.cantidad_minima,
+        promocion.porcentaje,
+        promocion.desde.as_deref().map(str::trim),
+        promocion.hasta.as_deref().map(str::trim),
+        promocion.activa,
+        promocion.id,
+    ];
+
+    let id = match promocion.id {
+        Some(id) => {
+            let affected = conn
+                .execute(
+                    "UPDATE promociones SET nombre = ?1, tipo = ?2, producto_id = ?3, categoria_id = ?4, \
+                     cantidad_minima = ?5, porcentaje = ?6, desde = ?7, hasta = ?8, activa = ?9 WHERE id = ?10",
+                    params,
+                )
+                .map_err(|e| format!("Error al actualizar la promocion: {}", e))?;
+            if affected == 0 {
+                return Err("No se encontro la promocion".to_string());
+            }
+            id
+        }
+        None => {
+            conn.execute(
+                "INSERT INTO promociones (nombre, tipo, producto_id, categoria_id, cantidad_minima, porcentaje, \
+                 desde, hasta, activa) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                &params[..9],
+            )
+            .map_err(|e| format!("Error al guardar la promocion: {}", e))?;
+            conn.last_insert_rowid()
+        }
+    };
+
+    auditoria::registrar_auditoria(
+        &conn,
+        "guardar_promocion",
+        &format!("promocion:{}", id),
+        None,
+        serde_json::to_value(&promocion).ok(),
+        true,
+    )?;
+    Ok(id)
+}
+
+#[tauri::command]
+pub fn eliminar_promocion(id: i64) -> Result<(), String> {
+    require_permiso(Permiso::EditarPrecios)?;
+    let conn = abrir_conexion()?;
+    let affected = conn
+        .execute("DELETE FROM promociones WHERE id = ?1", rusqlite::params![id])
+        .map_err(|e| format!("Error al eliminar la promocion: {}", e))?;
+    if affected == 0 {
+        return Err("No se encontro la promocion".to_string());
+    }
+    auditoria::registrar_auditoria(&conn, "eliminar_promocion", &format!("promocion:{}", id), None, None, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comun::pruebas;
+
+    fn base() -> Connection {
+        let conn = pruebas::base(&[(1, "Yerba", "Y1", 100.0), (2, "Azucar", "A1", 100.0)]);
+        conn.execute("UPDATE inventario SET precio_producto = '50' WHERE id = 2", []).unwrap();
+        conn
+    }
+
+    fn promocion(conn: &Connection, nombre: &str, tipo: &str, porcentaje: Option<f64>) {
+        conn.execute(
+            "INSERT INTO promociones (nombre, tipo, producto_id, porcentaje) VALUES (?1, ?2, 1, ?3)",
+            rusqlite::params![nombre, tipo, porcentaje],
+        )
+        .unwrap();
+    }
+
+    fn pedido(valor: serde_json::Value) -> CalculoVentaRequest {
+        serde_json::from_value(valor).unwrap()
+    }
+
+    /// El mismo carrito con la clave del administrador inicial como supervisor.
+    fn aprobado(mut valor: serde_json::Value) -> CalculoVentaRequest {
+        valor["supervisor_usuario"] = "user".into();
+        valor["supervisor_password"] = "user".into();
+        pedido(valor)
+    }
+
+    fn precio_manual() -> serde_json::Value {
+        serde_json::json!({
+            "ventas": [{ "id": 1, "nombre": "", "precio": 0.0, "cantidad": 2.0, "subtotal": 0.0, "precio_manual": 80.0 }]
+        })
+    }
+
+    #[test]
+    fn se_aplica_la_mejor_promocion_vigente() {
+        let conn = base();
+        promocion(&conn, "Diez por ciento", "porcentaje", Some(10.0));
+        promocion(&conn, "Dos por uno", "2x1", None);
+
+        let ticket = calcular_ticket(
+            &conn,
+            &pedido(serde_json::json!({
+                "ventas": [{ "id": 1, "nombre": "", "precio": 1.0, "cantidad": 3.0, "subtotal": 3.0 }]
+            })),
+            false,
+        )
+        .unwrap();
+
+        let linea = &ticket.ventas[0];
+        assert_eq!((linea.precio, linea.descuento, linea.subtotal), (100.0, 100.0, 200.0));
+        assert_eq!(linea.promocion.as_deref(), Some("Dos por uno"));
+        assert_eq!((ticket.subtotal, ticket.descuento, ticket.total), (300.0, 100.0, 200.0));
+    }
+
+    #[test]
+    fn el_precio_manual_deja_la_linea_fuera_de_las_promociones() {
+        let conn = base();
+        promocion(&conn, "Dos por uno", "2x1", None);
+
+        let ticket = calcular_ticket(&conn, &pedido(precio_manual()), true).unwrap();
+
+        let linea = &ticket.ventas[0];
+        assert_eq!((linea.precio, linea.descuento, linea.subtotal), (80.0, 0.0, 160.0));
+    }
+
+    #[test]
+    fn sin_permiso_el_precio_manual_pide_un_supervisor() {
+        let conn = base();
+
+        let error = calcular_ticket(&conn, &pedido(precio_manual()), false).err();
+        assert_eq!(
+            error.as_deref(),
+            Some("El cambio de precio requiere la aprobacion de un supervisor")
+        );
+
+        let mut rechazado = precio_manual();
+        rechazado["supervisor_usuario"] = "user".into();
+        rechazado["supervisor_password"] = "otra".into();
+        assert!(calcular_ticket(&conn, &pedido(rechazado), false).is_err());
+
+        let ticket = calcular_ticket(&conn, &aprobado(precio_manual()), false).unwrap();
+        assert_eq!(ticket.total, 160.0);
+    }
+
+    #[test]
+    fn el_descuento_del_ticket_se_reparte_sin_perder_centavos() {
+        let conn = base();
+
+        let ticket = calcular_ticket(
+            &conn,
+            &aprobado(serde_json::json!({
+                "ventas": [
+                    { "id": 1, "nombre": "", "precio": 0.0, "cantidad": 1.0, "subtotal": 0.0 },
+                    { "id": 2, "nombre": "", "precio": 0.0, "cantidad": 1.0, "subtotal": 0.0 }
+                ],
+                "descuento_ticket": { "tipo": "monto", "valor": 10.0 }
+            })),
+            false,
+        )
+        .unwrap();
+
+        let descuentos: Vec<f64> = ticket.ventas.iter().map(|l| l.descuento).collect();
+        assert_eq!(descuentos, vec![6.67, 3.33]);
+        assert_eq!((ticket.descuento, ticket.total), (10.0, 140.0));
+    }
+
+    #[test]
+    fn un_descuento_mayor_al_importe_se_rechaza() {
+        let conn = base();
+
+        let error = calcular_ticket(
+            &conn,
+            &pedido(serde_json::json!({
+                "ventas": [{
+                    "id": 1, "nombre": "", "precio": 0.0, "cantidad": 1.0, "subtotal": 0.0,
+                    "descuento_linea": { "tipo": "monto", "valor": 150.0 }
+                }]
+            })),
+            true,
+        )
+        .err();
+
+        assert_eq!(error.as_deref(), Some("El descuento supera el importe ($100.00)"));
+    }
+}
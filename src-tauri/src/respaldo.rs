@@ -0,0 +1,230 @@
+// Respaldos de la base de datos usando la API de backup en linea de SQLite.
+//
+// Los respaldos se guardan en `Documentos/respaldos`. Los manuales llevan fecha y hora
+// en el nombre; los automaticos (`auto-AAAAMMDD.db`) se toman una vez al dia al iniciar
+// la aplicacion o al cerrar el dia, y solo se conservan los ultimos
+// `RESPALDOS_AUTOMATICOS_A_CONSERVAR`.
+
+use chrono::Local;
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::permisos::{require_permiso, Permiso};
+use crate::{abrir_conexion, auditoria, crear_tablas, find_db_path, leer_usuario_sesion, validar_admin_password};
+
+const RESPALDOS_AUTOMATICOS_A_CONSERVAR: usize = 7;
+const PREFIJO_AUTOMATICO: &str = "auto-";
+
+#[derive(Serialize, Deserialize)]
+pub struct Respaldo {
+    nombre: String,
+    ruta: String,
+    tamano: u64,
+    automatico: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct VerificacionRespaldo {
+    ruta: String,
+    valido: bool,
+    detalles: Vec<String>,
+}
+
+fn get_documentos_respaldos_dir() -> Result<PathBuf, String> {
+    let documentos = dirs::document_dir().ok_or_else(|| "No se pudo obtener la carpeta Documentos".to_string())?;
+    let respaldos_dir = documentos.join("respaldos");
+    if let Err(e) = fs::create_dir_all(&respaldos_dir) {
+        return Err(format!("No se pudo crear la carpeta de respaldos: {}", e));
+    }
+    Ok(respaldos_dir)
+}
+
+fn copiar_base_de_datos(destino: &Path) -> Result<(), String> {
+    let conn = abrir_conexion()?;
+    conn.backup("main", destino, None)
+        .map_err(|e| format!("Error al crear el respaldo: {}", e))
+}
+
+fn hash_archivo(ruta: &Path) -> Result<String, String> {
+    let contenido = fs::read(ruta).map_err(|e| format!("No se pudo leer {}: {}", ruta.display(), e))?;
+    Ok(format!("{:x}", Sha256::digest(&contenido)))
+}
+
+/// Ultimo eslabon de la auditoria de una copia, si la tiene.
+fn ultimo_hash_auditoria(ruta: &Path) -> Result<Option<String>, String> {
+    let conn = Connection::open_with_flags(ruta, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| format!("No se pudo abrir {}: {}", ruta.display(), e))?;
+    conn.query_row("SELECT hash FROM auditoria ORDER BY id DESC LIMIT 1", [], |row| row.get(0))
+        .optional()
+        .map_err(|e| format!("Error al leer la auditoria de {}: {}", ruta.display(), e))
+}
+
+fn integridad(ruta: &Path) -> Result<Vec<String>, String> {
+    let conn = Connection::open_with_flags(ruta, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| format!("No se pudo abrir el respaldo: {} (ruta={})", e, ruta.display()))?;
+    let mut stmt = conn
+        .prepare("PRAGMA integrity_check")
+        .map_err(|e| format!("Error al verificar el respaldo: {}", e))?;
+    let filas = stmt
+        .query_map([], |row| row.get::<_, String>(0))
+        .map_err(|e| format!("Error al verificar el respaldo: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error al verificar el respaldo: {}", e))?;
+    Ok(filas)
+}
+
+fn podar_respaldos_automaticos(respaldos_dir: &Path) -> Result<(), String> {
+    let mut automaticos: Vec<PathBuf> = fs::read_dir(respaldos_dir)
+        .map_err(|e| format!("No se pudo leer la carpeta de respaldos: {}", e))?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|ruta| {
+            ruta.file_name()
+                .and_then(|n| n.to_str())
+                .map(|n| n.starts_with(PREFIJO_AUTOMATICO) && n.ends_with(".db"))
+                .unwrap_or(false)
+        })
+        .collect();
+
+    // El nombre lleva la fecha AAAAMMDD, asi que el orden alfabetico es cronologico
+    automaticos.sort();
+    let sobrantes = automaticos.len().saturating_sub(RESPALDOS_AUTOMATICOS_A_CONSERVAR);
+    for ruta in automaticos.into_iter().take(sobrantes) {
+        if let Err(e) = fs::remove_file(&ruta) {
+            println!("[warn] no se pudo eliminar el respaldo {}: {}", ruta.display(), e);
+        }
+    }
+    Ok(())
+}
+
+/// Toma el respaldo automatico del dia. Al iniciar solo se crea si no existe; al cerrar el dia
+/// se reemplaza para que incluya las ventas de la jornada.
+pub(crate) fn respaldo_automatico_diario(reemplazar: bool) -> Result<Option<PathBuf>, String> {
+    let respaldos_dir = get_documentos_respaldos_dir()?;
+    let ruta = respaldos_dir.join(format!("{}{}.db", PREFIJO_AUTOMATICO, Local::now().format("%Y%m%d")));
+    if ruta.exists() && !reemplazar {
+        return Ok(None);
+    }
+
+    // La copia se arma aparte y recien al terminar reemplaza a la del dia, para no quedar
+    // sin respaldo si falla a mitad de camino
+    let temporal = ruta.with_extension("db.tmp");
+    let _ = fs::remove_file(&temporal);
+    if let Err(e) = copiar_base_de_datos(&temporal) {
+        let _ = fs::remove_file(&temporal);
+        return Err(e);
+    }
+    fs::rename(&temporal, &ruta).map_err(|e| {
+        let _ = fs::remove_file(&temporal);
+        format!("No se pudo reemplazar el respaldo del dia: {}", e)
+    })?;
+    podar_respaldos_automaticos(&respaldos_dir)?;
+    println!("[info] respaldo automatico creado en {}", ruta.display());
+    Ok(Some(ruta))
+}
+
+#[tauri::command]
+pub fn crear_respaldo() -> Result<Respaldo, String> {
+    require_permiso(Permiso::AdministrarSistema)?;
+    let respaldos_dir = get_documentos_respaldos_dir()?;
+    let nombre = format!("database-{}.db", Local::now().format("%Y%m%d-%H%M%S"));
+    let ruta = respaldos_dir.join(&nombre);
+    copiar_base_de_datos(&ruta)?;
+
+    let tamano = fs::metadata(&ruta).map(|m| m.len()).unwrap_or(0);
+    Ok(Respaldo {
+        nombre,
+        ruta: ruta.display().to_string(),
+        tamano,
+        automatico: false,
+    })
+}
+
+#[tauri::command]
+pub fn listar_respaldos() -> Result<Vec<Respaldo>, String> {
+    require_permiso(Permiso::AdministrarSistema)?;
+    let respaldos_dir = get_documentos_respaldos_dir()?;
+    let mut respaldos: Vec<Respaldo> = fs::read_dir(&respaldos_dir)
+        .map_err(|e| format!("No se pudo leer la carpeta de respaldos: {}", e))?
+        .flatten()
+        .filter_map(|entry| {
+            let nombre = entry.file_name().to_str()?.to_string();
+            if !nombre.ends_with(".db") {
+                return None;
+            }
+            Some(Respaldo {
+                automatico: nombre.starts_with(PREFIJO_AUTOMATICO),
+                ruta: entry.path().display().to_string(),
+                tamano: entry.metadata().map(|m| m.len()).unwrap_or(0),
+                nombre,
+            })
+        })
+        .collect();
+
+    respaldos.sort_by(|a, b| b.nombre.cmp(&a.nombre));
+    Ok(respaldos)
+}
+
+#[tauri::command]
+pub fn verificar_respaldo(ruta: String) -> Result<VerificacionRespaldo, String> {
+    require_permiso(Permiso::AdministrarSistema)?;
+    let detalles = integridad(Path::new(ruta.trim()))?;
+    Ok(VerificacionRespaldo {
+        valido: detalles.len() == 1 && detalles[0] == "ok",
+        ruta,
+        detalles,
+    })
+}
+
+#[tauri::command]
+pub fn restaurar_respaldo(ruta: String, admin_password: String) -> Result<(), String> {
+    require_permiso(Permiso::AdministrarSistema)?;
+    // Se vuelve a pedir la clave de quien tiene la sesion abierta
+    let usuario = leer_usuario_sesion().ok_or_else(|| "Debe iniciar sesion.".to_string())?;
+    validar_admin_password(&usuario, admin_password.trim())?;
+
+    let origen = PathBuf::from(ruta.trim());
+    let detalles = integridad(&origen)?;
+    if detalles.len() != 1 || detalles[0] != "ok" {
+        return Err(format!("El respaldo no paso la verificacion de integridad: {}", detalles.join("; ")));
+    }
+
+    // Antes de sobrescribir guardamos el estado actual por si hay que deshacer
+    let respaldos_dir = get_documentos_respaldos_dir()?;
+    let previo = respaldos_dir.join(format!("pre-restauracion-{}.db", Local::now().format("%Y%m%d-%H%M%S")));
+    copiar_base_de_datos(&previo)?;
+
+    let db_path = find_db_path();
+    let mut conn = Connection::open(&db_path)
+        .map_err(|e| format!("Error al conectar: {} (ruta={})", e, db_path.display()))?;
+    conn.restore("main", &origen, None::<fn(rusqlite::backup::Progress)>)
+        .map_err(|e| format!("Error al restaurar el respaldo: {}", e))?;
+
+    // Un respaldo antiguo puede no tener las tablas mas recientes
+    let tx = conn
+        .transaction()
+        .map_err(|e| format!("Error al actualizar el esquema: {}", e))?;
+    crear_tablas(&tx)?;
+    // La cadena restaurada vuelve atras en el tiempo: dejamos constancia de quien la
+    // restauro y de la copia previa, para poder contrastarla con `verificar_auditoria`
+    auditoria::registrar_auditoria(
+        &tx,
+        "restaurar_respaldo",
+        &origen.display().to_string(),
+        None,
+        Some(serde_json::json!({
+            "respaldo": origen.display().to_string(),
+            "copia_previa": previo.display().to_string(),
+            "sha256_copia_previa": hash_archivo(&previo)?,
+            "ultimo_hash_auditoria_previo": ultimo_hash_auditoria(&previo)?,
+        })),
+        true,
+    )?;
+    tx.commit()
+        .map_err(|e| format!("Error al actualizar el esquema: {}", e))?;
+    println!("[info] base de datos restaurada desde {}", origen.display());
+    Ok(())
+}
@@ -0,0 +1,274 @@
+// Proteccion contra fuerza bruta en el login y en la clave de administrador.
+//
+// Cada clave (`usuario:<nombre>`, `admin:<nombre>` o `global`) lleva un contador de fallos.
+// Tras el segundo fallo seguido hay que esperar un tiempo que se duplica con cada intento,
+// y al llegar a `MAX_FALLOS` la cuenta queda bloqueada hasta que pase `BLOQUEO_SEGUNDOS`
+// o un administrador la desbloquee. El contador global nunca bloquea, solo frena, y solo a
+// las claves con fallos recientes propios: quien prueba claves al azar no deja afuera a los
+// demas usuarios.
+
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::comun::ahora_unix;
+use crate::permisos::{require_permiso, Permiso};
+use crate::{abrir_conexion, auditoria};
+
+pub(crate) const MENSAJE_CREDENCIALES_INVALIDAS: &str = "Usuario o contraseña incorrectos";
+
+const MAX_FALLOS: i64 = 5;
+const BLOQUEO_SEGUNDOS: i64 = 15 * 60;
+const VENTANA_SEGUNDOS: i64 = 15 * 60;
+const RETARDO_MAXIMO_SEGUNDOS: i64 = 60;
+const FALLOS_GLOBALES_ANTES_DE_FRENAR: i64 = 10;
+const CLAVE_GLOBAL: &str = "global";
+
+#[derive(Serialize, Deserialize)]
+pub struct Bloqueo {
+    clave: String,
+    fallos: i64,
+    bloqueado_hasta: String,
+}
+
+struct EstadoIntentos {
+    fallos: i64,
+    ultimo_fallo: i64,
+    espera_hasta: i64,
+    bloqueado_hasta: i64,
+}
+
+pub(crate) fn clave_login(usuario: &str) -> String {
+    format!("usuario:{}", usuario.trim().to_lowercase())
+}
+
+pub(crate) fn clave_admin(usuario: &str) -> String {
+    format!("admin:{}", usuario.trim().to_lowercase())
+}
+
+fn leer_estado(conn: &Connection, clave: &str) -> Result<EstadoIntentos, String> {
+    let estado = conn
+        .query_row(
+            "SELECT fallos, ultimo_fallo, espera_hasta, bloqueado_hasta FROM intentos_acceso WHERE clave = ?1",
+            rusqlite::params![clave],
+            |row| {
+                Ok(EstadoIntentos {
+                    fallos: row.get(0)?,
+                    ultimo_fallo: row.get(1)?,
+                    espera_hasta: row.get(2)?,
+                    bloqueado_hasta: row.get(3)?,
+                })
+            },
+        )
+        .optional()
+        .map_err(|e| format!("Error al consultar intentos: {}", e))?;
+
+    Ok(estado.unwrap_or(EstadoIntentos {
+        fallos: 0,
+        ultimo_fallo: 0,
+        espera_hasta: 0,
+        bloqueado_hasta: 0,
+    }))
+}
+
+fn retardo(fallos: i64, umbral: i64) -> i64 {
+    if fallos < umbral {
+        return 0;
+    }
+    let exponente = (fallos - umbral).min(6) as u32;
+    2i64.pow(exponente).min(RETARDO_MAXIMO_SEGUNDOS)
+}
+
+/// Rechaza el intento si la clave esta bloqueada o todavia en periodo de espera, propio o
+/// global.
+pub(crate) fn comprobar_intento(conn: &Connection, clave: &str) -> Result<(), String> {
+    let momento = ahora_unix();
+    let propio = leer_estado(conn, clave)?;
+    let fallos_recientes = propio.fallos > 0 && momento - propio.ultimo_fallo <= VENTANA_SEGUNDOS;
+    for (clave_actual, es_global) in [(clave, false), (CLAVE_GLOBAL, true)] {
+        if es_global && !fallos_recientes {
+            continue;
+        }
+        let estado = leer_estado(conn, clave_actual)?;
+        if !es_global && estado.bloqueado_hasta > momento {
+            let minutos = (estado.bloqueado_hasta - momento + 59) / 60;
+            return Err(format!(
+                "Cuenta bloqueada temporalmente por intentos fallidos. Intente en {} minuto(s) o contacte a un administrador",
+                minutos
+            ));
+        }
+        if estado.espera_hasta > momento {
+            return Err(format!(
+                "Demasiados intentos. Espere {} segundo(s)",
+                estado.espera_hasta - momento
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Suma un fallo a la clave y al contador global.
+pub(crate) fn registrar_fallo(conn: &Connection, clave: &str) -> Result<(), String> {
+    let momento = ahora_unix();
+    for (clave_actual, es_global) in [(clave, false), (CLAVE_GLOBAL, true)] {
+        let estado = leer_estado(conn, clave_actual)?;
+        // Los fallos antiguos caducan si la cuenta no esta bloqueada
+        let previos = if momento - estado.ultimo_fallo > VENTANA_SEGUNDOS && estado.bloqueado_hasta <= momento {
+            0
+        } else {
+            estado.fallos
+        };
+        let fallos = previos + 1;
+
+        let (espera_hasta, bloqueado_hasta) = if es_global {
+            (momento + retardo(fallos, FALLOS_GLOBALES_ANTES_DE_FRENAR), 0)
+        } else if fallos >= MAX_FALLOS {
+            (momento, momento + BLOQUEO_SEGUNDOS)
+        } else {
+            (momento + retardo(fallos, 2), 0)
+        };
+
+        conn.execute(
+            "INSERT INTO intentos_acceso (clave, fallos, ultimo_fallo, espera_hasta, bloqueado_hasta) \
+             VALUES (?1, ?2, ?3, ?4, ?5) \
+             ON CONFLICT(clave) DO UPDATE SET fallos = ?2, ultimo_fallo = ?3, espera_hasta = ?4, bloqueado_hasta = ?5",
+            rusqlite::params![clave_actual, fallos, momento, espera_hasta, bloqueado_hasta],
+        )
+        .map_err(|e| format!("Error al registrar intento: {}", e))?;
+
+        if !es_global && fallos == MAX_FALLOS {
+            println!("[warn] clave {} bloqueada por intentos fallidos", clave_actual);
+        }
+    }
+    Ok(())
+}
+
+/// Limpia el contador de la clave tras un acceso correcto.
+pub(crate) fn registrar_exito(conn: &Connection, clave: &str) -> Result<(), String> {
+    conn.execute("DELETE FROM intentos_acceso WHERE clave = ?1", rusqlite::params![clave])
+        .map_err(|e| format!("Error al registrar intento: {}", e))?;
+    Ok(())
+}
+
+#[tauri::command]
+pub fn listar_bloqueos() -> Result<Vec<Bloqueo>, String> {
+    require_permiso(Permiso::GestionarUsuarios)?;
+    let conn = abrir_conexion()?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT clave, fallos, datetime(bloqueado_hasta, 'unixepoch', 'localtime') FROM intentos_acceso \
+             WHERE bloqueado_hasta > ?1 ORDER BY clave",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+
+    let rows = stmt
+        .query_map(rusqlite::params![ahora_unix()], |row| {
+            Ok(Bloqueo {
+                clave: row.get(0)?,
+                fallos: row.get(1)?,
+                bloqueado_hasta: row.get(2)?,
+            })
+        })
+        .map_err(|e| format!("Error al leer bloqueos: {}", e))?;
+
+    let mut bloqueos = Vec::new();
+    for row in rows {
+        bloqueos.push(row.map_err(|e| format!("Error en fila: {}", e))?);
+    }
+    Ok(bloqueos)
+}
+
+#[tauri::command]
+pub fn desbloquear_usuario(name: String) -> Result<(), String> {
+    require_permiso(Permiso::GestionarUsuarios)?;
+    let trimmed = name.trim();
+    if trimmed.is_empty() {
+        return Err("El nombre de usuario es obligatorio".to_string());
+    }
+
+    let conn = abrir_conexion()?;
+    conn.execute(
+        "DELETE FROM intentos_acceso WHERE clave IN (?1, ?2)",
+        rusqlite::params![clave_login(trimmed), clave_admin(trimmed)],
+    )
+    .map_err(|e| format!("Error al desbloquear usuario: {}", e))?;
+
+    auditoria::registrar_auditoria(
+        &conn,
+        "desbloquear_usuario",
+        &format!("usuario:{}", trimmed),
+        None,
+        None,
+        true,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comun::pruebas;
+
+    fn fallar(conn: &Connection, clave: &str, veces: usize) {
+        for _ in 0..veces {
+            registrar_fallo(conn, clave).unwrap();
+        }
+    }
+
+    /// Adelanta el reloj de la clave como si los fallos hubieran sido hace `segundos`.
+    fn envejecer(conn: &Connection, clave: &str, segundos: i64) {
+        conn.execute(
+            "UPDATE intentos_acceso SET ultimo_fallo = ultimo_fallo - ?2, espera_hasta = espera_hasta - ?2, \
+             bloqueado_hasta = MAX(bloqueado_hasta - ?2, 0) WHERE clave = ?1",
+            rusqlite::params![clave, segundos],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn la_espera_crece_con_cada_fallo_seguido() {
+        assert_eq!((retardo(1, 2), retardo(2, 2), retardo(3, 2), retardo(4, 2)), (0, 1, 2, 4));
+        assert_eq!(retardo(20, 2), RETARDO_MAXIMO_SEGUNDOS);
+
+        let conn = pruebas::base(&[]);
+        let clave = clave_login("Cajero");
+        fallar(&conn, &clave, 1);
+        assert!(comprobar_intento(&conn, &clave).is_ok());
+        fallar(&conn, &clave, 1);
+        assert!(comprobar_intento(&conn, &clave).unwrap_err().starts_with("Demasiados intentos"));
+    }
+
+    #[test]
+    fn al_llegar_al_maximo_la_cuenta_se_bloquea_hasta_que_vence() {
+        let conn = pruebas::base(&[]);
+        let clave = clave_login("cajero");
+        fallar(&conn, &clave, MAX_FALLOS as usize);
+        assert!(comprobar_intento(&conn, &clave).unwrap_err().starts_with("Cuenta bloqueada"));
+
+        envejecer(&conn, &clave, BLOQUEO_SEGUNDOS + 1);
+        assert!(comprobar_intento(&conn, &clave).is_ok());
+        // Pasado el bloqueo los fallos viejos no cuentan
+        fallar(&conn, &clave, 1);
+        assert!(comprobar_intento(&conn, &clave).is_ok());
+    }
+
+    #[test]
+    fn un_acceso_correcto_limpia_los_fallos() {
+        let conn = pruebas::base(&[]);
+        let clave = clave_admin("user");
+        fallar(&conn, &clave, 3);
+        registrar_exito(&conn, &clave).unwrap();
+        assert!(comprobar_intento(&conn, &clave).is_ok());
+    }
+
+    #[test]
+    fn el_freno_global_no_alcanza_a_quien_no_fallo() {
+        let conn = pruebas::base(&[]);
+        for n in 0..FALLOS_GLOBALES_ANTES_DE_FRENAR {
+            fallar(&conn, &clave_login(&format!("intruso{}", n)), 1);
+        }
+
+        assert!(comprobar_intento(&conn, &clave_login("user")).is_ok());
+        assert!(comprobar_intento(&conn, &clave_login("intruso0"))
+            .unwrap_err()
+            .starts_with("Demasiados intentos"));
+    }
+}
@@ -0,0 +1,338 @@
+// Numeros de serie de productos de alto valor (telefonos, electrodomesticos).
+//
+// Un producto serializado registra cada unidad en `series`: las series entran con la
+// compra, la venta indica cuales se llevan (el stock baja al completar el ticket, no al
+// escanear) y una devolucion o anulacion las regresa al stock. Cada paso deja una fila en `series_historial`, que es lo que se consulta ante
+// un reclamo de garantia.
+
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::permisos::{require_permiso, Permiso};
+use crate::{abrir_conexion, auditoria, combos, comun};
+
+#[derive(Serialize, Deserialize)]
+pub struct Serie {
+    id: i64,
+    producto_id: i64,
+    serie: String,
+    /// "en_stock" o "vendida"
+    estado: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EventoSerie {
+    fecha: String,
+    producto_id: i64,
+    producto: String,
+    serie: String,
+    /// "recibida", "vendida", "devolucion" o "anulacion"
+    evento: String,
+    numero_recibo: Option<String>,
+    cliente: Option<String>,
+    referencia: Option<String>,
+    usuario: String,
+}
+
+pub(crate) fn normalizar_serie(serie: &str) -> String {
+    serie.trim().to_uppercase()
+}
+
+pub(crate) fn es_serializado(conn: &Connection, producto_id: i64) -> Result<bool, String> {
+    conn.query_row(
+        "SELECT COALESCE(serializado, 0) = 1 FROM inventario WHERE id = ?1",
+        rusqlite::params![producto_id],
+        |row| row.get(0),
+    )
+    .map_err(|_| format!("No se encontro el producto {}", producto_id))
+}
+
+/// Series normalizadas, sin vacias ni repetidas, y una por unidad.
+fn validar_lista(series: &[String], cantidad: f64) -> Result<Vec<String>, String> {
+    let mut normalizadas: Vec<String> = Vec::with_capacity(series.len());
+    for serie in series {
+        let serie = normalizar_serie(serie);
+        if serie.is_empty() {
+            return Err("Hay un numero de serie vacio".to_string());
+        }
+        if normalizadas.contains(&serie) {
+            return Err(format!("La serie {} esta repetida", serie));
+        }
+        normalizadas.push(serie);
+    }
+    if normalizadas.len() as f64 != cantidad {
+        return Err(format!(
+            "Se indicaron {} series para {} unidades; se requiere una serie por unidad",
+            normalizadas.len(),
+            cantidad
+        ));
+    }
+    Ok(normalizadas)
+}
+
+fn registrar_evento(
+    conn: &Connection,
+    serie_id: i64,
+    evento: &str,
+    venta_id: Option<i64>,
+    referencia: Option<&str>,
+) -> Result<(), String> {
+    conn.execute(
+        "INSERT INTO series_historial (serie_id, fecha, evento, venta_id, referencia, usuario) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        rusqlite::params![
+            serie_id,
+            comun::ahora(),
+            evento,
+            venta_id,
+            referencia,
+            comun::usuario()
+        ],
+    )
+    .map_err(|e| format!("Error al registrar el historial de la serie: {}", e))?;
+    Ok(())
+}
+
+fn buscar_serie(conn: &Connection, producto_id: i64, serie: &str) -> Result<Option<(i64, String)>, String> {
+    conn.query_row(
+        "SELECT id, estado FROM series WHERE producto_id = ?1 AND serie = ?2",
+        rusqlite::params![producto_id, serie],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )
+    .optional()
+    .map_err(|e| format!("Error en la consulta: {}", e))
+}
+
+/// Entrada de compra: una serie nueva por unidad. Una serie vendida puede volver a
+/// entrar, por ejemplo un equipo recomprado.
+pub(crate) fn recibir_series(
+    conn: &Connection,
+    producto_id: i64,
+    series: &[String],
+    cantidad: f64,
+) -> Result<(), String> {
+    for serie in validar_lista(series, cantidad)? {
+        let serie_id = match buscar_serie(conn, producto_id, &serie)? {
+            Some((_, estado)) if estado == "en_stock" => {
+                return Err(format!("La serie {} ya esta en stock", serie))
+            }
+            Some((serie_id, _)) => {
+                conn.execute(
+                    "UPDATE series SET estado = 'en_stock', venta_id = NULL WHERE id = ?1",
+                    rusqlite::params![serie_id],
+                )
+                .map_err(|e| format!("Error al registrar la serie: {}", e))?;
+                serie_id
+            }
+            None => {
+                conn.execute(
+                    "INSERT INTO series (producto_id, serie, estado) VALUES (?1, ?2, 'en_stock')",
+                    rusqlite::params![producto_id, serie],
+                )
+                .map_err(|e| format!("Error al registrar la serie: {}", e))?;
+                conn.last_insert_rowid()
+            }
+        };
+        registrar_evento(conn, serie_id, "recibida", None, None)?;
+    }
+    Ok(())
+}
+
+/// Comprueba las series elegidas para una linea de venta y las devuelve normalizadas.
+/// Un producto sin serie no debe traer series.
+pub(crate) fn validar_series_venta(
+    conn: &Connection,
+    producto_id: i64,
+    cantidad: f64,
+    series: &[String],
+) -> Result<Vec<String>, String> {
+    if !es_serializado(conn, producto_id)? {
+        if !series.is_empty() {
+            return Err(format!("El producto {} no se vende con numero de serie", producto_id));
+        }
+        return Ok(Vec::new());
+    }
+    let series = validar_lista(series, cantidad)?;
+    for serie in &series {
+        match buscar_serie(conn, producto_id, serie)? {
+            Some((_, estado)) if estado == "en_stock" => {}
+            Some(_) => return Err(format!("La serie {} ya fue vendida", serie)),
+            None => return Err(format!("La serie {} no existe para el producto {}", serie, producto_id)),
+        }
+    }
+    Ok(series)
+}
+
+/// Marca como vendidas las series de la linea `venta_id`.
+pub(crate) fn vender_series(
+    conn: &Connection,
+    producto_id: i64,
+    series: &[String],
+    venta_id: i64,
+    numero_recibo: &str,
+) -> Result<(), String> {
+    for serie in series {
+        let serie_id = match buscar_serie(conn, producto_id, serie)? {
+            Some((serie_id, estado)) if estado == "en_stock" => serie_id,
+            _ => return Err(format!("La serie {} no esta disponible", serie)),
+        };
+        conn.execute(
+            "UPDATE series SET estado = 'vendida', venta_id = ?1 WHERE id = ?2",
+            rusqlite::params![venta_id, serie_id],
+        )
+        .map_err(|e| format!("Error al actualizar la serie: {}", e))?;
+        registrar_evento(conn, serie_id, "vendida", Some(venta_id), Some(numero_recibo))?;
+    }
+    Ok(())
+}
+
+/// Series vendidas en la linea y todavia no devueltas.
+pub(crate) fn series_de_venta(conn: &Connection, venta_id: i64) -> Result<Vec<String>, String> {
+    let mut stmt = conn
+        .prepare("SELECT serie FROM series WHERE venta_id = ?1 AND estado = 'vendida' ORDER BY serie")
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let series = stmt
+        .query_map(rusqlite::params![venta_id], |row| row.get(0))
+        .map_err(|e| format!("Error al leer series: {}", e))?
+        .collect::<Result<Vec<String>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    Ok(series)
+}
+
+/// Regresa al stock series de la linea `venta_id`; `tipo` es "devolucion" o "anulacion".
+pub(crate) fn devolver_series(
+    conn: &Connection,
+    venta_id: i64,
+    series: &[String],
+    tipo: &str,
+    referencia: &str,
+) -> Result<(), String> {
+    let vendidas = series_de_venta(conn, venta_id)?;
+    for serie in series {
+        if !vendidas.contains(serie) {
+            return Err(format!("La serie {} no pertenece a esta venta", serie));
+        }
+        let serie_id: i64 = conn
+            .query_row(
+                "SELECT id FROM series WHERE venta_id = ?1 AND serie = ?2",
+                rusqlite::params![venta_id, serie],
+                |row| row.get(0),
+            )
+            .map_err(|e| format!("Error en la consulta: {}", e))?;
+        conn.execute(
+            "UPDATE series SET estado = 'en_stock', venta_id = NULL WHERE id = ?1",
+            rusqlite::params![serie_id],
+        )
+        .map_err(|e| format!("Error al actualizar la serie: {}", e))?;
+        registrar_evento(conn, serie_id, tipo, Some(venta_id), Some(referencia))?;
+    }
+    Ok(())
+}
+
+/// Activa o desactiva el control por serie. Solo con el stock en 0, para que cada
+/// unidad en existencia tenga su serie.
+#[tauri::command]
+pub fn marcar_serializado(producto_id: i64, serializado: bool) -> Result<(), String> {
+    require_permiso(Permiso::EditarPrecios)?;
+    let conn = abrir_conexion()?;
+    let cantidad: f64 = conn
+        .query_row(
+            "SELECT COALESCE(CAST(cantidad_producto AS REAL), 0) FROM inventario WHERE id = ?1",
+            rusqlite::params![producto_id],
+            |row| row.get(0),
+        )
+        .map_err(|_| "No se encontro el registro para actualizar".to_string())?;
+    if es_serializado(&conn, producto_id)? == serializado {
+        return Ok(());
+    }
+    if cantidad != 0.0 {
+        return Err(format!(
+            "El producto tiene {} unidades en stock; ajustelo a 0 antes de cambiar el control por serie",
+            cantidad
+        ));
+    }
+    if serializado && combos::es_componente(&conn, producto_id)? {
+        return Err("El producto es componente de un combo y no puede controlarse por serie".to_string());
+    }
+    conn.execute(
+        "UPDATE inventario SET serializado = ?1 WHERE id = ?2",
+        rusqlite::params![serializado, producto_id],
+    )
+    .map_err(|e| format!("Error al actualizar: {}", e))?;
+    auditoria::registrar_auditoria(
+        &conn,
+        "marcar_serializado",
+        &format!("inventario:{}", producto_id),
+        None,
+        Some(serde_json::json!({ "serializado": serializado })),
+        true,
+    )
+}
+
+/// Series del producto; con `solo_en_stock` solo las que se pueden vender.
+#[tauri::command]
+pub fn listar_series(producto_id: i64, solo_en_stock: bool) -> Result<Vec<Serie>, String> {
+    require_permiso(Permiso::ConsultarInventario)?;
+    let conn = abrir_conexion()?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, producto_id, serie, estado FROM series \
+             WHERE producto_id = ?1 AND (?2 = 0 OR estado = 'en_stock') ORDER BY serie",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let series = stmt
+        .query_map(rusqlite::params![producto_id, solo_en_stock], |row| {
+            Ok(Serie {
+                id: row.get(0)?,
+                producto_id: row.get(1)?,
+                serie: row.get(2)?,
+                estado: row.get(3)?,
+            })
+        })
+        .map_err(|e| format!("Error al leer series: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    Ok(series)
+}
+
+/// Historial de una serie, del evento mas antiguo al mas reciente: recepcion, venta con
+/// su recibo y cliente, devoluciones.
+#[tauri::command]
+pub fn historial_serie(serie: String) -> Result<Vec<EventoSerie>, String> {
+    require_permiso(Permiso::Vender)?;
+    let serie = normalizar_serie(&serie);
+    let conn = abrir_conexion()?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT h.fecha, s.producto_id, COALESCE(i.nombre_producto, ''), s.serie, h.evento, \
+             t.numero_recibo, c.nombre, h.referencia, h.usuario \
+             FROM series_historial h JOIN series s ON s.id = h.serie_id \
+             LEFT JOIN inventario i ON i.id = s.producto_id \
+             LEFT JOIN ventas v ON v.id = h.venta_id \
+             LEFT JOIN tickets t ON t.id = v.ticket_id \
+             LEFT JOIN clientes c ON c.id = t.cliente_id \
+             WHERE s.serie = ?1 ORDER BY h.id",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let eventos = stmt
+        .query_map(rusqlite::params![serie], |row| {
+            Ok(EventoSerie {
+                fecha: row.get(0)?,
+                producto_id: row.get(1)?,
+                producto: row.get(2)?,
+                serie: row.get(3)?,
+                evento: row.get(4)?,
+                numero_recibo: row.get(5)?,
+                cliente: row.get(6)?,
+                referencia: row.get(7)?,
+                usuario: row.get(8)?,
+            })
+        })
+        .map_err(|e| format!("Error al leer el historial: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    if eventos.is_empty() {
+        return Err(format!("No se encontro la serie {}", serie));
+    }
+    Ok(eventos)
+}
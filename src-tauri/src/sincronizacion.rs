@@ -0,0 +1,1211 @@
+// Sincronizacion entre sucursales.
+//
+// Cada sucursal trabaja con su propia base y sus disparadores anotan los cambios en una
+// bandeja de salida (`sync_salida`): altas y campos de catalogo de `inventario`,
+// movimientos de stock y lineas vendidas. Los cambios se intercambian con la central por
+// HTTP (`sincronizar_con_central`, contra una central en modo servidor, ver `terminales`)
+// o con archivos JSON llevados en un pendrive (`exportar_sincronizacion` e
+// `importar_sincronizacion`).
+//
+// Reglas: cada campo de catalogo guarda el instante (UTC) y la sucursal de su ultimo
+// cambio y gana el mas reciente; a igual instante gana el codigo de sucursal mayor. Los
+// movimientos y las ventas de las sucursales se suman en la central, nunca se pisan, y la
+// central reenvia a las demas sucursales los cambios de catalogo que acepta. Cada paquete
+// confirma lo recibido del otro lado, asi repetir un paquete no duplica nada.
+//
+// Solo viajan los campos de `CAMPOS`: categorias, marcas, impuestos y listas de precios
+// tienen ids propios de cada base. Lo vendido antes de configurar la sucursal no se envia;
+// el stock de ese momento viaja como un movimiento "saldo_inicial".
+//
+// Los productos viajan con un identificador global (`sync_productos`) que cada base
+// traduce a su propio `inventario.id`. Un alta desconocida con el SKU o el codigo de
+// barras de un producto local se asocia a ese producto en vez de duplicarlo.
+//
+// La central solo acepta paquetes por HTTP de las sucursales que registro con
+// `registrar_sucursal`; cada una envia su codigo y la clave que recibio al registrarse
+// (`central_clave=` en `terminal.conf`).
+
+use chrono::Local;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use rusqlite::types::Value as ValorSql;
+use rusqlite::{Connection, ErrorCode, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::catalogo::FiltroCatalogo;
+use crate::comun::{ahora, redondear};
+use crate::exportacion::parse_fecha;
+use crate::movimientos::transaccion_stock;
+use crate::permisos::{require_permiso, Permiso};
+use crate::precios::{registrar_historial, LISTA_MINORISTA};
+use crate::{abrir_conexion, auditoria, terminales};
+
+/// Campos de catalogo que viajan entre sucursales.
+const CAMPOS: &[&str] = &["nombre_producto", "precio_producto", "unidad", "sku", "codigo_barras"];
+
+/// Nombre del par de una sucursal; la central identifica a cada sucursal por su codigo.
+const CENTRAL: &str = "central";
+
+/// Instante de un cambio, en UTC para comparar relojes de distintas sucursales.
+const INSTANTE: &str = "strftime('%Y-%m-%d %H:%M:%f', 'now')";
+
+/// Da identificador global a los productos que todavia no lo tienen.
+const MAPEAR_PRODUCTOS: &str = "INSERT INTO sync_productos (uid, producto_id) \
+     SELECT lower(hex(randomblob(16))), id FROM inventario \
+     WHERE id NOT IN (SELECT producto_id FROM sync_productos)";
+
+#[derive(Serialize, Deserialize)]
+pub struct Sucursal {
+    codigo: String,
+    nombre: String,
+    central: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Cambio {
+    seq: i64,
+    uid: String,
+    origen: String,
+    /// "alta", "campo", "movimiento" o "venta"
+    tipo: String,
+    fecha: String,
+    datos: Value,
+}
+
+/// Lo que una base envia a otra: sus cambios pendientes y hasta donde recibio los del otro.
+#[derive(Serialize, Deserialize)]
+pub struct Paquete {
+    origen: String,
+    nombre: String,
+    /// Sucursal destino; `None` si va a la central.
+    destino: Option<String>,
+    generado: String,
+    confirmado_hasta: i64,
+    cambios: Vec<Cambio>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct ResumenSincronizacion {
+    origen: String,
+    enviados: usize,
+    aplicados: usize,
+    duplicados: usize,
+    /// Cambios de catalogo que perdieron frente a uno mas reciente.
+    descartados: usize,
+    ignorados: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ArchivoSincronizacion {
+    ruta: String,
+    cambios: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ParSincronizacion {
+    codigo: String,
+    nombre: Option<String>,
+    pendientes: i64,
+    ultima: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EstadoSincronizacion {
+    sucursal: Option<Sucursal>,
+    pares: Vec<ParSincronizacion>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ResumenSucursal {
+    sucursal: String,
+    nombre: String,
+    ventas: f64,
+    unidades: f64,
+    costo: f64,
+    margen: f64,
+    compras: f64,
+    ultima: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StockSucursal {
+    sucursal: String,
+    cantidad: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StockConsolidado {
+    producto_id: i64,
+    nombre: String,
+    local: f64,
+    sucursales: Vec<StockSucursal>,
+    total: f64,
+}
+
+#[derive(Deserialize)]
+struct DatosCampo {
+    producto: String,
+    campo: String,
+    valor: Value,
+}
+
+#[derive(Deserialize)]
+struct DatosMovimiento {
+    fecha: String,
+    producto: String,
+    cantidad: f64,
+    motivo: String,
+    costo_unitario: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct DatosVenta {
+    venta_id: i64,
+    numero_recibo: Option<String>,
+    fecha: String,
+    producto: String,
+    nombre_producto: String,
+    cantidad: f64,
+    cantidad_devuelta: f64,
+    subtotal: f64,
+    costo_unitario: Option<f64>,
+}
+
+/// Subconsulta con el identificador global del producto `id`. Si varios identificadores
+/// llevan al mismo producto se usa el primero que se le asigno.
+fn uid_producto(id: &str) -> String {
+    format!("(SELECT uid FROM sync_productos WHERE producto_id = {} ORDER BY rowid LIMIT 1)", id)
+}
+
+/// `json_object` con los campos de catalogo de la fila `fila` (`NEW` en un disparador).
+fn json_producto(fila: &str) -> String {
+    let campos: Vec<String> = CAMPOS.iter().map(|c| format!("'{}', {}.\"{}\"", c, fila, c)).collect();
+    format!("json_object('producto', {}, {})", uid_producto(&format!("{}.id", fila)), campos.join(", "))
+}
+
+/// Disparadores que llenan la bandeja de salida. Solo actuan con la sucursal configurada
+/// y no mientras se aplica un paquete recibido, para no reenviar lo que llega. Se recrean
+/// en cada arranque para que una base existente tome los cambios de formato.
+pub(crate) fn crear_disparadores(conn: &Connection) -> Result<(), String> {
+    let activa = "EXISTS (SELECT 1 FROM sync_sucursal WHERE aplicando = 0)";
+    let sucursal = "EXISTS (SELECT 1 FROM sync_sucursal WHERE aplicando = 0 AND central = 0)";
+    let encolar = |tipo: &str, datos: &str| {
+        format!(
+            "INSERT INTO sync_salida (origen, tipo, fecha, datos) SELECT codigo, '{}', {}, {} FROM sync_sucursal;",
+            tipo, INSTANTE, datos
+        )
+    };
+    let version = |campo: &str| {
+        format!(
+            "INSERT OR REPLACE INTO sync_versiones (producto_id, campo, fecha, origen) \
+             SELECT NEW.id, '{}', fecha, origen FROM sync_salida WHERE seq = (SELECT MAX(seq) FROM sync_salida);",
+            campo
+        )
+    };
+
+    // Bases configuradas antes de que los productos tuvieran identificador global
+    let mut sql = format!(
+        "{} AND EXISTS (SELECT 1 FROM sync_sucursal);\n\
+         DROP TRIGGER IF EXISTS sync_inventario_baja;\n\
+         CREATE TRIGGER sync_inventario_baja AFTER DELETE ON inventario BEGIN \
+         DELETE FROM sync_productos WHERE producto_id = OLD.id; END;\n\
+         DROP TRIGGER IF EXISTS sync_inventario_alta;\n\
+         CREATE TRIGGER sync_inventario_alta AFTER INSERT ON inventario WHEN {} BEGIN \
+         INSERT INTO sync_productos (uid, producto_id) VALUES (lower(hex(randomblob(16))), NEW.id); {} {} END;\n",
+        MAPEAR_PRODUCTOS,
+        activa,
+        encolar("alta", &json_producto("NEW")),
+        CAMPOS.iter().map(|c| version(c)).collect::<Vec<_>>().join(" ")
+    );
+    for campo in CAMPOS {
+        sql.push_str(&format!(
+            "DROP TRIGGER IF EXISTS sync_inventario_{c};\n\
+             CREATE TRIGGER sync_inventario_{c} AFTER UPDATE OF \"{c}\" ON inventario \
+             WHEN OLD.\"{c}\" IS NOT NEW.\"{c}\" AND {activa} BEGIN {encolar} {version} END;\n",
+            c = campo,
+            activa = activa,
+            encolar = encolar(
+                "campo",
+                &format!(
+                    "json_object('producto', {}, 'campo', '{c}', 'valor', NEW.\"{c}\")",
+                    uid_producto("NEW.id"),
+                    c = campo
+                )
+            ),
+            version = version(campo)
+        ));
+    }
+    sql.push_str(&format!(
+        "DROP TRIGGER IF EXISTS sync_movimientos;\n\
+         CREATE TRIGGER sync_movimientos AFTER INSERT ON movimientos_stock WHEN {} BEGIN {} END;\n",
+        sucursal,
+        encolar(
+            "movimiento",
+            &format!(
+                "json_object('fecha', NEW.fecha, 'producto', {}, 'cantidad', NEW.cantidad, \
+                 'motivo', NEW.motivo, 'costo_unitario', NEW.costo_unitario)",
+                uid_producto("NEW.producto_id")
+            )
+        )
+    ));
+    let venta = encolar(
+        "venta",
+        &format!(
+            "json_object('venta_id', NEW.id, 'numero_recibo', (SELECT numero_recibo FROM tickets WHERE id = NEW.ticket_id), \
+             'fecha', NEW.fecha, 'producto', {}, 'nombre_producto', NEW.nombre_producto, \
+             'cantidad', NEW.cantidad, 'cantidad_devuelta', NEW.cantidad_devuelta, 'subtotal', NEW.subtotal, \
+             'costo_unitario', NEW.costo_unitario)",
+            uid_producto("NEW.producto_id")
+        ),
+    );
+    sql.push_str(&format!(
+        "DROP TRIGGER IF EXISTS sync_ventas;\n\
+         CREATE TRIGGER sync_ventas AFTER INSERT ON ventas WHEN {s} BEGIN {v} END;\n\
+         DROP TRIGGER IF EXISTS sync_ventas_devolucion;\n\
+         CREATE TRIGGER sync_ventas_devolucion AFTER UPDATE OF cantidad_devuelta ON ventas \
+         WHEN OLD.cantidad_devuelta IS NOT NEW.cantidad_devuelta AND {s} BEGIN {v} END;\n",
+        s = sucursal,
+        v = venta
+    ));
+
+    conn.execute_batch(&sql)
+        .map_err(|e| format!("Error al crear los disparadores de sincronizacion: {}", e))
+}
+
+fn sucursal_local(conn: &Connection) -> Result<Option<Sucursal>, String> {
+    conn.query_row("SELECT codigo, nombre, central FROM sync_sucursal WHERE id = 1", [], |row| {
+        Ok(Sucursal {
+            codigo: row.get(0)?,
+            nombre: row.get(1)?,
+            central: row.get(2)?,
+        })
+    })
+    .optional()
+    .map_err(|e| format!("Error en la consulta: {}", e))
+}
+
+fn requerir_sucursal(conn: &Connection) -> Result<Sucursal, String> {
+    sucursal_local(conn)?.ok_or_else(|| "Configure primero el codigo de esta sucursal".to_string())
+}
+
+fn validar_codigo(codigo: &str) -> Result<(), String> {
+    if codigo.is_empty() || !codigo.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err("El codigo de sucursal solo admite letras, numeros, '-' y '_'".to_string());
+    }
+    if codigo == CENTRAL {
+        return Err(format!("\"{}\" es un codigo reservado", CENTRAL));
+    }
+    Ok(())
+}
+
+fn hash_clave(clave: &str) -> String {
+    format!("{:x}", Sha256::digest(clave.as_bytes()))
+}
+
+fn get_documentos_sincronizacion_dir() -> Result<PathBuf, String> {
+    let documentos = dirs::document_dir().ok_or_else(|| "No se pudo obtener la carpeta Documentos".to_string())?;
+    let sincronizacion_dir = documentos.join("sincronizacion");
+    if let Err(e) = fs::create_dir_all(&sincronizacion_dir) {
+        return Err(format!("No se pudo crear la carpeta de sincronizacion: {}", e));
+    }
+    Ok(sincronizacion_dir)
+}
+
+fn valor_sql(valor: &Value) -> ValorSql {
+    match valor {
+        Value::Null => ValorSql::Null,
+        Value::Bool(b) => ValorSql::Integer(*b as i64),
+        Value::Number(n) => n
+            .as_i64()
+            .map(ValorSql::Integer)
+            .unwrap_or_else(|| ValorSql::Real(n.as_f64().unwrap_or(0.0))),
+        Value::String(s) => ValorSql::Text(s.clone()),
+        otro => ValorSql::Text(otro.to_string()),
+    }
+}
+
+fn es_restriccion(error: &rusqlite::Error) -> bool {
+    matches!(error, rusqlite::Error::SqliteFailure(e, _) if e.code == ErrorCode::ConstraintViolation)
+}
+
+/// `inventario.id` del producto con identificador global `uid`, si ya llego a esta base.
+fn producto_local(conn: &Connection, uid: &str) -> Result<Option<i64>, String> {
+    conn.query_row(
+        "SELECT producto_id FROM sync_productos WHERE uid = ?1",
+        rusqlite::params![uid],
+        |row| row.get(0),
+    )
+    .optional()
+    .map_err(|e| format!("Error en la consulta: {}", e))
+}
+
+fn asociar_producto(conn: &Connection, uid: &str, producto_id: i64) -> Result<(), String> {
+    conn.execute(
+        "INSERT OR IGNORE INTO sync_productos (uid, producto_id) VALUES (?1, ?2)",
+        rusqlite::params![uid, producto_id],
+    )
+    .map_err(|e| format!("Error al asociar el producto: {}", e))?;
+    Ok(())
+}
+
+fn precio_actual(conn: &Connection, producto_id: i64) -> Result<Option<f64>, String> {
+    conn.query_row(
+        "SELECT CAST(precio_producto AS REAL) FROM inventario WHERE id = ?1",
+        rusqlite::params![producto_id],
+        |row| row.get(0),
+    )
+    .optional()
+    .map_err(|e| format!("Error en la consulta: {}", e))
+}
+
+fn fijar_version(conn: &Connection, producto_id: i64, campo: &str, fecha: &str, origen: &str) -> Result<(), String> {
+    conn.execute(
+        "INSERT OR REPLACE INTO sync_versiones (producto_id, campo, fecha, origen) VALUES (?1, ?2, ?3, ?4)",
+        rusqlite::params![producto_id, campo, fecha, origen],
+    )
+    .map_err(|e| format!("Error al registrar la version: {}", e))?;
+    Ok(())
+}
+
+/// Aplica un campo de catalogo si es mas reciente que el vigente. Devuelve si se aplico.
+fn aplicar_campo(
+    conn: &Connection,
+    producto_id: i64,
+    campo: &str,
+    valor: &Value,
+    fecha: &str,
+    origen: &str,
+) -> Result<bool, String> {
+    if !CAMPOS.contains(&campo) {
+        return Ok(false);
+    }
+    let vigente: Option<(String, String)> = conn
+        .query_row(
+            "SELECT fecha, origen FROM sync_versiones WHERE producto_id = ?1 AND campo = ?2",
+            rusqlite::params![producto_id, campo],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    if vigente.is_some_and(|(f, o)| (f.as_str(), o.as_str()) >= (fecha, origen)) {
+        return Ok(false);
+    }
+
+    let anterior = precio_actual(conn, producto_id)?;
+    match conn.execute(
+        &format!("UPDATE inventario SET \"{}\" = ?1 WHERE id = ?2", campo),
+        rusqlite::params![valor_sql(valor), producto_id],
+    ) {
+        Ok(0) => return Ok(false),
+        Ok(_) => {}
+        // Un SKU o codigo de barras que en esta base ya usa otro producto
+        Err(e) if es_restriccion(&e) => return Ok(false),
+        Err(e) => return Err(format!("Error al actualizar el producto {}: {}", producto_id, e)),
+    }
+    if campo == "precio_producto" {
+        if let Some(nuevo) = precio_actual(conn, producto_id)? {
+            registrar_historial(conn, producto_id, LISTA_MINORISTA, anterior, nuevo, "sincronizacion")?;
+        }
+    }
+    fijar_version(conn, producto_id, campo, fecha, origen)?;
+    Ok(true)
+}
+
+fn aplicar_campos(conn: &Connection, producto_id: i64, cambio: &Cambio) -> Result<bool, String> {
+    let mut aplicado = false;
+    for campo in CAMPOS {
+        if let Some(valor) = cambio.datos.get(*campo) {
+            aplicado |= aplicar_campo(conn, producto_id, campo, valor, &cambio.fecha, &cambio.origen)?;
+        }
+    }
+    Ok(aplicado)
+}
+
+fn aplicar_alta(conn: &Connection, cambio: &Cambio) -> Result<bool, String> {
+    let uid = cambio
+        .datos
+        .get("producto")
+        .and_then(Value::as_str)
+        .ok_or_else(|| format!("Cambio {} invalido: falta el producto", cambio.uid))?;
+    if let Some(producto_id) = producto_local(conn, uid)? {
+        return aplicar_campos(conn, producto_id, cambio);
+    }
+
+    let valor = |campo: &str| valor_sql(cambio.datos.get(campo).unwrap_or(&Value::Null));
+    // El mismo articulo dado de alta por separado en cada base
+    let existente: Option<i64> = conn
+        .query_row(
+            "SELECT id FROM inventario WHERE (sku IS NOT NULL AND sku = ?1) \
+             OR (codigo_barras IS NOT NULL AND codigo_barras = ?2) ORDER BY id LIMIT 1",
+            rusqlite::params![valor("sku"), valor("codigo_barras")],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    if let Some(producto_id) = existente {
+        asociar_producto(conn, uid, producto_id)?;
+        aplicar_campos(conn, producto_id, cambio)?;
+        return Ok(true);
+    }
+
+    let producto_id: i64 = conn
+        .query_row("SELECT COALESCE(MAX(id), 0) + 1 FROM inventario", [], |row| row.get(0))
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let insertado = conn.execute(
+        "INSERT INTO inventario (id, nombre_producto, precio_producto, cantidad_producto, unidad, sku, codigo_barras) \
+         VALUES (?1, ?2, ?3, 0, COALESCE(?4, 'unidad'), ?5, ?6)",
+        rusqlite::params![
+            producto_id,
+            valor("nombre_producto"),
+            valor("precio_producto"),
+            valor("unidad"),
+            valor("sku"),
+            valor("codigo_barras")
+        ],
+    );
+    match insertado {
+        Ok(_) => {}
+        Err(e) if es_restriccion(&e) => return Ok(false),
+        Err(e) => return Err(format!("Error al crear el producto {}: {}", uid, e)),
+    }
+    asociar_producto(conn, uid, producto_id)?;
+    for campo in CAMPOS {
+        fijar_version(conn, producto_id, campo, &cambio.fecha, &cambio.origen)?;
+    }
+    if let Some(precio) = precio_actual(conn, producto_id)? {
+        registrar_historial(conn, producto_id, LISTA_MINORISTA, None, precio, "sincronizacion")?;
+    }
+    Ok(true)
+}
+
+/// Producto local de un movimiento o una venta; su alta siempre viaja antes.
+fn requerir_producto(conn: &Connection, cambio: &Cambio, uid: &str) -> Result<i64, String> {
+    producto_local(conn, uid)?
+        .ok_or_else(|| format!("Cambio {} invalido: el producto {} no llego a esta base", cambio.uid, uid))
+}
+
+fn aplicar_movimiento(conn: &Connection, cambio: &Cambio) -> Result<(), String> {
+    let datos: DatosMovimiento = serde_json::from_value(cambio.datos.clone())
+        .map_err(|e| format!("Cambio {} invalido: {}", cambio.uid, e))?;
+    let producto_id = requerir_producto(conn, cambio, &datos.producto)?;
+    conn.execute(
+        "INSERT OR IGNORE INTO movimientos_sucursales (uid, sucursal, fecha, producto_id, cantidad, motivo, costo_unitario) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        rusqlite::params![
+            cambio.uid,
+            cambio.origen,
+            datos.fecha,
+            producto_id,
+            datos.cantidad,
+            datos.motivo,
+            datos.costo_unitario
+        ],
+    )
+    .map_err(|e| format!("Error al registrar el movimiento de {}: {}", cambio.origen, e))?;
+    Ok(())
+}
+
+/// Cada envio de una linea trae su estado completo; el ultimo reemplaza al anterior.
+fn aplicar_venta(conn: &Connection, cambio: &Cambio) -> Result<(), String> {
+    let datos: DatosVenta = serde_json::from_value(cambio.datos.clone())
+        .map_err(|e| format!("Cambio {} invalido: {}", cambio.uid, e))?;
+    let producto_id = requerir_producto(conn, cambio, &datos.producto)?;
+    conn.execute(
+        "INSERT INTO ventas_sucursales (sucursal, venta_id, numero_recibo, fecha, producto_id, nombre_producto, \
+         cantidad, cantidad_devuelta, subtotal, costo_unitario) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10) \
+         ON CONFLICT (sucursal, venta_id) DO UPDATE SET numero_recibo = excluded.numero_recibo, \
+         fecha = excluded.fecha, producto_id = excluded.producto_id, nombre_producto = excluded.nombre_producto, \
+         cantidad = excluded.cantidad, cantidad_devuelta = excluded.cantidad_devuelta, \
+         subtotal = excluded.subtotal, costo_unitario = excluded.costo_unitario",
+        rusqlite::params![
+            cambio.origen,
+            datos.venta_id,
+            datos.numero_recibo,
+            datos.fecha,
+            producto_id,
+            datos.nombre_producto,
+            datos.cantidad,
+            datos.cantidad_devuelta,
+            datos.subtotal,
+            datos.costo_unitario
+        ],
+    )
+    .map_err(|e| format!("Error al registrar la venta de {}: {}", cambio.origen, e))?;
+    Ok(())
+}
+
+/// Cambios que `destino` (la central si es `None`) todavia no confirmo. La central solo
+/// envia catalogo, y nunca a una sucursal sus propios cambios.
+fn preparar_paquete(conn: &Connection, local: &Sucursal, destino: Option<&str>) -> Result<Paquete, String> {
+    let par = destino.unwrap_or(CENTRAL);
+    let (enviado_hasta, recibido_hasta): (i64, i64) = conn
+        .query_row(
+            "SELECT enviado_hasta, recibido_hasta FROM sync_pares WHERE codigo = ?1",
+            rusqlite::params![par],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+        .map_err(|e| format!("Error en la consulta: {}", e))?
+        .unwrap_or((0, 0));
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT seq, COALESCE(uid, origen || ':' || seq), origen, tipo, fecha, datos FROM sync_salida \
+             WHERE seq > ?1 AND (?2 = 0 OR (tipo IN ('alta', 'campo') AND origen <> ?3)) ORDER BY seq",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let filas = stmt
+        .query_map(rusqlite::params![enviado_hasta, local.central, par], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, String>(5)?,
+            ))
+        })
+        .map_err(|e| format!("Error al leer la bandeja de salida: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+
+    let cambios = filas
+        .into_iter()
+        .map(|(seq, uid, origen, tipo, fecha, datos)| {
+            Ok(Cambio {
+                seq,
+                uid,
+                origen,
+                tipo,
+                fecha,
+                datos: serde_json::from_str(&datos).map_err(|e| format!("Cambio {} corrupto: {}", seq, e))?,
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    Ok(Paquete {
+        origen: local.codigo.clone(),
+        nombre: local.nombre.clone(),
+        destino: destino.map(str::to_string),
+        generado: ahora(),
+        confirmado_hasta: recibido_hasta,
+        cambios,
+    })
+}
+
+fn aplicar_paquete(conn: &mut Connection, paquete: Paquete) -> Result<ResumenSincronizacion, String> {
+    let tx = transaccion_stock(conn)?;
+    let local = requerir_sucursal(&tx)?;
+    if paquete.origen == local.codigo {
+        return Err("El paquete fue generado por esta misma sucursal".to_string());
+    }
+    let par = if local.central {
+        if let Some(destino) = &paquete.destino {
+            return Err(format!("El paquete es para la sucursal {}", destino));
+        }
+        paquete.origen.clone()
+    } else {
+        if paquete.destino.as_deref() != Some(local.codigo.as_str()) {
+            return Err(format!(
+                "El paquete es para {}",
+                paquete.destino.as_deref().unwrap_or("la central")
+            ));
+        }
+        CENTRAL.to_string()
+    };
+
+    tx.execute("UPDATE sync_sucursal SET aplicando = 1", [])
+        .map_err(|e| format!("Error al iniciar la sincronizacion: {}", e))?;
+    let mut resumen = ResumenSincronizacion {
+        origen: paquete.origen.clone(),
+        ..Default::default()
+    };
+    let mut recibido_hasta = 0;
+    for cambio in &paquete.cambios {
+        recibido_hasta = recibido_hasta.max(cambio.seq);
+        // Un cambio propio que vuelve, o uno que ya llego en un paquete anterior
+        let nuevo = cambio.origen != local.codigo
+            && tx
+                .execute(
+                    "INSERT OR IGNORE INTO sync_recibidos (uid, origen, recibido) VALUES (?1, ?2, ?3)",
+                    rusqlite::params![cambio.uid, cambio.origen, ahora()],
+                )
+                .map_err(|e| format!("Error al registrar el cambio: {}", e))?
+                > 0;
+        if !nuevo {
+            resumen.duplicados += 1;
+            continue;
+        }
+
+        let aplicado = match (cambio.tipo.as_str(), local.central) {
+            ("alta", _) => aplicar_alta(&tx, cambio)?,
+            ("campo", _) => {
+                let datos: DatosCampo = serde_json::from_value(cambio.datos.clone())
+                    .map_err(|e| format!("Cambio {} invalido: {}", cambio.uid, e))?;
+                match producto_local(&tx, &datos.producto)? {
+                    Some(producto_id) => {
+                        aplicar_campo(&tx, producto_id, &datos.campo, &datos.valor, &cambio.fecha, &cambio.origen)?
+                    }
+                    None => false,
+                }
+            }
+            ("movimiento", true) => {
+                aplicar_movimiento(&tx, cambio)?;
+                true
+            }
+            ("venta", true) => {
+                aplicar_venta(&tx, cambio)?;
+                true
+            }
+            _ => {
+                resumen.ignorados += 1;
+                continue;
+            }
+        };
+        if !aplicado {
+            resumen.descartados += 1;
+            continue;
+        }
+        resumen.aplicados += 1;
+
+        // La central reenvia el catalogo aceptado a las demas sucursales
+        if local.central && (cambio.tipo == "alta" || cambio.tipo == "campo") {
+            tx.execute(
+                "INSERT OR IGNORE INTO sync_salida (uid, origen, tipo, fecha, datos) VALUES (?1, ?2, ?3, ?4, ?5)",
+                rusqlite::params![cambio.uid, cambio.origen, cambio.tipo, cambio.fecha, cambio.datos.to_string()],
+            )
+            .map_err(|e| format!("Error al reenviar el cambio: {}", e))?;
+        }
+    }
+    tx.execute("UPDATE sync_sucursal SET aplicando = 0", [])
+        .map_err(|e| format!("Error al terminar la sincronizacion: {}", e))?;
+
+    tx.execute(
+        "INSERT INTO sync_pares (codigo, nombre, enviado_hasta, recibido_hasta, ultima) VALUES (?1, ?2, ?3, ?4, ?5) \
+         ON CONFLICT (codigo) DO UPDATE SET nombre = excluded.nombre, \
+         enviado_hasta = MAX(enviado_hasta, excluded.enviado_hasta), \
+         recibido_hasta = MAX(recibido_hasta, excluded.recibido_hasta), ultima = excluded.ultima",
+        rusqlite::params![par, paquete.nombre, paquete.confirmado_hasta, recibido_hasta, ahora()],
+    )
+    .map_err(|e| format!("Error al registrar la sincronizacion: {}", e))?;
+    auditoria::registrar_auditoria(
+        &tx,
+        "sincronizacion",
+        &format!("sucursal:{}", paquete.origen),
+        None,
+        Some(serde_json::json!({
+            "aplicados": resumen.aplicados,
+            "duplicados": resumen.duplicados,
+            "descartados": resumen.descartados,
+        })),
+        true,
+    )?;
+    tx.commit()
+        .map_err(|e| format!("Error al confirmar la sincronizacion: {}", e))?;
+    Ok(resumen)
+}
+
+/// Fija el codigo y el nombre de esta base. Al configurarla por primera vez se encola el
+/// catalogo completo y, en una sucursal, el stock actual como saldo inicial.
+#[tauri::command]
+pub fn configurar_sucursal(codigo: String, nombre: String, central: bool) -> Result<Sucursal, String> {
+    require_permiso(Permiso::AdministrarSistema)?;
+    let codigo = codigo.trim().to_string();
+    let nombre = nombre.trim().to_string();
+    validar_codigo(&codigo)?;
+    if nombre.is_empty() {
+        return Err("El nombre de la sucursal es obligatorio".to_string());
+    }
+
+    let mut conn = abrir_conexion()?;
+    configurar(&mut conn, codigo, nombre, central)
+}
+
+fn configurar(conn: &mut Connection, codigo: String, nombre: String, central: bool) -> Result<Sucursal, String> {
+    let tx = transaccion_stock(conn)?;
+    let anterior = sucursal_local(&tx)?;
+    match &anterior {
+        Some(actual) if actual.codigo != codigo || actual.central != central => {
+            return Err("El codigo y el rol de la sucursal no se pueden cambiar una vez configurados".to_string());
+        }
+        Some(_) => {
+            tx.execute("UPDATE sync_sucursal SET nombre = ?1 WHERE id = 1", rusqlite::params![nombre])
+                .map_err(|e| format!("Error al actualizar la sucursal: {}", e))?;
+        }
+        None => {
+            tx.execute(
+                "INSERT INTO sync_sucursal (id, codigo, nombre, central) VALUES (1, ?1, ?2, ?3)",
+                rusqlite::params![codigo, nombre, central],
+            )
+            .map_err(|e| format!("Error al configurar la sucursal: {}", e))?;
+            tx.execute(MAPEAR_PRODUCTOS, [])
+                .map_err(|e| format!("Error al identificar los productos: {}", e))?;
+            tx.execute(
+                &format!(
+                    "INSERT INTO sync_salida (origen, tipo, fecha, datos) SELECT ?1, 'alta', {}, {} FROM inventario ORDER BY id",
+                    INSTANTE,
+                    json_producto("inventario")
+                ),
+                rusqlite::params![codigo],
+            )
+            .map_err(|e| format!("Error al encolar el catalogo: {}", e))?;
+            for campo in CAMPOS {
+                tx.execute(
+                    &format!(
+                        "INSERT OR REPLACE INTO sync_versiones (producto_id, campo, fecha, origen) \
+                         SELECT id, ?1, {}, ?2 FROM inventario",
+                        INSTANTE
+                    ),
+                    rusqlite::params![campo, codigo],
+                )
+                .map_err(|e| format!("Error al encolar el catalogo: {}", e))?;
+            }
+            if !central {
+                tx.execute(
+                    &format!(
+                        "INSERT INTO sync_salida (origen, tipo, fecha, datos) SELECT ?1, 'movimiento', {}, \
+                         json_object('fecha', ?2, 'producto', {}, 'cantidad', CAST(cantidad_producto AS REAL), \
+                         'motivo', 'saldo_inicial', 'costo_unitario', costo_promedio) FROM inventario i \
+                         WHERE COALESCE(CAST(cantidad_producto AS REAL), 0) <> 0 \
+                         AND NOT EXISTS (SELECT 1 FROM componentes k WHERE k.producto_id = i.id) ORDER BY id",
+                        INSTANTE,
+                        uid_producto("i.id")
+                    ),
+                    rusqlite::params![codigo, ahora()],
+                )
+                .map_err(|e| format!("Error al encolar el saldo inicial: {}", e))?;
+            }
+        }
+    }
+
+    let sucursal = Sucursal { codigo, nombre, central };
+    auditoria::registrar_auditoria(
+        &tx,
+        "configurar_sucursal",
+        &format!("sucursal:{}", sucursal.codigo),
+        anterior.map(|a| serde_json::json!({ "nombre": a.nombre })),
+        Some(serde_json::json!({ "nombre": sucursal.nombre, "central": sucursal.central })),
+        true,
+    )?;
+    tx.commit()
+        .map_err(|e| format!("Error al confirmar la sucursal: {}", e))?;
+    Ok(sucursal)
+}
+
+/// Da de alta (o renueva) en la central la clave con la que la sucursal `codigo` se
+/// sincroniza por HTTP. La clave se muestra solo esta vez; la sucursal la guarda en
+/// `central_clave=` de su `terminal.conf`.
+#[tauri::command]
+pub fn registrar_sucursal(codigo: String, nombre: Option<String>) -> Result<String, String> {
+    require_permiso(Permiso::AdministrarSistema)?;
+    let codigo = codigo.trim().to_string();
+    validar_codigo(&codigo)?;
+    let nombre = nombre.map(|n| n.trim().to_string()).filter(|n| !n.is_empty());
+
+    let mut conn = abrir_conexion()?;
+    let tx = transaccion_stock(&mut conn)?;
+    if !requerir_sucursal(&tx)?.central {
+        return Err("Solo la central registra sucursales".to_string());
+    }
+    let clave: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(40)
+        .map(char::from)
+        .collect();
+    tx.execute(
+        "INSERT INTO sync_pares (codigo, nombre, clave) VALUES (?1, ?2, ?3) \
+         ON CONFLICT (codigo) DO UPDATE SET nombre = COALESCE(excluded.nombre, nombre), clave = excluded.clave",
+        rusqlite::params![codigo, nombre, hash_clave(&clave)],
+    )
+    .map_err(|e| format!("Error al registrar la sucursal: {}", e))?;
+    auditoria::registrar_auditoria(
+        &tx,
+        "registrar_sucursal",
+        &format!("sucursal:{}", codigo),
+        None,
+        Some(serde_json::json!({ "nombre": nombre })),
+        true,
+    )?;
+    tx.commit()
+        .map_err(|e| format!("Error al confirmar la sucursal: {}", e))?;
+    Ok(clave)
+}
+
+#[tauri::command]
+pub fn estado_sincronizacion() -> Result<EstadoSincronizacion, String> {
+    require_permiso(Permiso::VerReportes)?;
+    let conn = abrir_conexion()?;
+    let sucursal = sucursal_local(&conn)?;
+    let mut pares = Vec::new();
+    if let Some(local) = &sucursal {
+        let mut stmt = conn
+            .prepare("SELECT codigo, nombre, ultima FROM sync_pares ORDER BY codigo")
+            .map_err(|e| format!("Error en la consulta: {}", e))?;
+        let mut filas = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?, row.get::<_, Option<String>>(2)?))
+            })
+            .map_err(|e| format!("Error al leer las sucursales: {}", e))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("Error en fila: {}", e))?;
+        if !local.central && !filas.iter().any(|f| f.0 == CENTRAL) {
+            filas.push((CENTRAL.to_string(), None, None));
+        }
+        for (codigo, nombre, ultima) in filas {
+            let destino = if local.central { Some(codigo.as_str()) } else { None };
+            let pendientes = preparar_paquete(&conn, local, destino)?.cambios.len() as i64;
+            pares.push(ParSincronizacion {
+                codigo,
+                nombre,
+                pendientes,
+                ultima,
+            });
+        }
+    }
+    Ok(EstadoSincronizacion { sucursal, pares })
+}
+
+/// Escribe en Documentos/sincronizacion el paquete para la central o, desde la central,
+/// para la sucursal `destino`. El archivo se importa en la otra base.
+#[tauri::command]
+pub fn exportar_sincronizacion(destino: Option<String>) -> Result<ArchivoSincronizacion, String> {
+    require_permiso(Permiso::AdministrarSistema)?;
+    let conn = abrir_conexion()?;
+    let local = requerir_sucursal(&conn)?;
+    let destino = match (local.central, destino.as_deref().map(str::trim).filter(|d| !d.is_empty())) {
+        (true, Some(destino)) => Some(destino.to_string()),
+        (true, None) => return Err("Indique el codigo de la sucursal destino".to_string()),
+        (false, _) => None,
+    };
+    let paquete = preparar_paquete(&conn, &local, destino.as_deref())?;
+
+    let ruta = get_documentos_sincronizacion_dir()?.join(format!(
+        "sync-{}-a-{}-{}.json",
+        local.codigo,
+        destino.as_deref().unwrap_or(CENTRAL),
+        Local::now().format("%Y%m%d-%H%M%S")
+    ));
+    let contenido = serde_json::to_string_pretty(&paquete)
+        .map_err(|e| format!("Error al generar el paquete: {}", e))?;
+    fs::write(&ruta, contenido).map_err(|e| format!("No se pudo escribir {}: {}", ruta.display(), e))?;
+
+    Ok(ArchivoSincronizacion {
+        ruta: ruta.display().to_string(),
+        cambios: paquete.cambios.len(),
+    })
+}
+
+#[tauri::command]
+pub fn importar_sincronizacion(ruta: String) -> Result<ResumenSincronizacion, String> {
+    require_permiso(Permiso::AdministrarSistema)?;
+    let contenido = fs::read_to_string(ruta.trim()).map_err(|e| format!("No se pudo leer {}: {}", ruta, e))?;
+    let paquete: Paquete =
+        serde_json::from_str(&contenido).map_err(|e| format!("El archivo no es un paquete de sincronizacion: {}", e))?;
+    let mut conn = abrir_conexion()?;
+    aplicar_paquete(&mut conn, paquete)
+}
+
+/// Intercambio en linea con la central configurada en `central=` (`terminal.conf`) o
+/// `VENTAS_CENTRAL`: envia lo pendiente y aplica lo que la central devuelve.
+#[tauri::command]
+pub fn sincronizar_con_central() -> Result<ResumenSincronizacion, String> {
+    require_permiso(Permiso::AdministrarSistema)?;
+    let url = terminales::leer_configuracion("central")
+        .ok_or_else(|| "Configure la direccion de la central (central= en terminal.conf)".to_string())?;
+    let clave = terminales::leer_configuracion("central_clave")
+        .ok_or_else(|| "Configure la clave que entrego la central (central_clave= en terminal.conf)".to_string())?;
+    let mut conn = abrir_conexion()?;
+    let local = requerir_sucursal(&conn)?;
+    if local.central {
+        return Err("Esta base es la central".to_string());
+    }
+    let paquete = preparar_paquete(&conn, &local, None)?;
+    let enviados = paquete.cambios.len();
+    let cuerpo = serde_json::to_value(&paquete).map_err(|e| format!("Error al generar el paquete: {}", e))?;
+    let respuesta = terminales::enviar_json(
+        &format!("{}/sincronizacion", url.trim_end_matches('/')),
+        &[("X-Sucursal", &local.codigo), ("X-Sucursal-Clave", &clave)],
+        &cuerpo,
+    )?;
+    let respuesta: Paquete =
+        serde_json::from_value(respuesta).map_err(|e| format!("Respuesta invalida de la central: {}", e))?;
+
+    let mut resumen = aplicar_paquete(&mut conn, respuesta)?;
+    resumen.enviados = enviados;
+    Ok(resumen)
+}
+
+/// Si `clave` es la que la central entrego a la sucursal `codigo` con `registrar_sucursal`.
+pub(crate) fn autorizar_sucursal(codigo: &str, clave: &str) -> Result<bool, String> {
+    let conn = abrir_conexion()?;
+    conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sync_pares WHERE codigo = ?1 AND clave = ?2)",
+        rusqlite::params![codigo, hash_clave(clave)],
+        |row| row.get(0),
+    )
+    .map_err(|e| format!("Error en la consulta: {}", e))
+}
+
+/// Lado central del intercambio en linea: aplica el paquete de la sucursal `sucursal`, ya
+/// autorizada, y le responde con el catalogo que tiene pendiente.
+pub(crate) fn intercambiar(sucursal: &str, paquete: Paquete) -> Result<Paquete, String> {
+    if paquete.origen != sucursal {
+        return Err(format!("El paquete no es de la sucursal {}", sucursal));
+    }
+    let mut conn = abrir_conexion()?;
+    let local = requerir_sucursal(&conn)?;
+    if !local.central {
+        return Err("Esta base no es la central".to_string());
+    }
+    aplicar_paquete(&mut conn, paquete)?;
+    preparar_paquete(&conn, &local, Some(sucursal))
+}
+
+/// Ventas netas, unidades, costo y compras entre `desde` y `hasta`. `tablas` son las de
+/// ventas y movimientos; las de sucursales se filtran por `sucursal`.
+fn totales(
+    conn: &Connection,
+    tablas: (&str, &str),
+    sucursal: Option<&str>,
+    desde: &str,
+    hasta: &str,
+) -> Result<(f64, f64, f64, f64), String> {
+    let mut params = vec![ValorSql::Text(desde.to_string()), ValorSql::Text(hasta.to_string())];
+    let condicion = match sucursal {
+        Some(sucursal) => {
+            params.push(ValorSql::Text(sucursal.to_string()));
+            " AND sucursal = ?3"
+        }
+        None => "",
+    };
+    let (ventas, unidades, costo): (f64, f64, f64) = conn
+        .query_row(
+            &format!(
+                "SELECT COALESCE(SUM(subtotal * (cantidad - cantidad_devuelta) / cantidad), 0), \
+                 COALESCE(SUM(cantidad - cantidad_devuelta), 0), \
+                 COALESCE(SUM(COALESCE(costo_unitario, 0) * (cantidad - cantidad_devuelta)), 0) \
+                 FROM {} WHERE cantidad > 0 AND date(fecha) BETWEEN ?1 AND ?2{}",
+                tablas.0, condicion
+            ),
+            rusqlite::params_from_iter(params.iter()),
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .map_err(|e| format!("Error al totalizar ventas: {}", e))?;
+    let compras: f64 = conn
+        .query_row(
+            &format!(
+                "SELECT COALESCE(SUM(cantidad * COALESCE(costo_unitario, 0)), 0) FROM {} \
+                 WHERE motivo = 'compra' AND date(fecha) BETWEEN ?1 AND ?2{}",
+                tablas.1, condicion
+            ),
+            rusqlite::params_from_iter(params.iter()),
+            |row| row.get(0),
+        )
+        .map_err(|e| format!("Error al totalizar compras: {}", e))?;
+    Ok((ventas, unidades, costo, compras))
+}
+
+/// Numeros de esta base y de cada sucursal recibida, entre dos fechas (AAAA-MM-DD).
+#[tauri::command]
+pub fn consolidado_sucursales(desde: String, hasta: String) -> Result<Vec<ResumenSucursal>, String> {
+    require_permiso(Permiso::VerReportes)?;
+    let desde = parse_fecha(&desde)?.to_string();
+    let hasta = parse_fecha(&hasta)?.to_string();
+    let conn = abrir_conexion()?;
+    let local = sucursal_local(&conn)?;
+
+    let mut sucursales = vec![(
+        local.as_ref().map(|l| l.codigo.clone()).unwrap_or_default(),
+        local.as_ref().map(|l| l.nombre.clone()).unwrap_or_else(|| "Esta sucursal".to_string()),
+        None,
+        None,
+    )];
+    let mut stmt = conn
+        .prepare(
+            "SELECT codigo, COALESCE(nombre, codigo), ultima FROM sync_pares WHERE codigo <> ?1 \
+             AND (codigo IN (SELECT sucursal FROM ventas_sucursales) OR codigo IN (SELECT sucursal FROM movimientos_sucursales)) \
+             ORDER BY codigo",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let remotas = stmt
+        .query_map(rusqlite::params![CENTRAL], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, Option<String>>(2)?))
+        })
+        .map_err(|e| format!("Error al leer las sucursales: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    sucursales.extend(remotas.into_iter().map(|(codigo, nombre, ultima)| {
+        let filtro = Some(codigo.clone());
+        (codigo, nombre, ultima, filtro)
+    }));
+
+    sucursales
+        .into_iter()
+        .map(|(sucursal, nombre, ultima, filtro)| {
+            let tablas = match filtro {
+                Some(_) => ("ventas_sucursales", "movimientos_sucursales"),
+                None => ("ventas", "movimientos_stock"),
+            };
+            let (ventas, unidades, costo, compras) = totales(&conn, tablas, filtro.as_deref(), &desde, &hasta)?;
+            Ok(ResumenSucursal {
+                sucursal,
+                nombre,
+                ventas: redondear(ventas),
+                unidades: (unidades * 1000.0).round() / 1000.0,
+                costo: redondear(costo),
+                margen: redondear(ventas - costo),
+                compras: redondear(compras),
+                ultima,
+            })
+        })
+        .collect()
+}
+
+/// Stock de cada producto en esta base y en cada sucursal, sumando sus movimientos.
+#[tauri::command]
+pub fn stock_consolidado(filtro: Option<FiltroCatalogo>) -> Result<Vec<StockConsolidado>, String> {
+    require_permiso(Permiso::VerReportes)?;
+    let conn = abrir_conexion()?;
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT sucursal, producto_id, SUM(cantidad) FROM movimientos_sucursales \
+             GROUP BY sucursal, producto_id ORDER BY sucursal",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let filas = stmt
+        .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?, row.get::<_, f64>(2)?)))
+        .map_err(|e| format!("Error al leer movimientos de sucursales: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    let mut sucursales: Vec<String> = Vec::new();
+    let mut por_sucursal: HashMap<(String, i64), f64> = HashMap::new();
+    for (sucursal, producto_id, cantidad) in filas {
+        if !sucursales.contains(&sucursal) {
+            sucursales.push(sucursal.clone());
+        }
+        por_sucursal.insert((sucursal, producto_id), cantidad);
+    }
+
+    let (condiciones, params) = filtro.unwrap_or_default().clausula();
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT id, nombre_producto, COALESCE(CAST(cantidad_producto AS REAL), 0) FROM inventario \
+             WHERE NOT EXISTS (SELECT 1 FROM componentes k WHERE k.producto_id = inventario.id){} ORDER BY id",
+            condiciones
+        ))
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let productos = stmt
+        .query_map(rusqlite::params_from_iter(params), |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, f64>(2)?))
+        })
+        .map_err(|e| format!("Error al leer inventarios: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+
+    Ok(productos
+        .into_iter()
+        .map(|(producto_id, nombre, local)| {
+            let sucursales: Vec<StockSucursal> = sucursales
+                .iter()
+                .map(|sucursal| StockSucursal {
+                    sucursal: sucursal.clone(),
+                    cantidad: por_sucursal.get(&(sucursal.clone(), producto_id)).copied().unwrap_or(0.0),
+                })
+                .collect();
+            let total = local + sucursales.iter().map(|s| s.cantidad).sum::<f64>();
+            StockConsolidado {
+                producto_id,
+                nombre,
+                local,
+                sucursales,
+                total: (total * 1000.0).round() / 1000.0,
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comun::pruebas::base;
+
+    fn producto(conn: &Connection, sku: &str) -> (i64, String) {
+        conn.query_row(
+            "SELECT id, nombre_producto FROM inventario WHERE sku = ?1",
+            rusqlite::params![sku],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap()
+    }
+
+    fn contar(conn: &Connection, sql: &str) -> i64 {
+        conn.query_row(sql, [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn los_productos_se_identifican_por_su_uid_y_no_por_el_id_local() {
+        let mut central = base(&[(1, "Yerba", "Y1", 0.0)]);
+        let mut norte = base(&[(1, "Azucar", "A1", 5.0), (2, "Yerba", "Y1", 3.0)]);
+        let casa = configurar(&mut central, "casa".into(), "Casa central".into(), true).unwrap();
+        let sucursal = configurar(&mut norte, "norte".into(), "Norte".into(), false).unwrap();
+
+        let paquete = preparar_paquete(&norte, &sucursal, None).unwrap();
+        let resumen = aplicar_paquete(&mut central, paquete).unwrap();
+        assert_eq!(resumen.aplicados, 4);
+
+        // El id 1 de la sucursal no pisa al id 1 de la central
+        assert_eq!(producto(&central, "Y1"), (1, "Yerba".to_string()));
+        let (azucar, nombre) = producto(&central, "A1");
+        assert_eq!(nombre, "Azucar");
+        assert_ne!(azucar, 1);
+        let saldo = |producto_id: i64| -> f64 {
+            central
+                .query_row(
+                    "SELECT cantidad FROM movimientos_sucursales WHERE sucursal = 'norte' AND producto_id = ?1",
+                    rusqlite::params![producto_id],
+                    |row| row.get(0),
+                )
+                .unwrap()
+        };
+        assert_eq!(saldo(azucar), 5.0);
+        assert_eq!(saldo(1), 3.0);
+
+        // De vuelta, la Yerba de la central se asocia a la de la sucursal por su SKU
+        let respuesta = preparar_paquete(&central, &casa, Some("norte")).unwrap();
+        aplicar_paquete(&mut norte, respuesta).unwrap();
+        assert_eq!(contar(&norte, "SELECT COUNT(*) FROM inventario"), 2);
+        assert_eq!(producto(&norte, "Y1"), (2, "Yerba".to_string()));
+
+        // Un cambio de precio en la central llega al producto correcto de la sucursal
+        central
+            .execute("UPDATE inventario SET precio_producto = '150' WHERE id = 1", [])
+            .unwrap();
+        let respuesta = preparar_paquete(&central, &casa, Some("norte")).unwrap();
+        aplicar_paquete(&mut norte, respuesta).unwrap();
+        let precio = |id: i64| precio_actual(&norte, id).unwrap();
+        assert_eq!(precio(2), Some(150.0));
+        assert_eq!(precio(1), Some(100.0));
+    }
+
+    #[test]
+    fn repetir_un_paquete_no_duplica_movimientos() {
+        let mut central = base(&[]);
+        let mut norte = base(&[(1, "Azucar", "A1", 5.0)]);
+        configurar(&mut central, "casa".into(), "Casa central".into(), true).unwrap();
+        let sucursal = configurar(&mut norte, "norte".into(), "Norte".into(), false).unwrap();
+
+        let contenido = serde_json::to_string(&preparar_paquete(&norte, &sucursal, None).unwrap()).unwrap();
+        aplicar_paquete(&mut central, serde_json::from_str(&contenido).unwrap()).unwrap();
+        let resumen = aplicar_paquete(&mut central, serde_json::from_str(&contenido).unwrap()).unwrap();
+        assert_eq!(resumen.aplicados, 0);
+        assert_eq!(resumen.duplicados, 2);
+        assert_eq!(contar(&central, "SELECT COUNT(*) FROM movimientos_sucursales"), 1);
+        assert_eq!(contar(&central, "SELECT COUNT(*) FROM inventario"), 1);
+    }
+}
@@ -0,0 +1,624 @@
+// Varias cajas sobre una misma base en la red local.
+//
+// Una maquina corre `ventas --servidor [direccion]`: no abre ventanas, es la unica que usa
+// `database.db` y atiende `POST /comando/<nombre>` con los argumentos del invoke en JSON.
+// Por defecto escucha en 127.0.0.1:7878; para atender a la red hay que indicarlo
+// (`--servidor 0.0.0.0:7878`), y sin `clave=` (`VENTAS_CLAVE`) el servidor no arranca. Las
+// demas cajas abren la app con `servidor=http://<ip>:7878` y la misma `clave=` en
+// `terminal.conf` (junto a la base) o en `VENTAS_SERVIDOR`, y sus invokes se reenvian al
+// servidor, salvo los de la propia ventana. Cada caja se nombra con `terminal=<nombre>`
+// (`VENTAS_TERMINAL`); al iniciar sesion el servidor le entrega un token (`X-Sesion`) y es
+// ese token, no el nombre, el que identifica la sesion en las peticiones siguientes.
+//
+// El stock se bloquea en la base y no en la memoria del servidor: lo que lee y descuenta
+// stock corre en `movimientos::transaccion_stock`, asi queda protegido aunque dos procesos
+// abran el mismo archivo. Recibos, exportaciones y respaldos se escriben en el servidor.
+//
+// El servidor de una base central atiende ademas `POST /sincronizacion`, donde las
+// sucursales configuradas con `central=http://<ip>:7878` (`VENTAS_CENTRAL`) intercambian
+// sus cambios (ver `sincronizacion`). Ahi no vale la clave de las cajas: cada sucursal se
+// identifica con su codigo y la clave que le entrego la central (`central_clave=`).
+
+use chrono::Local;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use rusqlite::OptionalExtension;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use std::{env, fs, thread};
+use tauri::ipc::{Invoke, InvokeBody};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::{api, auditoria, catalogo, clientes, combos, comun, conteos, costos, cuentas, eventos};
+use crate::{exportacion, importacion, impuestos, lotes, movimientos, permisos, precios, promociones};
+use crate::{respaldo, seguridad, series, sincronizacion, ubicaciones, variantes, ventas};
+use crate::{abrir_conexion, ensure_db_initialized, find_db_path, LoginResponse};
+
+const DIRECCION_POR_DEFECTO: &str = "127.0.0.1:7878";
+const HILOS: usize = 8;
+/// Una sesion de caja caduca tras este tiempo sin uso.
+const SESION_SEGUNDOS: i64 = 12 * 60 * 60;
+
+/// Comandos que siempre atiende la caja local porque actuan sobre su ventana.
+const LOCALES: &[&str] = &["cerrar_ventana", "greet"];
+
+thread_local! {
+    static PETICION: RefCell<Option<PeticionCaja>> = const { RefCell::new(None) };
+}
+
+/// Caja que hizo la peticion que atiende este hilo.
+struct PeticionCaja {
+    terminal: String,
+    usuario: Option<String>,
+    /// Token emitido durante la peticion (inicio de sesion), para devolverlo en la respuesta
+    sesion_nueva: Option<String>,
+}
+
+struct Configuracion {
+    servidor: Option<String>,
+    terminal: String,
+    clave: Option<String>,
+}
+
+static CONFIGURACION: OnceLock<Configuracion> = OnceLock::new();
+
+/// Token de sesion que el servidor entrego a esta caja cliente.
+static SESION_CLIENTE: Mutex<Option<String>> = Mutex::new(None);
+
+/// Valor de `VENTAS_<CLAVE>` o, si no esta definida, de `terminal.conf`.
+pub(crate) fn leer_configuracion(clave: &str) -> Option<String> {
+    let valor = match env::var(format!("VENTAS_{}", clave.to_uppercase())) {
+        Ok(valor) => valor,
+        Err(_) => {
+            let ruta = find_db_path().with_file_name("terminal.conf");
+            let contenido = fs::read_to_string(ruta).ok()?;
+            contenido.lines().find_map(|linea| {
+                let (k, v) = linea.split_once('=')?;
+                (k.trim() == clave).then(|| v.to_string())
+            })?
+        }
+    };
+    Some(valor.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+fn configuracion() -> &'static Configuracion {
+    CONFIGURACION.get_or_init(|| Configuracion {
+        servidor: leer_configuracion("servidor").map(|s| s.trim_end_matches('/').to_string()),
+        terminal: leer_configuracion("terminal").unwrap_or_else(|| "caja".to_string()),
+        clave: leer_configuracion("clave"),
+    })
+}
+
+/// Terminal que hizo la peticion en curso; `None` fuera del modo servidor.
+pub(crate) fn terminal_actual() -> Option<String> {
+    PETICION.with(|p| p.borrow().as_ref().map(|p| p.terminal.clone()))
+}
+
+/// Usuario de la sesion de la caja que hizo la peticion; `None` si no la tiene.
+pub(crate) fn usuario_actual() -> Option<String> {
+    PETICION.with(|p| p.borrow().as_ref().and_then(|p| p.usuario.clone()))
+}
+
+pub(crate) fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Compara claves sin cortar en el primer byte distinto: se comparan los hashes, de largo
+/// fijo, para que el tiempo no diga cuanto de la clave se acerto ni cuanto mide.
+fn claves_iguales(a: &str, b: &str) -> bool {
+    Sha256::digest(a.as_bytes())
+        .iter()
+        .zip(Sha256::digest(b.as_bytes()).iter())
+        .fold(0u8, |dif, (x, y)| dif | (x ^ y))
+        == 0
+}
+
+/// Token de sesion al azar; en la base solo se guarda su hash.
+pub(crate) fn nuevo_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(40)
+        .map(char::from)
+        .collect()
+}
+
+/// Abre una sesion para la caja de la peticion en curso: reemplaza la anterior de esa caja y
+/// deja el token para enviarlo en la cabecera `X-Sesion` de la respuesta.
+pub(crate) fn abrir_sesion(usuario: &str) -> Result<(), String> {
+    let terminal = terminal_actual().ok_or_else(|| "No hay una caja en la peticion".to_string())?;
+    let token = nuevo_token();
+    let conn = abrir_conexion()?;
+    conn.execute("DELETE FROM sesiones_terminal WHERE terminal = ?1", rusqlite::params![terminal])
+        .map_err(|e| format!("Error al cerrar la sesion anterior: {}", e))?;
+    conn.execute(
+        "INSERT INTO sesiones_terminal (token, terminal, usuario, creada, ultimo_uso) VALUES (?1, ?2, ?3, ?4, ?5)",
+        rusqlite::params![
+            hash_token(&token),
+            terminal,
+            usuario,
+            comun::ahora(),
+            Local::now().timestamp()
+        ],
+    )
+    .map_err(|e| format!("Error al abrir la sesion: {}", e))?;
+    PETICION.with(|p| {
+        if let Some(peticion) = p.borrow_mut().as_mut() {
+            peticion.usuario = Some(usuario.to_string());
+            peticion.sesion_nueva = Some(token);
+        }
+    });
+    Ok(())
+}
+
+/// Terminal y usuario de un token vigente; renueva su ultimo uso.
+fn buscar_sesion(token: &str) -> Result<Option<(String, String)>, String> {
+    let conn = abrir_conexion()?;
+    let ahora = Local::now().timestamp();
+    let hash = hash_token(token);
+    let sesion = conn
+        .query_row(
+            "SELECT terminal, usuario FROM sesiones_terminal WHERE token = ?1 AND ultimo_uso > ?2",
+            rusqlite::params![hash, ahora - SESION_SEGUNDOS],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+        .map_err(|e| format!("Error al leer la sesion: {}", e))?;
+    if sesion.is_some() {
+        conn.execute(
+            "UPDATE sesiones_terminal SET ultimo_uso = ?1 WHERE token = ?2",
+            rusqlite::params![ahora, hash],
+        )
+        .map_err(|e| format!("Error al actualizar la sesion: {}", e))?;
+    }
+    Ok(sesion)
+}
+
+/// La app trabaja contra un servidor y no tiene base propia.
+pub(crate) fn es_cliente() -> bool {
+    configuracion().servidor.is_some()
+}
+
+/// Direccion en la que escuchar si la app se lanzo con `--servidor [direccion]`.
+pub(crate) fn direccion_servidor() -> Option<String> {
+    let args: Vec<String> = env::args().collect();
+    let posicion = args.iter().position(|a| a == "--servidor")?;
+    Some(
+        args.get(posicion + 1)
+            .filter(|a| !a.starts_with("--"))
+            .cloned()
+            .unwrap_or_else(|| DIRECCION_POR_DEFECTO.to_string()),
+    )
+}
+
+fn nombre_js(nombre: &str) -> String {
+    let mut resultado = String::with_capacity(nombre.len());
+    let mut mayuscula = false;
+    for c in nombre.chars() {
+        if c == '_' {
+            mayuscula = true;
+        } else if mayuscula {
+            resultado.extend(c.to_uppercase());
+            mayuscula = false;
+        } else {
+            resultado.push(c);
+        }
+    }
+    resultado
+}
+
+/// Argumento de un comando tal como lo manda el webview (`ubicacionId`), aceptando
+/// tambien el nombre de Rust. Si falta se toma `null`, como hace Tauri con los `Option`.
+fn argumento<T: DeserializeOwned>(args: &Value, nombre: &str) -> Result<T, String> {
+    let valor = args
+        .get(nombre_js(nombre))
+        .or_else(|| args.get(nombre))
+        .cloned()
+        .unwrap_or(Value::Null);
+    serde_json::from_value(valor).map_err(|e| format!("Argumento {} invalido: {}", nombre, e))
+}
+
+trait Respuesta {
+    fn en_json(self) -> Result<Value, String>;
+}
+
+impl<T: Serialize> Respuesta for Result<T, String> {
+    fn en_json(self) -> Result<Value, String> {
+        self.and_then(|valor| serde_json::to_value(valor).map_err(|e| format!("Error al serializar: {}", e)))
+    }
+}
+
+impl<T: Serialize> Respuesta for Vec<T> {
+    fn en_json(self) -> Result<Value, String> {
+        Ok(self).en_json()
+    }
+}
+
+impl Respuesta for LoginResponse {
+    fn en_json(self) -> Result<Value, String> {
+        Ok(self).en_json()
+    }
+}
+
+macro_rules! comandos {
+    ($comando:expr, $args:expr; $($modulo:ident :: $nombre:ident ( $($arg:ident),* );)*) => {
+        match $comando {
+            $(stringify!($nombre) => $modulo::$nombre($(argumento($args, stringify!($arg))?),*).en_json(),)*
+            otro => Err(format!("Comando desconocido: {}", otro)),
+        }
+    };
+}
+
+/// Unica lista de comandos: `despachar` la usa para atender a las cajas de la red y `main`
+/// para registrar los invokes de la ventana. Pasa la lista a la macro `$destino`, despues de
+/// los tokens `$previo`.
+macro_rules! con_comandos {
+    ($destino:ident ! ( $($previo:tt)* )) => {
+        $destino!($($previo)*
+            crate::validar_login(usuario, contrasena);
+            crate::listar_inventarios(filtro);
+            crate::obtener_inventario_por_id(id);
+            crate::obtener_inventario_por_nombre(nombre);
+            crate::actualizar_inventario(id, nombre, precio, cantidad);
+            crate::insertar_inventario(id, nombre, precio, cantidad, unidad, categoria_id, marca_id);
+            crate::registrar_venta(id, cantidad, ubicacion_id);
+            crate::registrar_compra(id, cantidad, lote, series, costo_unitario, ubicacion_id);
+            crate::generar_recibo_ventas(payload);
+            crate::validar_password_admin(usuario, password);
+            crate::listar_usuarios();
+            crate::insertar_usuario(name, password, correo, admin, rol);
+            crate::eliminar_usuario(name);
+            crate::actualizar_usuario(name, nuevo_nombre, correo, rol);
+            crate::cambiar_password(actual, nueva);
+            crate::restablecer_password(name);
+            api::crear_token_api(nombre, rol);
+            api::listar_tokens_api();
+            api::revocar_token_api(id);
+            auditoria::consultar_auditoria(filtro);
+            auditoria::verificar_auditoria();
+            catalogo::listar_categorias();
+            catalogo::guardar_categoria(categoria);
+            catalogo::eliminar_categoria(id);
+            catalogo::listar_marcas();
+            catalogo::guardar_marca(marca);
+            catalogo::listar_unidades();
+            catalogo::asignar_clasificacion(producto_id, categoria_id, marca_id, unidad);
+            catalogo::fijar_stock_minimo(producto_id, minimo);
+            clientes::buscar_clientes(texto);
+            clientes::obtener_cliente(id);
+            clientes::guardar_cliente(cliente);
+            clientes::historial_cliente(id);
+            combos::listar_componentes(producto_id);
+            combos::guardar_componentes(producto_id, componentes);
+            conteos::iniciar_conteo(filtro, ubicacion_id, descripcion);
+            conteos::listar_conteos();
+            conteos::registrar_conteo(conteo_id, producto_id, codigo, cantidad);
+            conteos::diferencias_conteo(conteo_id);
+            conteos::aplicar_conteo(conteo_id, productos, sin_contar_en_cero);
+            conteos::cancelar_conteo(conteo_id);
+            costos::asignar_costo(producto_id, costo);
+            costos::valorizar_inventario(fecha, metodo);
+            cuentas::estado_cuenta(cliente_id);
+            cuentas::registrar_abono(cliente_id, monto, referencia);
+            cuentas::asignar_limite_credito(cliente_id, limite);
+            cuentas::cuentas_por_cobrar();
+            cuentas::generar_estado_cuenta(cliente_id);
+            eventos::listar_webhooks();
+            eventos::guardar_webhook(webhook);
+            eventos::eliminar_webhook(id);
+            eventos::listar_entregas(webhook_id, estado);
+            eventos::reintentar_entregas(webhook_id);
+            eventos::probar_webhook(id);
+            eventos::eventos_desde(desde);
+            exportacion::exportar_inventario(opciones, filtro);
+            exportacion::exportar_ventas(desde, hasta, opciones);
+            exportacion::exportar_usuarios(opciones);
+            importacion::previsualizar_importacion(payload);
+            importacion::importar_inventario(payload);
+            impuestos::listar_impuestos();
+            impuestos::guardar_impuesto(impuesto);
+            impuestos::asignar_impuesto(producto_id, impuesto_id, precio_incluye_impuesto);
+            lotes::listar_lotes(producto_id);
+            lotes::lotes_por_vencer(dias);
+            lotes::ajustar_lote(lote_id, cantidad, motivo);
+            movimientos::listar_movimientos(producto_id);
+            permisos::listar_roles();
+            precios::listar_listas_precios();
+            precios::guardar_lista_precios(lista);
+            precios::asignar_lista_cliente(cliente_id, lista_id);
+            precios::precios_producto(producto_id);
+            precios::fijar_precio(producto_id, lista_id, precio, desde);
+            precios::listar_precios_programados();
+            precios::cancelar_precio_programado(id);
+            precios::previsualizar_actualizacion_precios(actualizacion);
+            precios::aplicar_actualizacion_precios(actualizacion);
+            precios::historial_precios(producto_id);
+            promociones::calcular_venta(payload);
+            promociones::listar_promociones();
+            promociones::guardar_promocion(promocion);
+            promociones::eliminar_promocion(id);
+            respaldo::crear_respaldo();
+            respaldo::listar_respaldos();
+            respaldo::verificar_respaldo(ruta);
+            respaldo::restaurar_respaldo(ruta, admin_password);
+            seguridad::listar_bloqueos();
+            seguridad::desbloquear_usuario(name);
+            series::marcar_serializado(producto_id, serializado);
+            series::listar_series(producto_id, solo_en_stock);
+            series::historial_serie(serie);
+            sincronizacion::configurar_sucursal(codigo, nombre, central);
+            sincronizacion::registrar_sucursal(codigo, nombre);
+            sincronizacion::estado_sincronizacion();
+            sincronizacion::exportar_sincronizacion(destino);
+            sincronizacion::importar_sincronizacion(ruta);
+            sincronizacion::sincronizar_con_central();
+            sincronizacion::consolidado_sucursales(desde, hasta);
+            sincronizacion::stock_consolidado(filtro);
+            ubicaciones::listar_ubicaciones();
+            ubicaciones::guardar_ubicacion(ubicacion);
+            ubicaciones::listar_stock_ubicaciones(filtro);
+            ubicaciones::crear_traslado(origen_id, destino_id, lineas, nota);
+            ubicaciones::recibir_traslado(id);
+            ubicaciones::cancelar_traslado(id);
+            ubicaciones::listar_traslados(estado);
+            variantes::listar_productos_padre();
+            variantes::guardar_producto_padre(padre);
+            variantes::guardar_variante(variante);
+            variantes::listar_variantes(padre_id);
+            variantes::obtener_inventario_por_codigo(codigo);
+            ventas::obtener_ticket(numero_recibo);
+            ventas::anular_venta(numero_recibo, motivo, supervisor_usuario, supervisor_password);
+            ventas::devolver_venta(numero_recibo, lineas, motivo);
+            ventas::finalizar_venta(payload);
+            ventas::cancelar_item_venta(id, cantidad, ubicacion_id);
+        )
+    };
+}
+pub(crate) use con_comandos;
+
+/// Ejecuta un comando por nombre con los argumentos de un invoke.
+pub(crate) fn despachar(comando: &str, args: &Value) -> Result<Value, String> {
+    con_comandos!(comandos!(comando, args;))
+}
+
+pub(crate) fn cabecera(peticion: &Request, nombre: &'static str) -> Option<String> {
+    peticion
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv(nombre))
+        .map(|h| h.value.as_str().trim().to_string())
+}
+
+/// Resultado del comando y, si la peticion inicio sesion, el token nuevo.
+fn procesar(peticion: &mut Request) -> Result<(Value, Option<String>), (u16, String)> {
+    // `None` es el intercambio de una sucursal con esta base central
+    let comando = match (peticion.method(), peticion.url()) {
+        (Method::Post, "/sincronizacion") => None,
+        (Method::Post, url) => match url.strip_prefix("/comando/") {
+            Some(comando) => Some(comando.to_string()),
+            None => return Err((404, "Ruta no encontrada".to_string())),
+        },
+        _ => return Err((404, "Ruta no encontrada".to_string())),
+    };
+    // Las cajas usan la clave de terminales; cada sucursal, la que le dio la central
+    let sucursal = match &comando {
+        Some(_) => None,
+        None => {
+            let codigo = cabecera(peticion, "X-Sucursal").unwrap_or_default();
+            let clave = cabecera(peticion, "X-Sucursal-Clave").unwrap_or_default();
+            if !sincronizacion::autorizar_sucursal(&codigo, &clave).map_err(|e| (500, e))? {
+                return Err((401, "Sucursal no registrada o clave incorrecta".to_string()));
+            }
+            Some(codigo)
+        }
+    };
+    if comando.is_some() {
+        if let Some(clave) = &configuracion().clave {
+            if !cabecera(peticion, "X-Clave").is_some_and(|dada| claves_iguales(&dada, clave)) {
+                return Err((401, "Clave de terminal incorrecta".to_string()));
+            }
+        }
+    }
+    let caja = match &comando {
+        Some(_) => {
+            let terminal = cabecera(peticion, "X-Terminal")
+                .filter(|t| !t.is_empty() && t.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+                .ok_or_else(|| (400, "Identificador de terminal invalido".to_string()))?;
+            // La sesion sale del token que entrego el servidor; el nombre de la caja solo
+            // tiene que coincidir con el de la sesion
+            let usuario = match cabecera(peticion, "X-Sesion").filter(|t| !t.is_empty()) {
+                Some(token) => match buscar_sesion(&token).map_err(|e| (500, e))? {
+                    Some((dueno, usuario)) if dueno == terminal => Some(usuario),
+                    Some(_) => return Err((401, "La sesion pertenece a otra caja".to_string())),
+                    None => None,
+                },
+                None => None,
+            };
+            Some(PeticionCaja {
+                terminal,
+                usuario,
+                sesion_nueva: None,
+            })
+        }
+        None => None,
+    };
+
+    let mut cuerpo = String::new();
+    peticion
+        .as_reader()
+        .read_to_string(&mut cuerpo)
+        .map_err(|e| (400, format!("No se pudo leer la peticion: {}", e)))?;
+    let args: Value = if cuerpo.trim().is_empty() {
+        Value::Object(Default::default())
+    } else {
+        serde_json::from_str(&cuerpo).map_err(|e| (400, format!("JSON invalido: {}", e)))?
+    };
+
+    let Some(comando) = comando else {
+        let paquete = serde_json::from_value(args).map_err(|e| (400, format!("Paquete invalido: {}", e)))?;
+        return sincronizacion::intercambiar(sucursal.as_deref().unwrap_or_default(), paquete)
+            .and_then(|respuesta| serde_json::to_value(respuesta).map_err(|e| e.to_string()))
+            .map(|valor| (valor, None))
+            .map_err(|e| (400, e));
+    };
+    PETICION.with(|p| *p.borrow_mut() = caja);
+    let resultado = panic::catch_unwind(AssertUnwindSafe(|| despachar(&comando, &args)));
+    let sesion_nueva = PETICION.with(|p| p.borrow_mut().take().and_then(|p| p.sesion_nueva));
+    match resultado {
+        Ok(resultado) => resultado.map(|valor| (valor, sesion_nueva)).map_err(|e| (400, e)),
+        Err(_) => Err((500, format!("Error interno al ejecutar {}", comando))),
+    }
+}
+
+/// Responde con `cuerpo` en JSON.
+pub(crate) fn responder(peticion: Request, estado: u16, cuerpo: &Value) {
+    responder_con_sesion(peticion, estado, cuerpo, None);
+}
+
+fn responder_con_sesion(peticion: Request, estado: u16, cuerpo: &Value, sesion: Option<String>) {
+    let mut respuesta = Response::from_string(cuerpo.to_string())
+        .with_status_code(estado)
+        .with_header(Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).expect("cabecera valida"));
+    if let Some(sesion) = sesion {
+        respuesta.add_header(Header::from_bytes(&b"X-Sesion"[..], sesion.as_bytes()).expect("cabecera valida"));
+    }
+    if let Err(e) = peticion.respond(respuesta) {
+        println!("[warn] no se pudo responder la peticion: {}", e);
+    }
+}
+
+fn atender(mut peticion: Request) {
+    let (estado, cuerpo, sesion) = match procesar(&mut peticion) {
+        Ok((valor, sesion)) => (200, valor, sesion),
+        Err((estado, e)) => (estado, Value::String(e), None),
+    };
+    responder_con_sesion(peticion, estado, &cuerpo, sesion);
+}
+
+/// Atiende a las cajas de la red hasta que se cierre el proceso.
+pub(crate) fn servir(direccion: &str) -> Result<(), String> {
+    if configuracion().clave.is_none() {
+        return Err("Defina clave= en terminal.conf (o VENTAS_CLAVE) para iniciar el servidor de cajas".to_string());
+    }
+    ensure_db_initialized()?;
+    let servidor = Arc::new(
+        Server::http(direccion).map_err(|e| format!("No se pudo escuchar en {}: {}", direccion, e))?,
+    );
+    println!("[info] servidor de cajas escuchando en {}", direccion);
+
+    let hilos: Vec<_> = (0..HILOS)
+        .map(|_| {
+            let servidor = Arc::clone(&servidor);
+            thread::spawn(move || {
+                for peticion in servidor.incoming_requests() {
+                    atender(peticion);
+                }
+            })
+        })
+        .collect();
+    for hilo in hilos {
+        let _ = hilo.join();
+    }
+    Ok(())
+}
+
+fn agente() -> &'static ureq::Agent {
+    static AGENTE: OnceLock<ureq::Agent> = OnceLock::new();
+    AGENTE.get_or_init(|| ureq::AgentBuilder::new().timeout_connect(Duration::from_secs(5)).build())
+}
+
+/// Los errores del otro lado llegan como un texto JSON.
+fn leer_respuesta(url: &str, resultado: Result<ureq::Response, ureq::Error>) -> Result<Value, String> {
+    match resultado {
+        Ok(respuesta) => respuesta
+            .into_json()
+            .map_err(|e| format!("Respuesta invalida del servidor: {}", e)),
+        Err(ureq::Error::Status(_, respuesta)) => Err(respuesta
+            .into_json::<String>()
+            .unwrap_or_else(|e| format!("Respuesta invalida del servidor: {}", e))),
+        Err(e) => Err(format!("No se pudo conectar con {}: {}", url, e)),
+    }
+}
+
+/// POST de `cuerpo` en JSON a `url` con las `cabeceras` indicadas.
+pub(crate) fn enviar_json(url: &str, cabeceras: &[(&str, &str)], cuerpo: &Value) -> Result<Value, String> {
+    let peticion = cabeceras
+        .iter()
+        .fold(agente().post(url), |peticion, (nombre, valor)| peticion.set(nombre, valor));
+    leer_respuesta(url, peticion.send_json(cuerpo))
+}
+
+/// Comando en el servidor con la clave de terminales pero sin la sesion de la caja, para
+/// lo que la app consulta por su cuenta (como los eventos): asi no mantiene viva la sesion.
+pub(crate) fn consultar_servidor(comando: &str, args: &Value) -> Result<Value, String> {
+    let configuracion = configuracion();
+    let servidor = configuracion
+        .servidor
+        .as_ref()
+        .ok_or_else(|| "Esta caja no trabaja contra un servidor".to_string())?;
+    let mut cabeceras = vec![("X-Terminal", configuracion.terminal.as_str())];
+    if let Some(clave) = &configuracion.clave {
+        cabeceras.push(("X-Clave", clave.as_str()));
+    }
+    enviar_json(&format!("{}/comando/{}", servidor, comando), &cabeceras, args)
+}
+
+/// Comando en el servidor con la identificacion de esta caja y su token de sesion; guarda
+/// el que entregue el servidor.
+fn invocar_remoto(servidor: &str, comando: &str, args: &Value) -> Result<Value, String> {
+    let configuracion = configuracion();
+    let url = format!("{}/comando/{}", servidor, comando);
+    let mut peticion = agente().post(&url).set("X-Terminal", &configuracion.terminal);
+    if let Some(clave) = &configuracion.clave {
+        peticion = peticion.set("X-Clave", clave);
+    }
+    if let Some(sesion) = SESION_CLIENTE.lock().map_err(|e| e.to_string())?.clone() {
+        peticion = peticion.set("X-Sesion", &sesion);
+    }
+    let resultado = peticion.send_json(args);
+    if let Some(sesion) = resultado.as_ref().ok().and_then(|r| r.header("X-Sesion")) {
+        *SESION_CLIENTE.lock().map_err(|e| e.to_string())? = Some(sesion.to_string());
+    }
+    leer_respuesta(&url, resultado)
+}
+
+/// En una caja cliente reenvia el invoke al servidor y devuelve `None`. Sin servidor
+/// configurado, o si el comando es de la propia ventana, lo devuelve para atenderlo aqui.
+pub(crate) fn reenviar(invoke: Invoke) -> Option<Invoke> {
+    let servidor = match &configuracion().servidor {
+        Some(servidor) if !LOCALES.contains(&invoke.message.command()) => servidor.clone(),
+        _ => return Some(invoke),
+    };
+    let comando = invoke.message.command().to_string();
+    let args = match invoke.message.payload() {
+        InvokeBody::Json(args) => args.clone(),
+        InvokeBody::Raw(_) => {
+            invoke.resolver.reject(format!("{} no se puede enviar al servidor", comando));
+            return None;
+        }
+    };
+    let resolver = invoke.resolver;
+    thread::spawn(move || match invocar_remoto(&servidor, &comando, &args) {
+        Ok(valor) => resolver.resolve(valor),
+        Err(e) => resolver.reject(e),
+    });
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn claves_iguales_solo_con_la_misma_clave() {
+        assert!(claves_iguales("secreta", "secreta"));
+        assert!(!claves_iguales("secreta", "secretb"));
+        assert!(!claves_iguales("secreta", "secreta2"));
+        assert!(!claves_iguales("", "secreta"));
+    }
+}
@@ -0,0 +1,496 @@
+// Ubicaciones (deposito, salon, sucursales) y traslados entre ellas.
+//
+// `cantidad_producto` sigue siendo el stock total. Las ubicaciones distintas de la
+// principal guardan su stock en `stock_ubicacion`; la principal no tiene filas y su stock
+// es el total menos lo de las demas, asi todo lo que ajusta el total sin indicar ubicacion
+// (altas, importaciones, lotes) cae en la principal. Un traslado descuenta del origen al
+// enviarse y suma al destino al recibirse; mientras tanto esta en transito y no cuenta en
+// el total ni en la valorizacion. Los lotes y las series se siguen llevando por producto,
+// por eso los productos que los usan no se trasladan: la ubicacion no sabria que lote o
+// que numero de serie tiene.
+
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::catalogo::{redondear_cantidad, validar_cantidad, FiltroCatalogo};
+use crate::comun::{ahora, usuario};
+use crate::{combos, costos, lotes, series};
+use crate::movimientos::{ajustar_stock, ajustar_stock_costeado, transaccion_stock};
+use crate::permisos::{require_permiso, Permiso};
+use crate::{abrir_conexion, auditoria};
+
+pub(crate) const PRINCIPAL: i64 = 1;
+
+#[derive(Serialize, Deserialize)]
+pub struct Ubicacion {
+    id: Option<i64>,
+    nombre: String,
+    activa: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StockEnUbicacion {
+    ubicacion_id: i64,
+    ubicacion: String,
+    cantidad: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StockProducto {
+    producto_id: i64,
+    nombre: String,
+    unidad: String,
+    ubicaciones: Vec<StockEnUbicacion>,
+    en_transito: f64,
+    total: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LineaTraslado {
+    producto_id: i64,
+    cantidad: f64,
+    /// Solo lectura
+    #[serde(default)]
+    nombre: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Traslado {
+    id: i64,
+    origen_id: i64,
+    destino_id: i64,
+    /// "en_transito", "recibido" o "cancelado"
+    estado: String,
+    nota: Option<String>,
+    enviado: String,
+    enviado_por: String,
+    cerrado: Option<String>,
+    cerrado_por: Option<String>,
+    lineas: Vec<LineaTraslado>,
+}
+
+fn nombre_ubicacion(conn: &Connection, id: i64) -> Result<(String, bool), String> {
+    conn.query_row(
+        "SELECT nombre, activa FROM ubicaciones WHERE id = ?1",
+        rusqlite::params![id],
+        |row| Ok((row.get(0)?, row.get::<_, i64>(1)? == 1)),
+    )
+    .optional()
+    .map_err(|e| format!("Error en la consulta: {}", e))?
+    .ok_or_else(|| format!("No se encontro la ubicacion {}", id))
+}
+
+/// Ubicacion de una operacion; la principal si no se indica. Debe estar activa.
+pub(crate) fn validar_ubicacion(conn: &Connection, ubicacion_id: Option<i64>) -> Result<i64, String> {
+    let id = ubicacion_id.unwrap_or(PRINCIPAL);
+    let (nombre, activa) = nombre_ubicacion(conn, id)?;
+    if !activa {
+        return Err(format!("La ubicacion {} esta inactiva", nombre));
+    }
+    Ok(id)
+}
+
+/// Stock del producto en la ubicacion.
+pub(crate) fn stock_en(conn: &Connection, producto_id: i64, ubicacion_id: i64) -> Result<f64, String> {
+    let cantidad: f64 = if ubicacion_id == PRINCIPAL {
+        conn.query_row(
+            "SELECT COALESCE(CAST(cantidad_producto AS REAL), 0) \
+             - (SELECT COALESCE(SUM(cantidad), 0) FROM stock_ubicacion WHERE producto_id = ?1) \
+             FROM inventario WHERE id = ?1",
+            rusqlite::params![producto_id],
+            |row| row.get(0),
+        )
+    } else {
+        conn.query_row(
+            "SELECT COALESCE((SELECT cantidad FROM stock_ubicacion WHERE producto_id = ?1 AND ubicacion_id = ?2), 0)",
+            rusqlite::params![producto_id, ubicacion_id],
+            |row| row.get(0),
+        )
+    }
+    .map_err(|e| format!("No se encontro el producto: {}", e))?;
+    Ok(redondear_cantidad(cantidad))
+}
+
+/// Suma `cantidad` al stock propio de una ubicacion secundaria. Se llama desde
+/// `movimientos::ajustar_stock_costeado` junto con el cambio del total.
+pub(crate) fn sumar_en(conn: &Connection, producto_id: i64, ubicacion_id: i64, cantidad: f64) -> Result<(), String> {
+    if ubicacion_id == PRINCIPAL {
+        return Ok(());
+    }
+    conn.execute(
+        "INSERT INTO stock_ubicacion (producto_id, ubicacion_id, cantidad) VALUES (?1, ?2, ?3) \
+         ON CONFLICT (producto_id, ubicacion_id) DO UPDATE SET cantidad = ROUND(cantidad + excluded.cantidad, 3)",
+        rusqlite::params![producto_id, ubicacion_id, cantidad],
+    )
+    .map_err(|e| format!("Error al actualizar el stock de la ubicacion: {}", e))?;
+    Ok(())
+}
+
+/// Para los cambios del total que no indican ubicacion: la principal no puede quedar en
+/// negativo por un ajuste que en realidad corresponde a otra ubicacion.
+pub(crate) fn validar_principal(conn: &Connection, producto_id: i64, diferencia: f64) -> Result<(), String> {
+    if diferencia >= 0.0 {
+        return Ok(());
+    }
+    let disponible = stock_en(conn, producto_id, PRINCIPAL)?;
+    if redondear_cantidad(disponible + diferencia) < 0.0 {
+        return Err(format!(
+            "La ubicacion principal solo tiene {}; el resto del stock esta en otras ubicaciones",
+            disponible
+        ));
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub fn listar_ubicaciones() -> Result<Vec<Ubicacion>, String> {
+    require_permiso(Permiso::ConsultarInventario)?;
+    let conn = abrir_conexion()?;
+    let mut stmt = conn
+        .prepare("SELECT id, nombre, activa FROM ubicaciones ORDER BY id")
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let ubicaciones = stmt
+        .query_map([], |row| {
+            Ok(Ubicacion {
+                id: row.get(0)?,
+                nombre: row.get(1)?,
+                activa: row.get::<_, i64>(2)? == 1,
+            })
+        })
+        .map_err(|e| format!("Error al leer ubicaciones: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    Ok(ubicaciones)
+}
+
+/// Crea o actualiza una ubicacion. Solo se puede desactivar si no tiene stock ni
+/// traslados pendientes; la principal no se desactiva.
+#[tauri::command]
+pub fn guardar_ubicacion(ubicacion: Ubicacion) -> Result<i64, String> {
+    require_permiso(Permiso::AdministrarSistema)?;
+    let nombre = ubicacion.nombre.trim();
+    if nombre.is_empty() {
+        return Err("El nombre de la ubicacion es obligatorio".to_string());
+    }
+    let conn = abrir_conexion()?;
+    if let (Some(id), false) = (ubicacion.id, ubicacion.activa) {
+        if id == PRINCIPAL {
+            return Err("La ubicacion principal no se puede desactivar".to_string());
+        }
+        let ocupada: bool = conn
+            .query_row(
+                "SELECT EXISTS (SELECT 1 FROM stock_ubicacion WHERE ubicacion_id = ?1 AND cantidad <> 0) \
+                 OR EXISTS (SELECT 1 FROM traslados WHERE estado = 'en_transito' AND (origen_id = ?1 OR destino_id = ?1))",
+                rusqlite::params![id],
+                |row| row.get(0),
+            )
+            .map_err(|e| format!("Error en la consulta: {}", e))?;
+        if ocupada {
+            return Err(format!("{} tiene stock o traslados pendientes", nombre));
+        }
+    }
+    let duplicado = |e: rusqlite::Error| match e {
+        rusqlite::Error::SqliteFailure(err, _) if err.code == rusqlite::ErrorCode::ConstraintViolation => {
+            format!("Ya existe una ubicacion llamada {}", nombre)
+        }
+        e => format!("Error al guardar la ubicacion: {}", e),
+    };
+    let id = match ubicacion.id {
+        Some(id) => {
+            nombre_ubicacion(&conn, id)?;
+            conn.execute(
+                "UPDATE ubicaciones SET nombre = ?1, activa = ?2 WHERE id = ?3",
+                rusqlite::params![nombre, ubicacion.activa, id],
+            )
+            .map_err(duplicado)?;
+            id
+        }
+        None => {
+            conn.execute(
+                "INSERT INTO ubicaciones (nombre, activa) VALUES (?1, ?2)",
+                rusqlite::params![nombre, ubicacion.activa],
+            )
+            .map_err(duplicado)?;
+            conn.last_insert_rowid()
+        }
+    };
+    auditoria::registrar_auditoria(
+        &conn,
+        "guardar_ubicacion",
+        &format!("ubicacion:{}", id),
+        None,
+        serde_json::to_value(&ubicacion).ok(),
+        true,
+    )?;
+    Ok(id)
+}
+
+/// Stock por ubicacion, en transito y total de los productos que pasan el filtro. Los
+/// combos no tienen stock propio y no se listan.
+#[tauri::command]
+pub fn listar_stock_ubicaciones(filtro: Option<FiltroCatalogo>) -> Result<Vec<StockProducto>, String> {
+    require_permiso(Permiso::ConsultarInventario)?;
+    let conn = abrir_conexion()?;
+    let ubicaciones: Vec<(i64, String)> = {
+        let mut stmt = conn
+            .prepare("SELECT id, nombre FROM ubicaciones WHERE activa = 1 OR id = ?1 ORDER BY id")
+            .map_err(|e| format!("Error en la consulta: {}", e))?;
+        let filas = stmt
+            .query_map(rusqlite::params![PRINCIPAL], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(|e| format!("Error al leer ubicaciones: {}", e))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("Error en fila: {}", e))?;
+        filas
+    };
+
+    let (condiciones, params) = filtro.unwrap_or_default().clausula();
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT id, nombre_producto, unidad, COALESCE(CAST(cantidad_producto AS REAL), 0), \
+             (SELECT COALESCE(SUM(d.cantidad), 0) FROM traslados_detalle d JOIN traslados t ON t.id = d.traslado_id \
+              WHERE d.producto_id = inventario.id AND t.estado = 'en_transito') \
+             FROM inventario WHERE NOT EXISTS (SELECT 1 FROM componentes k WHERE k.producto_id = inventario.id){} \
+             ORDER BY id",
+            condiciones
+        ))
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let productos = stmt
+        .query_map(rusqlite::params_from_iter(params), |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, f64>(3)?,
+                row.get::<_, f64>(4)?,
+            ))
+        })
+        .map_err(|e| format!("Error al leer inventarios: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+
+    productos
+        .into_iter()
+        .map(|(producto_id, nombre, unidad, total, en_transito)| {
+            let ubicaciones = ubicaciones
+                .iter()
+                .map(|(ubicacion_id, ubicacion)| {
+                    Ok(StockEnUbicacion {
+                        ubicacion_id: *ubicacion_id,
+                        ubicacion: ubicacion.clone(),
+                        cantidad: stock_en(&conn, producto_id, *ubicacion_id)?,
+                    })
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            Ok(StockProducto {
+                producto_id,
+                nombre,
+                unidad,
+                ubicaciones,
+                en_transito: redondear_cantidad(en_transito),
+                total: redondear_cantidad(total),
+            })
+        })
+        .collect()
+}
+
+fn cargar_traslado(conn: &Connection, id: i64) -> Result<Traslado, String> {
+    let mut traslado = conn
+        .query_row(
+            "SELECT id, origen_id, destino_id, estado, nota, enviado, enviado_por, cerrado, cerrado_por \
+             FROM traslados WHERE id = ?1",
+            rusqlite::params![id],
+            |row| {
+                Ok(Traslado {
+                    id: row.get(0)?,
+                    origen_id: row.get(1)?,
+                    destino_id: row.get(2)?,
+                    estado: row.get(3)?,
+                    nota: row.get(4)?,
+                    enviado: row.get(5)?,
+                    enviado_por: row.get(6)?,
+                    cerrado: row.get(7)?,
+                    cerrado_por: row.get(8)?,
+                    lineas: Vec::new(),
+                })
+            },
+        )
+        .optional()
+        .map_err(|e| format!("Error en la consulta: {}", e))?
+        .ok_or_else(|| format!("No se encontro el traslado {}", id))?;
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT d.producto_id, d.cantidad, i.nombre_producto FROM traslados_detalle d \
+             JOIN inventario i ON i.id = d.producto_id WHERE d.traslado_id = ?1 ORDER BY d.producto_id",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    traslado.lineas = stmt
+        .query_map(rusqlite::params![id], |row| {
+            Ok(LineaTraslado {
+                producto_id: row.get(0)?,
+                cantidad: row.get(1)?,
+                nombre: row.get(2)?,
+            })
+        })
+        .map_err(|e| format!("Error al leer el traslado: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    Ok(traslado)
+}
+
+/// Costo con que salio cada linea, para que vuelva a entrar al mismo costo.
+fn costos_traslado(conn: &Connection, id: i64) -> Result<Vec<(i64, f64, f64)>, String> {
+    let mut stmt = conn
+        .prepare("SELECT producto_id, cantidad, costo_unitario FROM traslados_detalle WHERE traslado_id = ?1")
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let lineas = stmt
+        .query_map(rusqlite::params![id], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .map_err(|e| format!("Error al leer el traslado: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    Ok(lineas)
+}
+
+/// Envia mercaderia a otra ubicacion: sale del origen y queda en transito hasta que el
+/// destino la recibe.
+#[tauri::command]
+pub fn crear_traslado(
+    origen_id: i64,
+    destino_id: i64,
+    lineas: Vec<LineaTraslado>,
+    nota: Option<String>,
+) -> Result<Traslado, String> {
+    require_permiso(Permiso::AjustarStock)?;
+    if origen_id == destino_id {
+        return Err("El origen y el destino deben ser distintos".to_string());
+    }
+    if lineas.is_empty() {
+        return Err("El traslado no tiene productos".to_string());
+    }
+    let mut conn = abrir_conexion()?;
+    validar_ubicacion(&conn, Some(origen_id))?;
+    validar_ubicacion(&conn, Some(destino_id))?;
+    let mut vistos = Vec::with_capacity(lineas.len());
+    for linea in &lineas {
+        if vistos.contains(&linea.producto_id) {
+            return Err(format!("El producto {} esta repetido", linea.producto_id));
+        }
+        if combos::es_combo(&conn, linea.producto_id)? {
+            return Err("Los combos no se trasladan; traslade sus componentes".to_string());
+        }
+        if series::es_serializado(&conn, linea.producto_id)? {
+            return Err(format!(
+                "El producto {} se controla por numero de serie y no se puede trasladar",
+                linea.producto_id
+            ));
+        }
+        if lotes::controla_lotes(&conn, linea.producto_id)? {
+            return Err(format!(
+                "El producto {} se controla por lotes y no se puede trasladar",
+                linea.producto_id
+            ));
+        }
+        vistos.push(linea.producto_id);
+    }
+
+    let nota = nota.map(|n| n.trim().to_string()).filter(|n| !n.is_empty());
+    let tx = transaccion_stock(&mut conn)?;
+    tx.execute(
+        "INSERT INTO traslados (origen_id, destino_id, estado, nota, enviado, enviado_por) \
+         VALUES (?1, ?2, 'en_transito', ?3, ?4, ?5)",
+        rusqlite::params![origen_id, destino_id, nota, ahora(), usuario()],
+    )
+    .map_err(|e| format!("Error al registrar el traslado: {}", e))?;
+    let id = tx.last_insert_rowid();
+    let referencia = format!("traslado {}", id);
+    for linea in &lineas {
+        let cantidad = validar_cantidad(&tx, linea.producto_id, linea.cantidad)?;
+        let costo = costos::costo_promedio(&tx, linea.producto_id)?;
+        ajustar_stock(&tx, linea.producto_id, -cantidad, origen_id, "traslado", Some(&referencia))?;
+        tx.execute(
+            "INSERT INTO traslados_detalle (traslado_id, producto_id, cantidad, costo_unitario) VALUES (?1, ?2, ?3, ?4)",
+            rusqlite::params![id, linea.producto_id, cantidad, costo],
+        )
+        .map_err(|e| format!("Error al registrar el traslado: {}", e))?;
+    }
+    let traslado = cargar_traslado(&tx, id)?;
+    auditoria::registrar_auditoria(
+        &tx,
+        "crear_traslado",
+        &format!("traslado:{}", id),
+        None,
+        serde_json::to_value(&traslado).ok(),
+        true,
+    )?;
+    tx.commit()
+        .map_err(|e| format!("Error al confirmar: {}", e))?;
+    Ok(traslado)
+}
+
+/// Cierra un traslado en transito: al recibirlo entra en el destino; al cancelarlo
+/// vuelve al origen.
+fn cerrar_traslado(id: i64, recibir: bool) -> Result<Traslado, String> {
+    require_permiso(Permiso::AjustarStock)?;
+    let mut conn = abrir_conexion()?;
+    // El estado se lee con la base bloqueada: dos cajas no pueden recibir el mismo traslado
+    let tx = transaccion_stock(&mut conn)?;
+    let traslado = cargar_traslado(&tx, id)?;
+    if traslado.estado != "en_transito" {
+        return Err(format!("El traslado {} ya esta {}", id, traslado.estado));
+    }
+    let (ubicacion_id, estado, motivo) = if recibir {
+        (traslado.destino_id, "recibido", "traslado")
+    } else {
+        (traslado.origen_id, "cancelado", "cancelacion")
+    };
+    validar_ubicacion(&tx, Some(ubicacion_id))?;
+
+    let referencia = format!("traslado {}", id);
+    for (producto_id, cantidad, costo) in costos_traslado(&tx, id)? {
+        ajustar_stock_costeado(&tx, producto_id, cantidad, Some(costo), ubicacion_id, motivo, Some(&referencia))?;
+    }
+    tx.execute(
+        "UPDATE traslados SET estado = ?1, cerrado = ?2, cerrado_por = ?3 WHERE id = ?4",
+        rusqlite::params![estado, ahora(), usuario(), id],
+    )
+    .map_err(|e| format!("Error al actualizar el traslado: {}", e))?;
+    auditoria::registrar_auditoria(
+        &tx,
+        if recibir { "recibir_traslado" } else { "cancelar_traslado" },
+        &format!("traslado:{}", id),
+        Some(serde_json::json!({ "estado": traslado.estado })),
+        Some(serde_json::json!({ "estado": estado })),
+        true,
+    )?;
+    let traslado = cargar_traslado(&tx, id)?;
+    tx.commit()
+        .map_err(|e| format!("Error al confirmar: {}", e))?;
+    Ok(traslado)
+}
+
+#[tauri::command]
+pub fn recibir_traslado(id: i64) -> Result<Traslado, String> {
+    cerrar_traslado(id, true)
+}
+
+#[tauri::command]
+pub fn cancelar_traslado(id: i64) -> Result<Traslado, String> {
+    cerrar_traslado(id, false)
+}
+
+/// Traslados del mas reciente al mas antiguo; `estado` filtra, por ejemplo "en_transito".
+#[tauri::command]
+pub fn listar_traslados(estado: Option<String>) -> Result<Vec<Traslado>, String> {
+    require_permiso(Permiso::ConsultarInventario)?;
+    let conn = abrir_conexion()?;
+    let estado = estado.map(|e| e.trim().to_lowercase()).filter(|e| !e.is_empty());
+    let mut stmt = conn
+        .prepare("SELECT id FROM traslados WHERE ?1 IS NULL OR estado = ?1 ORDER BY id DESC")
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let ids = stmt
+        .query_map(rusqlite::params![estado], |row| row.get::<_, i64>(0))
+        .map_err(|e| format!("Error al leer traslados: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    ids.into_iter().map(|id| cargar_traslado(&conn, id)).collect()
+}
@@ -0,0 +1,405 @@
+// Variantes de producto (talla, color, ...).
+//
+// Un producto padre agrupa sus variantes. Cada variante es una fila propia de `inventario`
+// con su stock, SKU y codigo de barras, asi ventas, compras y movimientos siguen trabajando
+// por id de producto. El nombre de la variante se arma con el del padre y los valores de
+// sus atributos ("Camisa (Rojo / M)"), de modo que la busqueda por nombre las distingue.
+// Sin precio propio, la variante usa el precio del padre y lo sigue cuando este cambia.
+
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::catalogo::{admite_fraccion, validar_unidad};
+use crate::permisos::{require_permiso, Permiso};
+use crate::precios::{registrar_historial, LISTA_MINORISTA};
+use crate::{abrir_conexion, auditoria, obtener_item_por_id, InventarioItem};
+
+#[derive(Serialize, Deserialize)]
+pub struct ProductoPadre {
+    id: Option<i64>,
+    nombre: String,
+    precio: f64,
+    categoria_id: Option<i64>,
+    marca_id: Option<i64>,
+    /// Por defecto "unidad"
+    #[serde(default)]
+    unidad: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Variante {
+    /// Id en `inventario`; `None` al crear la variante
+    id: Option<i64>,
+    padre_id: i64,
+    /// Atributo -> valor, por ejemplo {"talla": "M", "color": "Rojo"}
+    atributos: BTreeMap<String, String>,
+    sku: Option<String>,
+    codigo_barras: Option<String>,
+    /// `None` usa el precio del padre
+    precio_propio: Option<f64>,
+    /// Solo lectura: nombre armado, precio vigente y stock
+    #[serde(default)]
+    nombre: String,
+    #[serde(default)]
+    precio: f64,
+    #[serde(default)]
+    cantidad: f64,
+}
+
+fn texto_opcional(texto: Option<&str>) -> Option<String> {
+    texto.map(str::trim).filter(|t| !t.is_empty()).map(str::to_string)
+}
+
+fn nombre_variante(padre: &str, atributos: &BTreeMap<String, String>) -> String {
+    let valores: Vec<&str> = atributos.values().map(String::as_str).collect();
+    format!("{} ({})", padre, valores.join(" / "))
+}
+
+/// Mismos atributos con los mismos valores, sin distinguir mayusculas.
+fn misma_combinacion(a: &BTreeMap<String, String>, b: &BTreeMap<String, String>) -> bool {
+    a.len() == b.len()
+        && a.iter().all(|(atributo, valor)| {
+            b.get(atributo).is_some_and(|otro| otro.eq_ignore_ascii_case(valor))
+        })
+}
+
+fn cargar_padre(conn: &Connection, id: i64) -> Result<ProductoPadre, String> {
+    conn.query_row(
+        "SELECT id, nombre, precio, categoria_id, marca_id, unidad FROM productos_padre WHERE id = ?1",
+        rusqlite::params![id],
+        |row| {
+            Ok(ProductoPadre {
+                id: row.get(0)?,
+                nombre: row.get(1)?,
+                precio: row.get(2)?,
+                categoria_id: row.get(3)?,
+                marca_id: row.get(4)?,
+                unidad: row.get(5)?,
+            })
+        },
+    )
+    .optional()
+    .map_err(|e| format!("Error en la consulta: {}", e))?
+    .ok_or_else(|| format!("No se encontro el producto padre {}", id))
+}
+
+fn cargar_atributos(conn: &Connection, producto_id: i64) -> Result<BTreeMap<String, String>, String> {
+    let mut stmt = conn
+        .prepare("SELECT atributo, valor FROM variantes_atributos WHERE producto_id = ?1")
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let atributos = stmt
+        .query_map(rusqlite::params![producto_id], |row| Ok((row.get(0)?, row.get(1)?)))
+        .map_err(|e| format!("Error al leer atributos: {}", e))?
+        .collect::<Result<BTreeMap<String, String>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    Ok(atributos)
+}
+
+fn cargar_variantes(conn: &Connection, padre_id: i64) -> Result<Vec<Variante>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, padre_id, sku, codigo_barras, hereda_precio, nombre_producto, \
+             CAST(precio_producto AS REAL), COALESCE(CAST(cantidad_producto AS REAL), 0) \
+             FROM inventario WHERE padre_id = ?1 ORDER BY id",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let mut variantes = stmt
+        .query_map(rusqlite::params![padre_id], |row| {
+            let precio: f64 = row.get(6)?;
+            Ok(Variante {
+                id: row.get(0)?,
+                padre_id: row.get(1)?,
+                atributos: BTreeMap::new(),
+                sku: row.get(2)?,
+                codigo_barras: row.get(3)?,
+                precio_propio: if row.get::<_, i64>(4)? == 1 { None } else { Some(precio) },
+                nombre: row.get(5)?,
+                precio,
+                cantidad: row.get(7)?,
+            })
+        })
+        .map_err(|e| format!("Error al leer variantes: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    for variante in variantes.iter_mut() {
+        if let Some(id) = variante.id {
+            variante.atributos = cargar_atributos(conn, id)?;
+        }
+    }
+    Ok(variantes)
+}
+
+fn existe(conn: &Connection, tabla: &str, id: Option<i64>) -> Result<bool, String> {
+    match id {
+        None => Ok(true),
+        Some(id) => conn
+            .query_row(
+                &format!("SELECT EXISTS (SELECT 1 FROM {} WHERE id = ?1)", tabla),
+                rusqlite::params![id],
+                |row| row.get(0),
+            )
+            .map_err(|e| format!("Error en la consulta: {}", e)),
+    }
+}
+
+fn error_duplicado(e: rusqlite::Error, que: &str) -> String {
+    match e {
+        rusqlite::Error::SqliteFailure(err, _) if err.code == rusqlite::ErrorCode::ConstraintViolation => {
+            format!("Ya existe {}", que)
+        }
+        e => format!("Error al guardar: {}", e),
+    }
+}
+
+#[tauri::command]
+pub fn listar_productos_padre() -> Result<Vec<ProductoPadre>, String> {
+    require_permiso(Permiso::ConsultarInventario)?;
+    let conn = abrir_conexion()?;
+    let mut stmt = conn
+        .prepare("SELECT id FROM productos_padre ORDER BY nombre")
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let ids = stmt
+        .query_map([], |row| row.get::<_, i64>(0))
+        .map_err(|e| format!("Error al leer productos: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    ids.into_iter().map(|id| cargar_padre(&conn, id)).collect()
+}
+
+/// Crea o actualiza el padre. El nombre, la clasificacion y el precio (en las variantes
+/// sin precio propio) se propagan a todas sus variantes.
+#[tauri::command]
+pub fn guardar_producto_padre(padre: ProductoPadre) -> Result<i64, String> {
+    require_permiso(Permiso::EditarPrecios)?;
+    let nombre = padre.nombre.trim().to_string();
+    if nombre.is_empty() {
+        return Err("El nombre del producto es obligatorio".to_string());
+    }
+    if !padre.precio.is_finite() || padre.precio < 0.0 {
+        return Err("El precio no puede ser negativo".to_string());
+    }
+    let unidad = validar_unidad(padre.unidad.as_deref().unwrap_or("unidad"))?;
+    let mut conn = abrir_conexion()?;
+    if !existe(&conn, "categorias", padre.categoria_id)? {
+        return Err("No se encontro la categoria".to_string());
+    }
+    if !existe(&conn, "marcas", padre.marca_id)? {
+        return Err("No se encontro la marca".to_string());
+    }
+
+    let tx = conn
+        .transaction()
+        .map_err(|e| format!("Error al iniciar la transaccion: {}", e))?;
+    let id = match padre.id {
+        Some(id) => {
+            cargar_padre(&tx, id)?;
+            if !admite_fraccion(&unidad) {
+                let fraccionarias: bool = tx
+                    .query_row(
+                        "SELECT EXISTS (SELECT 1 FROM inventario WHERE padre_id = ?1 \
+                         AND CAST(cantidad_producto AS REAL) <> CAST(CAST(cantidad_producto AS REAL) AS INTEGER))",
+                        rusqlite::params![id],
+                        |row| row.get(0),
+                    )
+                    .map_err(|e| format!("Error en la consulta: {}", e))?;
+                if fraccionarias {
+                    return Err(format!(
+                        "Hay variantes con stock fraccionario; ajustelas antes de cambiar la unidad a {}",
+                        unidad
+                    ));
+                }
+            }
+            tx.execute(
+                "UPDATE productos_padre SET nombre = ?1, precio = ?2, categoria_id = ?3, marca_id = ?4, unidad = ?5 \
+                 WHERE id = ?6",
+                rusqlite::params![nombre, padre.precio, padre.categoria_id, padre.marca_id, unidad, id],
+            )
+            .map_err(|e| error_duplicado(e, &format!("un producto llamado {}", nombre)))?;
+            id
+        }
+        None => {
+            tx.execute(
+                "INSERT INTO productos_padre (nombre, precio, categoria_id, marca_id, unidad) VALUES (?1, ?2, ?3, ?4, ?5)",
+                rusqlite::params![nombre, padre.precio, padre.categoria_id, padre.marca_id, unidad],
+            )
+            .map_err(|e| error_duplicado(e, &format!("un producto llamado {}", nombre)))?;
+            tx.last_insert_rowid()
+        }
+    };
+
+    for variante in cargar_variantes(&tx, id)? {
+        if let (Some(variante_id), None) = (variante.id, variante.precio_propio) {
+            registrar_historial(&tx, variante_id, LISTA_MINORISTA, Some(variante.precio), padre.precio, "variante")?;
+        }
+        tx.execute(
+            "UPDATE inventario SET nombre_producto = ?1, categoria_id = ?2, marca_id = ?3, unidad = ?4, \
+             precio_producto = CASE WHEN hereda_precio = 1 THEN ?5 ELSE precio_producto END WHERE id = ?6",
+            rusqlite::params![
+                nombre_variante(&nombre, &variante.atributos),
+                padre.categoria_id,
+                padre.marca_id,
+                unidad,
+                padre.precio,
+                variante.id
+            ],
+        )
+        .map_err(|e| format!("Error al actualizar la variante: {}", e))?;
+    }
+
+    auditoria::registrar_auditoria(
+        &tx,
+        "guardar_producto_padre",
+        &format!("producto_padre:{}", id),
+        None,
+        serde_json::to_value(&padre).ok(),
+        true,
+    )?;
+    tx.commit()
+        .map_err(|e| format!("Error al confirmar: {}", e))?;
+    Ok(id)
+}
+
+/// Crea o actualiza una variante. Las nuevas entran con stock 0; el stock se carga con
+/// compras o ajustes como cualquier producto.
+#[tauri::command]
+pub fn guardar_variante(variante: Variante) -> Result<Variante, String> {
+    require_permiso(Permiso::EditarPrecios)?;
+    let atributos: BTreeMap<String, String> = variante
+        .atributos
+        .iter()
+        .map(|(atributo, valor)| (atributo.trim().to_lowercase(), valor.trim().to_string()))
+        .collect();
+    if atributos.is_empty() || atributos.iter().any(|(a, v)| a.is_empty() || v.is_empty()) {
+        return Err("La variante requiere al menos un atributo con valor".to_string());
+    }
+    if let Some(precio) = variante.precio_propio {
+        if !precio.is_finite() || precio < 0.0 {
+            return Err("El precio no puede ser negativo".to_string());
+        }
+    }
+    let sku = texto_opcional(variante.sku.as_deref()).map(|s| s.to_uppercase());
+    let codigo_barras = texto_opcional(variante.codigo_barras.as_deref());
+    if codigo_barras.as_deref().is_some_and(|c| !c.chars().all(|ch| ch.is_ascii_digit())) {
+        return Err("El codigo de barras solo admite digitos".to_string());
+    }
+
+    let mut conn = abrir_conexion()?;
+    let padre = cargar_padre(&conn, variante.padre_id)?;
+    let hermanas = cargar_variantes(&conn, variante.padre_id)?;
+    if let Some(igual) = hermanas
+        .iter()
+        .find(|h| h.id != variante.id && misma_combinacion(&h.atributos, &atributos))
+    {
+        return Err(format!("Ya existe la variante {}", igual.nombre));
+    }
+    if let Some(id) = variante.id {
+        if !hermanas.iter().any(|h| h.id == Some(id)) {
+            return Err(format!("El producto {} no es una variante de {}", id, padre.nombre));
+        }
+    }
+
+    let nombre = nombre_variante(&padre.nombre, &atributos);
+    let precio = variante.precio_propio.unwrap_or(padre.precio);
+    let hereda_precio = variante.precio_propio.is_none();
+    let precio_anterior = hermanas.iter().find(|h| variante.id.is_some() && h.id == variante.id).map(|h| h.precio);
+    let duplicado = "un producto con ese SKU o codigo de barras";
+
+    let tx = conn
+        .transaction()
+        .map_err(|e| format!("Error al iniciar la transaccion: {}", e))?;
+    let id = match variante.id {
+        Some(id) => {
+            tx.execute(
+                "UPDATE inventario SET nombre_producto = ?1, precio_producto = ?2, sku = ?3, codigo_barras = ?4, \
+                 hereda_precio = ?5 WHERE id = ?6",
+                rusqlite::params![nombre, precio, sku, codigo_barras, hereda_precio, id],
+            )
+            .map_err(|e| error_duplicado(e, duplicado))?;
+            id
+        }
+        None => {
+            let id: i64 = tx
+                .query_row("SELECT COALESCE(MAX(id), 0) + 1 FROM inventario", [], |row| row.get(0))
+                .map_err(|e| format!("Error en la consulta: {}", e))?;
+            tx.execute(
+                "INSERT INTO inventario (id, nombre_producto, precio_producto, cantidad_producto, padre_id, sku, \
+                 codigo_barras, hereda_precio, categoria_id, marca_id, unidad) \
+                 VALUES (?1, ?2, ?3, 0, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                rusqlite::params![
+                    id,
+                    nombre,
+                    precio,
+                    variante.padre_id,
+                    sku,
+                    codigo_barras,
+                    hereda_precio,
+                    padre.categoria_id,
+                    padre.marca_id,
+                    padre.unidad
+                ],
+            )
+            .map_err(|e| error_duplicado(e, duplicado))?;
+            id
+        }
+    };
+
+    registrar_historial(&tx, id, LISTA_MINORISTA, precio_anterior, precio, "variante")?;
+
+    tx.execute("DELETE FROM variantes_atributos WHERE producto_id = ?1", rusqlite::params![id])
+        .map_err(|e| format!("Error al guardar atributos: {}", e))?;
+    for (atributo, valor) in &atributos {
+        tx.execute(
+            "INSERT INTO variantes_atributos (producto_id, atributo, valor) VALUES (?1, ?2, ?3)",
+            rusqlite::params![id, atributo, valor],
+        )
+        .map_err(|e| format!("Error al guardar atributos: {}", e))?;
+    }
+
+    let guardada = cargar_variantes(&tx, variante.padre_id)?
+        .into_iter()
+        .find(|v| v.id == Some(id))
+        .ok_or_else(|| "No se encontro la variante guardada".to_string())?;
+    auditoria::registrar_auditoria(
+        &tx,
+        "guardar_variante",
+        &format!("inventario:{}", id),
+        None,
+        serde_json::to_value(&guardada).ok(),
+        true,
+    )?;
+    tx.commit()
+        .map_err(|e| format!("Error al confirmar: {}", e))?;
+    Ok(guardada)
+}
+
+/// Variantes del padre con sus atributos, precio vigente y stock.
+#[tauri::command]
+pub fn listar_variantes(padre_id: i64) -> Result<Vec<Variante>, String> {
+    require_permiso(Permiso::ConsultarInventario)?;
+    let conn = abrir_conexion()?;
+    cargar_padre(&conn, padre_id)?;
+    cargar_variantes(&conn, padre_id)
+}
+
+/// Id del producto con ese SKU o codigo de barras.
+pub(crate) fn id_por_codigo(conn: &Connection, codigo: &str) -> Result<i64, String> {
+    let codigo = codigo.trim();
+    conn.query_row(
+        "SELECT id FROM inventario WHERE codigo_barras = ?1 OR UPPER(sku) = UPPER(?1) LIMIT 1",
+        rusqlite::params![codigo],
+        |row| row.get(0),
+    )
+    .optional()
+    .map_err(|e| format!("Error en la consulta: {}", e))?
+    .ok_or_else(|| format!("No se encontro el producto con codigo {}", codigo))
+}
+
+/// Busca un producto por SKU o codigo de barras, por ejemplo desde el lector de la caja.
+#[tauri::command]
+pub fn obtener_inventario_por_codigo(codigo: String) -> Result<InventarioItem, String> {
+    require_permiso(Permiso::ConsultarInventario)?;
+    let conn = abrir_conexion()?;
+    let id = id_por_codigo(&conn, &codigo)?;
+    obtener_item_por_id(&conn, id)
+}
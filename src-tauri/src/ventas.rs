@@ -0,0 +1,672 @@
+// Tickets de venta, anulaciones y devoluciones.
+//
+// Cada recibo de cliente queda registrado como un ticket con sus lineas en `ventas`.
+// Una anulacion revierte el ticket completo (solo el mismo dia y con aprobacion de un
+// supervisor); una devolucion revierte algunas lineas en cualquier fecha. Ambas devuelven
+// el stock, registran el movimiento y emiten una nota de credito en PDF que referencia el
+// numero de recibo original.
+
+use chrono::Local;
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::catalogo::{redondear_cantidad, validar_cantidad};
+use crate::clientes::{self, Cliente};
+use crate::comun::{ahora, redondear, usuario};
+use crate::{costos, cuentas, eventos};
+use crate::movimientos::{ajustar_stock, transaccion_stock};
+use crate::permisos::{credenciales_aprobacion, require_permiso, tiene_permiso, validar_aprobacion, Permiso};
+use crate::promociones::{self, CalculoVentaRequest};
+use crate::{api, series, terminales, ubicaciones};
+use crate::{
+    abrir_conexion, auditoria, crear_pdf_recibo, format_date_stamp, get_documentos_recibos_dir,
+    impuestos, obtener_item_por_id, obtener_siguiente_numero, InventarioItem,
+    VentaItem,
+};
+
+#[derive(Serialize, Deserialize)]
+pub struct LineaTicket {
+    venta_id: i64,
+    producto_id: i64,
+    nombre: String,
+    precio: f64,
+    cantidad: f64,
+    cantidad_devuelta: f64,
+    subtotal: f64,
+    tasa_impuesto: f64,
+    base_imponible: f64,
+    impuesto: f64,
+    /// Series vendidas en la linea que no se han devuelto
+    series: Vec<String>,
+    /// Ubicacion de donde salio; las devoluciones vuelven ahi
+    ubicacion_id: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Ticket {
+    id: i64,
+    numero_recibo: String,
+    fecha: String,
+    total: f64,
+    usuario: String,
+    estado: String,
+    cliente_id: Option<i64>,
+    a_credito: bool,
+    lineas: Vec<LineaTicket>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LineaDevolucion {
+    venta_id: i64,
+    cantidad: f64,
+    /// Requeridas si el producto es serializado, una por unidad devuelta
+    #[serde(default)]
+    series: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct VentaCompletada {
+    pub(crate) numero: String,
+    pub(crate) total: f64,
+    pub(crate) ruta: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NotaCreditoResponse {
+    numero: String,
+    numero_recibo: String,
+    total: f64,
+    ruta: String,
+}
+
+/// Caja duena del carrito: la terminal en modo servidor, el token en la API o la propia
+/// maquina. No depende del usuario para que un cambio de turno no pierda el carrito.
+fn caja_actual() -> String {
+    match (terminales::terminal_actual(), api::sesion_actual()) {
+        (Some(terminal), _) => format!("terminal:{}", terminal),
+        (None, Some(sesion)) => sesion.usuario(),
+        (None, None) => "local".to_string(),
+    }
+}
+
+/// Anota en el carrito de la caja lo escaneado (y ya descontado del stock).
+pub(crate) fn agregar_al_carrito(conn: &Connection, producto_id: i64, ubicacion_id: i64, cantidad: f64) -> Result<(), String> {
+    conn.execute(
+        "INSERT INTO carrito (caja, producto_id, ubicacion_id, cantidad) VALUES (?1, ?2, ?3, ?4) \
+         ON CONFLICT(caja, producto_id, ubicacion_id) DO UPDATE SET cantidad = cantidad + excluded.cantidad",
+        rusqlite::params![caja_actual(), producto_id, ubicacion_id, cantidad],
+    )
+    .map_err(|e| format!("Error al actualizar el carrito: {}", e))?;
+    Ok(())
+}
+
+/// Descuenta hasta `cantidad` del carrito de la caja y devuelve cuanto habia.
+fn quitar_del_carrito(conn: &Connection, producto_id: i64, ubicacion_id: i64, cantidad: f64) -> Result<f64, String> {
+    let caja = caja_actual();
+    let en_carrito: f64 = conn
+        .query_row(
+            "SELECT cantidad FROM carrito WHERE caja = ?1 AND producto_id = ?2 AND ubicacion_id = ?3",
+            rusqlite::params![caja, producto_id, ubicacion_id],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| format!("Error al leer el carrito: {}", e))?
+        .unwrap_or(0.0);
+    let quitado = en_carrito.min(cantidad);
+    if quitado > 0.0 {
+        conn.execute(
+            "UPDATE carrito SET cantidad = cantidad - ?4 WHERE caja = ?1 AND producto_id = ?2 AND ubicacion_id = ?3",
+            rusqlite::params![caja, producto_id, ubicacion_id, quitado],
+        )
+        .map_err(|e| format!("Error al actualizar el carrito: {}", e))?;
+        conn.execute("DELETE FROM carrito WHERE cantidad <= 0.000001", [])
+            .map_err(|e| format!("Error al actualizar el carrito: {}", e))?;
+    }
+    Ok(redondear_cantidad(quitado))
+}
+
+/// Siguiente numero `AAAAMMDD-N` del dia. Considera los PDF emitidos y tambien los tickets
+/// cerrados sin recibo impreso, que no dejan archivo.
+pub(crate) fn siguiente_numero(conn: &Connection, recibos_dir: &PathBuf, date_stamp: &str) -> Result<u32, String> {
+    siguiente_numero_en(conn, recibos_dir, date_stamp, "SELECT numero_recibo FROM tickets WHERE numero_recibo LIKE ?1")
+}
+
+/// Mayor sufijo `<prefijo>-N` entre los PDF y la columna que lee `consulta`, mas uno. Debe
+/// llamarse dentro de `transaccion_stock` para que dos cajas no tomen el mismo numero.
+fn siguiente_numero_en(conn: &Connection, recibos_dir: &PathBuf, prefijo: &str, consulta: &str) -> Result<u32, String> {
+    let por_archivos = obtener_siguiente_numero(recibos_dir, prefijo)?;
+    let mut stmt = conn
+        .prepare(consulta)
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let numeros = stmt
+        .query_map(rusqlite::params![format!("{}-%", prefijo)], |row| row.get::<_, String>(0))
+        .map_err(|e| format!("Error al leer los numeros emitidos: {}", e))?;
+
+    let mut siguiente = por_archivos;
+    for numero in numeros {
+        let numero = numero.map_err(|e| format!("Error en fila: {}", e))?;
+        if let Some(n) = numero.rsplit('-').next().and_then(|n| n.parse::<u32>().ok()) {
+            siguiente = siguiente.max(n + 1);
+        }
+    }
+    Ok(siguiente)
+}
+
+/// Calcula y registra el ticket; con `con_recibo` tambien emite el PDF del cliente.
+pub(crate) fn completar_venta(
+    conn: &mut Connection,
+    pedido: &CalculoVentaRequest,
+    con_recibo: bool,
+) -> Result<VentaCompletada, String> {
+    if pedido.ventas.is_empty() {
+        return Err("No hay ventas para registrar".to_string());
+    }
+    let cliente = pedido
+        .cliente_id
+        .map(|id| clientes::cargar_cliente(conn, id))
+        .transpose()?;
+    if pedido.a_credito && cliente.is_none() {
+        return Err("Una venta a credito requiere un cliente".to_string());
+    }
+    // Precios y descuentos los decide el backend; la aprobacion de un precio manual
+    // se valida fuera de la transaccion para que los intentos fallidos queden registrados
+    let calculo = promociones::calcular_ticket(conn, pedido, tiene_permiso(Permiso::EditarPrecios))?;
+
+    // El ticket y el PDF se confirman juntos para que todo recibo tenga su registro; el
+    // numero y el stock se leen con la base bloqueada frente a las demas cajas
+    let tx = transaccion_stock(conn)?;
+    let recibos_dir = get_documentos_recibos_dir()?;
+    let date_stamp = format_date_stamp();
+    let numero = format!("{}-{}", date_stamp, siguiente_numero(&tx, &recibos_dir, &date_stamp)?);
+    let ticket_id = registrar_ticket(&tx, &numero, &calculo.ventas, calculo.total, pedido.cliente_id)?;
+    // Lo escaneado ya salio del stock y deja el carrito; el resto (productos con serie,
+    // pedidos de la API) se descuenta aqui
+    for venta in &calculo.ventas {
+        let ubicacion_id = venta.ubicacion_id.unwrap_or(ubicaciones::PRINCIPAL);
+        let faltante = redondear_cantidad(venta.cantidad - quitar_del_carrito(&tx, venta.id, ubicacion_id, venta.cantidad)?);
+        if faltante > 0.0 {
+            ajustar_stock(&tx, venta.id, -faltante, ubicacion_id, "venta", Some(&numero))
+                .map_err(|e| format!("{}: {}", venta.nombre, e))?;
+        }
+    }
+    if let (true, Some(cliente)) = (pedido.a_credito, &cliente) {
+        cuentas::cargar_venta_a_credito(&tx, cliente, ticket_id, &numero, calculo.total)?;
+        tx.execute(
+            "UPDATE tickets SET a_credito = 1 WHERE id = ?1",
+            rusqlite::params![ticket_id],
+        )
+        .map_err(|e| format!("Error al actualizar el ticket: {}", e))?;
+    }
+    let ruta = if con_recibo {
+        let ruta = recibos_dir.join(format!("{}.pdf", numero));
+        crear_pdf_recibo(&calculo.ventas, calculo.total, "Recibo cliente", cliente.as_ref(), &ruta)?;
+        Some(ruta)
+    } else {
+        None
+    };
+    let confirmar = || -> Result<(), String> {
+        eventos::emitir(
+            &tx,
+            "venta_completada",
+            serde_json::json!({
+                "numero": numero,
+                "total": calculo.total,
+                "cliente_id": pedido.cliente_id,
+                "a_credito": pedido.a_credito,
+                "lineas": calculo
+                    .ventas
+                    .iter()
+                    .map(|v| serde_json::json!({
+                        "producto_id": v.id,
+                        "nombre": v.nombre,
+                        "cantidad": v.cantidad,
+                        "subtotal": v.subtotal,
+                    }))
+                    .collect::<Vec<_>>(),
+            }),
+        )?;
+        tx.commit()
+            .map_err(|e| format!("Error al confirmar el ticket: {}", e))
+    };
+    // Sin ticket confirmado el recibo no debe quedar en la carpeta
+    if let Err(e) = confirmar() {
+        if let Some(ruta) = &ruta {
+            let _ = fs::remove_file(ruta);
+        }
+        return Err(e);
+    }
+
+    Ok(VentaCompletada {
+        numero,
+        total: calculo.total,
+        ruta: ruta.map(|r| r.display().to_string()),
+    })
+}
+
+/// Lineas vendidas en la fecha, agrupadas por producto y tasa, sin tickets anulados y
+/// descontando lo devuelto. Base del cierre del dia.
+pub(crate) fn lineas_del_dia(conn: &Connection, fecha: &str) -> Result<Vec<VentaItem>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT v.producto_id, v.nombre_producto, v.tasa_impuesto, \
+             SUM(v.cantidad - v.cantidad_devuelta), \
+             SUM(v.precio * (v.cantidad - v.cantidad_devuelta)), \
+             SUM(v.descuento * (v.cantidad - v.cantidad_devuelta) / v.cantidad), \
+             SUM(v.subtotal * (v.cantidad - v.cantidad_devuelta) / v.cantidad), \
+             SUM(COALESCE(v.base_imponible, v.subtotal) * (v.cantidad - v.cantidad_devuelta) / v.cantidad), \
+             SUM(v.impuesto * (v.cantidad - v.cantidad_devuelta) / v.cantidad) \
+             FROM ventas v JOIN tickets t ON t.id = v.ticket_id \
+             WHERE date(t.fecha) = ?1 AND t.estado <> 'anulada' AND v.cantidad > v.cantidad_devuelta \
+             GROUP BY v.producto_id, v.nombre_producto, v.tasa_impuesto ORDER BY v.producto_id",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+
+    let lineas = stmt
+        .query_map(rusqlite::params![fecha], |row| {
+            let cantidad: f64 = row.get(3)?;
+            let bruto: f64 = row.get(4)?;
+            Ok(VentaItem {
+                id: row.get(0)?,
+                nombre: row.get(1)?,
+                precio: bruto / cantidad,
+                cantidad,
+                descuento: redondear(row.get(5)?),
+                subtotal: redondear(row.get(6)?),
+                tasa_impuesto: row.get(2)?,
+                base_imponible: redondear(row.get(7)?),
+                impuesto: redondear(row.get(8)?),
+                ..Default::default()
+            })
+        })
+        .map_err(|e| format!("Error al leer ventas del dia: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Error en fila: {}", e))?;
+    Ok(lineas)
+}
+
+/// Guarda el ticket y sus lineas. Se llama al emitir el recibo del cliente.
+pub(crate) fn registrar_ticket(
+    conn: &Connection,
+    numero_recibo: &str,
+    ventas: &[VentaItem],
+    total: f64,
+    cliente_id: Option<i64>,
+) -> Result<i64, String> {
+    let fecha = ahora();
+    let descuento: f64 = ventas.iter().map(|v| v.descuento).sum();
+    let desglose = impuestos::desglosar(ventas);
+    conn.execute(
+        "INSERT INTO tickets (numero_recibo, fecha, total, descuento, usuario, estado, cliente_id) \
+         VALUES (?1, ?2, ?3, ?4, ?5, 'completada', ?6)",
+        rusqlite::params![
+            numero_recibo,
+            fecha,
+            total,
+            descuento,
+            usuario(),
+            cliente_id
+        ],
+    )
+    .map_err(|e| format!("Error al registrar el ticket: {}", e))?;
+    let ticket_id = conn.last_insert_rowid();
+
+    for venta in ventas {
+        conn.execute(
+            "INSERT INTO ventas (fecha, producto_id, nombre_producto, precio, cantidad, subtotal, ticket_id, \
+             descuento, promocion, tasa_impuesto, base_imponible, impuesto, costo_unitario, ubicacion_id) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+            rusqlite::params![
+                fecha,
+                venta.id,
+                venta.nombre,
+                venta.precio,
+                venta.cantidad,
+                venta.subtotal,
+                ticket_id,
+                venta.descuento,
+                venta.promocion,
+                venta.tasa_impuesto,
+                venta.base_imponible,
+                venta.impuesto,
+                costos::costo_promedio(conn, venta.id)?,
+                venta.ubicacion_id.unwrap_or(ubicaciones::PRINCIPAL)
+            ],
+        )
+        .map_err(|e| format!("Error al registrar la venta: {}", e))?;
+        if !venta.series.is_empty() {
+            series::vender_series(conn, venta.id, &venta.series, conn.last_insert_rowid(), numero_recibo)?;
+        }
+    }
+
+    for tasa in desglose {
+        conn.execute(
+            "INSERT INTO tickets_impuestos (ticket_id, tasa, base_imponible, impuesto) VALUES (?1, ?2, ?3, ?4)",
+            rusqlite::params![ticket_id, tasa.tasa, tasa.base_imponible, tasa.impuesto],
+        )
+        .map_err(|e| format!("Error al registrar el desglose de impuestos: {}", e))?;
+    }
+
+    Ok(ticket_id)
+}
+
+pub(crate) fn cargar_ticket(conn: &Connection, numero_recibo: &str) -> Result<Ticket, String> {
+    let mut ticket = conn
+        .query_row(
+            "SELECT id, numero_recibo, fecha, total, usuario, estado, cliente_id, a_credito FROM tickets \
+             WHERE numero_recibo = ?1",
+            rusqlite::params![numero_recibo.trim()],
+            |row| {
+                Ok(Ticket {
+                    id: row.get(0)?,
+                    numero_recibo: row.get(1)?,
+                    fecha: row.get(2)?,
+                    total: row.get(3)?,
+                    usuario: row.get(4)?,
+                    estado: row.get(5)?,
+                    cliente_id: row.get(6)?,
+                    a_credito: row.get::<_, i64>(7)? == 1,
+                    lineas: Vec::new(),
+                })
+            },
+        )
+        .map_err(|_| format!("No se encontro el recibo {}", numero_recibo.trim()))?;
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, producto_id, nombre_producto, precio, cantidad, cantidad_devuelta, subtotal, \
+             tasa_impuesto, COALESCE(base_imponible, subtotal), impuesto, ubicacion_id \
+             FROM ventas WHERE ticket_id = ?1 ORDER BY id",
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let rows = stmt
+        .query_map(rusqlite::params![ticket.id], |row| {
+            Ok(LineaTicket {
+                venta_id: row.get(0)?,
+                producto_id: row.get(1)?,
+                nombre: row.get(2)?,
+                precio: row.get(3)?,
+                cantidad: row.get(4)?,
+                cantidad_devuelta: row.get(5)?,
+                subtotal: row.get(6)?,
+                tasa_impuesto: row.get(7)?,
+                base_imponible: row.get(8)?,
+                impuesto: row.get(9)?,
+                series: Vec::new(),
+                ubicacion_id: row.get(10)?,
+            })
+        })
+        .map_err(|e| format!("Error al leer el ticket: {}", e))?;
+    for row in rows {
+        let mut linea = row.map_err(|e| format!("Error en fila: {}", e))?;
+        linea.series = series::series_de_venta(conn, linea.venta_id)?;
+        ticket.lineas.push(linea);
+    }
+
+    Ok(ticket)
+}
+
+/// Devuelve al stock las cantidades indicadas y emite la nota de credito.
+fn emitir_nota_credito(
+    conn: &mut Connection,
+    ticket: &Ticket,
+    devoluciones: &[(&LineaTicket, f64, Vec<String>)],
+    tipo: &str,
+    motivo: &str,
+) -> Result<NotaCreditoResponse, String> {
+    let items: Vec<VentaItem> = devoluciones
+        .iter()
+        .map(|(linea, cantidad, series)| {
+            // Importes proporcionales a lo devuelto para respetar lo cobrado
+            let proporcion = *cantidad / linea.cantidad;
+                    VentaItem {
+                id: linea.producto_id,
+                nombre: linea.nombre.clone(),
+                precio: linea.subtotal / linea.cantidad,
+                cantidad: *cantidad,
+                subtotal: redondear(linea.subtotal * proporcion),
+                tasa_impuesto: linea.tasa_impuesto,
+                base_imponible: redondear(linea.base_imponible * proporcion),
+                impuesto: redondear(linea.impuesto * proporcion),
+                series: series.clone(),
+                ..Default::default()
+            }
+        })
+        .collect();
+    let total: f64 = items.iter().map(|i| i.subtotal).sum();
+
+    // El numero se toma con la base bloqueada para que dos cajas no emitan el mismo
+    let tx = transaccion_stock(conn)?;
+    let recibos_dir = get_documentos_recibos_dir()?;
+    let prefijo = format!("NC-{}", Local::now().format("%Y%m%d"));
+    let numero = format!(
+        "{}-{}",
+        prefijo,
+        siguiente_numero_en(&tx, &recibos_dir, &prefijo, "SELECT numero FROM notas_credito WHERE numero LIKE ?1")?
+    );
+    let ruta = recibos_dir.join(format!("{}.pdf", numero));
+
+    tx.execute(
+        "INSERT INTO notas_credito (numero, ticket_id, fecha, tipo, motivo, total, usuario) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        rusqlite::params![
+            numero,
+            ticket.id,
+            ahora(),
+            tipo,
+            motivo,
+            total,
+            usuario()
+        ],
+    )
+    .map_err(|e| format!("Error al registrar la nota de credito: {}", e))?;
+    let nota_id = tx.last_insert_rowid();
+
+    for ((linea, cantidad, series), item) in devoluciones.iter().zip(&items) {
+        ajustar_stock(&tx, linea.producto_id, *cantidad, linea.ubicacion_id, tipo, Some(&ticket.numero_recibo))?;
+        series::devolver_series(&tx, linea.venta_id, series, tipo, &numero)?;
+        // Otra caja pudo devolver la misma linea desde que se cargo el recibo
+        let actualizadas = tx
+            .execute(
+                "UPDATE ventas SET cantidad_devuelta = cantidad_devuelta + ?1 \
+                 WHERE id = ?2 AND cantidad - cantidad_devuelta >= ?1 - 0.0005",
+                rusqlite::params![cantidad, linea.venta_id],
+            )
+            .map_err(|e| format!("Error al actualizar la venta: {}", e))?;
+        if actualizadas == 0 {
+            return Err(format!(
+                "La linea de {} ya fue devuelta desde otra caja; vuelva a cargar el recibo",
+                linea.nombre
+            ));
+        }
+        tx.execute(
+            "INSERT INTO notas_credito_detalle (nota_id, venta_id, producto_id, cantidad, subtotal, \
+             base_imponible, impuesto) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            rusqlite::params![
+                nota_id,
+                linea.venta_id,
+                linea.producto_id,
+                cantidad,
+                item.subtotal,
+                item.base_imponible,
+                item.impuesto
+            ],
+        )
+        .map_err(|e| format!("Error al registrar la nota de credito: {}", e))?;
+    }
+
+    let pendientes: f64 = tx
+        .query_row(
+            "SELECT COALESCE(SUM(cantidad - cantidad_devuelta), 0.0) FROM ventas WHERE ticket_id = ?1",
+            rusqlite::params![ticket.id],
+            |row| row.get(0),
+        )
+        .map_err(|e| format!("Error en la consulta: {}", e))?;
+    let estado = match tipo {
+        "anulacion" => "anulada",
+        _ if redondear_cantidad(pendientes) <= 0.0 => "devuelta",
+        _ => "devuelta_parcial",
+    };
+    tx.execute(
+        "UPDATE tickets SET estado = ?1 WHERE id = ?2",
+        rusqlite::params![estado, ticket.id],
+    )
+    .map_err(|e| format!("Error al actualizar el ticket: {}", e))?;
+
+    // Lo devuelto de una venta al fiado se abona a la cuenta del cliente
+    if let (true, Some(cliente_id)) = (ticket.a_credito, ticket.cliente_id) {
+        cuentas::registrar_movimiento_cuenta(&tx, cliente_id, "nota_credito", -total, Some(ticket.id), Some(&numero))?;
+    }
+
+    let titulo = format!("Nota de credito {} - Ref. recibo {}", numero, ticket.numero_recibo);
+    let cliente: Option<Cliente> = ticket
+        .cliente_id
+        .map(|id| clientes::cargar_cliente(&tx, id))
+        .transpose()?;
+    crear_pdf_recibo(&items, total, &titulo, cliente.as_ref(), &ruta)?;
+
+    auditoria::registrar_auditoria(
+        &tx,
+        tipo,
+        &format!("ticket:{}", ticket.numero_recibo),
+        None,
+        Some(serde_json::json!({ "nota_credito": numero, "total": total, "motivo": motivo })),
+        true,
+    )?;
+
+    tx.commit()
+        .map_err(|e| format!("Error al confirmar la nota de credito: {}", e))?;
+
+    Ok(NotaCreditoResponse {
+        numero,
+        numero_recibo: ticket.numero_recibo.clone(),
+        total,
+        ruta: ruta.display().to_string(),
+    })
+}
+
+#[tauri::command]
+pub fn obtener_ticket(numero_recibo: String) -> Result<Ticket, String> {
+    require_permiso(Permiso::Vender)?;
+    let conn = abrir_conexion()?;
+    cargar_ticket(&conn, &numero_recibo)
+}
+
+#[tauri::command]
+pub fn anular_venta(
+    numero_recibo: String,
+    motivo: Option<String>,
+    supervisor_usuario: Option<String>,
+    supervisor_password: Option<String>,
+) -> Result<NotaCreditoResponse, String> {
+    require_permiso(Permiso::Vender)?;
+    let mut conn = abrir_conexion()?;
+
+    // Un cajero puede anular si un supervisor escribe su usuario y clave
+    if !tiene_permiso(Permiso::Anular) {
+        let (usuario, pass) = credenciales_aprobacion(
+            supervisor_usuario.as_deref(),
+            supervisor_password.as_deref(),
+            "Se requiere la aprobacion de un supervisor",
+        )?;
+        validar_aprobacion(&conn, Permiso::Anular, usuario, pass)?;
+    }
+
+    let ticket = cargar_ticket(&conn, &numero_recibo)?;
+    if ticket.estado != "completada" {
+        return Err(format!("El recibo {} no se puede anular (estado: {})", ticket.numero_recibo, ticket.estado));
+    }
+    let hoy = Local::now().format("%Y-%m-%d").to_string();
+    if !ticket.fecha.starts_with(&hoy) {
+        return Err("Solo se pueden anular ventas del mismo dia; use una devolucion".to_string());
+    }
+
+    let devoluciones: Vec<(&LineaTicket, f64, Vec<String>)> = ticket
+        .lineas
+        .iter()
+        .map(|l| (l, l.cantidad, l.series.clone()))
+        .collect();
+    let motivo = motivo.unwrap_or_default();
+    emitir_nota_credito(&mut conn, &ticket, &devoluciones, "anulacion", motivo.trim())
+}
+
+#[tauri::command]
+pub fn devolver_venta(numero_recibo: String, lineas: Vec<LineaDevolucion>, motivo: String) -> Result<NotaCreditoResponse, String> {
+    require_permiso(Permiso::Vender)?;
+    let motivo = motivo.trim();
+    if motivo.is_empty() {
+        return Err("El motivo de la devolucion es obligatorio".to_string());
+    }
+    if lineas.is_empty() {
+        return Err("Seleccione al menos una linea para devolver".to_string());
+    }
+
+    let mut conn = abrir_conexion()?;
+    let ticket = cargar_ticket(&conn, &numero_recibo)?;
+    if ticket.estado == "anulada" {
+        return Err(format!("El recibo {} esta anulado", ticket.numero_recibo));
+    }
+
+    let mut devoluciones = Vec::new();
+    for pedida in &lineas {
+        let linea = ticket
+            .lineas
+            .iter()
+            .find(|l| l.venta_id == pedida.venta_id)
+            .ok_or_else(|| format!("La linea {} no pertenece al recibo {}", pedida.venta_id, ticket.numero_recibo))?;
+        let disponible = redondear_cantidad(linea.cantidad - linea.cantidad_devuelta);
+        let cantidad = redondear_cantidad(pedida.cantidad);
+        if cantidad <= 0.0 || cantidad > disponible {
+            return Err(format!(
+                "Cantidad invalida para {}. Se puede devolver hasta {}",
+                linea.nombre, disponible
+            ));
+        }
+        if devoluciones.iter().any(|(l, _, _): &(&LineaTicket, f64, Vec<String>)| l.venta_id == linea.venta_id) {
+            return Err(format!("La linea {} esta repetida", linea.venta_id));
+        }
+        let series: Vec<String> = pedida.series.iter().map(|s| series::normalizar_serie(s)).collect();
+        if !linea.series.is_empty() && series.len() as f64 != cantidad {
+            return Err(format!(
+                "Indique la serie de cada unidad devuelta de {} ({} series para {} unidades)",
+                linea.nombre,
+                series.len(),
+                cantidad
+            ));
+        }
+        devoluciones.push((linea, cantidad, series));
+    }
+
+    emitir_nota_credito(&mut conn, &ticket, &devoluciones, "devolucion", motivo)
+}
+
+/// Cierra la venta sin imprimir recibo; el ticket queda registrado igual.
+#[tauri::command]
+pub fn finalizar_venta(payload: CalculoVentaRequest) -> Result<VentaCompletada, String> {
+    require_permiso(Permiso::Vender)?;
+    let mut conn = abrir_conexion()?;
+    completar_venta(&mut conn, &payload, false)
+}
+
+/// Devuelve al stock un producto que se quito del carrito antes de emitir el recibo. Solo
+/// se devuelve lo que esta caja escaneo y todavia no cobro.
+#[tauri::command]
+pub fn cancelar_item_venta(id: i64, cantidad: f64, ubicacion_id: Option<i64>) -> Result<InventarioItem, String> {
+    require_permiso(Permiso::Vender)?;
+    let mut conn = abrir_conexion()?;
+    let cantidad = validar_cantidad(&conn, id, cantidad)?;
+    let ubicacion_id = ubicaciones::validar_ubicacion(&conn, ubicacion_id)?;
+    let tx = transaccion_stock(&mut conn)?;
+    // Los productos con serie no se descontaron al escanearlos
+    if series::es_serializado(&tx, id)? {
+        return obtener_item_por_id(&tx, id);
+    }
+    if quitar_del_carrito(&tx, id, ubicacion_id, cantidad)? < cantidad {
+        return Err("Solo se puede cancelar lo escaneado en esta caja y aun no cobrado".to_string());
+    }
+    ajustar_stock(&tx, id, cantidad, ubicacion_id, "cancelacion", None)?;
+    tx.commit()
+        .map_err(|e| format!("Error al confirmar la cancelacion: {}", e))?;
+    obtener_item_por_id(&conn, id)
+}
@@ -0,0 +1,175 @@
+//! Levanta el servidor de cajas en otro proceso y lo usa como lo haria una caja de la red.
+
+use serde_json::{json, Value};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+use std::{env, fs, thread};
+
+const CLAVE: &str = "clave-de-prueba";
+
+struct Servidor {
+    proceso: Child,
+    url: String,
+    _dir: Directorio,
+}
+
+impl Drop for Servidor {
+    fn drop(&mut self) {
+        let _ = self.proceso.kill();
+        let _ = self.proceso.wait();
+    }
+}
+
+struct Directorio(PathBuf);
+
+impl Drop for Directorio {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Carpeta con una base vacia y un HOME propio para no tocar los datos del equipo.
+fn preparar(nombre: &str) -> Directorio {
+    let dir = env::temp_dir().join(format!("ventas-{}-{}", nombre, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("src/database")).unwrap();
+    fs::write(dir.join("src/database/database.db"), b"").unwrap();
+    fs::create_dir_all(dir.join("home/.config")).unwrap();
+    fs::write(
+        dir.join("home/.config/user-dirs.dirs"),
+        "XDG_DOCUMENTS_DIR=\"$HOME/Documentos\"\n",
+    )
+    .unwrap();
+    Directorio(dir)
+}
+
+fn puerto_libre() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+fn comando(dir: &Path, direccion: &str, clave: Option<&str>) -> Command {
+    let mut comando = Command::new(env!("CARGO_BIN_EXE_ventas"));
+    comando
+        .args(["--servidor", direccion])
+        .current_dir(dir)
+        .env("HOME", dir.join("home"))
+        .env_remove("VENTAS_SERVIDOR")
+        .env_remove("VENTAS_TERMINAL")
+        .env_remove("VENTAS_API")
+        .env_remove("VENTAS_CLAVE");
+    if let Some(clave) = clave {
+        comando.env("VENTAS_CLAVE", clave);
+    }
+    comando
+}
+
+fn iniciar(nombre: &str) -> Servidor {
+    let dir = preparar(nombre);
+    let direccion = format!("127.0.0.1:{}", puerto_libre());
+    let proceso = comando(&dir.0, &direccion, Some(CLAVE)).spawn().unwrap();
+    let servidor = Servidor {
+        proceso,
+        url: format!("http://{}", direccion),
+        _dir: dir,
+    };
+    let limite = Instant::now() + Duration::from_secs(30);
+    while TcpStream::connect(&direccion).is_err() {
+        assert!(Instant::now() < limite, "el servidor no arranco");
+        thread::sleep(Duration::from_millis(100));
+    }
+    servidor
+}
+
+/// Respuesta del comando: estado, cuerpo y el token de sesion si el servidor lo entrego.
+fn invocar(
+    servidor: &Servidor,
+    terminal: &str,
+    clave: Option<&str>,
+    sesion: Option<&str>,
+    nombre: &str,
+    args: Value,
+) -> (u16, Value, Option<String>) {
+    let mut peticion = ureq::post(&format!("{}/comando/{}", servidor.url, nombre)).set("X-Terminal", terminal);
+    if let Some(clave) = clave {
+        peticion = peticion.set("X-Clave", clave);
+    }
+    if let Some(sesion) = sesion {
+        peticion = peticion.set("X-Sesion", sesion);
+    }
+    let respuesta = match peticion.send_json(args) {
+        Ok(respuesta) => respuesta,
+        Err(ureq::Error::Status(_, respuesta)) => respuesta,
+        Err(e) => panic!("no se pudo conectar: {}", e),
+    };
+    let estado = respuesta.status();
+    let sesion = respuesta.header("X-Sesion").map(str::to_string);
+    (estado, respuesta.into_json().unwrap(), sesion)
+}
+
+#[test]
+fn sin_clave_el_servidor_no_arranca() {
+    let dir = preparar("sin-clave");
+    let direccion = format!("127.0.0.1:{}", puerto_libre());
+    let mut proceso = comando(&dir.0, &direccion, None).spawn().unwrap();
+    let limite = Instant::now() + Duration::from_secs(30);
+    let estado = loop {
+        if let Some(estado) = proceso.try_wait().unwrap() {
+            break estado;
+        }
+        if Instant::now() > limite {
+            let _ = proceso.kill();
+            panic!("el servidor arranco sin clave");
+        }
+        thread::sleep(Duration::from_millis(100));
+    };
+    assert!(!estado.success());
+}
+
+#[test]
+fn la_sesion_es_el_token_que_entrega_el_servidor() {
+    let servidor = iniciar("sesiones");
+
+    let (estado, _, _) = invocar(&servidor, "caja-a", None, None, "listar_usuarios", json!({}));
+    assert_eq!(estado, 401);
+
+    let (estado, respuesta, token) = invocar(
+        &servidor,
+        "caja-a",
+        Some(CLAVE),
+        None,
+        "validar_login",
+        json!({ "usuario": "user", "contrasena": "user" }),
+    );
+    assert_eq!(estado, 200);
+    assert_eq!(respuesta["success"], true);
+    let token = token.expect("el login devuelve X-Sesion");
+
+    // Otra caja que solo copia el nombre de la terminal no hereda la sesion
+    let (estado, respuesta, _) = invocar(&servidor, "caja-a", Some(CLAVE), None, "listar_usuarios", json!({}));
+    assert_eq!(estado, 400);
+    assert_eq!(respuesta, "Debe iniciar sesion.");
+
+    // El token solo vale desde la caja que inicio la sesion
+    let (estado, _, _) = invocar(&servidor, "caja-b", Some(CLAVE), Some(&token), "listar_usuarios", json!({}));
+    assert_eq!(estado, 401);
+
+    let (estado, respuesta, _) = invocar(&servidor, "caja-a", Some(CLAVE), Some(&token), "listar_usuarios", json!({}));
+    assert_eq!(estado, 200);
+    assert!(respuesta.as_array().is_some_and(|usuarios| !usuarios.is_empty()));
+}
+
+#[test]
+fn la_sincronizacion_no_acepta_la_clave_de_las_cajas() {
+    let servidor = iniciar("sincronizacion");
+    let resultado = ureq::post(&format!("{}/sincronizacion", servidor.url))
+        .set("X-Clave", CLAVE)
+        .set("X-Sucursal", "norte")
+        .set("X-Sucursal-Clave", "inventada")
+        .send_json(json!({}));
+    match resultado {
+        Err(ureq::Error::Status(estado, _)) => assert_eq!(estado, 401),
+        otro => panic!("respuesta inesperada: {:?}", otro.map(|r| r.status())),
+    }
+}
@@ -13,6 +13,13 @@ function tauriInvoke(command, payload) {
     return Promise.reject(new Error(msg));
 }
 
+function tauriListen(evento, handler) {
+    if (window.__TAURI__ && window.__TAURI__.event && typeof window.__TAURI__.event.listen === 'function') {
+        return window.__TAURI__.event.listen(evento, handler);
+    }
+    return Promise.resolve(() => {});
+}
+
 function getAdminFlag() {
     try {
         return sessionStorage.getItem('is_admin') === '1';
@@ -78,6 +85,7 @@ document.addEventListener('DOMContentLoaded', () => {
     const addNombre = document.getElementById('add-nombre');
     const addPrecio = document.getElementById('add-precio');
     const addCantidad = document.getElementById('add-cantidad');
+    const addUnidad = document.getElementById('add-unidad');
     const btnAdd = document.getElementById('btn-add');
 
     const isAdmin = getAdminFlag();
@@ -89,6 +97,7 @@ document.addEventListener('DOMContentLoaded', () => {
     }
 
     let inventarios = [];
+    let avisoStock = '';
 
     function setStatus(message, isError) {
         if (!statusEl) return;
@@ -134,12 +143,12 @@ document.addEventListener('DOMContentLoaded', () => {
                 const precioCell = createInputCell(item.precio, 'number', false);
                 precioCell.input.step = '0.01';
                 const cantidadCell = createInputCell(item.cantidad, 'number', false);
-                cantidadCell.input.step = '1';
+                cantidadCell.input.step = item.unidad === 'kg' || item.unidad === 'litro' ? '0.001' : '1';
 
                 const debouncedUpdate = debounce(async () => {
                     const nombre = normalizeValue(nombreCell.input.value);
                     const precio = parseFloat(precioCell.input.value);
-                    const cantidad = parseInt(cantidadCell.input.value, 10);
+                    const cantidad = parseFloat(cantidadCell.input.value);
 
                     if (!nombre || Number.isNaN(precio) || Number.isNaN(cantidad)) {
                         setStatus('Revisa los valores antes de guardar.', true);
@@ -181,6 +190,10 @@ document.addEventListener('DOMContentLoaded', () => {
         try {
             inventarios = await tauriInvoke('listar_inventarios');
             renderTable(filterInventarios(normalizeValue(searchInput?.value)));
+            if (avisoStock) {
+                setStatus(avisoStock, true);
+                avisoStock = '';
+            }
         } catch (err) {
             setStatus(`Error al cargar: ${err}`, true);
         }
@@ -221,7 +234,7 @@ document.addEventListener('DOMContentLoaded', () => {
             const nombre = normalizeValue(addNombre?.value);
             const precioValue = parseFloat(normalizeValue(addPrecio?.value));
             const cantidadRaw = normalizeValue(addCantidad?.value);
-            const cantidadValue = cantidadRaw ? parseInt(cantidadRaw, 10) : null;
+            const cantidadValue = cantidadRaw ? parseFloat(cantidadRaw) : null;
 
             if (!idValue || !nombre || Number.isNaN(precioValue)) {
                 setAddStatus('Completa ID, nombre y precio correctamente.', true);
@@ -238,6 +251,7 @@ document.addEventListener('DOMContentLoaded', () => {
                     nombre,
                     precio: precioValue,
                     cantidad: cantidadValue,
+                    unidad: addUnidad ? addUnidad.value : null,
                 });
                 setAddStatus('Producto agregado.', false);
                 if (addId) addId.value = '';
@@ -251,5 +265,15 @@ document.addEventListener('DOMContentLoaded', () => {
         });
     }
 
+    // Ventas y compras de otras ventanas o cajas cambian el stock y los precios
+    const recargarPorEvento = debounce(() => cargarInventarios(), 300);
+    tauriListen('stock_cambiado', recargarPorEvento);
+    tauriListen('precio_cambiado', recargarPorEvento);
+    tauriListen('stock_bajo', (evento) => {
+        const datos = (evento.payload && evento.payload.datos) || {};
+        avisoStock = `Stock bajo: ${datos.nombre} (${datos.cantidad})`;
+        recargarPorEvento();
+    });
+
     cargarInventarios();
 });